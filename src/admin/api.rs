@@ -0,0 +1,464 @@
+// src/admin/api.rs
+//
+// JSON endpoints of the admin listener:
+//
+//   GET  /routes
+//   GET  /upstreams
+//   GET  /upstreams/{name}
+//   POST /upstreams/{name}/backends/{address}/drain
+//   POST /upstreams/{name}/backends/{address}/undrain
+//   PUT  /upstreams/{name}/backends/{address}/weight   {"weight": 5}
+//   GET  /connections
+//   GET  /config
+//   GET  /cache
+//   POST /cache/purge   {"key": "..."}, {"prefix": "..."} or {"tags": ["..."]}
+//   DELETE /cache
+
+use std::sync::atomic::Ordering;
+use std::time::{Duration, SystemTime};
+
+use crate::config::{RateLimit, RequestIdFormat};
+use crate::http::util::url_decode;
+use crate::http::{HttpMethod, HttpRequest, HttpResponse, HttpStatus};
+use crate::json::{self, ObjectWriter};
+use crate::logging::{AccessLogFormat, AccessLogTarget};
+use crate::log_info;
+use crate::proxy::cache::{Cache, Purge};
+use crate::proxy::context::ProxyContext;
+use crate::proxy::upstream::{Backend, UpstreamPool};
+
+pub fn error(status: HttpStatus, message: &str) -> HttpResponse {
+    HttpResponse::json(status, ObjectWriter::new().string("error", message).finish())
+}
+
+fn ok(body: String) -> HttpResponse {
+    HttpResponse::json(HttpStatus::Ok, body)
+}
+
+/// Dispatch a JSON API request, or `None` when the path is not an API path.
+pub fn handle(req: &HttpRequest, path: &str, context: &ProxyContext) -> Option<HttpResponse> {
+    let segments: Vec<String> = path
+        .trim_matches('/')
+        .split('/')
+        .map(url_decode)
+        .collect();
+    let segments: Vec<&str> = segments.iter().map(String::as_str).collect();
+
+    let response = match (&req.method, segments.as_slice()) {
+        (HttpMethod::GET, ["routes"]) => ok(routes_json(context)),
+        (HttpMethod::GET, ["upstreams"]) => ok(json::array(context.upstreams.iter().map(|u| upstream_json(u)))),
+        (HttpMethod::GET, ["upstreams", name]) => match find_upstream(context, name) {
+            Some(pool) => ok(upstream_json(pool)),
+            None => error(HttpStatus::NotFound, "Unknown upstream"),
+        },
+        (HttpMethod::POST, ["upstreams", name, "backends", address, action @ ("drain" | "undrain")]) => {
+            with_backend(context, name, address, |pool, backend| {
+                backend.set_drained(*action == "drain");
+                log_info!("Admin API: {} upstream {} backend {}", action, pool.name, backend.address);
+                ok(backend_json(backend))
+            })
+        }
+        (HttpMethod::PUT | HttpMethod::POST, ["upstreams", name, "backends", address, "weight"]) => {
+            with_backend(context, name, address, |pool, backend| match parse_weight(req) {
+                Ok(weight) => {
+                    backend.set_weight(weight);
+                    log_info!("Admin API: set upstream {} backend {} weight to {}", pool.name, backend.address, weight);
+                    ok(backend_json(backend))
+                }
+                Err(message) => error(HttpStatus::BadRequest, &message),
+            })
+        }
+        (HttpMethod::GET, ["connections"]) => ok(connections_json(context)),
+        (HttpMethod::GET, ["config"]) => ok(config_json(context)),
+        (HttpMethod::GET, ["cache"]) => with_cache(context, |cache| ok(cache_json(cache))),
+        (HttpMethod::POST, ["cache", "purge"]) => with_cache(context, |cache| match parse_purge(req) {
+            Ok(purge) => purge_cache(cache, &purge),
+            Err(message) => error(HttpStatus::BadRequest, &message),
+        }),
+        (HttpMethod::DELETE, ["cache"]) => with_cache(context, |cache| purge_cache(cache, &Purge::Prefix(String::new()))),
+        (_, ["routes" | "upstreams" | "connections" | "config" | "cache", ..]) => {
+            error(HttpStatus::MethodNotAllowed, "Method Not Allowed")
+        }
+        _ => return None,
+    };
+    Some(response)
+}
+
+fn find_upstream<'a>(context: &'a ProxyContext, name: &str) -> Option<&'a UpstreamPool> {
+    context
+        .upstreams
+        .iter()
+        .find(|u| u.name == name)
+        .map(|u| u.as_ref())
+}
+
+fn with_backend(
+    context: &ProxyContext,
+    name: &str,
+    address: &str,
+    action: impl FnOnce(&UpstreamPool, &Backend) -> HttpResponse,
+) -> HttpResponse {
+    let Some(pool) = find_upstream(context, name) else {
+        return error(HttpStatus::NotFound, "Unknown upstream");
+    };
+    match pool.backend(address) {
+        Some(backend) => action(pool, backend),
+        None => error(HttpStatus::NotFound, "Unknown backend"),
+    }
+}
+
+/// Weight from a `{"weight": N}` body.
+fn parse_weight(req: &HttpRequest) -> Result<u32, String> {
+    let body = req.body_as_string().unwrap_or_default();
+    let document = json::parse(&body).map_err(|e| format!("Invalid JSON body: {}", e))?;
+    let weight = document
+        .get("weight")
+        .and_then(|w| w.as_f64())
+        .ok_or_else(|| "Expected a numeric \"weight\" field".to_string())?;
+    if weight < 0.0 || weight.fract() != 0.0 || weight > u32::MAX as f64 {
+        return Err("Weight must be a non-negative integer".to_string());
+    }
+    Ok(weight as u32)
+}
+
+fn with_cache(context: &ProxyContext, action: impl FnOnce(&Cache) -> HttpResponse) -> HttpResponse {
+    match &context.cache {
+        Some(cache) => action(cache),
+        None => error(HttpStatus::NotFound, "Caching is not enabled"),
+    }
+}
+
+/// What to purge from a `{"key": ...}`, `{"prefix": ...}` or `{"tags": [...]}`
+/// body.
+fn parse_purge(req: &HttpRequest) -> Result<Purge, String> {
+    let body = req.body_as_string().unwrap_or_default();
+    let document = json::parse(&body).map_err(|e| format!("Invalid JSON body: {}", e))?;
+    if let Some(key) = document.get("key").and_then(|k| k.as_str()) {
+        return Ok(Purge::Key(key.to_string()));
+    }
+    if let Some(prefix) = document.get("prefix").and_then(|p| p.as_str()) {
+        return Ok(Purge::Prefix(prefix.to_string()));
+    }
+    if let Some(tags) = document.get("tags").and_then(|t| t.as_array()) {
+        let tags = tags.iter().filter_map(|tag| tag.as_str()).map(str::to_string).collect();
+        return Ok(Purge::Tags(tags));
+    }
+    Err("Expected a \"key\", \"prefix\" or \"tags\" field".to_string())
+}
+
+fn purge_cache(cache: &Cache, purge: &Purge) -> HttpResponse {
+    let purged = cache.purge(purge);
+    log_info!("Admin API: purged {} cached responses ({:?})", purged, purge);
+    ok(ObjectWriter::new().number("purged", purged).finish())
+}
+
+fn millis(duration: Duration) -> u128 {
+    duration.as_millis()
+}
+
+fn routes_json(context: &ProxyContext) -> String {
+    json::array(context.router.routes().iter().map(|route| {
+        ObjectWriter::new()
+            .string("name", &route.name)
+            .optional_string("host", route.host.as_deref())
+            .string("prefix", &route.prefix)
+            .raw("client_subjects", &json::array(route.client_subjects.iter().map(|s| json::string(s))))
+            .string("upstream", &route.upstream.name)
+            .finish()
+    }))
+}
+
+fn cache_json(cache: &Cache) -> String {
+    let now = SystemTime::now();
+    json::array(cache.entries().iter().map(|(tier, entry)| {
+        ObjectWriter::new()
+            .string("key", &entry.key)
+            .string("tier", &tier.to_string())
+            .number("status", entry.status.code())
+            .number("size", entry.size())
+            .number("age_ms", millis(entry.age(now)))
+            .number("fresh_ms", millis(entry.lifetime.saturating_sub(entry.age(now))))
+            .number("hits", entry.hits.load(Ordering::Relaxed))
+            .raw(
+                "vary",
+                &json::array(entry.vary.iter().map(|(field, value)| json::string(&format!("{}: {}", field, value)))),
+            )
+            .raw("tags", &json::array(entry.tags().map(json::string)))
+            .finish()
+    }))
+}
+
+fn backend_json(backend: &Backend) -> String {
+    ObjectWriter::new()
+        .string("address", &backend.address)
+        .number("weight", backend.weight())
+        .boolean("healthy", backend.is_healthy())
+        .boolean("drained", backend.is_drained())
+        .number("connections", backend.in_use())
+        .number("max_connections", backend.max_connections())
+        .finish()
+}
+
+fn upstream_json(pool: &UpstreamPool) -> String {
+    ObjectWriter::new()
+        .string("name", &pool.name)
+        .raw("backends", &json::array(pool.backends.iter().map(|b| backend_json(b))))
+        .finish()
+}
+
+fn connections_json(context: &ProxyContext) -> String {
+    let upstreams = json::array(context.upstreams.iter().map(|pool| {
+        let backends = json::array(pool.backends.iter().map(|backend| {
+            ObjectWriter::new()
+                .string("address", &backend.address)
+                .number("connections", backend.in_use())
+                .finish()
+        }));
+        ObjectWriter::new()
+            .string("name", &pool.name)
+            .number("connections", pool.backends.iter().map(|b| b.in_use()).sum::<usize>())
+            .raw("backends", &backends)
+            .finish()
+    }));
+
+    ObjectWriter::new()
+        .number("client_connections", context.metrics.active_connections.get())
+        .raw("upstreams", &upstreams)
+        .finish()
+}
+
+/// The configuration in effect, with runtime changes (weights, drains)
+/// applied to the upstream servers.
+fn config_json(context: &ProxyContext) -> String {
+    let config = &context.config;
+
+    let server = ObjectWriter::new()
+        .string("listen", &config.server.listen.to_string())
+        .number("read_timeout_ms", millis(config.server.read_timeout))
+        .number("shutdown_grace_ms", millis(config.server.shutdown_grace))
+        .number("tunnel_idle_timeout_ms", millis(config.server.tunnel_idle_timeout))
+        .optional_string(
+            "upgrade_socket",
+            config.server.upgrade_socket.as_ref().map(|p| p.display().to_string()).as_deref(),
+        )
+        .number("workers", config.server.workers)
+        .boolean("pin_workers", config.server.pin_workers)
+        .boolean("http2", config.server.http2)
+        .optional_string("default_host", config.server.default_host.as_deref())
+        .finish();
+
+    let request_id = ObjectWriter::new()
+        .string("header", &config.request_id.header)
+        .string(
+            "format",
+            match config.request_id.format {
+                RequestIdFormat::Uuid => "uuid",
+                RequestIdFormat::Ulid => "ulid",
+            },
+        )
+        .boolean("trust_incoming", config.request_id.trust_incoming)
+        .raw(
+            "trusted_clients",
+            &json::array(
+                config
+                    .request_id
+                    .trusted_clients
+                    .iter()
+                    .map(|ip| json::string(&ip.to_string())),
+            ),
+        )
+        .finish();
+
+    let access_log = ObjectWriter::new()
+        .boolean("enabled", config.access_log.enabled)
+        .string(
+            "format",
+            match &config.access_log.format {
+                AccessLogFormat::Common => "common",
+                AccessLogFormat::Combined => "combined",
+                AccessLogFormat::Json => "json",
+                AccessLogFormat::Custom(pattern) => pattern,
+            },
+        )
+        .string(
+            "path",
+            &match &config.access_log.target {
+                AccessLogTarget::Stdout => "stdout".to_string(),
+                AccessLogTarget::File(path) => path.display().to_string(),
+            },
+        )
+        .finish();
+
+    let upstreams = json::array(config.upstreams.iter().map(|upstream| {
+        let pool = find_upstream(context, &upstream.name);
+        let servers = json::array(upstream.servers.iter().map(|server| {
+            let backend = pool.and_then(|p| p.backend(&server.address));
+            ObjectWriter::new()
+                .string("address", &server.address)
+                .number("weight", backend.map_or(server.weight, |b| b.weight()))
+                .boolean("drained", backend.is_some_and(|b| b.is_drained()))
+                .finish()
+        }));
+        let health_check = match &upstream.health_check {
+            Some(check) => ObjectWriter::new()
+                .string("path", &check.path)
+                .number("interval_ms", millis(check.interval))
+                .number("timeout_ms", millis(check.timeout))
+                .number("healthy_threshold", check.healthy_threshold)
+                .number("unhealthy_threshold", check.unhealthy_threshold)
+                .finish(),
+            None => "null".to_string(),
+        };
+        let tls = match &upstream.tls {
+            Some(tls) => {
+                let path = |p: &Option<std::path::PathBuf>| p.as_ref().map(|p| p.display().to_string());
+                ObjectWriter::new()
+                    .optional_string("ca_file", path(&tls.ca_file).as_deref())
+                    .optional_string("server_name", tls.server_name.as_deref())
+                    .boolean("insecure_skip_verify", tls.insecure_skip_verify)
+                    .optional_string("client_cert", path(&tls.client_cert).as_deref())
+                    .optional_string("client_key", path(&tls.client_key).as_deref())
+                    .finish()
+            }
+            None => "null".to_string(),
+        };
+        ObjectWriter::new()
+            .string("name", &upstream.name)
+            .raw("servers", &servers)
+            .number("timeout_ms", millis(upstream.timeout))
+            .number("max_connections", upstream.max_connections)
+            .boolean("http2", upstream.http2)
+            .raw("health_check", &health_check)
+            .raw("tls", &tls)
+            .finish()
+    }));
+
+    let routes = json::array(config.routes.iter().map(|route| {
+        ObjectWriter::new()
+            .string("name", &route.name)
+            .optional_string("host", route.host.as_deref())
+            .string("prefix", &route.prefix)
+            .raw("client_subjects", &json::array(route.client_subjects.iter().map(|s| json::string(s))))
+            .string("upstream", &route.upstream)
+            .raw(
+                "cache_key",
+                &match &route.cache_key {
+                    Some(key) => json::array(key.iter().map(|part| json::string(&part.to_string()))),
+                    None => "null".to_string(),
+                },
+            )
+            .boolean("decompress_requests", route.decompress_requests)
+            .boolean("decompress_responses", route.decompress_responses)
+            .raw("rate_limit", &rate_limit_json(route.rate_limit.as_ref()))
+            .finish()
+    }));
+
+    let admin = match &config.admin {
+        Some(admin) => ObjectWriter::new()
+            .string("listen", &admin.listen.to_string())
+            .boolean("token_required", admin.token.is_some())
+            .finish(),
+        None => "null".to_string(),
+    };
+
+    let tls = match &config.tls {
+        Some(tls) => ObjectWriter::new()
+            .string("listen", &tls.listen.to_string())
+            .string("min_version", tls.min_version.as_str())
+            .raw("cipher_suites", &json::array(tls.cipher_suites.iter().map(|s| json::string(s))))
+            .optional_string("client_ca", tls.client_auth.as_ref().map(|a| a.ca_file.display().to_string()).as_deref())
+            .optional_string(
+                "client_auth",
+                tls.client_auth
+                    .as_ref()
+                    .map(|a| if a.required { "require" } else { "optional" }),
+            )
+            .number("reload_interval_ms", tls.reload_interval.map_or(0, millis))
+            .optional_string("default_host", tls.default_host.as_deref())
+            .raw(
+                "certificates",
+                &json::array(tls.certificates.iter().map(|cert| {
+                    ObjectWriter::new()
+                        .string("cert", &cert.cert.display().to_string())
+                        .string("key", &cert.key.display().to_string())
+                        .raw("server_names", &json::array(cert.server_names.iter().map(|n| json::string(n))))
+                        .finish()
+                })),
+            )
+            .finish(),
+        None => "null".to_string(),
+    };
+
+    let forward_proxy = match &config.forward_proxy {
+        Some(forward) => ObjectWriter::new()
+            .raw("allow", &json::array(forward.allow.iter().map(|d| json::string(&d.to_string()))))
+            .number("connect_timeout_ms", millis(forward.connect_timeout))
+            .finish(),
+        None => "null".to_string(),
+    };
+
+    let cache = match &config.cache {
+        Some(cache) => ObjectWriter::new()
+            .number("max_size", cache.max_size)
+            .number("max_entry_size", cache.max_entry_size)
+            .raw("key", &json::array(cache.key.iter().map(|part| json::string(&part.to_string()))))
+            .boolean("all_routes", cache.all_routes)
+            .optional_string("disk_path", cache.disk.as_ref().map(|d| d.path.display().to_string()).as_deref())
+            .raw("disk_max_size", &cache.disk.as_ref().map_or("null".to_string(), |d| d.max_size.to_string()))
+            .raw(
+                "purge_clients",
+                &json::array(cache.purge_clients.iter().map(|ip| json::string(&ip.to_string()))),
+            )
+            .boolean("coalesce", cache.coalesce)
+            .number("coalesce_timeout_ms", millis(cache.coalesce_timeout))
+            .finish(),
+        None => "null".to_string(),
+    };
+
+    let compression = match &config.compression {
+        Some(compression) => ObjectWriter::new()
+            .raw(
+                "algorithms",
+                &json::array(compression.algorithms.iter().map(|coding| json::string(coding.name()))),
+            )
+            .number("min_size", compression.min_size)
+            .raw("content_types", &json::array(compression.content_types.iter().map(|t| json::string(t))))
+            .number("level", compression.level)
+            .finish(),
+        None => "null".to_string(),
+    };
+
+    let rate_limit = match &config.rate_limit {
+        Some(rate_limit) => ObjectWriter::new()
+            .number("max_keys", rate_limit.max_keys)
+            .raw("default", &rate_limit_json(rate_limit.default.as_ref()))
+            .finish(),
+        None => "null".to_string(),
+    };
+
+    ObjectWriter::new()
+        .raw("server", &server)
+        .raw("upstreams", &upstreams)
+        .raw("routes", &routes)
+        .raw("request_id", &request_id)
+        .raw("access_log", &access_log)
+        .raw("admin", &admin)
+        .raw("tls", &tls)
+        .raw("forward_proxy", &forward_proxy)
+        .raw("cache", &cache)
+        .raw("compression", &compression)
+        .raw("rate_limit", &rate_limit)
+        .finish()
+}
+
+fn rate_limit_json(limit: Option<&RateLimit>) -> String {
+    match limit {
+        Some(limit) => ObjectWriter::new()
+            .number("rate", limit.rate)
+            .number("burst", limit.burst)
+            .raw("key", &json::array(limit.key.iter().map(|part| json::string(&part.to_string()))))
+            .optional_string("scope", limit.scope.as_deref())
+            .finish(),
+        None => "null".to_string(),
+    }
+}
@@ -0,0 +1,108 @@
+// src/admin/mod.rs
+//
+// The admin listener: a separate, usually loopback-only, HTTP endpoint for
+// operating the proxy. Requests are read and answered with the same `http`
+// types the proxy itself uses. With `token` set in `[admin]`, every request
+// must present it as a bearer token.
+
+pub mod api;
+
+use std::io::{self, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Arc;
+use std::thread;
+
+use crate::http::util::create_error_response;
+use crate::http::{HttpMethod, HttpRequest, HttpResponse, HttpStatus};
+use crate::http::util::parser::extract_query_params;
+use crate::metrics::exposition::CONTENT_TYPE;
+use crate::proxy::connection::{Connection, ReadError};
+use crate::proxy::context::ProxyContext;
+use crate::{log_info, log_warn};
+
+/// Serve the admin listener from a background thread.
+pub fn spawn(listener: TcpListener, context: Arc<ProxyContext>) -> io::Result<()> {
+    log_info!("Admin listener on {}", listener.local_addr()?);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let context = Arc::clone(&context);
+                    thread::spawn(move || handle_connection(stream, context));
+                }
+                Err(e) => log_warn!("Failed to accept admin connection: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+fn handle_connection(stream: TcpStream, context: Arc<ProxyContext>) {
+    let _ = stream.set_read_timeout(Some(context.config.server.read_timeout));
+    let mut conn = Connection::new(stream);
+
+    loop {
+        let response = match conn.read_request() {
+            Ok((mut req, _)) => match conn.read_body() {
+                Ok(body) => {
+                    req.body = Some(body);
+                    handle_request(&req, &context)
+                }
+                Err(ReadError::Rejected(status, message)) => create_error_response(status, message),
+                Err(_) => return,
+            },
+            Err(ReadError::Closed) => return,
+            Err(ReadError::Parse(e)) => create_error_response(HttpStatus::BadRequest, e.to_string()),
+            Err(ReadError::Rejected(status, message)) => create_error_response(status, message),
+        };
+
+        let close = response
+            .headers
+            .get("connection")
+            .is_some_and(|v| v.eq_ignore_ascii_case("close"));
+        if conn.stream.write_all(&response.to_bytes()).is_err() || close {
+            return;
+        }
+    }
+}
+
+fn handle_request(req: &HttpRequest, context: &ProxyContext) -> HttpResponse {
+    if let Some(token) = context.config.admin.as_ref().and_then(|admin| admin.token.as_deref())
+        && !authorized(req, token)
+    {
+        return api::error(HttpStatus::Unauthorized, "Missing or invalid admin token")
+            .with_header("WWW-Authenticate", "Bearer realm=\"orion-admin\"");
+    }
+    let (path, _) = extract_query_params(&req.path);
+
+    match (&req.method, path.as_str()) {
+        (HttpMethod::GET, "/metrics") => {
+            HttpResponse::text(HttpStatus::Ok, context.metrics.render(&context.upstreams))
+                .with_header("Content-Type", CONTENT_TYPE)
+        }
+        (_, "/metrics") => create_error_response(HttpStatus::MethodNotAllowed, "Method Not Allowed"),
+        _ => api::handle(req, &path, context)
+            .unwrap_or_else(|| api::error(HttpStatus::NotFound, "Not Found")),
+    }
+}
+
+/// Whether `req` carries `Authorization: Bearer <token>`. The comparison
+/// takes the same time wherever the tokens differ.
+fn authorized(req: &HttpRequest, token: &str) -> bool {
+    let Some(presented) = req
+        .headers
+        .get("authorization")
+        .and_then(|value| value.split_once(' '))
+        .filter(|(scheme, _)| scheme.eq_ignore_ascii_case("bearer"))
+        .map(|(_, presented)| presented.trim())
+    else {
+        return false;
+    };
+    presented.len() == token.len()
+        && presented
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
@@ -0,0 +1,207 @@
+// src/config/cache.rs
+//
+// The response cache.
+//
+//   [cache]
+//   max_size = 67108864                # bytes of responses held in memory
+//   max_entry_size = 1048576           # larger responses are not stored
+//   key = ["scheme", "host", "path", "query"]
+//   all_routes = true                  # or opt routes in with `cache = true`
+//   disk_path = "/var/cache/orion"     # keep entries evicted from memory
+//   disk_max_size = 1073741824
+//   purge_clients = ["127.0.0.1", "::1"]
+//   coalesce = true                    # one upstream request per missed key
+//   coalesce_timeout_ms = 5000
+//
+//   [[routes]]
+//   prefix = "/static"
+//   upstream = "assets"
+//   cache_key = ["host", "path", "header:accept-language", "cookie:region"]
+//
+// A route is cached when it sets `cache = true` or a `cache_key`, or when
+// `all_routes` is set and it does not say `cache = false`. Routes without a
+// `cache_key` use `key`. Setting either on a route is enough to turn the
+// cache on; the section only changes its limits.
+//
+// `PURGE` requests on a cached route drop stored responses: the request's
+// own key, every key starting with it when the path ends in `*`, or every
+// response tagged with one of the names in its `Surrogate-Key` header. They
+// are accepted only from `purge_clients`, loopback by default. The admin
+// API has the same operations.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::config::errors::ConfigError;
+use crate::config::section::Section;
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Bytes of stored responses (bodies and headers) held in memory.
+    pub max_size: u64,
+    /// Responses larger than this are passed through without being stored.
+    pub max_entry_size: u64,
+    /// Key for cached routes without a `cache_key` of their own.
+    pub key: Vec<CacheKeyPart>,
+    /// Cache every route that does not opt out.
+    pub all_routes: bool,
+    /// Second tier on disk for entries evicted from memory.
+    pub disk: Option<DiskCacheConfig>,
+    /// Addresses allowed to send `PURGE` requests.
+    pub purge_clients: Vec<IpAddr>,
+    /// Collapse concurrent misses on a key into one upstream request.
+    pub coalesce: bool,
+    /// How long a collapsed request waits for the leading one before going
+    /// to the upstream itself.
+    pub coalesce_timeout: Duration,
+}
+
+#[derive(Debug, Clone)]
+pub struct DiskCacheConfig {
+    pub path: PathBuf,
+    pub max_size: u64,
+}
+
+/// One component of a cache key, taken from the request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CacheKeyPart {
+    Scheme,
+    Host,
+    Path,
+    Query,
+    /// A request header, by lowercase name.
+    Header(String),
+    /// One cookie from the `Cookie` header.
+    Cookie(String),
+}
+
+impl CacheKeyPart {
+    pub fn parse(part: &str) -> Option<Self> {
+        Some(match part {
+            "scheme" => CacheKeyPart::Scheme,
+            "host" => CacheKeyPart::Host,
+            "path" => CacheKeyPart::Path,
+            "query" => CacheKeyPart::Query,
+            _ => {
+                let (kind, name) = part.split_once(':')?;
+                if name.is_empty() {
+                    return None;
+                }
+                match kind {
+                    "header" => CacheKeyPart::Header(name.to_ascii_lowercase()),
+                    "cookie" => CacheKeyPart::Cookie(name.to_string()),
+                    _ => return None,
+                }
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for CacheKeyPart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CacheKeyPart::Scheme => f.write_str("scheme"),
+            CacheKeyPart::Host => f.write_str("host"),
+            CacheKeyPart::Path => f.write_str("path"),
+            CacheKeyPart::Query => f.write_str("query"),
+            CacheKeyPart::Header(name) => write!(f, "header:{}", name),
+            CacheKeyPart::Cookie(name) => write!(f, "cookie:{}", name),
+        }
+    }
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        Self {
+            max_size: 64 * 1024 * 1024,
+            max_entry_size: 1024 * 1024,
+            key: vec![CacheKeyPart::Scheme, CacheKeyPart::Host, CacheKeyPart::Path, CacheKeyPart::Query],
+            all_routes: false,
+            disk: None,
+            purge_clients: vec![IpAddr::V4(Ipv4Addr::LOCALHOST), IpAddr::V6(Ipv6Addr::LOCALHOST)],
+            coalesce: true,
+            coalesce_timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+fn key_parts(section: &Section<'_>, key: &str) -> Result<Option<Vec<CacheKeyPart>>, ConfigError> {
+    let Some(parts) = section.string_list(key)? else {
+        return Ok(None);
+    };
+    let parts = parts
+        .iter()
+        .map(|part| {
+            CacheKeyPart::parse(part).ok_or_else(|| {
+                section.invalid(
+                    key,
+                    format!(
+                        "'{}' is not one of \"scheme\", \"host\", \"path\", \"query\", \"header:NAME\" or \"cookie:NAME\"",
+                        part
+                    ),
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if parts.is_empty() {
+        return Err(section.invalid(key, "must name at least one part"));
+    }
+    Ok(Some(parts))
+}
+
+/// Runs after the routes are parsed, to resolve each route's cache key.
+pub(crate) fn parse(root: &Section<'_>, config: &mut Config) -> Result<(), ConfigError> {
+    let mut cache = CacheConfig::default();
+    let section = root.section("cache")?;
+    if let Some(section) = &section {
+        if let Some(size) = section.unsigned("max_size")? {
+            cache.max_size = size;
+        }
+        if let Some(size) = section.unsigned("max_entry_size")? {
+            cache.max_entry_size = size;
+        }
+        if let Some(key) = key_parts(section, "key")? {
+            cache.key = key;
+        }
+        if let Some(all) = section.boolean("all_routes")? {
+            cache.all_routes = all;
+        }
+        if let Some(path) = section.string("disk_path")? {
+            cache.disk = Some(DiskCacheConfig {
+                path: PathBuf::from(path),
+                max_size: section.unsigned("disk_max_size")?.unwrap_or(1024 * 1024 * 1024),
+            });
+        }
+        if let Some(clients) = section.ip_list("purge_clients")? {
+            cache.purge_clients = clients;
+        }
+        if let Some(coalesce) = section.boolean("coalesce")? {
+            cache.coalesce = coalesce;
+        }
+        if let Some(timeout) = section.millis("coalesce_timeout_ms")? {
+            cache.coalesce_timeout = timeout;
+        }
+    }
+
+    // Explicit routes line up with their sections; a generated catch-all
+    // route has none and follows `all_routes`.
+    let sections = root.sections("routes")?;
+    for (i, route) in config.routes.iter_mut().enumerate() {
+        let (enabled, key) = match sections.get(i) {
+            Some(section) => (section.boolean("cache")?, key_parts(section, "cache_key")?),
+            None => (None, None),
+        };
+        let enabled = match enabled {
+            Some(enabled) => enabled,
+            None => key.is_some() || cache.all_routes,
+        };
+        route.cache_key = enabled.then(|| key.unwrap_or_else(|| cache.key.clone()));
+    }
+
+    if section.is_some() || config.routes.iter().any(|route| route.cache_key.is_some()) {
+        config.cache = Some(cache);
+    }
+    Ok(())
+}
@@ -0,0 +1,98 @@
+// src/config/compression.rs
+//
+// Response compression.
+//
+//   [compression]
+//   algorithms = ["zstd", "br", "gzip"]   # preferred first
+//   min_size = 1024                       # smaller bodies are sent as they are
+//   content_types = ["text/*", "application/json", "application/javascript"]
+//   level = 5
+//
+// With this section present, responses whose type matches `content_types`
+// are compressed with the first of `algorithms` the client's
+// `Accept-Encoding` allows. A `type/*` entry matches every subtype. `level`
+// applies to each algorithm within its own range (gzip 0-9, brotli 0-11,
+// zstd 1-22).
+
+use crate::config::Config;
+use crate::config::errors::ConfigError;
+use crate::config::section::Section;
+use crate::http::coding::ContentCoding;
+
+#[derive(Debug, Clone)]
+pub struct CompressionConfig {
+    pub algorithms: Vec<ContentCoding>,
+    /// Bodies known to be smaller than this are not compressed.
+    pub min_size: u64,
+    /// Lowercase media types, or `type/*`.
+    pub content_types: Vec<String>,
+    pub level: u32,
+}
+
+impl Default for CompressionConfig {
+    fn default() -> Self {
+        Self {
+            algorithms: vec![ContentCoding::Zstd, ContentCoding::Brotli, ContentCoding::Gzip],
+            min_size: 1024,
+            content_types: [
+                "text/*",
+                "application/json",
+                "application/javascript",
+                "application/xml",
+                "application/wasm",
+                "image/svg+xml",
+            ]
+            .iter()
+            .map(|t| t.to_string())
+            .collect(),
+            level: 5,
+        }
+    }
+}
+
+impl CompressionConfig {
+    /// Whether a `Content-Type` value is in the allowlist.
+    pub fn compresses(&self, content_type: &str) -> bool {
+        let media_type = content_type.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+        self.content_types.iter().any(|allowed| match allowed.strip_suffix("/*") {
+            Some(prefix) => media_type.strip_prefix(prefix).is_some_and(|rest| rest.starts_with('/')),
+            None => *allowed == media_type,
+        })
+    }
+}
+
+pub(crate) fn parse(root: &Section<'_>, config: &mut Config) -> Result<(), ConfigError> {
+    let Some(section) = root.section("compression")? else {
+        return Ok(());
+    };
+
+    let mut compression = CompressionConfig::default();
+    if let Some(names) = section.string_list("algorithms")? {
+        compression.algorithms = names
+            .iter()
+            .map(|name| {
+                ContentCoding::parse(name).ok_or_else(|| {
+                    section.invalid("algorithms", format!("'{}' is not one of \"gzip\", \"br\" or \"zstd\"", name))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        if compression.algorithms.is_empty() {
+            return Err(section.invalid("algorithms", "must name at least one algorithm"));
+        }
+    }
+    if let Some(size) = section.unsigned("min_size")? {
+        compression.min_size = size;
+    }
+    if let Some(types) = section.string_list("content_types")? {
+        compression.content_types = types.iter().map(|t| t.trim().to_ascii_lowercase()).collect();
+    }
+    if let Some(level) = section.unsigned("level")? {
+        compression.level = u32::try_from(level)
+            .ok()
+            .filter(|&level| level <= 22)
+            .ok_or_else(|| section.invalid("level", "must be at most 22"))?;
+    }
+
+    config.compression = Some(compression);
+    Ok(())
+}
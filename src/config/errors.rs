@@ -0,0 +1,19 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(String),
+    Syntax { line: usize, message: String },
+    InvalidValue { key: String, message: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(error) => write!(f, "Unable to read config: {}", error),
+            ConfigError::Syntax { line, message } => write!(f, "Config syntax error on line {}: {}", line, message),
+            ConfigError::InvalidValue { key, message } => write!(f, "Invalid value for '{}': {}", key, message),
+        }
+    }
+}
+impl std::error::Error for ConfigError {}
@@ -0,0 +1,100 @@
+// src/config/forward_proxy.rs
+//
+// Forward-proxy (egress) mode.
+//
+//   [forward_proxy]
+//   allow = ["github.com:443", "*.npmjs.org:443", "mirror.internal:*"]
+//   connect_timeout_ms = 10000
+//
+// With this section present, `CONNECT host:port` requests open a TCP tunnel
+// and absolute-form requests (`GET http://host/path`) are forwarded to the
+// host they name, instead of going through the routes. Only destinations
+// matching an `allow` entry are reached; everything else gets 403. A host of
+// `*.example.com` matches subdomains of example.com, `*` matches any host,
+// and a port of `*` matches any port.
+
+use std::time::Duration;
+
+use crate::config::Config;
+use crate::config::errors::ConfigError;
+use crate::config::section::Section;
+
+#[derive(Debug, Clone)]
+pub struct ForwardProxyConfig {
+    pub allow: Vec<Destination>,
+    /// Limit for connecting to a destination, and for each read and write on
+    /// forwarded requests.
+    pub connect_timeout: Duration,
+}
+
+/// One allowlist entry.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Destination {
+    /// Lowercase host, `*.suffix`, or `*`.
+    pub host: String,
+    /// `None` matches any port.
+    pub port: Option<u16>,
+}
+
+impl Destination {
+    pub fn parse(entry: &str) -> Option<Self> {
+        let (host, port) = entry.rsplit_once(':')?;
+        let port = match port {
+            "*" => None,
+            port => Some(port.parse().ok().filter(|&p| p != 0)?),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+        if host.is_empty() {
+            return None;
+        }
+        Some(Self { host, port })
+    }
+
+    pub fn matches(&self, host: &str, port: u16) -> bool {
+        if self.port.is_some_and(|p| p != port) {
+            return false;
+        }
+        let host = host.trim_start_matches('[').trim_end_matches(']').to_ascii_lowercase();
+        match self.host.strip_prefix("*.") {
+            _ if self.host == "*" => true,
+            Some(suffix) => host.strip_suffix(suffix).is_some_and(|rest| rest.ends_with('.') && rest.len() > 1),
+            None => host == self.host,
+        }
+    }
+}
+
+impl std::fmt::Display for Destination {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.port {
+            Some(port) => write!(f, "{}:{}", self.host, port),
+            None => write!(f, "{}:*", self.host),
+        }
+    }
+}
+
+impl ForwardProxyConfig {
+    pub fn allows(&self, host: &str, port: u16) -> bool {
+        self.allow.iter().any(|d| d.matches(host, port))
+    }
+}
+
+pub(crate) fn parse(root: &Section<'_>, config: &mut Config) -> Result<(), ConfigError> {
+    let Some(section) = root.section("forward_proxy")? else {
+        return Ok(());
+    };
+
+    let mut allow = Vec::new();
+    for entry in section.string_list("allow")?.unwrap_or_default() {
+        let destination = Destination::parse(&entry)
+            .ok_or_else(|| section.invalid("allow", format!("'{}' is not a host:port pattern", entry)))?;
+        allow.push(destination);
+    }
+
+    config.forward_proxy = Some(ForwardProxyConfig {
+        allow,
+        connect_timeout: section
+            .millis("connect_timeout_ms")?
+            .unwrap_or(Duration::from_secs(10)),
+    });
+    Ok(())
+}
@@ -0,0 +1,280 @@
+// src/config/mod.rs
+
+pub mod cache;
+pub mod compression;
+pub mod errors;
+pub mod forward_proxy;
+pub mod rate_limit;
+pub mod section;
+pub mod tls;
+pub mod toml;
+pub mod upstream;
+
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+pub use cache::{CacheConfig, CacheKeyPart, DiskCacheConfig};
+pub use compression::CompressionConfig;
+pub use errors::ConfigError;
+pub use forward_proxy::{Destination, ForwardProxyConfig};
+pub use rate_limit::{RateLimit, RateLimitConfig, RateLimitKeyPart};
+pub use section::Section;
+pub use tls::{CertificateConfig, ClientAuthConfig, TlsConfig, TlsVersion};
+pub use upstream::{BackendConfig, HealthCheckConfig, RouteConfig, UpstreamConfig, UpstreamTlsConfig};
+
+use crate::logging::{AccessLogFormat, AccessLogTarget};
+
+/// Top level configuration for the proxy, usually loaded from a TOML file.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub upstreams: Vec<UpstreamConfig>,
+    pub routes: Vec<RouteConfig>,
+    pub request_id: RequestIdConfig,
+    pub access_log: AccessLogConfig,
+    /// Admin listener (metrics); disabled unless configured.
+    pub admin: Option<AdminConfig>,
+    /// HTTPS listener; disabled unless configured.
+    pub tls: Option<TlsConfig>,
+    /// Forward-proxy mode (CONNECT and absolute-form requests); disabled
+    /// unless configured.
+    pub forward_proxy: Option<ForwardProxyConfig>,
+    /// Response cache; on when configured or when a route is cached.
+    pub cache: Option<CacheConfig>,
+    /// Response compression; disabled unless configured.
+    pub compression: Option<CompressionConfig>,
+    /// Request rate limiting; on when configured or when a route is
+    /// limited.
+    pub rate_limit: Option<RateLimitConfig>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub listen: SocketAddr,
+    /// How long a client connection may sit idle before it is closed.
+    pub read_timeout: Duration,
+    /// How long in-flight requests get to finish after SIGTERM/SIGINT.
+    pub shutdown_grace: Duration,
+    /// How long an upgraded (e.g. WebSocket) connection may pass no data
+    /// in either direction before it is closed.
+    pub tunnel_idle_timeout: Duration,
+    /// Unix socket used to hand listeners to a new binary during an upgrade.
+    pub upgrade_socket: Option<PathBuf>,
+    /// Accept threads, each with its own SO_REUSEPORT listener.
+    pub workers: usize,
+    /// Pin each worker (and the threads it starts) to one CPU.
+    pub pin_workers: bool,
+    /// Speak HTTP/2 to clients that ask for it: h2 over TLS via ALPN, and
+    /// h2c with prior knowledge or `Upgrade: h2c`.
+    pub http2: bool,
+    /// Host for HTTP/1.0 requests that come without one; the address the
+    /// connection was accepted on when unset.
+    pub default_host: Option<String>,
+}
+
+/// Upper bound on `server.workers`: every worker's listeners must fit in one
+/// upgrade handoff message.
+pub const MAX_WORKERS: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct AdminConfig {
+    pub listen: SocketAddr,
+    /// When set, every admin request must carry `Authorization: Bearer
+    /// <token>`.
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestIdFormat {
+    Uuid,
+    Ulid,
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestIdConfig {
+    /// Header used to receive, forward and echo the ID.
+    pub header: String,
+    pub format: RequestIdFormat,
+    /// Reuse an ID supplied by the client instead of generating one.
+    pub trust_incoming: bool,
+    /// When non-empty, only IDs from these client addresses are trusted.
+    pub trusted_clients: Vec<IpAddr>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AccessLogConfig {
+    pub enabled: bool,
+    pub format: AccessLogFormat,
+    pub target: AccessLogTarget,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            server: ServerConfig {
+                listen: SocketAddr::from(([0, 0, 0, 0], 8080)),
+                read_timeout: Duration::from_secs(30),
+                shutdown_grace: Duration::from_secs(30),
+                tunnel_idle_timeout: Duration::from_secs(300),
+                upgrade_socket: None,
+                workers: 1,
+                pin_workers: false,
+                http2: true,
+                default_host: None,
+            },
+            upstreams: vec![UpstreamConfig::new("default", "127.0.0.1:8081")],
+            routes: vec![RouteConfig::catch_all("default")],
+            request_id: RequestIdConfig::default(),
+            access_log: AccessLogConfig {
+                enabled: true,
+                format: AccessLogFormat::Combined,
+                target: AccessLogTarget::Stdout,
+            },
+            admin: None,
+            tls: None,
+            forward_proxy: None,
+            cache: None,
+            compression: None,
+            rate_limit: None,
+        }
+    }
+}
+
+impl Default for RequestIdConfig {
+    fn default() -> Self {
+        Self {
+            header: "X-Request-Id".to_string(),
+            format: RequestIdFormat::Uuid,
+            trust_incoming: false,
+            trusted_clients: Vec::new(),
+        }
+    }
+}
+
+impl RequestIdConfig {
+    pub fn trusts(&self, client: IpAddr) -> bool {
+        self.trust_incoming && (self.trusted_clients.is_empty() || self.trusted_clients.contains(&client))
+    }
+}
+
+/// `default_host`, for the listeners that accept HTTP/1.0.
+pub(crate) fn default_host(section: &Section<'_>) -> Result<Option<String>, ConfigError> {
+    let Some(host) = section.string("default_host")? else {
+        return Ok(None);
+    };
+    if host.is_empty() || host.bytes().any(|b| b.is_ascii_whitespace() || b.is_ascii_control()) {
+        return Err(section.invalid("default_host", format!("'{}' is not a valid host", host)));
+    }
+    Ok(Some(host))
+}
+
+impl Config {
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .map_err(|e| ConfigError::Io(format!("{}: {}", path.display(), e)))?;
+        Self::parse(&contents)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, ConfigError> {
+        let table = toml::parse(contents)?;
+        let root = Section::root(&table);
+        let mut config = Config::default();
+
+        if let Some(server) = root.section("server")? {
+            if let Some(listen) = server.socket_addr("listen")? {
+                config.server.listen = listen;
+            }
+            if let Some(timeout) = server.millis("read_timeout_ms")? {
+                config.server.read_timeout = timeout;
+            }
+            if let Some(grace) = server.millis("shutdown_grace_ms")? {
+                config.server.shutdown_grace = grace;
+            }
+            if let Some(timeout) = server.millis("tunnel_idle_timeout_ms")? {
+                config.server.tunnel_idle_timeout = timeout;
+            }
+            if let Some(path) = server.string("upgrade_socket")? {
+                config.server.upgrade_socket = Some(PathBuf::from(path));
+            }
+            if let Some(workers) = server.unsigned("workers")? {
+                if workers == 0 || workers > MAX_WORKERS as u64 {
+                    return Err(server.invalid("workers", format!("must be between 1 and {}", MAX_WORKERS)));
+                }
+                config.server.workers = workers as usize;
+            }
+            if let Some(pin) = server.boolean("pin_workers")? {
+                config.server.pin_workers = pin;
+            }
+            if let Some(http2) = server.boolean("http2")? {
+                config.server.http2 = http2;
+            }
+            config.server.default_host = default_host(&server)?;
+        }
+
+        upstream::parse(&root, &mut config)?;
+        tls::parse(&root, &mut config)?;
+        forward_proxy::parse(&root, &mut config)?;
+        cache::parse(&root, &mut config)?;
+        compression::parse(&root, &mut config)?;
+        rate_limit::parse(&root, &mut config)?;
+
+        if let Some(section) = root.section("request_id")? {
+            let request_id = &mut config.request_id;
+            if let Some(header) = section.string("header")? {
+                if header.is_empty() || !header.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_') {
+                    return Err(section.invalid("header", format!("'{}' is not a valid header name", header)));
+                }
+                request_id.header = header;
+            }
+            if let Some(format) = section.string("format")? {
+                request_id.format = match format.as_str() {
+                    "uuid" => RequestIdFormat::Uuid,
+                    "ulid" => RequestIdFormat::Ulid,
+                    other => return Err(section.invalid("format", format!("expected \"uuid\" or \"ulid\", found \"{}\"", other))),
+                };
+            }
+            if let Some(trust) = section.boolean("trust_incoming")? {
+                request_id.trust_incoming = trust;
+            }
+            if let Some(clients) = section.ip_list("trusted_clients")? {
+                request_id.trusted_clients = clients;
+            }
+        }
+
+        if let Some(section) = root.section("access_log")? {
+            let access_log = &mut config.access_log;
+            if let Some(enabled) = section.boolean("enabled")? {
+                access_log.enabled = enabled;
+            }
+            if let Some(format) = section.string("format")? {
+                access_log.format = AccessLogFormat::parse(&format).ok_or_else(|| {
+                    section.invalid(
+                        "format",
+                        format!("expected \"common\", \"combined\", \"json\" or a custom format, found \"{}\"", format),
+                    )
+                })?;
+            }
+            if let Some(path) = section.string("path")? {
+                access_log.target = match path.as_str() {
+                    "stdout" | "-" => AccessLogTarget::Stdout,
+                    file => AccessLogTarget::File(PathBuf::from(file)),
+                };
+            }
+        }
+
+        if let Some(section) = root.section("admin")? {
+            let listen = section
+                .socket_addr("listen")?
+                .ok_or_else(|| section.invalid("listen", "is required"))?;
+            let token = section.string("token")?;
+            if token.as_deref().is_some_and(|token| token.is_empty() || token.bytes().any(|b| !b.is_ascii_graphic())) {
+                return Err(section.invalid("token", "must be non-empty printable ASCII without spaces"));
+            }
+            config.admin = Some(AdminConfig { listen, token });
+        }
+
+        Ok(config)
+    }
+}
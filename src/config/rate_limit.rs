@@ -0,0 +1,179 @@
+// src/config/rate_limit.rs
+//
+// Request rate limiting with token buckets.
+//
+//   [rate_limit]
+//   rate = 10                          # tokens added per second
+//   burst = 20                         # bucket size; `rate` by default
+//   key = ["client_ip"]                # what gets a bucket of its own
+//   max_keys = 100000                  # buckets kept; idle ones go first
+//
+//   [[routes]]
+//   prefix = "/api"
+//   upstream = "api"
+//   rate_limit = { rate = 100, burst = 200, key = ["header:x-api-key"] }
+//
+// Each request takes a token from the bucket for its key and is answered
+// with 429 when there is none left. A key combines any of `client_ip`,
+// `route` and `header:NAME`; requests without the header share the bucket
+// of an empty value.
+//
+// The `rate` in `[rate_limit]` applies to every route without a limit of
+// its own, with buckets shared across those routes unless the key has
+// `route` in it. A route's own limit replaces it and keeps buckets of its
+// own; `rate_limit = false` exempts a route. Buckets not used for a while
+// are dropped first once there are `max_keys` of them; a bucket that is
+// dropped starts again full.
+
+use crate::config::Config;
+use crate::config::errors::ConfigError;
+use crate::config::section::Section;
+use crate::config::toml::Value;
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    /// Most buckets held in memory.
+    pub max_keys: u64,
+    /// The limit for routes without one of their own.
+    pub default: Option<RateLimit>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimit {
+    /// Tokens added per second.
+    pub rate: f64,
+    /// Tokens a full bucket holds, and so the most requests let through at
+    /// once.
+    pub burst: u64,
+    pub key: Vec<RateLimitKeyPart>,
+    /// Routes with the same scope share buckets: the route's name for a
+    /// limit it sets itself, `None` for the `[rate_limit]` one.
+    pub scope: Option<String>,
+}
+
+/// One component of a bucket key, taken from the request.
+#[derive(Debug, Clone, PartialEq)]
+pub enum RateLimitKeyPart {
+    ClientIp,
+    Route,
+    /// A request header, by lowercase name.
+    Header(String),
+}
+
+impl RateLimitKeyPart {
+    pub fn parse(part: &str) -> Option<Self> {
+        match part {
+            "client_ip" => Some(RateLimitKeyPart::ClientIp),
+            "route" => Some(RateLimitKeyPart::Route),
+            _ => match part.split_once(':') {
+                Some(("header", name)) if !name.is_empty() => Some(RateLimitKeyPart::Header(name.to_ascii_lowercase())),
+                _ => None,
+            },
+        }
+    }
+}
+
+impl std::fmt::Display for RateLimitKeyPart {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RateLimitKeyPart::ClientIp => f.write_str("client_ip"),
+            RateLimitKeyPart::Route => f.write_str("route"),
+            RateLimitKeyPart::Header(name) => write!(f, "header:{}", name),
+        }
+    }
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        Self {
+            max_keys: 100_000,
+            default: None,
+        }
+    }
+}
+
+fn key_parts(section: &Section<'_>) -> Result<Option<Vec<RateLimitKeyPart>>, ConfigError> {
+    let Some(names) = section.string_list("key")? else {
+        return Ok(None);
+    };
+    let parts = names
+        .iter()
+        .map(|name| {
+            RateLimitKeyPart::parse(name).ok_or_else(|| {
+                section.invalid(
+                    "key",
+                    format!("'{}' is not one of \"client_ip\", \"route\" or \"header:NAME\"", name),
+                )
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if parts.is_empty() {
+        return Err(section.invalid("key", "must name at least one part"));
+    }
+    Ok(Some(parts))
+}
+
+/// A limit from a section holding `rate`, `burst` and `key`; `None` without
+/// a `rate`.
+fn limit(section: &Section<'_>, scope: Option<String>) -> Result<Option<RateLimit>, ConfigError> {
+    let Some(rate) = section.float("rate")? else {
+        if section.contains("burst") || section.contains("key") {
+            return Err(section.invalid("rate", "is required"));
+        }
+        return Ok(None);
+    };
+    if !(rate > 0.0 && rate.is_finite()) {
+        return Err(section.invalid("rate", "must be greater than 0"));
+    }
+    let burst = match section.unsigned("burst")? {
+        Some(0) => return Err(section.invalid("burst", "must be at least 1")),
+        Some(burst) => burst,
+        None => (rate.ceil() as u64).max(1),
+    };
+    Ok(Some(RateLimit {
+        rate,
+        burst,
+        key: key_parts(section)?.unwrap_or_else(|| vec![RateLimitKeyPart::ClientIp]),
+        scope,
+    }))
+}
+
+/// Runs after the routes are parsed, to give each route its limit.
+pub(crate) fn parse(root: &Section<'_>, config: &mut Config) -> Result<(), ConfigError> {
+    let mut rate_limit = RateLimitConfig::default();
+    let section = root.section("rate_limit")?;
+    if let Some(section) = &section {
+        if let Some(max) = section.unsigned("max_keys")? {
+            if max == 0 {
+                return Err(section.invalid("max_keys", "must be at least 1"));
+            }
+            rate_limit.max_keys = max;
+        }
+        rate_limit.default = limit(section, None)?;
+    }
+
+    // Explicit routes line up with their sections; a generated catch-all
+    // route has none and takes the default.
+    let sections = root.sections("routes")?;
+    for (i, route) in config.routes.iter_mut().enumerate() {
+        route.rate_limit = match sections.get(i).and_then(|section| Some((section, section.value("rate_limit")?))) {
+            None | Some((_, Value::Boolean(true))) => rate_limit.default.clone(),
+            Some((_, Value::Boolean(false))) => None,
+            Some((section, Value::Table(table))) => {
+                let own = section.nested("rate_limit", table);
+                Some(limit(&own, Some(route.name.clone()))?.ok_or_else(|| own.invalid("rate", "is required"))?)
+            }
+            Some((section, other)) => {
+                return Err(section.invalid(
+                    "rate_limit",
+                    format!("expected a table or boolean, found {}", other.type_name()),
+                ));
+            }
+        };
+    }
+
+    if section.is_some() || config.routes.iter().any(|route| route.rate_limit.is_some()) {
+        config.rate_limit = Some(rate_limit);
+    }
+    Ok(())
+}
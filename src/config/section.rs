@@ -0,0 +1,184 @@
+/// Typed, path-aware access to a parsed config table.
+/// src/config/section.rs
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+use crate::config::errors::ConfigError;
+use crate::config::toml::{Table, Value};
+
+#[derive(Debug, Clone)]
+pub struct Section<'a> {
+    table: &'a Table,
+    path: String,
+}
+
+impl<'a> Section<'a> {
+    pub fn root(table: &'a Table) -> Self {
+        Self {
+            table,
+            path: String::new(),
+        }
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Full dotted name of `key` inside this section, used in error messages.
+    pub fn key_path(&self, key: &str) -> String {
+        if self.path.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}.{}", self.path, key)
+        }
+    }
+
+    pub fn invalid(&self, key: &str, message: impl Into<String>) -> ConfigError {
+        ConfigError::InvalidValue {
+            key: self.key_path(key),
+            message: message.into(),
+        }
+    }
+
+    fn type_error(&self, key: &str, expected: &str, found: &Value) -> ConfigError {
+        self.invalid(key, format!("expected {}, found {}", expected, found.type_name()))
+    }
+
+    pub fn value(&self, key: &str) -> Option<&'a Value> {
+        self.table.get(key)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.table.contains_key(key)
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &'a String> {
+        self.table.keys()
+    }
+
+    pub fn string(&self, key: &str) -> Result<Option<String>, ConfigError> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::String(s)) => Ok(Some(s.clone())),
+            Some(other) => Err(self.type_error(key, "string", other)),
+        }
+    }
+
+    pub fn integer(&self, key: &str) -> Result<Option<i64>, ConfigError> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::Integer(i)) => Ok(Some(*i)),
+            Some(other) => Err(self.type_error(key, "integer", other)),
+        }
+    }
+
+    /// A non-negative integer that must fit in `u64`.
+    pub fn unsigned(&self, key: &str) -> Result<Option<u64>, ConfigError> {
+        match self.integer(key)? {
+            None => Ok(None),
+            Some(i) if i >= 0 => Ok(Some(i as u64)),
+            Some(_) => Err(self.invalid(key, "must not be negative")),
+        }
+    }
+
+    pub fn float(&self, key: &str) -> Result<Option<f64>, ConfigError> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::Float(f)) => Ok(Some(*f)),
+            Some(Value::Integer(i)) => Ok(Some(*i as f64)),
+            Some(other) => Err(self.type_error(key, "float", other)),
+        }
+    }
+
+    pub fn boolean(&self, key: &str) -> Result<Option<bool>, ConfigError> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::Boolean(b)) => Ok(Some(*b)),
+            Some(other) => Err(self.type_error(key, "boolean", other)),
+        }
+    }
+
+    pub fn string_list(&self, key: &str) -> Result<Option<Vec<String>>, ConfigError> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::Array(items)) => items
+                .iter()
+                .map(|item| match item {
+                    Value::String(s) => Ok(s.clone()),
+                    other => Err(self.type_error(key, "array of strings", other)),
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map(Some),
+            Some(other) => Err(self.type_error(key, "array of strings", other)),
+        }
+    }
+
+    /// Durations are written in milliseconds (`timeout_ms = 5000`).
+    pub fn millis(&self, key: &str) -> Result<Option<Duration>, ConfigError> {
+        Ok(self.unsigned(key)?.map(Duration::from_millis))
+    }
+
+    pub fn socket_addr(&self, key: &str) -> Result<Option<SocketAddr>, ConfigError> {
+        match self.string(key)? {
+            None => Ok(None),
+            Some(addr) => addr
+                .to_socket_addrs()
+                .ok()
+                .and_then(|mut addrs| addrs.next())
+                .map(Some)
+                .ok_or_else(|| self.invalid(key, format!("'{}' is not a valid socket address", addr))),
+        }
+    }
+
+    pub fn ip_list(&self, key: &str) -> Result<Option<Vec<IpAddr>>, ConfigError> {
+        match self.string_list(key)? {
+            None => Ok(None),
+            Some(items) => items
+                .iter()
+                .map(|item| {
+                    item.parse::<IpAddr>()
+                        .map_err(|_| self.invalid(key, format!("'{}' is not a valid IP address", item)))
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map(Some),
+        }
+    }
+
+    pub fn section(&self, key: &str) -> Result<Option<Section<'a>>, ConfigError> {
+        match self.table.get(key) {
+            None => Ok(None),
+            Some(Value::Table(table)) => Ok(Some(Section {
+                table,
+                path: self.key_path(key),
+            })),
+            Some(other) => Err(self.type_error(key, "table", other)),
+        }
+    }
+
+    /// A table found inside one of this section's values (e.g. an inline
+    /// table in an array), reported under `key` in errors.
+    pub fn nested(&self, key: &str, table: &'a Table) -> Section<'a> {
+        Section {
+            table,
+            path: self.key_path(key),
+        }
+    }
+
+    /// Entries of an array of tables such as `[[route]]`.
+    pub fn sections(&self, key: &str) -> Result<Vec<Section<'a>>, ConfigError> {
+        match self.table.get(key) {
+            None => Ok(Vec::new()),
+            Some(Value::Array(items)) => items
+                .iter()
+                .map(|item| match item {
+                    Value::Table(table) => Ok(Section {
+                        table,
+                        path: self.key_path(key),
+                    }),
+                    other => Err(self.type_error(key, "array of tables", other)),
+                })
+                .collect(),
+            Some(other) => Err(self.type_error(key, "array of tables", other)),
+        }
+    }
+}
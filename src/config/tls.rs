@@ -0,0 +1,158 @@
+// src/config/tls.rs
+//
+// HTTPS listener settings.
+//
+//   [tls]
+//   listen = "0.0.0.0:8443"
+//   min_version = "1.2"                # or "1.3"
+//   cipher_suites = ["TLS13_AES_256_GCM_SHA384", "TLS13_AES_128_GCM_SHA256"]
+//   client_ca = "/etc/orion/clients-ca.pem"  # ask clients for a certificate
+//   client_auth = "require"                   # or "optional"
+//   reload_interval_ms = 5000          # 0 turns certificate reloading off
+//   default_host = "example.com"       # for HTTP/1.0 requests without Host
+//
+//   [[tls.certificates]]
+//   cert = "/etc/orion/example.com.pem"
+//   key = "/etc/orion/example.com.key"
+//   server_names = ["example.com", "*.example.com"]
+//
+// A certificate without `server_names` is the default, used when the client
+// sends no SNI or a name no other certificate matches. Without one, the
+// first certificate is the default.
+//
+// Certificate and key files are checked for changes every
+// `reload_interval_ms`; new handshakes use the rewritten files once they load.
+//
+// With `client_ca` set, client certificates are verified against it after
+// the handshake. Requests on a connection without a valid certificate get
+// 403 when `client_auth = "require"` (the default); with "optional" only an
+// invalid certificate is refused.
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::errors::ConfigError;
+use crate::config::section::Section;
+use crate::config::Config;
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub listen: SocketAddr,
+    pub certificates: Vec<CertificateConfig>,
+    pub min_version: TlsVersion,
+    /// IANA names of the allowed cipher suites; empty means the defaults.
+    pub cipher_suites: Vec<String>,
+    pub client_auth: Option<ClientAuthConfig>,
+    /// How often certificate files are checked for changes; `None` never.
+    pub reload_interval: Option<Duration>,
+    /// Host for HTTP/1.0 requests without one; see `ServerConfig`.
+    pub default_host: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ClientAuthConfig {
+    /// PEM bundle of CAs client certificates must chain to.
+    pub ca_file: PathBuf,
+    /// Refuse requests from clients that present no certificate.
+    pub required: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct CertificateConfig {
+    /// PEM certificate chain, leaf first.
+    pub cert: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1).
+    pub key: PathBuf,
+    /// Exact names or `*.` wildcards selected by SNI.
+    pub server_names: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+impl TlsVersion {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TlsVersion::Tls12 => "1.2",
+            TlsVersion::Tls13 => "1.3",
+        }
+    }
+}
+
+pub(crate) fn parse(root: &Section<'_>, config: &mut Config) -> Result<(), ConfigError> {
+    let Some(section) = root.section("tls")? else {
+        return Ok(());
+    };
+
+    let listen = section
+        .socket_addr("listen")?
+        .ok_or_else(|| section.invalid("listen", "is required"))?;
+
+    let min_version = match section.string("min_version")?.as_deref() {
+        None | Some("1.2") => TlsVersion::Tls12,
+        Some("1.3") => TlsVersion::Tls13,
+        Some(other) => {
+            return Err(section.invalid("min_version", format!("expected \"1.2\" or \"1.3\", found \"{}\"", other)));
+        }
+    };
+
+    let mut certificates = Vec::new();
+    for cert in section.sections("certificates")? {
+        let path = |key: &str| -> Result<PathBuf, ConfigError> {
+            cert.string(key)?
+                .map(PathBuf::from)
+                .ok_or_else(|| cert.invalid(key, "is required"))
+        };
+        let server_names = cert
+            .string_list("server_names")?
+            .unwrap_or_default()
+            .into_iter()
+            .map(|name| name.to_ascii_lowercase())
+            .collect();
+        certificates.push(CertificateConfig {
+            cert: path("cert")?,
+            key: path("key")?,
+            server_names,
+        });
+    }
+    if certificates.is_empty() {
+        return Err(section.invalid("certificates", "at least one certificate is required"));
+    }
+
+    let required = match section.string("client_auth")?.as_deref() {
+        None | Some("require") => true,
+        Some("optional") => false,
+        Some(other) => {
+            return Err(section.invalid("client_auth", format!("expected \"require\" or \"optional\", found \"{}\"", other)));
+        }
+    };
+    let client_auth = match section.string("client_ca")? {
+        Some(ca_file) => Some(ClientAuthConfig {
+            ca_file: PathBuf::from(ca_file),
+            required,
+        }),
+        None if section.contains("client_auth") => {
+            return Err(section.invalid("client_auth", "requires client_ca"));
+        }
+        None => None,
+    };
+
+    config.tls = Some(TlsConfig {
+        listen,
+        certificates,
+        min_version,
+        cipher_suites: section.string_list("cipher_suites")?.unwrap_or_default(),
+        client_auth,
+        reload_interval: match section.millis("reload_interval_ms")? {
+            Some(Duration::ZERO) => None,
+            Some(interval) => Some(interval),
+            None => Some(Duration::from_secs(5)),
+        },
+        default_host: crate::config::default_host(&section)?,
+    });
+    Ok(())
+}
@@ -0,0 +1,534 @@
+/// A small TOML subset parser used to read the Orion configuration file.
+/// src/config/toml.rs
+///
+/// Supported: comments, `[tables]`, `[dotted.tables]`, `[[arrays.of.tables]]`,
+/// bare/quoted/dotted keys, basic and literal strings, integers, floats,
+/// booleans, arrays and inline tables. Dates and multi-line strings are not.
+use std::collections::{BTreeMap, HashSet};
+use crate::config::errors::ConfigError;
+
+pub type Table = BTreeMap<String, Value>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Float(f64),
+    Boolean(bool),
+    Array(Vec<Value>),
+    Table(Table),
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::String(_) => "string",
+            Value::Integer(_) => "integer",
+            Value::Float(_) => "float",
+            Value::Boolean(_) => "boolean",
+            Value::Array(_) => "array",
+            Value::Table(_) => "table",
+        }
+    }
+}
+
+pub fn parse(input: &str) -> Result<Table, ConfigError> {
+    let mut parser = Parser {
+        chars: input.chars().collect(),
+        pos: 0,
+        line: 1,
+    };
+    parser.parse_document()
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    line: usize,
+}
+
+impl Parser {
+    fn parse_document(&mut self) -> Result<Table, ConfigError> {
+        let mut root = Table::new();
+        let mut current: Vec<String> = Vec::new();
+        // Tables defined by a header, an inline table or a dotted key, by
+        // their path with the index into each array of tables along it.
+        let mut defined: HashSet<Vec<String>> = HashSet::new();
+
+        loop {
+            self.skip_whitespace_and_newlines();
+            let Some(c) = self.peek() else { break };
+
+            if c == '[' {
+                self.advance();
+                let array_of_tables = self.peek() == Some('[');
+                if array_of_tables {
+                    self.advance();
+                }
+                self.skip_whitespace();
+                let path = self.parse_key()?;
+                self.skip_whitespace();
+                self.expect(']')?;
+                if array_of_tables {
+                    self.expect(']')?;
+                }
+                self.expect_line_end()?;
+
+                let (last, parents) = path.split_last().expect("keys are never empty");
+                let parent = table_at(&mut root, parents, self.line)?;
+                if array_of_tables {
+                    match parent
+                        .entry(last.clone())
+                        .or_insert_with(|| Value::Array(Vec::new()))
+                    {
+                        Value::Array(items) => items.push(Value::Table(Table::new())),
+                        _ => return Err(self.error(format!("'{}' is not an array of tables", last))),
+                    }
+                } else {
+                    match parent
+                        .entry(last.clone())
+                        .or_insert_with(|| Value::Table(Table::new()))
+                    {
+                        Value::Table(_) => {}
+                        _ => return Err(self.error(format!("'{}' is not a table", last))),
+                    }
+                    if !defined.insert(resolve(&root, &path)) {
+                        return Err(self.error(format!("Table '{}' is defined more than once", path.join("."))));
+                    }
+                }
+                current = path;
+            } else {
+                let (key, value) = self.parse_key_value()?;
+                self.expect_line_end()?;
+                let table = table_at(&mut root, &current, self.line)?;
+                let inline = matches!(value, Value::Table(_));
+                self.insert(table, &key, value)?;
+                // `a.b = 1` defines table `a`, as `a = {}` does.
+                let mut path = current.clone();
+                let defines = if inline { key.len() } else { key.len() - 1 };
+                for part in &key[..defines] {
+                    path.push(part.clone());
+                    defined.insert(resolve(&root, &path));
+                }
+            }
+        }
+
+        Ok(root)
+    }
+
+    fn parse_key_value(&mut self) -> Result<(Vec<String>, Value), ConfigError> {
+        let key = self.parse_key()?;
+        self.skip_whitespace();
+        self.expect('=')?;
+        self.skip_whitespace();
+        let value = self.parse_value()?;
+        Ok((key, value))
+    }
+
+    fn insert(&self, table: &mut Table, key: &[String], value: Value) -> Result<(), ConfigError> {
+        let (last, parents) = key.split_last().expect("keys are never empty");
+        let table = table_at(table, parents, self.line)?;
+        if table.contains_key(last) {
+            return Err(self.error(format!("Duplicate key '{}'", last)));
+        }
+        table.insert(last.clone(), value);
+        Ok(())
+    }
+
+    /// Parse a (possibly dotted) key: `a`, `"quoted key"`, `a.b.'c'`.
+    fn parse_key(&mut self) -> Result<Vec<String>, ConfigError> {
+        let mut parts = Vec::new();
+        loop {
+            self.skip_whitespace();
+            let part = match self.peek() {
+                Some('"') => self.parse_basic_string()?,
+                Some('\'') => self.parse_literal_string()?,
+                _ => {
+                    let start = self.pos;
+                    while let Some(c) = self.peek() {
+                        if c.is_ascii_alphanumeric() || c == '_' || c == '-' {
+                            self.advance();
+                        } else {
+                            break;
+                        }
+                    }
+                    if start == self.pos {
+                        return Err(self.error("Expected a key".to_string()));
+                    }
+                    self.chars[start..self.pos].iter().collect()
+                }
+            };
+            parts.push(part);
+            self.skip_whitespace();
+            if self.peek() == Some('.') {
+                self.advance();
+            } else {
+                return Ok(parts);
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<Value, ConfigError> {
+        match self.peek() {
+            Some('"') => Ok(Value::String(self.parse_basic_string()?)),
+            Some('\'') => Ok(Value::String(self.parse_literal_string()?)),
+            Some('[') => self.parse_array(),
+            Some('{') => self.parse_inline_table(),
+            Some(_) => self.parse_scalar(),
+            None => Err(self.error("Expected a value".to_string())),
+        }
+    }
+
+    fn parse_basic_string(&mut self) -> Result<String, ConfigError> {
+        self.expect('"')?;
+        let mut out = String::new();
+        loop {
+            // Checked before advancing, so the error names the string's line.
+            let Some(c) = self.peek().filter(|&c| c != '\n') else {
+                return Err(self.error("Unterminated string".to_string()));
+            };
+            self.advance();
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let escaped = match self.advance() {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('u') => self.parse_unicode_escape(4)?,
+                        Some('U') => self.parse_unicode_escape(8)?,
+                        other => {
+                            return Err(self.error(format!("Invalid escape sequence '\\{}'", other.unwrap_or(' '))));
+                        }
+                    };
+                    out.push(escaped);
+                }
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn parse_unicode_escape(&mut self, digits: usize) -> Result<char, ConfigError> {
+        let mut code = 0u32;
+        for _ in 0..digits {
+            let digit = self
+                .advance()
+                .and_then(|c| c.to_digit(16))
+                .ok_or_else(|| self.error("Invalid unicode escape".to_string()))?;
+            code = code * 16 + digit;
+        }
+        char::from_u32(code).ok_or_else(|| self.error("Invalid unicode scalar value".to_string()))
+    }
+
+    fn parse_literal_string(&mut self) -> Result<String, ConfigError> {
+        self.expect('\'')?;
+        let mut out = String::new();
+        loop {
+            let Some(c) = self.peek().filter(|&c| c != '\n') else {
+                return Err(self.error("Unterminated string".to_string()));
+            };
+            self.advance();
+            match c {
+                '\'' => return Ok(out),
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn parse_array(&mut self) -> Result<Value, ConfigError> {
+        self.expect('[')?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace_and_newlines();
+            if self.peek() == Some(']') {
+                self.advance();
+                return Ok(Value::Array(items));
+            }
+            items.push(self.parse_value()?);
+            self.skip_whitespace_and_newlines();
+            match self.advance() {
+                Some(',') => continue,
+                Some(']') => return Ok(Value::Array(items)),
+                _ => return Err(self.error("Expected ',' or ']' in array".to_string())),
+            }
+        }
+    }
+
+    fn parse_inline_table(&mut self) -> Result<Value, ConfigError> {
+        self.expect('{')?;
+        let mut table = Table::new();
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.advance();
+            return Ok(Value::Table(table));
+        }
+        loop {
+            self.skip_whitespace();
+            let (key, value) = self.parse_key_value()?;
+            self.insert(&mut table, &key, value)?;
+            self.skip_whitespace();
+            match self.advance() {
+                Some(',') => continue,
+                Some('}') => return Ok(Value::Table(table)),
+                _ => return Err(self.error("Expected ',' or '}' in inline table".to_string())),
+            }
+        }
+    }
+
+    fn parse_scalar(&mut self) -> Result<Value, ConfigError> {
+        let start = self.pos;
+        while let Some(c) = self.peek() {
+            if c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+' | '.') {
+                self.advance();
+            } else {
+                break;
+            }
+        }
+        let token: String = self.chars[start..self.pos].iter().collect();
+        match token.as_str() {
+            "true" => return Ok(Value::Boolean(true)),
+            "false" => return Ok(Value::Boolean(false)),
+            "" => return Err(self.error("Expected a value".to_string())),
+            _ => {}
+        }
+        let digits = token.replace('_', "");
+        if let Ok(integer) = digits.parse::<i64>() {
+            return Ok(Value::Integer(integer));
+        }
+        if digits.chars().any(|c| c.is_ascii_digit())
+            && let Ok(float) = digits.parse::<f64>()
+        {
+            return Ok(Value::Float(float));
+        }
+        Err(self.error(format!("Invalid value '{}'", token)))
+    }
+
+    fn expect_line_end(&mut self) -> Result<(), ConfigError> {
+        self.skip_whitespace();
+        match self.peek() {
+            None => Ok(()),
+            Some('#') => {
+                self.skip_comment();
+                Ok(())
+            }
+            Some('\n') => Ok(()),
+            Some('\r') if self.chars.get(self.pos + 1) == Some(&'\n') => Ok(()),
+            Some(c) => Err(self.error(format!("Unexpected character '{}' after value", c))),
+        }
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), ConfigError> {
+        match self.advance() {
+            Some(c) if c == expected => Ok(()),
+            Some(c) => Err(self.error(format!("Expected '{}', found '{}'", expected, c))),
+            None => Err(self.error(format!("Expected '{}', found end of file", expected))),
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ') | Some('\t')) {
+            self.advance();
+        }
+    }
+
+    fn skip_whitespace_and_newlines(&mut self) {
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') | Some('\r') | Some('\n') => {
+                    self.advance();
+                }
+                Some('#') => self.skip_comment(),
+                _ => return,
+            }
+        }
+    }
+
+    fn skip_comment(&mut self) {
+        while let Some(c) = self.peek() {
+            if c == '\n' {
+                return;
+            }
+            self.advance();
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn advance(&mut self) -> Option<char> {
+        let c = self.chars.get(self.pos).copied()?;
+        self.pos += 1;
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error(&self, message: String) -> ConfigError {
+        ConfigError::Syntax {
+            line: self.line,
+            message,
+        }
+    }
+}
+
+/// `path` with the index of the last element after each array of tables
+/// along it, telling apart tables nested in different elements.
+fn resolve(root: &Table, path: &[String]) -> Vec<String> {
+    let mut resolved = Vec::new();
+    let mut table = Some(root);
+    for key in path {
+        resolved.push(key.clone());
+        table = match table.and_then(|table| table.get(key)) {
+            Some(Value::Table(inner)) => Some(inner),
+            Some(Value::Array(items)) => {
+                resolved.push(format!("#{}", items.len()));
+                match items.last() {
+                    Some(Value::Table(inner)) => Some(inner),
+                    _ => None,
+                }
+            }
+            _ => None,
+        };
+    }
+    resolved
+}
+
+/// Walk (and create) the tables along `path`. Arrays of tables resolve to
+/// their last element, matching how TOML headers nest under `[[array]]`.
+fn table_at<'a>(root: &'a mut Table, path: &[String], line: usize) -> Result<&'a mut Table, ConfigError> {
+    let mut table = root;
+    for key in path {
+        let entry = table
+            .entry(key.clone())
+            .or_insert_with(|| Value::Table(Table::new()));
+        table = match entry {
+            Value::Table(inner) => inner,
+            Value::Array(items) => match items.last_mut() {
+                Some(Value::Table(inner)) => inner,
+                _ => {
+                    return Err(ConfigError::Syntax {
+                        line,
+                        message: format!("'{}' is not an array of tables", key),
+                    });
+                }
+            },
+            _ => {
+                return Err(ConfigError::Syntax {
+                    line,
+                    message: format!("'{}' is not a table", key),
+                });
+            }
+        };
+    }
+    Ok(table)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table(value: &Value) -> &Table {
+        match value {
+            Value::Table(table) => table,
+            other => panic!("expected a table, found {}", other.type_name()),
+        }
+    }
+
+    fn error_line(input: &str) -> usize {
+        match parse(input) {
+            Err(ConfigError::Syntax { line, .. }) => line,
+            other => panic!("expected a syntax error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn dotted_keys_and_headers_nest_tables() {
+        let root = parse("a.b = 1\n\"quoted key\".'c' = true\n[x.y]\nz = 2.5\n").unwrap();
+        assert_eq!(table(&root["a"])["b"], Value::Integer(1));
+        assert_eq!(table(&root["quoted key"])["c"], Value::Boolean(true));
+        assert_eq!(table(&table(&root["x"])["y"])["z"], Value::Float(2.5));
+    }
+
+    #[test]
+    fn arrays_of_tables_collect_each_header() {
+        let root = parse(
+            r#"
+[[routes]]
+name = "one"
+[routes.limit]
+rate = 1
+
+[[routes]]
+name = "two"
+[routes.limit]
+rate = 2
+"#,
+        )
+        .unwrap();
+        let Value::Array(routes) = &root["routes"] else { panic!("{:?}", root) };
+        assert_eq!(routes.len(), 2);
+        for (route, (name, rate)) in routes.iter().zip([("one", 1), ("two", 2)]) {
+            assert_eq!(table(route)["name"], Value::String(name.to_string()));
+            assert_eq!(table(&table(route)["limit"])["rate"], Value::Integer(rate));
+        }
+    }
+
+    #[test]
+    fn inline_tables_and_arrays() {
+        let root = parse("limit = { rate = 1, key = [\"a\", 'b'], nested = {} }\nempty = []\n").unwrap();
+        let limit = table(&root["limit"]);
+        assert_eq!(limit["rate"], Value::Integer(1));
+        assert_eq!(
+            limit["key"],
+            Value::Array(vec![Value::String("a".to_string()), Value::String("b".to_string())])
+        );
+        assert_eq!(limit["nested"], Value::Table(Table::new()));
+        assert_eq!(root["empty"], Value::Array(Vec::new()));
+        assert!(parse("a = { b = 1, b = 2 }").is_err());
+    }
+
+    #[test]
+    fn string_escapes() {
+        let root = parse(r#"basic = "tab\there \"q\" \\ \u00e9\U0001F600"
+literal = 'C:\no\escapes'"#)
+        .unwrap();
+        assert_eq!(root["basic"], Value::String("tab\there \"q\" \\ \u{e9}\u{1F600}".to_string()));
+        assert_eq!(root["literal"], Value::String("C:\\no\\escapes".to_string()));
+        assert!(parse(r#"bad = "\q""#).is_err());
+        assert!(parse(r#"bad = "\uD800""#).is_err());
+    }
+
+    #[test]
+    fn integers_floats_and_booleans() {
+        let root = parse("a = 1_000\nb = -3\nc = 0.5\nd = false\n").unwrap();
+        assert_eq!(root["a"], Value::Integer(1000));
+        assert_eq!(root["b"], Value::Integer(-3));
+        assert_eq!(root["c"], Value::Float(0.5));
+        assert_eq!(root["d"], Value::Boolean(false));
+        assert!(parse("a = yes").is_err());
+    }
+
+    #[test]
+    fn errors_report_their_line() {
+        assert_eq!(error_line("a = 1\n\n# comment\nb = \"unterminated\n"), 4);
+        assert_eq!(error_line("a = 'unterminated\nb = 1\n"), 1);
+        assert_eq!(error_line("a = 1\na = 2\n"), 2);
+        assert_eq!(error_line("[server]\nlisten = 1 2\n"), 2);
+        assert_eq!(error_line("a = [\n  1,\n  2\n  3\n]\n"), 4);
+    }
+
+    #[test]
+    fn tables_cannot_be_defined_twice() {
+        assert_eq!(error_line("[server]\na = 1\n\n[server]\nb = 2\n"), 4);
+        assert_eq!(error_line("[a.b]\n[a]\n[a.b]\n"), 3);
+        assert_eq!(error_line("a = { b = 1 }\n[a]\n"), 2);
+        assert_eq!(error_line("[a]\nb.c = 1\n[a.b]\n"), 3);
+        assert_eq!(error_line("[a]\n[[a]]\n"), 2);
+        // A table may still be defined after its subtables, once.
+        assert!(parse("[a.b]\nc = 1\n[a]\nd = 2\n").is_ok());
+    }
+}
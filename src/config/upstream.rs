@@ -0,0 +1,341 @@
+// src/config/upstream.rs
+//
+// Upstream groups and the routes that point at them.
+//
+//   [upstreams.api]
+//   servers = ["10.0.0.1:8080", { address = "10.0.0.2:8080", weight = 3 }]
+//   timeout_ms = 30000
+//   max_connections = 256
+//   http2 = true                       # h2 with tls below, h2c without
+//
+//   [upstreams.api.health_check]
+//   path = "/health"
+//
+//   [upstreams.api.tls]                # connect to the servers over HTTPS
+//   ca_file = "/etc/orion/internal-ca.pem"
+//   server_name = "api.internal"
+//   client_cert = "/etc/orion/client.pem"
+//   client_key = "/etc/orion/client.key"
+//
+//   [[routes]]
+//   prefix = "/api"
+//   upstream = "api"
+//   client_subjects = ["CN=billing", "DNS:billing.internal"]  # mTLS only
+//   decompress_requests = true         # forward request bodies decoded
+//   decompress_responses = true        # decode for clients that can't
+//   rate_limit = { rate = 100, burst = 200 }
+//
+// With `decompress_requests`, request bodies sent with a gzip, br or zstd
+// `Content-Encoding` reach the upstream decoded; each coding may expand to
+// at most the request body limit, and other codings are refused with 415.
+// With `decompress_responses`, encoded responses are decoded for clients
+// whose `Accept-Encoding` does not take the coding (or who send none).
+//
+// Caching and rate limits are configured per route too; see `cache.rs` and
+// `rate_limit.rs`.
+//
+// The older single-upstream form (`[upstream] address = "..."`) is still
+// accepted and becomes an upstream named "default".
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::config::errors::ConfigError;
+use crate::config::section::Section;
+use crate::config::toml::Value;
+use crate::config::{CacheKeyPart, Config, RateLimit};
+
+#[derive(Debug, Clone)]
+pub struct UpstreamConfig {
+    pub name: String,
+    pub servers: Vec<BackendConfig>,
+    /// Applied to connecting, writing the request and reading the response.
+    pub timeout: Duration,
+    /// Concurrent connections allowed to each server.
+    pub max_connections: usize,
+    pub health_check: Option<HealthCheckConfig>,
+    /// Plain HTTP unless set.
+    pub tls: Option<UpstreamTlsConfig>,
+    /// Speak HTTP/2 to the servers, multiplexing requests over a few
+    /// connections: h2 (ALPN) over TLS, h2c with prior knowledge otherwise.
+    /// `max_connections` then limits concurrent requests.
+    pub http2: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct BackendConfig {
+    pub address: String, // host:port
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct HealthCheckConfig {
+    pub path: String,
+    pub interval: Duration,
+    pub timeout: Duration,
+    /// Consecutive successes before an unhealthy server is used again.
+    pub healthy_threshold: u32,
+    /// Consecutive failures before a server is taken out of rotation.
+    pub unhealthy_threshold: u32,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamTlsConfig {
+    /// PEM bundle of trusted CAs; the Mozilla root set when unset.
+    pub ca_file: Option<PathBuf>,
+    /// Name sent as SNI and verified against the certificate, instead of
+    /// the host part of each server address.
+    pub server_name: Option<String>,
+    /// Accept any server certificate. Development only.
+    pub insecure_skip_verify: bool,
+    /// Client certificate chain and key presented to the servers.
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub struct RouteConfig {
+    pub name: String,
+    /// Only match requests whose Host is this value.
+    pub host: Option<String>,
+    pub prefix: String,
+    pub upstream: String,
+    /// Only match requests whose verified client certificate has one of
+    /// these subjects or SANs; empty matches any request.
+    pub client_subjects: Vec<String>,
+    /// How responses are keyed in the cache; `None` when not cached.
+    pub cache_key: Option<Vec<CacheKeyPart>>,
+    /// Forward encoded request bodies decoded.
+    pub decompress_requests: bool,
+    /// Decode encoded responses for clients that do not accept the coding.
+    pub decompress_responses: bool,
+    /// The route's own limit or the default one; `None` when not limited.
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl UpstreamConfig {
+    pub fn new(name: impl Into<String>, address: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            servers: vec![BackendConfig {
+                address: address.into(),
+                weight: 1,
+            }],
+            timeout: Duration::from_secs(30),
+            max_connections: 1024,
+            health_check: None,
+            tls: None,
+            http2: false,
+        }
+    }
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            path: "/".to_string(),
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(2),
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        }
+    }
+}
+
+impl RouteConfig {
+    pub fn catch_all(upstream: impl Into<String>) -> Self {
+        let upstream = upstream.into();
+        Self {
+            name: upstream.clone(),
+            host: None,
+            prefix: "/".to_string(),
+            upstream,
+            client_subjects: Vec::new(),
+            cache_key: None,
+            decompress_requests: false,
+            decompress_responses: false,
+            rate_limit: None,
+        }
+    }
+}
+
+pub(crate) fn parse(root: &Section<'_>, config: &mut Config) -> Result<(), ConfigError> {
+    let mut upstreams = Vec::new();
+
+    if let Some(legacy) = root.section("upstream")? {
+        let mut upstream = UpstreamConfig::new(
+            "default",
+            legacy
+                .string("address")?
+                .unwrap_or_else(|| "127.0.0.1:8081".to_string()),
+        );
+        if let Some(timeout) = legacy.millis("timeout_ms")? {
+            upstream.timeout = timeout;
+        }
+        upstreams.push(upstream);
+    }
+
+    if let Some(section) = root.section("upstreams")? {
+        let names: Vec<String> = section.keys().cloned().collect();
+        for name in names {
+            let Some(group) = section.section(&name)? else { continue };
+            upstreams.push(parse_upstream(name, &group)?);
+        }
+    }
+
+    for (i, upstream) in upstreams.iter().enumerate() {
+        if upstreams[..i].iter().any(|other| other.name == upstream.name) {
+            return Err(ConfigError::InvalidValue {
+                key: format!("upstreams.{}", upstream.name),
+                message: "is defined more than once".to_string(),
+            });
+        }
+    }
+
+    let mut routes = Vec::new();
+    for (i, section) in root.sections("routes")?.iter().enumerate() {
+        let upstream = section
+            .string("upstream")?
+            .ok_or_else(|| section.invalid("upstream", "is required"))?;
+        let prefix = section.string("prefix")?.unwrap_or_else(|| "/".to_string());
+        if !prefix.starts_with('/') {
+            return Err(section.invalid("prefix", "must start with '/'"));
+        }
+        routes.push(RouteConfig {
+            name: section.string("name")?.unwrap_or_else(|| format!("route{}", i)),
+            host: section.string("host")?.map(|h| h.to_ascii_lowercase()),
+            prefix,
+            upstream,
+            client_subjects: section.string_list("client_subjects")?.unwrap_or_default(),
+            cache_key: None,
+            decompress_requests: section.boolean("decompress_requests")?.unwrap_or(false),
+            decompress_responses: section.boolean("decompress_responses")?.unwrap_or(false),
+            rate_limit: None,
+        });
+    }
+
+    if !upstreams.is_empty() {
+        config.upstreams = upstreams;
+        config.routes.clear();
+    }
+    if !routes.is_empty() {
+        config.routes = routes;
+    }
+    if config.routes.is_empty() {
+        // A single upstream needs no routing table.
+        match config.upstreams.as_slice() {
+            [only] => config.routes.push(RouteConfig::catch_all(only.name.clone())),
+            _ => {
+                return Err(ConfigError::InvalidValue {
+                    key: "routes".to_string(),
+                    message: "required when more than one upstream is configured".to_string(),
+                });
+            }
+        }
+    }
+
+    for route in &config.routes {
+        if !config.upstreams.iter().any(|u| u.name == route.upstream) {
+            return Err(ConfigError::InvalidValue {
+                key: format!("routes.{}", route.name),
+                message: format!("unknown upstream '{}'", route.upstream),
+            });
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_upstream(name: String, section: &Section<'_>) -> Result<UpstreamConfig, ConfigError> {
+    let mut upstream = UpstreamConfig::new(name, String::new());
+    upstream.servers = parse_servers(section)?;
+
+    if let Some(timeout) = section.millis("timeout_ms")? {
+        upstream.timeout = timeout;
+    }
+    if let Some(max) = section.unsigned("max_connections")? {
+        if max == 0 {
+            return Err(section.invalid("max_connections", "must be at least 1"));
+        }
+        upstream.max_connections = max as usize;
+    }
+    if let Some(http2) = section.boolean("http2")? {
+        upstream.http2 = http2;
+    }
+
+    if let Some(check) = section.section("health_check")? {
+        let mut health = HealthCheckConfig::default();
+        if let Some(path) = check.string("path")? {
+            health.path = path;
+        }
+        if let Some(interval) = check.millis("interval_ms")? {
+            health.interval = interval;
+        }
+        if let Some(timeout) = check.millis("timeout_ms")? {
+            health.timeout = timeout;
+        }
+        if let Some(threshold) = check.unsigned("healthy_threshold")? {
+            health.healthy_threshold = threshold.max(1) as u32;
+        }
+        if let Some(threshold) = check.unsigned("unhealthy_threshold")? {
+            health.unhealthy_threshold = threshold.max(1) as u32;
+        }
+        upstream.health_check = Some(health);
+    }
+
+    if let Some(section) = section.section("tls")? {
+        let path = |key: &str| -> Result<Option<PathBuf>, ConfigError> { Ok(section.string(key)?.map(PathBuf::from)) };
+        let tls = UpstreamTlsConfig {
+            ca_file: path("ca_file")?,
+            server_name: section.string("server_name")?,
+            insecure_skip_verify: section.boolean("insecure_skip_verify")?.unwrap_or(false),
+            client_cert: path("client_cert")?,
+            client_key: path("client_key")?,
+        };
+        if tls.client_cert.is_some() != tls.client_key.is_some() {
+            return Err(section.invalid("client_cert", "client_cert and client_key must be set together"));
+        }
+        upstream.tls = Some(tls);
+    }
+
+    Ok(upstream)
+}
+
+/// `servers` accepts plain addresses and `{ address, weight }` tables.
+fn parse_servers(section: &Section<'_>) -> Result<Vec<BackendConfig>, ConfigError> {
+    let Some(Value::Array(items)) = section.value("servers") else {
+        return Err(section.invalid("servers", "expected a non-empty array"));
+    };
+
+    let mut servers = Vec::new();
+    for item in items {
+        match item {
+            Value::String(address) => servers.push(BackendConfig {
+                address: address.clone(),
+                weight: 1,
+            }),
+            Value::Table(table) => {
+                let server = section.nested("servers", table);
+                let address = server
+                    .string("address")?
+                    .ok_or_else(|| server.invalid("address", "is required"))?;
+                let weight = server.unsigned("weight")?.unwrap_or(1);
+                if weight > u32::MAX as u64 {
+                    return Err(server.invalid("weight", "is too large"));
+                }
+                servers.push(BackendConfig {
+                    address,
+                    weight: weight as u32,
+                });
+            }
+            other => {
+                return Err(section.invalid("servers", format!("unexpected {}", other.type_name())));
+            }
+        }
+    }
+
+    if servers.is_empty() {
+        return Err(section.invalid("servers", "expected a non-empty array"));
+    }
+    Ok(servers)
+}
@@ -0,0 +1,474 @@
+/// Message bodies: held in memory, or streamed through fixed-size buffers.
+/// src/http/body.rs
+use crate::http::enums::{HttpMethod, HttpStatus};
+use crate::http::headers::HttpHeaders;
+use std::fmt;
+use std::io::{self, BufRead, Read, Write};
+use std::sync::{Arc, Mutex};
+
+/// Size of the buffer bodies are copied through.
+pub const COPY_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Longest chunk-size or trailer line accepted in chunked coding.
+const MAX_LINE_LEN: usize = 4096;
+
+/// Most trailer fields kept from one chunked body.
+const MAX_TRAILERS: usize = 64;
+
+pub enum Body {
+    Buffered(Vec<u8>),
+    /// Decoded body bytes, read as they arrive from the peer.
+    Stream(Box<dyn Read + Send>),
+}
+
+impl Body {
+    pub fn empty() -> Self {
+        Body::Buffered(Vec::new())
+    }
+
+    /// The bytes of a buffered body; `None` for a stream.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Buffered(bytes) => Some(bytes),
+            Body::Stream(_) => None,
+        }
+    }
+
+    pub fn is_stream(&self) -> bool {
+        matches!(self, Body::Stream(_))
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Body::empty()
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Buffered(bytes)
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Buffered(bytes) => write!(f, "Buffered({} bytes)", bytes.len()),
+            Body::Stream(_) => write!(f, "Stream"),
+        }
+    }
+}
+
+/// Trailer fields of a streamed body (gRPC's `grpc-status`, for one). They
+/// arrive after the body, so whoever reads the body to its end fills them
+/// in, and whoever writes the message out takes them afterwards.
+#[derive(Debug, Clone, Default)]
+pub struct Trailers(Arc<Mutex<Option<HttpHeaders>>>);
+
+impl Trailers {
+    /// Trailers known up front, as for a generated response.
+    pub fn with(fields: HttpHeaders) -> Self {
+        let trailers = Self::default();
+        trailers.set(fields);
+        trailers
+    }
+
+    pub fn set(&self, fields: HttpHeaders) {
+        *self.0.lock().unwrap_or_else(|e| e.into_inner()) = Some(fields);
+    }
+
+    /// True unless fields have been set and not taken.
+    pub fn is_empty(&self) -> bool {
+        self.0
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+            .is_none_or(HttpHeaders::is_empty)
+    }
+
+    /// The fields, once the body has been read; `None` if there were none.
+    pub fn take(&self) -> Option<HttpHeaders> {
+        let fields = self.0.lock().unwrap_or_else(|e| e.into_inner()).take()?;
+        (!fields.is_empty()).then_some(fields)
+    }
+}
+
+/// Why a body could not be read. Carried inside the `io::Error`s returned by
+/// `Decoder` so callers can answer with the right status.
+#[derive(Debug)]
+pub enum BodyError {
+    TooLarge(u64),
+    Malformed(String),
+    NotImplemented(String),
+}
+
+impl BodyError {
+    pub fn status(&self) -> HttpStatus {
+        match self {
+            BodyError::TooLarge(_) => HttpStatus::PayloadTooLarge,
+            BodyError::Malformed(_) => HttpStatus::BadRequest,
+            BodyError::NotImplemented(_) => HttpStatus::NotImplemented,
+        }
+    }
+
+    /// The `BodyError` behind an I/O error from a `Decoder`, if any.
+    pub fn from_io(error: &io::Error) -> Option<&BodyError> {
+        error.get_ref().and_then(|inner| inner.downcast_ref::<BodyError>())
+    }
+}
+
+impl fmt::Display for BodyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BodyError::TooLarge(limit) => write!(f, "Request body exceeds maximum size of {} bytes", limit),
+            BodyError::Malformed(message) => write!(f, "Malformed body: {}", message),
+            BodyError::NotImplemented(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+impl From<BodyError> for io::Error {
+    fn from(error: BodyError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, error)
+    }
+}
+
+/// How the end of a message body is found (RFC 9112 §6.3).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Framing {
+    Empty,
+    Length(u64),
+    Chunked,
+    /// The body runs until the connection closes; responses only.
+    UntilClose,
+}
+
+/// True when `chunked` is the final transfer coding.
+pub fn is_chunked(headers: &HttpHeaders) -> bool {
+    headers
+        .get("transfer-encoding")
+        .and_then(|value| value.rsplit(',').next())
+        .is_some_and(|coding| coding.trim().eq_ignore_ascii_case("chunked"))
+}
+
+fn content_length(headers: &HttpHeaders) -> Result<Option<u64>, BodyError> {
+    match headers.get("content-length") {
+        None => Ok(None),
+        Some(value) => value
+            .trim()
+            .parse::<u64>()
+            .map(Some)
+            .map_err(|_| BodyError::Malformed("Invalid Content-Length".to_string())),
+    }
+}
+
+impl Framing {
+    pub fn of_request(headers: &HttpHeaders) -> Result<Self, BodyError> {
+        if let Some(coding) = headers.get("transfer-encoding") {
+            // Both headers is how requests are smuggled past other proxies.
+            if headers.get("content-length").is_some() {
+                return Err(BodyError::Malformed(
+                    "Both Transfer-Encoding and Content-Length are present".to_string(),
+                ));
+            }
+            if !coding.trim().eq_ignore_ascii_case("chunked") {
+                return Err(BodyError::NotImplemented(format!("Unsupported transfer coding '{}'", coding)));
+            }
+            return Ok(Framing::Chunked);
+        }
+        Ok(match content_length(headers)? {
+            None | Some(0) => Framing::Empty,
+            Some(length) => Framing::Length(length),
+        })
+    }
+
+    pub fn of_response(method: &HttpMethod, status: u16, headers: &HttpHeaders) -> Result<Self, BodyError> {
+        if *method == HttpMethod::HEAD || (100..200).contains(&status) || status == 204 || status == 304 {
+            return Ok(Framing::Empty);
+        }
+        if headers.get("transfer-encoding").is_some() {
+            return Ok(if is_chunked(headers) { Framing::Chunked } else { Framing::UntilClose });
+        }
+        Ok(match content_length(headers)? {
+            Some(0) => Framing::Empty,
+            Some(length) => Framing::Length(length),
+            None => Framing::UntilClose,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum State {
+    Length(u64),
+    ChunkSize,
+    ChunkData(u64),
+    ChunkEnd,
+    Trailers,
+    UntilClose,
+    Done,
+    Failed,
+}
+
+/// Decodes one framed body off a buffered source, a read at a time, and
+/// enforces a size limit as the bytes arrive.
+#[derive(Debug)]
+pub struct Decoder {
+    state: State,
+    limit: u64,
+    decoded: u64,
+    consumed: u64,
+    /// Where trailer fields go; they are dropped when unset.
+    trailers: Option<(Trailers, HttpHeaders)>,
+}
+
+impl Decoder {
+    pub fn new(framing: Framing, limit: u64) -> Self {
+        let state = match framing {
+            Framing::Empty => State::Done,
+            Framing::Length(length) => State::Length(length),
+            Framing::Chunked => State::ChunkSize,
+            Framing::UntilClose => State::UntilClose,
+        };
+        Self {
+            state,
+            limit,
+            decoded: 0,
+            consumed: 0,
+            trailers: None,
+        }
+    }
+
+    /// Keep the trailer fields of a chunked body in `trailers`.
+    pub fn keep_trailers(mut self, trailers: Trailers) -> Self {
+        self.trailers = Some((trailers, HttpHeaders::new()));
+        self
+    }
+
+    /// A decoder for a message without a body.
+    pub fn finished() -> Self {
+        Self::new(Framing::Empty, 0)
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state == State::Done
+    }
+
+    pub fn has_failed(&self) -> bool {
+        self.state == State::Failed
+    }
+
+    /// Bytes taken from the source so far, including chunk framing.
+    pub fn bytes_consumed(&self) -> u64 {
+        self.consumed
+    }
+
+    pub fn read(&mut self, source: &mut impl BufRead, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.step(source, buf);
+        if result.is_err() {
+            self.state = State::Failed;
+        }
+        result
+    }
+
+    fn step(&mut self, source: &mut impl BufRead, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.state {
+                State::Done => return Ok(0),
+                State::Failed => {
+                    return Err(io::Error::other("body was abandoned after an error"));
+                }
+                State::Length(0) => self.state = State::Done,
+                State::Length(left) => {
+                    let n = self.copy(source, buf, left)?;
+                    self.state = State::Length(left - n as u64);
+                    return Ok(n);
+                }
+                State::ChunkData(0) => self.state = State::ChunkEnd,
+                State::ChunkData(left) => {
+                    let n = self.copy(source, buf, left)?;
+                    self.state = State::ChunkData(left - n as u64);
+                    return Ok(n);
+                }
+                State::UntilClose => {
+                    let data = source.fill_buf()?;
+                    if data.is_empty() {
+                        self.state = State::Done;
+                        return Ok(0);
+                    }
+                    let n = data.len().min(buf.len());
+                    buf[..n].copy_from_slice(&data[..n]);
+                    self.take(source, n);
+                    self.count(n)?;
+                    return Ok(n);
+                }
+                State::ChunkSize => {
+                    let line = self.line(source)?;
+                    let size = line.split(';').next().unwrap_or_default().trim();
+                    let size = u64::from_str_radix(size, 16)
+                        .map_err(|_| BodyError::Malformed(format!("invalid chunk size '{}'", size)))?;
+                    if self.decoded.saturating_add(size) > self.limit {
+                        return Err(BodyError::TooLarge(self.limit).into());
+                    }
+                    self.state = if size == 0 { State::Trailers } else { State::ChunkData(size) };
+                }
+                State::ChunkEnd => {
+                    if !self.line(source)?.is_empty() {
+                        return Err(BodyError::Malformed("chunk data is longer than its size".to_string()).into());
+                    }
+                    self.state = State::ChunkSize;
+                }
+                State::Trailers => {
+                    let line = self.line(source)?;
+                    let Some((trailers, fields)) = &mut self.trailers else {
+                        if line.is_empty() {
+                            self.state = State::Done;
+                        }
+                        continue;
+                    };
+                    if line.is_empty() {
+                        trailers.set(std::mem::take(fields));
+                        self.state = State::Done;
+                        continue;
+                    }
+                    let Some((name, value)) = line.split_once(':') else {
+                        return Err(BodyError::Malformed(format!("invalid trailer field '{}'", line)).into());
+                    };
+                    if fields.iter().count() >= MAX_TRAILERS {
+                        return Err(BodyError::Malformed("too many trailer fields".to_string()).into());
+                    }
+                    fields.insert(name.trim().to_string(), value.trim().to_string());
+                }
+            }
+        }
+    }
+
+    /// Copy up to `left` body bytes into `buf`.
+    fn copy(&mut self, source: &mut impl BufRead, buf: &mut [u8], left: u64) -> io::Result<usize> {
+        let data = source.fill_buf()?;
+        if data.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "body ended early"));
+        }
+        let n = data.len().min(buf.len()).min(usize::try_from(left).unwrap_or(usize::MAX));
+        buf[..n].copy_from_slice(&data[..n]);
+        self.take(source, n);
+        self.count(n)?;
+        Ok(n)
+    }
+
+    fn take(&mut self, source: &mut impl BufRead, n: usize) {
+        source.consume(n);
+        self.consumed += n as u64;
+    }
+
+    fn count(&mut self, n: usize) -> io::Result<()> {
+        self.decoded += n as u64;
+        if self.decoded > self.limit {
+            return Err(BodyError::TooLarge(self.limit).into());
+        }
+        Ok(())
+    }
+
+    /// One CRLF-terminated line of chunk framing, without the terminator.
+    fn line(&mut self, source: &mut impl BufRead) -> io::Result<String> {
+        let mut line = Vec::new();
+        loop {
+            let data = source.fill_buf()?;
+            if data.is_empty() {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "body ended early"));
+            }
+            let (n, done) = match data.iter().position(|&b| b == b'\n') {
+                Some(pos) => (pos + 1, true),
+                None => (data.len(), false),
+            };
+            line.extend_from_slice(&data[..n]);
+            self.take(source, n);
+            if line.len() > MAX_LINE_LEN {
+                return Err(BodyError::Malformed("chunk line too long".to_string()).into());
+            }
+            if done {
+                break;
+            }
+        }
+        line.pop();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        String::from_utf8(line).map_err(|_| BodyError::Malformed("chunk line is not valid UTF-8".to_string()).into())
+    }
+}
+
+/// A decoded body read from a source the reader owns.
+pub struct BodyReader<R> {
+    source: R,
+    decoder: Decoder,
+}
+
+impl<R: BufRead> BodyReader<R> {
+    pub fn new(source: R, decoder: Decoder) -> Self {
+        Self { source, decoder }
+    }
+}
+
+impl<R: BufRead> Read for BodyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(&mut self.source, buf)
+    }
+}
+
+/// Why a body copy stopped.
+#[derive(Debug)]
+pub enum CopyError {
+    Read(io::Error),
+    Write(io::Error),
+}
+
+impl fmt::Display for CopyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CopyError::Read(e) => write!(f, "reading the body failed: {}", e),
+            CopyError::Write(e) => write!(f, "writing failed: {}", e),
+        }
+    }
+}
+
+/// Copy `body` to `out` through a fixed-size buffer, in chunked coding when
+/// `chunked`. Each piece is flushed before the next is read, so a slow
+/// reader slows the writer down instead of filling memory. A chunked body
+/// ends with whatever `trailers` hold by then.
+pub fn copy_body(body: &mut dyn Read, out: &mut dyn Write, chunked: bool, trailers: Option<&Trailers>) -> Result<u64, CopyError> {
+    let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+    let mut written = 0u64;
+    loop {
+        let n = match body.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(CopyError::Read(e)),
+        };
+        let piece = &buffer[..n];
+        if chunked {
+            let size = format!("{:x}\r\n", n);
+            out.write_all(size.as_bytes()).map_err(CopyError::Write)?;
+            out.write_all(piece).map_err(CopyError::Write)?;
+            out.write_all(b"\r\n").map_err(CopyError::Write)?;
+            written += (size.len() + n + 2) as u64;
+        } else {
+            out.write_all(piece).map_err(CopyError::Write)?;
+            written += n as u64;
+        }
+        out.flush().map_err(CopyError::Write)?;
+    }
+    if chunked {
+        let mut end = b"0\r\n".to_vec();
+        for (name, value) in trailers.and_then(Trailers::take).iter().flat_map(HttpHeaders::iter) {
+            end.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+        end.extend_from_slice(b"\r\n");
+        out.write_all(&end).map_err(CopyError::Write)?;
+        written += end.len() as u64;
+    }
+    Ok(written)
+}
@@ -0,0 +1,279 @@
+/// Content codings (RFC 9110 §8.4.1): gzip, brotli and zstd, applied to
+/// and removed from bodies as they are read, plus `Accept-Encoding`
+/// negotiation.
+/// src/http/coding.rs
+use std::fmt;
+use std::io::{self, BufReader, Read, Write};
+
+use flate2::Compression;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+
+use crate::http::body::{BodyError, COPY_BUFFER_SIZE};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContentCoding {
+    Gzip,
+    Brotli,
+    Zstd,
+}
+
+impl ContentCoding {
+    /// The coding named in `Content-Encoding` or `Accept-Encoding`.
+    pub fn parse(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(ContentCoding::Gzip),
+            "br" => Some(ContentCoding::Brotli),
+            "zstd" => Some(ContentCoding::Zstd),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Brotli => "br",
+            ContentCoding::Zstd => "zstd",
+        }
+    }
+
+    /// Compress all of `data` at once.
+    pub fn encode(&self, data: &[u8], level: u32) -> io::Result<Vec<u8>> {
+        let mut encoder = Encoder::new(*self, level)?;
+        encoder.write(data)?;
+        encoder.finish()
+    }
+}
+
+/// The codings listed in a `Content-Encoding` value, in the order they were
+/// applied; `identity` is skipped. `Err` carries the first one not known.
+pub fn parse_list(content_encoding: &str) -> Result<Vec<ContentCoding>, String> {
+    content_encoding
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && !name.eq_ignore_ascii_case("identity"))
+        .map(|name| ContentCoding::parse(name).ok_or_else(|| name.to_string()))
+        .collect()
+}
+
+impl fmt::Display for ContentCoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Choose a coding from `supported` (most preferred first) that the
+/// `Accept-Encoding` value allows: the highest q-value wins, ties going to
+/// the earlier one. `None` when the client takes none of them.
+pub fn negotiate(accept_encoding: &str, supported: &[ContentCoding]) -> Option<ContentCoding> {
+    let mut wildcard = None;
+    let mut weights: Vec<(ContentCoding, f32)> = Vec::new();
+    for item in accept_encoding.split(',') {
+        let mut params = item.split(';');
+        let name = params.next().unwrap_or_default().trim();
+        let q = params
+            .filter_map(|param| param.trim().strip_prefix("q="))
+            .find_map(|q| q.trim().parse::<f32>().ok())
+            .unwrap_or(1.0);
+        if name == "*" {
+            wildcard = Some(q);
+        } else if let Some(coding) = ContentCoding::parse(name) {
+            weights.push((coding, q));
+        }
+    }
+
+    let mut best: Option<(ContentCoding, f32)> = None;
+    for coding in supported {
+        let q = match weights.iter().find(|(c, _)| c == coding) {
+            Some((_, q)) => *q,
+            None => wildcard.unwrap_or(0.0),
+        };
+        if q > 0.0 && best.is_none_or(|(_, best_q)| q > best_q) {
+            best = Some((*coding, q));
+        }
+    }
+    best.map(|(coding, _)| coding)
+}
+
+/// A compressor writing into memory.
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Brotli(Box<brotli::CompressorWriter<Vec<u8>>>),
+    Zstd(zstd::stream::write::Encoder<'static, Vec<u8>>),
+}
+
+impl Encoder {
+    /// `level` is clamped to what each coding allows: 0-9 for gzip, 0-11
+    /// for brotli, 1-22 for zstd.
+    fn new(coding: ContentCoding, level: u32) -> io::Result<Self> {
+        Ok(match coding {
+            ContentCoding::Gzip => Encoder::Gzip(GzEncoder::new(Vec::new(), Compression::new(level.min(9)))),
+            ContentCoding::Brotli => Encoder::Brotli(Box::new(brotli::CompressorWriter::new(
+                Vec::new(),
+                COPY_BUFFER_SIZE,
+                level.min(11),
+                22,
+            ))),
+            ContentCoding::Zstd => {
+                Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), level.clamp(1, 22) as i32)?)
+            }
+        })
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.write_all(data),
+            Encoder::Brotli(encoder) => encoder.write_all(data),
+            Encoder::Zstd(encoder) => encoder.write_all(data),
+        }
+    }
+
+    /// Push out what has been written so far, so a streamed body reaches
+    /// the client as it arrives.
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.flush(),
+            Encoder::Brotli(encoder) => encoder.flush(),
+            Encoder::Zstd(encoder) => encoder.flush(),
+        }
+    }
+
+    /// Compressed bytes produced so far.
+    fn take(&mut self) -> Vec<u8> {
+        let output = match self {
+            Encoder::Gzip(encoder) => encoder.get_mut(),
+            Encoder::Brotli(encoder) => encoder.get_mut(),
+            Encoder::Zstd(encoder) => encoder.get_mut(),
+        };
+        std::mem::take(output)
+    }
+
+    fn finish(self) -> io::Result<Vec<u8>> {
+        match self {
+            Encoder::Gzip(encoder) => encoder.finish(),
+            Encoder::Brotli(encoder) => Ok(encoder.into_inner()),
+            Encoder::Zstd(encoder) => encoder.finish(),
+        }
+    }
+}
+
+/// A body compressed as it is read.
+pub struct Encoded {
+    inner: Box<dyn Read + Send>,
+    coding: ContentCoding,
+    level: u32,
+    /// Created on the first read; gone once the input has ended.
+    encoder: Option<Encoder>,
+    finished: bool,
+    output: Vec<u8>,
+    pos: usize,
+}
+
+impl Encoded {
+    pub fn new(inner: Box<dyn Read + Send>, coding: ContentCoding, level: u32) -> Self {
+        Self {
+            inner,
+            coding,
+            level,
+            encoder: None,
+            finished: false,
+            output: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl Read for Encoded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut chunk = [0u8; COPY_BUFFER_SIZE];
+        while self.pos == self.output.len() {
+            if self.finished {
+                return Ok(0);
+            }
+            let encoder = match &mut self.encoder {
+                Some(encoder) => encoder,
+                None => self.encoder.insert(Encoder::new(self.coding, self.level)?),
+            };
+            let n = self.inner.read(&mut chunk)?;
+            self.pos = 0;
+            if n == 0 {
+                self.finished = true;
+                self.output = self.encoder.take().map_or(Ok(Vec::new()), Encoder::finish)?;
+            } else {
+                encoder.write(&chunk[..n])?;
+                encoder.flush()?;
+                self.output = encoder.take();
+            }
+        }
+        let n = buf.len().min(self.output.len() - self.pos);
+        buf[..n].copy_from_slice(&self.output[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+/// A decompressor reading from a body.
+enum Decoder<R: Read> {
+    Gzip(MultiGzDecoder<R>),
+    Brotli(Box<brotli::Decompressor<R>>),
+    Zstd(zstd::stream::read::Decoder<'static, BufReader<R>>),
+}
+
+/// A body decompressed as it is read. Reading more than `limit` decoded
+/// bytes fails with `BodyError::TooLarge`, so a small compressed body cannot
+/// expand without bound; data that does not decode fails with
+/// `BodyError::Malformed`.
+pub struct Decoded<R: Read> {
+    /// Until the first read, when the decoder takes it over.
+    inner: Option<R>,
+    decoder: Option<Decoder<R>>,
+    coding: ContentCoding,
+    read: u64,
+    limit: u64,
+}
+
+impl<R: Read> Decoded<R> {
+    pub fn new(inner: R, coding: ContentCoding, limit: u64) -> Self {
+        Self {
+            inner: Some(inner),
+            decoder: None,
+            coding,
+            read: 0,
+            limit,
+        }
+    }
+}
+
+impl<R: Read> Read for Decoded<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let decoder = match (&mut self.decoder, self.inner.take()) {
+            (Some(decoder), _) => decoder,
+            (None, Some(inner)) => self.decoder.insert(match self.coding {
+                ContentCoding::Gzip => Decoder::Gzip(MultiGzDecoder::new(inner)),
+                ContentCoding::Brotli => Decoder::Brotli(Box::new(brotli::Decompressor::new(inner, COPY_BUFFER_SIZE))),
+                ContentCoding::Zstd => Decoder::Zstd(zstd::stream::read::Decoder::new(inner)?),
+            }),
+            // Creating the decoder failed.
+            (None, None) => return Err(io::Error::other(format!("no {} decoder", self.coding))),
+        };
+        let result = match decoder {
+            Decoder::Gzip(decoder) => decoder.read(buf),
+            Decoder::Brotli(decoder) => decoder.read(buf),
+            Decoder::Zstd(decoder) => decoder.read(buf),
+        };
+        let n = result.map_err(|e| match e.kind() {
+            // Errors of the body underneath pass through as they are.
+            io::ErrorKind::InvalidData | io::ErrorKind::InvalidInput | io::ErrorKind::UnexpectedEof
+                if BodyError::from_io(&e).is_none() =>
+            {
+                BodyError::Malformed(format!("not valid {}: {}", self.coding, e)).into()
+            }
+            _ => e,
+        })?;
+        self.read += n as u64;
+        if self.read > self.limit {
+            return Err(BodyError::TooLarge(self.limit).into());
+        }
+        Ok(n)
+    }
+}
@@ -1,7 +1,6 @@
 
 /// This module defines the HTTP methods used in the Orion project.
 /// src/http/enums/method.rs
-
 use std::fmt;
 use crate::http::util::errors::HttpParseError;
 #[derive(Debug, Clone, PartialEq)]
@@ -13,6 +12,9 @@ pub enum HttpMethod {
     DELETE,
     HEAD,
     OPTIONS,
+    CONNECT,
+    /// Extension method: invalidate cached responses (see `proxy::cache`).
+    PURGE,
 }
 // Implement for string conversion and parsing
 impl fmt::Display for HttpMethod {
@@ -24,6 +26,8 @@ impl fmt::Display for HttpMethod {
             HttpMethod::DELETE => "DELETE",
             HttpMethod::HEAD => "HEAD",
             HttpMethod::OPTIONS => "OPTIONS",
+            HttpMethod::CONNECT => "CONNECT",
+            HttpMethod::PURGE => "PURGE",
         };
         write!(f, "{}", method_str)
     }
@@ -40,6 +44,8 @@ impl std::str::FromStr for HttpMethod {
             "DELETE" => Ok(HttpMethod::DELETE),
             "HEAD" => Ok(HttpMethod::HEAD),
             "OPTIONS" => Ok(HttpMethod::OPTIONS),
+            "CONNECT" => Ok(HttpMethod::CONNECT),
+            "PURGE" => Ok(HttpMethod::PURGE),
             _ => Err(HttpParseError::UnsupportedMethod(s.to_string())),
         }
     }
@@ -3,82 +3,201 @@
 
 #[derive(Debug, Clone, PartialEq, Copy)]
 pub enum HttpStatus {
+    // Informational Codes
+    Continue,
+    SwitchingProtocols,
+    EarlyHints,
+
     // Success Codes
-    Ok = 200,
-    Created = 201,
-    NoContent = 204,
+    Ok,
+    Created,
+    Accepted,
+    NonAuthoritativeInformation,
+    NoContent,
+    PartialContent,
 
     // Redirection Codes
-    MovedPermanently = 301,
-    Found = 302,
-    NotModified = 304,
+    MultipleChoices,
+    MovedPermanently,
+    Found,
+    SeeOther,
+    NotModified,
+    TemporaryRedirect,
+    PermanentRedirect,
 
     // Client Error Codes
-    BadRequest = 400,
-    Unauthorized = 401,
-    Forbidden = 403,
-    NotFound = 404,
-    MethodNotAllowed = 405,
-    PayloadTooLarge = 413,
-    RequestUriTooLong = 414,
+    BadRequest,
+    Unauthorized,
+    Forbidden,
+    NotFound,
+    MethodNotAllowed,
+    RequestTimeout,
+    Conflict,
+    Gone,
+    LengthRequired,
+    PreconditionFailed,
+    PayloadTooLarge,
+    RequestUriTooLong,
+    UnsupportedMediaType,
+    RangeNotSatisfiable,
+    ExpectationFailed,
+    UnprocessableContent,
+    TooManyRequests,
 
     // Server Error Codes
-    InternalServerError = 500,
-    NotImplemented = 501,
-    BadGateway = 502,
-    ServiceUnavailable = 503,
-    HttpVersionNotSupported = 505,
+    InternalServerError,
+    NotImplemented,
+    BadGateway,
+    ServiceUnavailable,
+    GatewayTimeout,
+    HttpVersionNotSupported,
+
+    /// Any other status, as relayed from an upstream.
+    Other(u16),
 }
 
 impl HttpStatus {
     pub fn code(&self) -> u16 {
-        *self as u16
+        match self {
+            HttpStatus::Continue => 100,
+            HttpStatus::SwitchingProtocols => 101,
+            HttpStatus::EarlyHints => 103,
+            HttpStatus::Ok => 200,
+            HttpStatus::Created => 201,
+            HttpStatus::Accepted => 202,
+            HttpStatus::NonAuthoritativeInformation => 203,
+            HttpStatus::NoContent => 204,
+            HttpStatus::PartialContent => 206,
+            HttpStatus::MultipleChoices => 300,
+            HttpStatus::MovedPermanently => 301,
+            HttpStatus::Found => 302,
+            HttpStatus::SeeOther => 303,
+            HttpStatus::NotModified => 304,
+            HttpStatus::TemporaryRedirect => 307,
+            HttpStatus::PermanentRedirect => 308,
+            HttpStatus::BadRequest => 400,
+            HttpStatus::Unauthorized => 401,
+            HttpStatus::Forbidden => 403,
+            HttpStatus::NotFound => 404,
+            HttpStatus::MethodNotAllowed => 405,
+            HttpStatus::RequestTimeout => 408,
+            HttpStatus::Conflict => 409,
+            HttpStatus::Gone => 410,
+            HttpStatus::LengthRequired => 411,
+            HttpStatus::PreconditionFailed => 412,
+            HttpStatus::PayloadTooLarge => 413,
+            HttpStatus::RequestUriTooLong => 414,
+            HttpStatus::UnsupportedMediaType => 415,
+            HttpStatus::RangeNotSatisfiable => 416,
+            HttpStatus::ExpectationFailed => 417,
+            HttpStatus::UnprocessableContent => 422,
+            HttpStatus::TooManyRequests => 429,
+            HttpStatus::InternalServerError => 500,
+            HttpStatus::NotImplemented => 501,
+            HttpStatus::BadGateway => 502,
+            HttpStatus::ServiceUnavailable => 503,
+            HttpStatus::GatewayTimeout => 504,
+            HttpStatus::HttpVersionNotSupported => 505,
+            HttpStatus::Other(code) => *code,
+        }
     }
+    /// 1xx: an interim response, followed by the final one.
+    pub fn is_informational(&self) -> bool {
+        (100..200).contains(&self.code())
+    }
+    /// The standard reason phrase; empty for `Other` statuses, which RFC
+    /// 9112 §4 allows.
     pub fn reason_phrase(&self) -> &str {
         match self {
+            HttpStatus::Continue => "Continue",
+            HttpStatus::SwitchingProtocols => "Switching Protocols",
+            HttpStatus::EarlyHints => "Early Hints",
             HttpStatus::Ok => "OK",
             HttpStatus::Created => "Created",
+            HttpStatus::Accepted => "Accepted",
+            HttpStatus::NonAuthoritativeInformation => "Non-Authoritative Information",
             HttpStatus::NoContent => "No Content",
+            HttpStatus::PartialContent => "Partial Content",
+            HttpStatus::MultipleChoices => "Multiple Choices",
             HttpStatus::MovedPermanently => "Moved Permanently",
             HttpStatus::Found => "Found",
+            HttpStatus::SeeOther => "See Other",
             HttpStatus::NotModified => "Not Modified",
+            HttpStatus::TemporaryRedirect => "Temporary Redirect",
+            HttpStatus::PermanentRedirect => "Permanent Redirect",
             HttpStatus::BadRequest => "Bad Request",
             HttpStatus::Unauthorized => "Unauthorized",
             HttpStatus::Forbidden => "Forbidden",
             HttpStatus::NotFound => "Not Found",
             HttpStatus::MethodNotAllowed => "Method Not Allowed",
+            HttpStatus::RequestTimeout => "Request Timeout",
+            HttpStatus::Conflict => "Conflict",
+            HttpStatus::Gone => "Gone",
+            HttpStatus::LengthRequired => "Length Required",
+            HttpStatus::PreconditionFailed => "Precondition Failed",
             HttpStatus::PayloadTooLarge => "Payload Too Large",
             HttpStatus::RequestUriTooLong => "Request URI Too Long",
+            HttpStatus::UnsupportedMediaType => "Unsupported Media Type",
+            HttpStatus::RangeNotSatisfiable => "Range Not Satisfiable",
+            HttpStatus::ExpectationFailed => "Expectation Failed",
+            HttpStatus::UnprocessableContent => "Unprocessable Content",
+            HttpStatus::TooManyRequests => "Too Many Requests",
             HttpStatus::InternalServerError => "Internal Server Error",
             HttpStatus::NotImplemented => "Not Implemented",
             HttpStatus::BadGateway => "Bad Gateway",
             HttpStatus::ServiceUnavailable => "Service Unavailable",
+            HttpStatus::GatewayTimeout => "Gateway Timeout",
             HttpStatus::HttpVersionNotSupported => "HTTP Version Not Supported",
-
+            HttpStatus::Other(_) => "",
         }
     }
-    
+
+    /// Any three-digit status (RFC 9110 §15); `None` outside 100-999.
     pub fn from_code(code: u16) -> Option<HttpStatus> {
-        match code {
-            200 => Some(HttpStatus::Ok),
-            201 => Some(HttpStatus::Created),
-            204 => Some(HttpStatus::NoContent),
-            301 => Some(HttpStatus::MovedPermanently),
-            302 => Some(HttpStatus::Found),
-            304 => Some(HttpStatus::NotModified),
-            400 => Some(HttpStatus::BadRequest),
-            401 => Some(HttpStatus::Unauthorized),
-            403 => Some(HttpStatus::Forbidden),
-            404 => Some(HttpStatus::NotFound),
-            405 => Some(HttpStatus::MethodNotAllowed),
-            413 => Some(HttpStatus::PayloadTooLarge),
-            414 => Some(HttpStatus::RequestUriTooLong),
-            500 => Some(HttpStatus::InternalServerError),
-            501 => Some(HttpStatus::NotImplemented),
-            502 => Some(HttpStatus::BadGateway),
-            503 => Some(HttpStatus::ServiceUnavailable),
-            505 => Some(HttpStatus::HttpVersionNotSupported),
-            _ => None,
+        if !(100..=999).contains(&code) {
+            return None;
         }
+        Some(match code {
+            100 => HttpStatus::Continue,
+            101 => HttpStatus::SwitchingProtocols,
+            103 => HttpStatus::EarlyHints,
+            200 => HttpStatus::Ok,
+            201 => HttpStatus::Created,
+            202 => HttpStatus::Accepted,
+            203 => HttpStatus::NonAuthoritativeInformation,
+            204 => HttpStatus::NoContent,
+            206 => HttpStatus::PartialContent,
+            300 => HttpStatus::MultipleChoices,
+            301 => HttpStatus::MovedPermanently,
+            302 => HttpStatus::Found,
+            303 => HttpStatus::SeeOther,
+            304 => HttpStatus::NotModified,
+            307 => HttpStatus::TemporaryRedirect,
+            308 => HttpStatus::PermanentRedirect,
+            400 => HttpStatus::BadRequest,
+            401 => HttpStatus::Unauthorized,
+            403 => HttpStatus::Forbidden,
+            404 => HttpStatus::NotFound,
+            405 => HttpStatus::MethodNotAllowed,
+            408 => HttpStatus::RequestTimeout,
+            409 => HttpStatus::Conflict,
+            410 => HttpStatus::Gone,
+            411 => HttpStatus::LengthRequired,
+            412 => HttpStatus::PreconditionFailed,
+            413 => HttpStatus::PayloadTooLarge,
+            414 => HttpStatus::RequestUriTooLong,
+            415 => HttpStatus::UnsupportedMediaType,
+            416 => HttpStatus::RangeNotSatisfiable,
+            417 => HttpStatus::ExpectationFailed,
+            422 => HttpStatus::UnprocessableContent,
+            429 => HttpStatus::TooManyRequests,
+            500 => HttpStatus::InternalServerError,
+            501 => HttpStatus::NotImplemented,
+            502 => HttpStatus::BadGateway,
+            503 => HttpStatus::ServiceUnavailable,
+            504 => HttpStatus::GatewayTimeout,
+            505 => HttpStatus::HttpVersionNotSupported,
+            _ => HttpStatus::Other(code),
+        })
     }
-}
\ No newline at end of file
+}
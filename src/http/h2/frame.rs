@@ -0,0 +1,336 @@
+// src/http/h2/frame.rs
+//
+// HTTP/2 framing (RFC 9113 §4 and §6): the 9-byte frame header, the frame
+// types and flags the proxy handles, error codes and SETTINGS parameters.
+
+use std::fmt;
+
+/// What a client sends before its first frame (RFC 9113 §3.4).
+pub const PREFACE: &[u8; 24] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+pub const FRAME_HEADER_LEN: usize = 9;
+
+/// SETTINGS_MAX_FRAME_SIZE before either side changes it, and its bounds.
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16_384;
+pub const LARGEST_MAX_FRAME_SIZE: u32 = (1 << 24) - 1;
+
+/// Flow-control window of new streams and connections, and its bound.
+pub const DEFAULT_WINDOW_SIZE: u32 = 65_535;
+pub const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+
+pub const DATA: u8 = 0x0;
+pub const HEADERS: u8 = 0x1;
+pub const PRIORITY: u8 = 0x2;
+pub const RST_STREAM: u8 = 0x3;
+pub const SETTINGS: u8 = 0x4;
+pub const PUSH_PROMISE: u8 = 0x5;
+pub const PING: u8 = 0x6;
+pub const GOAWAY: u8 = 0x7;
+pub const WINDOW_UPDATE: u8 = 0x8;
+pub const CONTINUATION: u8 = 0x9;
+
+pub const END_STREAM: u8 = 0x1;
+/// SETTINGS and PING acknowledgements share the END_STREAM bit.
+pub const ACK: u8 = 0x1;
+pub const END_HEADERS: u8 = 0x4;
+pub const PADDED: u8 = 0x8;
+pub const PRIORITY_FLAG: u8 = 0x20;
+
+pub const SETTINGS_HEADER_TABLE_SIZE: u16 = 0x1;
+pub const SETTINGS_ENABLE_PUSH: u16 = 0x2;
+pub const SETTINGS_MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub const SETTINGS_INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub const SETTINGS_MAX_FRAME_SIZE: u16 = 0x5;
+pub const SETTINGS_MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// Error codes of RST_STREAM and GOAWAY (RFC 9113 §7).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ErrorCode(pub u32);
+
+impl ErrorCode {
+    pub const NO_ERROR: Self = Self(0x0);
+    pub const PROTOCOL_ERROR: Self = Self(0x1);
+    pub const INTERNAL_ERROR: Self = Self(0x2);
+    pub const FLOW_CONTROL_ERROR: Self = Self(0x3);
+    pub const STREAM_CLOSED: Self = Self(0x5);
+    pub const FRAME_SIZE_ERROR: Self = Self(0x6);
+    pub const REFUSED_STREAM: Self = Self(0x7);
+    pub const CANCEL: Self = Self(0x8);
+    pub const COMPRESSION_ERROR: Self = Self(0x9);
+    pub const ENHANCE_YOUR_CALM: Self = Self(0xb);
+
+    pub fn name(self) -> &'static str {
+        match self.0 {
+            0x0 => "NO_ERROR",
+            0x1 => "PROTOCOL_ERROR",
+            0x2 => "INTERNAL_ERROR",
+            0x3 => "FLOW_CONTROL_ERROR",
+            0x4 => "SETTINGS_TIMEOUT",
+            0x5 => "STREAM_CLOSED",
+            0x6 => "FRAME_SIZE_ERROR",
+            0x7 => "REFUSED_STREAM",
+            0x8 => "CANCEL",
+            0x9 => "COMPRESSION_ERROR",
+            0xa => "CONNECT_ERROR",
+            0xb => "ENHANCE_YOUR_CALM",
+            0xc => "INADEQUATE_SECURITY",
+            0xd => "HTTP_1_1_REQUIRED",
+            _ => "UNKNOWN",
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// A frame as read off the wire; the payload still includes any padding.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub kind: u8,
+    pub flags: u8,
+    pub stream: u32,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// Take one frame off the front of `buf`, returning it with the number of
+    /// bytes it used; `None` until the whole frame has arrived.
+    pub fn parse(buf: &[u8], max_size: u32) -> Result<Option<(Frame, usize)>, ErrorCode> {
+        if buf.len() < FRAME_HEADER_LEN {
+            return Ok(None);
+        }
+        let len = u32::from_be_bytes([0, buf[0], buf[1], buf[2]]);
+        if len > max_size {
+            return Err(ErrorCode::FRAME_SIZE_ERROR);
+        }
+        let end = FRAME_HEADER_LEN + len as usize;
+        if buf.len() < end {
+            return Ok(None);
+        }
+        let stream = u32::from_be_bytes([buf[5], buf[6], buf[7], buf[8]]) & MAX_WINDOW_SIZE;
+        let frame = Frame {
+            kind: buf[3],
+            flags: buf[4],
+            stream,
+            payload: buf[FRAME_HEADER_LEN..end].to_vec(),
+        };
+        Ok(Some((frame, end)))
+    }
+
+    pub fn has(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// The payload of a DATA or HEADERS frame without its padding.
+    pub fn unpadded(&self) -> Result<&[u8], ErrorCode> {
+        if !self.has(PADDED) {
+            return Ok(&self.payload);
+        }
+        let (&pad, rest) = self.payload.split_first().ok_or(ErrorCode::FRAME_SIZE_ERROR)?;
+        rest.len()
+            .checked_sub(pad as usize)
+            .map(|len| &rest[..len])
+            .ok_or(ErrorCode::PROTOCOL_ERROR)
+    }
+
+    /// The header block fragment of a HEADERS frame, without its padding or
+    /// priority fields.
+    pub fn header_block(&self) -> Result<&[u8], ErrorCode> {
+        let block = self.unpadded()?;
+        match self.has(PRIORITY_FLAG) {
+            true => block.get(5..).ok_or(ErrorCode::FRAME_SIZE_ERROR),
+            false => Ok(block),
+        }
+    }
+}
+
+/// Append one frame to `out`.
+pub fn encode(out: &mut Vec<u8>, kind: u8, flags: u8, stream: u32, payload: &[u8]) {
+    let len = (payload.len() as u32).to_be_bytes();
+    out.extend_from_slice(&len[1..]);
+    out.push(kind);
+    out.push(flags);
+    out.extend_from_slice(&(stream & MAX_WINDOW_SIZE).to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// Append a header block as HEADERS plus as many CONTINUATION frames as it
+/// takes to stay within `max_frame_size`.
+pub fn encode_headers(out: &mut Vec<u8>, stream: u32, block: &[u8], end_stream: bool, max_frame_size: u32) {
+    let mut chunks = block.chunks(max_frame_size as usize).peekable();
+    let mut kind = HEADERS;
+    let mut flags = if end_stream { END_STREAM } else { 0 };
+    loop {
+        let chunk = chunks.next().unwrap_or_default();
+        if chunks.peek().is_none() {
+            flags |= END_HEADERS;
+        }
+        encode(out, kind, flags, stream, chunk);
+        if flags & END_HEADERS != 0 {
+            return;
+        }
+        kind = CONTINUATION;
+        flags = 0;
+    }
+}
+
+pub fn encode_settings(out: &mut Vec<u8>, settings: &[(u16, u32)]) {
+    let mut payload = Vec::with_capacity(settings.len() * 6);
+    for (id, value) in settings {
+        payload.extend_from_slice(&id.to_be_bytes());
+        payload.extend_from_slice(&value.to_be_bytes());
+    }
+    encode(out, SETTINGS, 0, 0, &payload);
+}
+
+/// The parameters of a SETTINGS payload, in order.
+pub fn parse_settings(payload: &[u8]) -> Result<Vec<(u16, u32)>, ErrorCode> {
+    if !payload.len().is_multiple_of(6) {
+        return Err(ErrorCode::FRAME_SIZE_ERROR);
+    }
+    Ok(payload
+        .chunks(6)
+        .map(|p| (u16::from_be_bytes([p[0], p[1]]), u32::from_be_bytes([p[2], p[3], p[4], p[5]])))
+        .collect())
+}
+
+pub fn encode_window_update(out: &mut Vec<u8>, stream: u32, increment: u32) {
+    encode(out, WINDOW_UPDATE, 0, stream, &increment.to_be_bytes());
+}
+
+pub fn encode_rst_stream(out: &mut Vec<u8>, stream: u32, code: ErrorCode) {
+    encode(out, RST_STREAM, 0, stream, &code.0.to_be_bytes());
+}
+
+pub fn encode_goaway(out: &mut Vec<u8>, last_stream: u32, code: ErrorCode, debug: &str) {
+    let mut payload = Vec::with_capacity(8 + debug.len());
+    payload.extend_from_slice(&last_stream.to_be_bytes());
+    payload.extend_from_slice(&code.0.to_be_bytes());
+    payload.extend_from_slice(debug.as_bytes());
+    encode(out, GOAWAY, 0, 0, &payload);
+}
+
+/// The 31-bit value at the start of `payload` (window increments, stream
+/// IDs), or the 32-bit one for error codes.
+pub fn read_u32(payload: &[u8]) -> Option<u32> {
+    payload.get(..4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(kind: u8, flags: u8, payload: &[u8]) -> Frame {
+        Frame {
+            kind,
+            flags,
+            stream: 1,
+            payload: payload.to_vec(),
+        }
+    }
+
+    #[test]
+    fn frames_are_parsed_once_complete() {
+        let mut wire = Vec::new();
+        encode(&mut wire, HEADERS, END_HEADERS, 3, b"block");
+        encode(&mut wire, PING, 0, 0, &[0; 8]);
+
+        for partial in [0, 8, wire.len() - 9 - 8 - 1] {
+            assert!(Frame::parse(&wire[..partial], DEFAULT_MAX_FRAME_SIZE).unwrap().is_none());
+        }
+        let (first, used) = Frame::parse(&wire, DEFAULT_MAX_FRAME_SIZE).unwrap().unwrap();
+        assert_eq!((first.kind, first.flags, first.stream), (HEADERS, END_HEADERS, 3));
+        assert_eq!(first.payload, b"block");
+        assert_eq!(used, 14);
+        let (second, used) = Frame::parse(&wire[used..], DEFAULT_MAX_FRAME_SIZE).unwrap().unwrap();
+        assert_eq!((second.kind, second.payload.len(), used), (PING, 8, 17));
+    }
+
+    #[test]
+    fn the_reserved_bit_of_the_stream_id_is_ignored() {
+        let wire = [0, 0, 0, DATA, 0, 0x80, 0, 0, 5];
+        let (frame, _) = Frame::parse(&wire, DEFAULT_MAX_FRAME_SIZE).unwrap().unwrap();
+        assert_eq!(frame.stream, 5);
+    }
+
+    #[test]
+    fn frames_over_the_size_limit_are_refused_from_their_header() {
+        let mut wire = Vec::new();
+        encode(&mut wire, DATA, 0, 1, &[0; 100]);
+        assert!(Frame::parse(&wire, 100).unwrap().is_some());
+        // Refused before the payload has arrived.
+        assert_eq!(Frame::parse(&wire[..9], 99).unwrap_err(), ErrorCode::FRAME_SIZE_ERROR);
+
+        let largest = [0xff, 0xff, 0xff, DATA, 0, 0, 0, 0, 1];
+        assert_eq!(
+            Frame::parse(&largest, LARGEST_MAX_FRAME_SIZE - 1).unwrap_err(),
+            ErrorCode::FRAME_SIZE_ERROR
+        );
+        assert!(Frame::parse(&largest, LARGEST_MAX_FRAME_SIZE).unwrap().is_none());
+    }
+
+    #[test]
+    fn padding_is_stripped() {
+        assert_eq!(frame(DATA, 0, b"\x02ab").unpadded().unwrap(), b"\x02ab");
+        assert_eq!(frame(DATA, PADDED, b"\x02abcd").unpadded().unwrap(), b"ab");
+        assert_eq!(frame(DATA, PADDED, b"\x00ab").unpadded().unwrap(), b"ab");
+        assert_eq!(frame(DATA, PADDED, b"\x02ab").unpadded().unwrap(), b"");
+        // Padding longer than the payload, or no pad length at all.
+        assert_eq!(frame(DATA, PADDED, b"\x03ab").unpadded().unwrap_err(), ErrorCode::PROTOCOL_ERROR);
+        assert_eq!(frame(DATA, PADDED, b"").unpadded().unwrap_err(), ErrorCode::FRAME_SIZE_ERROR);
+    }
+
+    #[test]
+    fn priority_fields_are_stripped_from_header_blocks() {
+        let priority = [0x80, 0, 0, 1, 15];
+        let block = [&priority[..], b"block"].concat();
+        assert_eq!(frame(HEADERS, PRIORITY_FLAG, &block).header_block().unwrap(), b"block");
+        assert_eq!(frame(HEADERS, 0, &block).header_block().unwrap(), &block[..]);
+
+        // Padding comes off first: pad length, priority, block, padding.
+        let padded = [&[2][..], &priority, b"block", &[0, 0]].concat();
+        let flags = PADDED | PRIORITY_FLAG;
+        assert_eq!(frame(HEADERS, flags, &padded).header_block().unwrap(), b"block");
+
+        assert_eq!(
+            frame(HEADERS, PRIORITY_FLAG, &priority[..4]).header_block().unwrap_err(),
+            ErrorCode::FRAME_SIZE_ERROR
+        );
+    }
+
+    #[test]
+    fn header_blocks_are_split_into_continuations() {
+        let mut wire = Vec::new();
+        encode_headers(&mut wire, 1, &[7; 25], true, 10);
+        let mut frames = Vec::new();
+        let mut rest = &wire[..];
+        while let Some((frame, used)) = Frame::parse(rest, 10).unwrap() {
+            frames.push((frame.kind, frame.flags, frame.payload.len()));
+            rest = &rest[used..];
+        }
+        assert_eq!(
+            frames,
+            [(HEADERS, END_STREAM, 10), (CONTINUATION, 0, 10), (CONTINUATION, END_HEADERS, 5)]
+        );
+
+        let mut wire = Vec::new();
+        encode_headers(&mut wire, 1, &[], false, 10);
+        let (frame, _) = Frame::parse(&wire, 10).unwrap().unwrap();
+        assert_eq!((frame.kind, frame.flags, frame.payload.len()), (HEADERS, END_HEADERS, 0));
+    }
+
+    #[test]
+    fn settings_payloads_must_be_whole_parameters() {
+        let mut wire = Vec::new();
+        encode_settings(&mut wire, &[(SETTINGS_MAX_FRAME_SIZE, 32_768), (SETTINGS_ENABLE_PUSH, 0)]);
+        let (frame, _) = Frame::parse(&wire, DEFAULT_MAX_FRAME_SIZE).unwrap().unwrap();
+        assert_eq!(
+            parse_settings(&frame.payload).unwrap(),
+            [(SETTINGS_MAX_FRAME_SIZE, 32_768), (SETTINGS_ENABLE_PUSH, 0)]
+        );
+        assert_eq!(parse_settings(&[0; 7]).unwrap_err(), ErrorCode::FRAME_SIZE_ERROR);
+    }
+}
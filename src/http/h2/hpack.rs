@@ -0,0 +1,522 @@
+// src/http/h2/hpack.rs
+//
+// HPACK header compression (RFC 7541). The decoder keeps the dynamic table
+// the peer's encoder builds up, so header blocks must be decoded in the
+// order they arrive on the connection. The encoder never adds to a dynamic
+// table: it refers to the static table and Huffman-codes literals where
+// that is shorter, which lets any thread encode a block on its own.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::http::h2::huffman;
+
+/// A decoded header field. Names are lowercase on the wire in HTTP/2.
+pub type Header = (String, String);
+
+/// Dynamic table size both sides start with (SETTINGS_HEADER_TABLE_SIZE).
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// Per-entry overhead counted against the table size (RFC 7541 §4.1).
+const ENTRY_OVERHEAD: usize = 32;
+
+#[rustfmt::skip]
+const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""), (":method", "GET"), (":method", "POST"), (":path", "/"),
+    (":path", "/index.html"), (":scheme", "http"), (":scheme", "https"), (":status", "200"),
+    (":status", "204"), (":status", "206"), (":status", "304"), (":status", "400"),
+    (":status", "404"), (":status", "500"), ("accept-charset", ""), ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""), ("accept-ranges", ""), ("accept", ""), ("access-control-allow-origin", ""),
+    ("age", ""), ("allow", ""), ("authorization", ""), ("cache-control", ""),
+    ("content-disposition", ""), ("content-encoding", ""), ("content-language", ""), ("content-length", ""),
+    ("content-location", ""), ("content-range", ""), ("content-type", ""), ("cookie", ""),
+    ("date", ""), ("etag", ""), ("expect", ""), ("expires", ""),
+    ("from", ""), ("host", ""), ("if-match", ""), ("if-modified-since", ""),
+    ("if-none-match", ""), ("if-range", ""), ("if-unmodified-since", ""), ("last-modified", ""),
+    ("link", ""), ("location", ""), ("max-forwards", ""), ("proxy-authenticate", ""),
+    ("proxy-authorization", ""), ("range", ""), ("referer", ""), ("refresh", ""),
+    ("retry-after", ""), ("server", ""), ("set-cookie", ""), ("strict-transport-security", ""),
+    ("transfer-encoding", ""), ("user-agent", ""), ("vary", ""), ("via", ""),
+    ("www-authenticate", ""),
+];
+
+/// Values an intermediary must not let anyone index (RFC 7541 §7.1.3).
+const SENSITIVE: [&str; 3] = ["authorization", "cookie", "proxy-authorization"];
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    /// The block does not decode; the connection cannot continue.
+    Compression(&'static str),
+    /// The decoded list is larger than the limit given to `decode`.
+    TooLarge,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Compression(reason) => write!(f, "HPACK decoding failed: {}", reason),
+            DecodeError::TooLarge => write!(f, "Header list too large"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Decodes the header blocks of one direction of a connection.
+pub struct Decoder {
+    entries: VecDeque<Header>,
+    size: usize,
+    /// Current limit, as last set by the peer's encoder.
+    max_size: usize,
+    /// Largest limit the encoder may set: our SETTINGS_HEADER_TABLE_SIZE.
+    allowed_size: usize,
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self::new(DEFAULT_TABLE_SIZE)
+    }
+}
+
+impl Decoder {
+    pub fn new(allowed_size: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size: allowed_size,
+            allowed_size,
+        }
+    }
+
+    /// Decode a complete header block. `max_list_size` bounds the decoded
+    /// list as SETTINGS_MAX_HEADER_LIST_SIZE counts it; the whole block is
+    /// still decoded when it is exceeded, keeping the table in step.
+    pub fn decode(&mut self, block: &[u8], max_list_size: usize) -> Result<Vec<Header>, DecodeError> {
+        let mut headers = Vec::new();
+        let mut list_size = 0usize;
+        let mut input = Input { data: block, pos: 0 };
+        let mut fields_seen = false;
+
+        while !input.is_empty() {
+            let first = input.peek();
+            let header = if first & 0x80 != 0 {
+                let index = input.integer(7)?;
+                self.get(index)?.clone()
+            } else if first & 0xc0 == 0x40 {
+                let header = self.literal(&mut input, 6)?;
+                self.insert(header.clone());
+                header
+            } else if first & 0xe0 == 0x20 {
+                // Size updates may only open a block.
+                if fields_seen {
+                    return Err(DecodeError::Compression("table size update after a header field"));
+                }
+                let size = input.integer(5)?;
+                if size > self.allowed_size {
+                    return Err(DecodeError::Compression("table size update above the advertised limit"));
+                }
+                self.max_size = size;
+                self.evict(0);
+                continue;
+            } else {
+                // Without indexing (0000) or never indexed (0001).
+                self.literal(&mut input, 4)?
+            };
+            fields_seen = true;
+            list_size += header.0.len() + header.1.len() + ENTRY_OVERHEAD;
+            if list_size <= max_list_size {
+                headers.push(header);
+            }
+        }
+        if list_size > max_list_size {
+            return Err(DecodeError::TooLarge);
+        }
+        Ok(headers)
+    }
+
+    fn get(&self, index: usize) -> Result<&Header, DecodeError> {
+        match index {
+            0 => Err(DecodeError::Compression("index 0")),
+            1..=61 => Ok(static_entry(index)),
+            _ => self
+                .entries
+                .get(index - 62)
+                .ok_or(DecodeError::Compression("index past the dynamic table")),
+        }
+    }
+
+    fn literal(&self, input: &mut Input<'_>, prefix: u8) -> Result<Header, DecodeError> {
+        let index = input.integer(prefix)?;
+        let name = match index {
+            0 => input.string()?,
+            index => self.get(index)?.0.clone(),
+        };
+        let value = input.string()?;
+        Ok((name, value))
+    }
+
+    fn insert(&mut self, header: Header) {
+        let size = header.0.len() + header.1.len() + ENTRY_OVERHEAD;
+        self.evict(size);
+        // An entry larger than the whole table just empties it.
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front(header);
+        }
+    }
+
+    /// Drop the oldest entries until `incoming` more bytes fit.
+    fn evict(&mut self, incoming: usize) {
+        while self.size + incoming > self.max_size {
+            let Some((name, value)) = self.entries.pop_back() else { break };
+            self.size -= name.len() + value.len() + ENTRY_OVERHEAD;
+        }
+    }
+}
+
+fn static_entry(index: usize) -> &'static Header {
+    static ENTRIES: std::sync::OnceLock<Vec<Header>> = std::sync::OnceLock::new();
+    let entries = ENTRIES.get_or_init(|| {
+        STATIC_TABLE
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    });
+    &entries[index - 1]
+}
+
+struct Input<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Input<'_> {
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn peek(&self) -> u8 {
+        self.data[self.pos]
+    }
+
+    fn byte(&mut self) -> Result<u8, DecodeError> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .ok_or(DecodeError::Compression("block ends inside a field"))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    /// An integer with an N-bit prefix (RFC 7541 §5.1).
+    fn integer(&mut self, prefix: u8) -> Result<usize, DecodeError> {
+        let max_prefix = (1usize << prefix) - 1;
+        let mut value = self.byte()? as usize & max_prefix;
+        if value < max_prefix {
+            return Ok(value);
+        }
+        let mut shift = 0;
+        loop {
+            let byte = self.byte()?;
+            if shift > 28 {
+                return Err(DecodeError::Compression("integer overflow"));
+            }
+            value += ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// A string literal, Huffman-coded or raw (RFC 7541 §5.2).
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let huffman = self.data.get(self.pos).is_some_and(|b| b & 0x80 != 0);
+        let len = self.integer(7)?;
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or(DecodeError::Compression("string runs past the block"))?;
+        let raw = &self.data[self.pos..end];
+        self.pos = end;
+        let bytes = match huffman {
+            true => huffman::decode(raw).ok_or(DecodeError::Compression("invalid Huffman code"))?,
+            false => raw.to_vec(),
+        };
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+/// Encode a header block. Names are lowercased; pseudo-headers must come
+/// first in `headers`.
+pub fn encode<'a>(headers: impl IntoIterator<Item = (&'a str, &'a str)>) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (name, value) in headers {
+        let name = name.to_ascii_lowercase();
+        let exact = STATIC_TABLE.iter().position(|&(n, v)| n == name && v == value);
+        if let Some(index) = exact {
+            encode_integer(&mut out, 0x80, 7, index + 1);
+            continue;
+        }
+        let flags = if SENSITIVE.contains(&name.as_str()) { 0x10 } else { 0x00 };
+        match STATIC_TABLE.iter().position(|&(n, _)| n == name) {
+            Some(index) => encode_integer(&mut out, flags, 4, index + 1),
+            None => {
+                out.push(flags);
+                encode_string(&mut out, name.as_bytes());
+            }
+        }
+        encode_string(&mut out, value.as_bytes());
+    }
+    out
+}
+
+fn encode_integer(out: &mut Vec<u8>, flags: u8, prefix: u8, mut value: usize) {
+    let max_prefix = (1usize << prefix) - 1;
+    if value < max_prefix {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max_prefix as u8);
+    value -= max_prefix;
+    while value >= 0x80 {
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn encode_string(out: &mut Vec<u8>, value: &[u8]) {
+    let huffman_len = huffman::encoded_len(value);
+    if huffman_len < value.len() {
+        encode_integer(out, 0x80, 7, huffman_len);
+        out.extend_from_slice(&huffman::encode(value));
+    } else {
+        encode_integer(out, 0x00, 7, value.len());
+        out.extend_from_slice(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes from the spaced hex of RFC 7541 Appendix C.
+    fn hex(text: &str) -> Vec<u8> {
+        let digits: Vec<u8> = text.bytes().filter(u8::is_ascii_hexdigit).collect();
+        digits
+            .chunks(2)
+            .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap())
+            .collect()
+    }
+
+    fn headers(list: &[(&str, &str)]) -> Vec<Header> {
+        list.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    /// Decode `block` and check the result and the dynamic table after it.
+    fn decodes(decoder: &mut Decoder, block: &str, expected: &[(&str, &str)], table: &[(&str, &str)], size: usize) {
+        assert_eq!(decoder.decode(&hex(block), usize::MAX).unwrap(), headers(expected));
+        assert_eq!(decoder.entries.iter().cloned().collect::<Vec<_>>(), headers(table));
+        assert_eq!(decoder.size, size);
+    }
+
+    #[test]
+    fn integers() {
+        // C.1.1 to C.1.3.
+        for (value, prefix, wire) in [(10, 5, "0a"), (1337, 5, "1f9a0a"), (42, 8, "2a")] {
+            let mut out = Vec::new();
+            encode_integer(&mut out, 0, prefix, value);
+            assert_eq!(out, hex(wire));
+            let data = hex(wire);
+            let mut input = Input { data: &data, pos: 0 };
+            assert_eq!(input.integer(prefix).unwrap(), value);
+        }
+        let data = [0x1f, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01];
+        let mut input = Input { data: &data, pos: 0 };
+        assert_eq!(input.integer(5).unwrap_err(), DecodeError::Compression("integer overflow"));
+    }
+
+    #[test]
+    fn header_field_representations() {
+        // C.2.1 to C.2.4.
+        let mut decoder = Decoder::default();
+        decodes(
+            &mut decoder,
+            "400a 6375 7374 6f6d 2d6b 6579 0d63 7573 746f 6d2d 6865 6164 6572",
+            &[("custom-key", "custom-header")],
+            &[("custom-key", "custom-header")],
+            55,
+        );
+        let mut decoder = Decoder::default();
+        decodes(&mut decoder, "040c 2f73 616d 706c 652f 7061 7468", &[(":path", "/sample/path")], &[], 0);
+        decodes(&mut decoder, "1008 7061 7373 776f 7264 0673 6563 7265 74", &[("password", "secret")], &[], 0);
+        decodes(&mut decoder, "82", &[(":method", "GET")], &[], 0);
+    }
+
+    fn requests(blocks: [&str; 3]) {
+        let mut decoder = Decoder::default();
+        decodes(
+            &mut decoder,
+            blocks[0],
+            &[(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com")],
+            &[(":authority", "www.example.com")],
+            57,
+        );
+        decodes(
+            &mut decoder,
+            blocks[1],
+            &[
+                (":method", "GET"),
+                (":scheme", "http"),
+                (":path", "/"),
+                (":authority", "www.example.com"),
+                ("cache-control", "no-cache"),
+            ],
+            &[("cache-control", "no-cache"), (":authority", "www.example.com")],
+            110,
+        );
+        decodes(
+            &mut decoder,
+            blocks[2],
+            &[
+                (":method", "GET"),
+                (":scheme", "https"),
+                (":path", "/index.html"),
+                (":authority", "www.example.com"),
+                ("custom-key", "custom-value"),
+            ],
+            &[("custom-key", "custom-value"), ("cache-control", "no-cache"), (":authority", "www.example.com")],
+            164,
+        );
+    }
+
+    #[test]
+    fn requests_without_huffman_coding() {
+        // C.3.
+        requests([
+            "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+            "8286 84be 5808 6e6f 2d63 6163 6865",
+            "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65",
+        ]);
+    }
+
+    #[test]
+    fn requests_with_huffman_coding() {
+        // C.4.
+        requests([
+            "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+            "8286 84be 5886 a8eb 1064 9cbf",
+            "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf",
+        ]);
+    }
+
+    /// Responses decoded with a 256-byte table, so entries are evicted.
+    fn responses(blocks: [&str; 3]) {
+        let date = ("date", "Mon, 21 Oct 2013 20:13:21 GMT");
+        let location = ("location", "https://www.example.com");
+        let mut decoder = Decoder::new(256);
+        decodes(
+            &mut decoder,
+            blocks[0],
+            &[(":status", "302"), ("cache-control", "private"), date, location],
+            &[location, date, ("cache-control", "private"), (":status", "302")],
+            222,
+        );
+        decodes(
+            &mut decoder,
+            blocks[1],
+            &[(":status", "307"), ("cache-control", "private"), date, location],
+            &[(":status", "307"), location, date, ("cache-control", "private")],
+            222,
+        );
+        let date = ("date", "Mon, 21 Oct 2013 20:13:22 GMT");
+        let cookie = ("set-cookie", "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1");
+        decodes(
+            &mut decoder,
+            blocks[2],
+            &[(":status", "200"), ("cache-control", "private"), date, location, ("content-encoding", "gzip"), cookie],
+            &[cookie, ("content-encoding", "gzip"), date],
+            215,
+        );
+    }
+
+    #[test]
+    fn responses_without_huffman_coding() {
+        // C.5.
+        responses([
+            "4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a 3133 3a32 \
+             3120 474d 546e 1768 7474 7073 3a2f 2f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+            "4803 3330 37c1 c0bf",
+            "88c1 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a 3133 3a32 3220 474d 54c0 5a04 677a 6970 \
+             7738 666f 6f3d 4153 444a 4b48 514b 425a 584f 5157 454f 5049 5541 5851 5745 4f49 553b 206d 6178 2d61 \
+             6765 3d33 3630 303b 2076 6572 7369 6f6e 3d31",
+        ]);
+    }
+
+    #[test]
+    fn responses_with_huffman_coding() {
+        // C.6.
+        responses([
+            "4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 82a6 2d1b ff6e 919d 29ad \
+             1718 63c7 8f0b 97c8 e9ae 82ae 43d3",
+            "4883 640e ffc1 c0bf",
+            "88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b d9ab 77ad 94e7 821d d7f2 \
+             e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 0fb5 291f 9587 3160 65c0 03ed 4ee5 b106 3d50 07",
+        ]);
+    }
+
+    #[test]
+    fn table_size_updates() {
+        let mut decoder = Decoder::new(256);
+        decoder.decode(&hex("400a 6375 7374 6f6d 2d6b 6579 0d63 7573 746f 6d2d 6865 6164 6572"), usize::MAX).unwrap();
+        // Shrinking the table evicts what no longer fits.
+        assert_eq!(decoder.decode(&hex("3f11 82"), usize::MAX).unwrap(), headers(&[(":method", "GET")]));
+        assert_eq!((decoder.entries.len(), decoder.size, decoder.max_size), (0, 0, 48));
+
+        let refused = |block: &str| Decoder::new(256).decode(&hex(block), usize::MAX).unwrap_err();
+        // Above our SETTINGS_HEADER_TABLE_SIZE, and after a field.
+        assert_eq!(refused("3fe2 01"), DecodeError::Compression("table size update above the advertised limit"));
+        assert_eq!(refused("8220"), DecodeError::Compression("table size update after a header field"));
+    }
+
+    #[test]
+    fn malformed_blocks_are_refused() {
+        let refused = |block: &str| Decoder::default().decode(&hex(block), usize::MAX).unwrap_err();
+        assert_eq!(refused("80"), DecodeError::Compression("index 0"));
+        assert_eq!(refused("be"), DecodeError::Compression("index past the dynamic table"));
+        assert_eq!(refused("4005 6375"), DecodeError::Compression("string runs past the block"));
+        assert_eq!(refused("40"), DecodeError::Compression("block ends inside a field"));
+        assert_eq!(refused("0081 ff"), DecodeError::Compression("invalid Huffman code"));
+    }
+
+    #[test]
+    fn oversized_header_lists_still_update_the_table() {
+        let mut decoder = Decoder::default();
+        let block = hex("400a 6375 7374 6f6d 2d6b 6579 0d63 7573 746f 6d2d 6865 6164 6572");
+        assert_eq!(decoder.decode(&block, 54).unwrap_err(), DecodeError::TooLarge);
+        assert_eq!(decoder.decode(&hex("be"), 55).unwrap(), headers(&[("custom-key", "custom-header")]));
+    }
+
+    #[test]
+    fn encoded_blocks_decode_to_the_same_headers() {
+        let list = [
+            (":status", "200"),
+            ("content-type", "text/html; charset=utf-8"),
+            ("X-Custom", "Value"),
+            ("cookie", "session=1"),
+            ("x-empty", ""),
+        ];
+        let block = encode(list);
+        let decoded = Decoder::default().decode(&block, usize::MAX).unwrap();
+        assert_eq!(decoded[2], ("x-custom".to_string(), "Value".to_string()));
+        assert_eq!(decoded.len(), list.len());
+
+        // Exact static entries are indexed; nothing is added to the table.
+        assert_eq!(encode([(":method", "GET"), (":path", "/")]), hex("8284"));
+        // Sensitive values are never indexed.
+        assert_eq!(encode([("authorization", "x")])[0], 0x1f);
+        assert_eq!(encode([("authorization", "x")])[1], 0x08);
+    }
+}
@@ -0,0 +1,194 @@
+// src/http/h2/huffman.rs
+//
+// The static Huffman code of HPACK (RFC 7541 Appendix B). The code is
+// canonical: codes of one length are consecutive, in symbol order, so the
+// bit length of each symbol is all that needs to be stored.
+
+use std::sync::OnceLock;
+
+/// End-of-string symbol; only ever seen as padding.
+const EOS: usize = 256;
+
+/// Code length in bits of each symbol, 0-255 and EOS.
+#[rustfmt::skip]
+const LENGTHS: [u8; 257] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28,
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+     6, 10, 10, 12, 13,  6,  8, 11, 10, 10,  8, 11,  8,  6,  6,  6,
+     5,  5,  5,  6,  6,  6,  6,  6,  6,  6,  7,  8, 15,  6, 12, 10,
+    13,  6,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,  7,
+     7,  7,  7,  7,  7,  7,  7,  7,  8,  7,  8, 13, 19, 13, 14,  6,
+    15,  5,  6,  5,  6,  5,  6,  6,  6,  5,  7,  7,  6,  6,  6,  5,
+     6,  7,  6,  5,  5,  6,  7,  7,  7,  7,  7, 15, 11, 14, 13, 28,
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23,
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24,
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23,
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23,
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25,
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27,
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23,
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26,
+    30,
+];
+
+const MAX_LENGTH: usize = 30;
+
+/// Canonical decoding tables: for each length, the first code of that
+/// length and where its symbols start in `symbols`.
+struct Table {
+    codes: [u32; 257],
+    first_code: [u32; MAX_LENGTH + 1],
+    first_index: [usize; MAX_LENGTH + 1],
+    count: [u32; MAX_LENGTH + 1],
+    /// Symbols ordered by (length, symbol).
+    symbols: Vec<u16>,
+}
+
+fn table() -> &'static Table {
+    static TABLE: OnceLock<Table> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let mut symbols: Vec<u16> = (0..257).collect();
+        symbols.sort_by_key(|&s| (LENGTHS[s as usize], s));
+
+        let mut table = Table {
+            codes: [0; 257],
+            first_code: [0; MAX_LENGTH + 1],
+            first_index: [0; MAX_LENGTH + 1],
+            count: [0; MAX_LENGTH + 1],
+            symbols,
+        };
+        let mut code = 0u32;
+        let mut length = LENGTHS[table.symbols[0] as usize] as usize;
+        table.first_index[length] = 0;
+        for (index, &symbol) in table.symbols.iter().enumerate() {
+            let symbol_length = LENGTHS[symbol as usize] as usize;
+            if index > 0 {
+                code += 1;
+                if symbol_length != length {
+                    code <<= symbol_length - length;
+                    length = symbol_length;
+                    table.first_index[length] = index;
+                }
+            }
+            if table.count[length] == 0 {
+                table.first_code[length] = code;
+            }
+            table.count[length] += 1;
+            table.codes[symbol as usize] = code;
+        }
+        table
+    })
+}
+
+/// Decode a Huffman-coded string literal. Padding must be a prefix of EOS
+/// shorter than a byte, and EOS itself must not appear (RFC 7541 §5.2).
+pub fn decode(input: &[u8]) -> Option<Vec<u8>> {
+    let table = table();
+    let mut out = Vec::with_capacity(input.len() * 8 / 5);
+    let mut code = 0u32;
+    let mut length = 0usize;
+    for byte in input {
+        for shift in (0..8).rev() {
+            code = (code << 1) | u32::from((byte >> shift) & 1);
+            length += 1;
+            if length > MAX_LENGTH {
+                return None;
+            }
+            let offset = code.wrapping_sub(table.first_code[length]);
+            if table.count[length] > 0 && code >= table.first_code[length] && offset < table.count[length] {
+                let symbol = table.symbols[table.first_index[length] + offset as usize] as usize;
+                if symbol == EOS {
+                    return None;
+                }
+                out.push(symbol as u8);
+                code = 0;
+                length = 0;
+            }
+        }
+    }
+    // Whatever is left must be all ones (the start of EOS), under 8 bits.
+    if length >= 8 || code != (1 << length) - 1 {
+        return None;
+    }
+    Some(out)
+}
+
+/// Huffman-code `input`, padding the last byte with the start of EOS.
+pub fn encode(input: &[u8]) -> Vec<u8> {
+    let table = table();
+    let mut out = Vec::with_capacity(input.len());
+    let mut bits = 0u64;
+    let mut pending = 0usize;
+    for &byte in input {
+        let length = LENGTHS[byte as usize] as usize;
+        bits = (bits << length) | u64::from(table.codes[byte as usize]);
+        pending += length;
+        while pending >= 8 {
+            pending -= 8;
+            out.push((bits >> pending) as u8);
+        }
+        bits &= (1 << pending) - 1;
+    }
+    if pending > 0 {
+        let padding = 8 - pending;
+        out.push(((bits << padding) | ((1 << padding) - 1)) as u8);
+    }
+    out
+}
+
+/// Length of `input` once Huffman-coded, in bytes.
+pub fn encoded_len(input: &[u8]) -> usize {
+    let bits: usize = input.iter().map(|&b| LENGTHS[b as usize] as usize).sum();
+    bits.div_ceil(8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Strings and their codes from RFC 7541 Appendix C.4 and C.6.
+    const EXAMPLES: [(&str, &[u8]); 5] = [
+        ("www.example.com", &[0xf1, 0xe3, 0xc2, 0xe5, 0xf2, 0x3a, 0x6b, 0xa0, 0xab, 0x90, 0xf4, 0xff]),
+        ("no-cache", &[0xa8, 0xeb, 0x10, 0x64, 0x9c, 0xbf]),
+        ("custom-key", &[0x25, 0xa8, 0x49, 0xe9, 0x5b, 0xa9, 0x7d, 0x7f]),
+        ("302", &[0x64, 0x02]),
+        ("gzip", &[0x9b, 0xd9, 0xab]),
+    ];
+
+    #[test]
+    fn rfc_examples() {
+        for (text, code) in EXAMPLES {
+            assert_eq!(encode(text.as_bytes()), code, "{}", text);
+            assert_eq!(encoded_len(text.as_bytes()), code.len(), "{}", text);
+            assert_eq!(decode(code).unwrap(), text.as_bytes(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn every_byte_round_trips() {
+        let all: Vec<u8> = (0..=255).collect();
+        let code = encode(&all);
+        assert_eq!(code.len(), encoded_len(&all));
+        assert_eq!(decode(&code).unwrap(), all);
+        for byte in all {
+            assert_eq!(decode(&encode(&[byte])).unwrap(), [byte]);
+        }
+        assert_eq!(decode(&[]).unwrap(), b"");
+    }
+
+    #[test]
+    fn invalid_padding_is_refused() {
+        // "0" is 00000 (5 bits); the 3 bits of padding must be ones.
+        assert_eq!(decode(&[0x07]).unwrap(), b"0");
+        assert_eq!(decode(&[0x06]), None);
+        // A whole byte of padding is too much.
+        assert_eq!(decode(&[0x07, 0xff]), None);
+        assert_eq!(decode(&[0xff]), None);
+    }
+
+    #[test]
+    fn eos_is_refused() {
+        // EOS is 30 ones, padded here to 32.
+        assert_eq!(decode(&[0xff, 0xff, 0xff, 0xff]), None);
+    }
+}
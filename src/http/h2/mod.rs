@@ -0,0 +1,11 @@
+// src/http/h2/mod.rs
+//
+// The HTTP/2 wire format: frames (RFC 9113) and HPACK header compression
+// (RFC 7541). Connection state and I/O live in `proxy::h2`.
+
+pub mod frame;
+pub mod hpack;
+pub mod huffman;
+
+pub use frame::{ErrorCode, Frame};
+pub use hpack::Header;
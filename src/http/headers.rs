@@ -21,6 +21,22 @@ impl HttpHeaders {
     pub fn get(&self, key: &str) -> Option<&String> {
         self.headers.get(&key.to_ascii_lowercase())
     }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        self.headers.remove(&key.to_ascii_lowercase())
+    }
+
+    /// True when the comma-separated list in `key` contains `token`,
+    /// compared case-insensitively (e.g. `Connection: keep-alive, Upgrade`).
+    pub fn has_token(&self, key: &str, token: &str) -> bool {
+        self.get(key)
+            .is_some_and(|value| value.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.headers.is_empty()
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
         self.headers.iter()
     }
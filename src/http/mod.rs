@@ -1,19 +1,24 @@
 // src/http/mod.rs
 
+pub mod body;
+pub mod coding;
 pub mod enums;
+pub mod h2;
 pub mod headers;
 pub mod request;
 pub mod response;
 pub mod util;
 
 
+pub use body::Body;
 pub use enums::{HttpMethod, HttpVersion, HttpStatus};
 pub use headers::HttpHeaders;
-pub use request::HttpRequest;
+pub use request::{ClientCertificate, HttpRequest, RequestTarget};
 pub use response::HttpResponse;
 
 pub use util::{
     parse_http_request,
+    parse_http_response,
     verify_http_request,
 };
 
@@ -11,7 +11,43 @@ pub struct HttpRequest {
     pub version: HttpVersion,
     pub headers: HttpHeaders,
     pub origin: (String, u16), // (host, port)
-    pub body: Option<Vec<u8>>,
+    pub target: RequestTarget, // form of the request line's target
+    pub body: Option<Vec<u8>>, // buffered body; proxied bodies stream from the connection instead
+    pub request_id: Option<String>, // correlation ID assigned by the proxy
+    pub secure: bool,               // received over TLS
+    pub client_cert: Option<ClientCertificate>, // verified TLS client identity
+}
+
+/// The form of the request target (RFC 9112 §3.2). Absolute-form requests
+/// keep only the path in `path`; their authority is in `origin` and `Host`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RequestTarget {
+    /// `/path?query`
+    Origin,
+    /// `http://host:port/path?query`, sent to forward proxies.
+    Absolute,
+    /// `host:port`, the target of CONNECT.
+    Authority,
+}
+
+/// Identity taken from a verified TLS client certificate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientCertificate {
+    /// RFC 4514 subject, e.g. `CN=client,O=Example`.
+    pub subject: String,
+    /// Subject alternative names such as `DNS:client.example.com`.
+    pub sans: Vec<String>,
+}
+
+impl ClientCertificate {
+    /// True when `name` is the subject or one of the SANs, with or without
+    /// its `DNS:`/`IP:`/`URI:`/`email:` prefix.
+    pub fn matches(&self, name: &str) -> bool {
+        self.subject == name
+            || self.sans.iter().any(|san| {
+                san == name || san.split_once(':').is_some_and(|(_, value)| value == name)
+            })
+    }
 }
 
 impl HttpRequest {
@@ -22,7 +58,11 @@ impl HttpRequest {
             version: HttpVersion::HTTP1_1,
             headers: HttpHeaders::new(),
             origin: ("localhost".to_string(), 80),
+            target: RequestTarget::Origin,
             body: None,
+            request_id: None,
+            secure: false,
+            client_cert: None,
         }
     }
 
@@ -32,7 +72,7 @@ impl HttpRequest {
     }
 
     pub fn with_body(mut self, body: Vec<u8>) -> Self {
-        let body_data: Vec<u8> = body.into();
+        let body_data: Vec<u8> = body;
         self.headers
             .insert("Content-Length".to_string(), body_data.len().to_string());
         self.body = Some(body_data);
@@ -44,6 +84,55 @@ impl HttpRequest {
             .as_ref()
             .and_then(|b| String::from_utf8(b.clone()).ok())
     }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.request_id.as_deref()
+    }
+
+    /// `"https"` for requests received over TLS, otherwise `"http"`.
+    pub fn scheme(&self) -> &'static str {
+        if self.secure { "https" } else { "http" }
+    }
+
+    /// True for gRPC calls (`application/grpc`, `application/grpc+proto`,
+    /// ...), which expect failures as gRPC statuses rather than HTTP ones.
+    /// gRPC-Web is left out; it carries its status in the body.
+    pub fn is_grpc(&self) -> bool {
+        self.headers.get("content-type").is_some_and(|value| {
+            let value = value.trim().to_ascii_lowercase();
+            value
+                .strip_prefix("application/grpc")
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with(['+', ';']))
+        })
+    }
+
+    /// The protocol a client asks to switch to with `Connection: Upgrade`
+    /// and `Upgrade: <protocol>`, e.g. `websocket`.
+    pub fn upgrade(&self) -> Option<&str> {
+        if !self.headers.has_token("connection", "upgrade") {
+            return None;
+        }
+        self.headers.get("upgrade").map(|p| p.trim()).filter(|p| !p.is_empty())
+    }
+
+    /// Serialize the request line and headers (CRLF line endings)
+    pub fn head_to_bytes(&self) -> Vec<u8> {
+        let mut out = format!("{} {} {}\r\n", self.method, self.path, self.version).into_bytes();
+        for (key, value) in self.headers.iter() {
+            out.extend_from_slice(format!("{}: {}\r\n", key, value).as_bytes());
+        }
+        out.extend_from_slice(b"\r\n");
+        out
+    }
+
+    /// Serialize the request in wire format (CRLF line endings)
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = self.head_to_bytes();
+        if let Some(body) = &self.body {
+            out.extend_from_slice(body);
+        }
+        out
+    }
 }
 
 impl fmt::Display for HttpRequest {
@@ -56,10 +145,10 @@ impl fmt::Display for HttpRequest {
 
         writeln!(f)?;
 
-        if let Some(body) = &self.body {
-            if let Ok(body_str) = String::from_utf8(body.clone()) {
-                write!(f, "{}", body_str)?;
-            }
+        if let Some(body) = &self.body
+            && let Ok(body_str) = String::from_utf8(body.clone())
+        {
+            write!(f, "{}", body_str)?;
         }
 
         Ok(())
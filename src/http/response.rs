@@ -1,13 +1,21 @@
 /// This module defines the `HttpResponse` struct and its associated methods for creating HTTP responses.
 /// src/http/response.rs
-
-use crate::http::enums::HttpStatus;
+use crate::http::body::{self, Body, CopyError, Trailers};
+use crate::http::enums::{HttpStatus, HttpVersion};
 use crate::http::headers::HttpHeaders;
 use std::fmt;
+use std::io::Write;
 pub struct HttpResponse {
+    /// Written in the status line: HTTP/1.1 unless answering HTTP/1.0.
+    pub version: HttpVersion,
     pub status: HttpStatus,
+    /// The reason phrase an upstream sent, relayed as it was; the status's
+    /// standard one when `None`.
+    pub reason: Option<String>,
     pub headers: HttpHeaders,
-    pub body: Vec<u8>,
+    pub body: Body,
+    /// Sent after the body, where the protocol allows.
+    pub trailers: Trailers,
 }
 
 impl HttpResponse {
@@ -22,9 +30,23 @@ impl HttpResponse {
         headers.insert("Server".to_string(), "Orion/1.0".to_string());
 
         Self {
+            version: HttpVersion::HTTP1_1,
             status,
+            reason: None,
             headers,
-            body,
+            body: Body::Buffered(body),
+            trailers: Trailers::default(),
+        }
+    }
+    /// An interim (1xx) response: a status line and no body
+    pub fn informational(status: HttpStatus) -> Self {
+        Self {
+            version: HttpVersion::HTTP1_1,
+            status,
+            reason: None,
+            headers: HttpHeaders::new(),
+            body: Body::empty(),
+            trailers: Trailers::default(),
         }
     }
     /// Create a new response with a status code and body   
@@ -63,13 +85,60 @@ impl HttpResponse {
         self
     }
 
+    /// The body as text; `None` for streamed or non-UTF-8 bodies.
     pub fn body_as_string(&self) -> Option<String> {
-        String::from_utf8(self.body.clone()).ok()
+        String::from_utf8(self.body.as_bytes()?.to_vec()).ok()
     }
 
     pub fn status_code(&self) -> u16 {
         self.status.code()
     }
+
+    pub fn reason_phrase(&self) -> &str {
+        self.reason.as_deref().unwrap_or(self.status.reason_phrase())
+    }
+
+    /// Serialize the status line and headers (CRLF line endings)
+    pub fn head_to_bytes(&self) -> Vec<u8> {
+        let mut out = format!(
+            "{} {} {}\r\n",
+            self.version,
+            self.status.code(),
+            self.reason_phrase()
+        )
+        .into_bytes();
+        for (key, value) in self.headers.iter() {
+            out.extend_from_slice(format!("{}: {}\r\n", key, value).as_bytes());
+        }
+        out.extend_from_slice(b"\r\n");
+        out
+    }
+
+    /// Serialize the response in wire format; a streamed body is left out.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = self.head_to_bytes();
+        out.extend_from_slice(self.body.as_bytes().unwrap_or_default());
+        out
+    }
+
+    /// Write the response to `out`, streaming the body in chunked coding
+    /// when the headers say so; only chunked bodies carry trailers. Returns
+    /// the number of bytes written.
+    pub fn write_to(&mut self, out: &mut dyn Write) -> Result<u64, CopyError> {
+        let head = self.head_to_bytes();
+        out.write_all(&head).map_err(CopyError::Write)?;
+        let chunked = body::is_chunked(&self.headers);
+        let written = match &mut self.body {
+            Body::Buffered(bytes) if !chunked => {
+                out.write_all(bytes).map_err(CopyError::Write)?;
+                bytes.len() as u64
+            }
+            Body::Buffered(bytes) => body::copy_body(&mut bytes.as_slice(), out, true, Some(&self.trailers))?,
+            Body::Stream(stream) => body::copy_body(stream.as_mut(), out, chunked, Some(&self.trailers))?,
+        };
+        out.flush().map_err(CopyError::Write)?;
+        Ok(head.len() as u64 + written)
+    }
 }
 
 impl fmt::Display for HttpResponse {
@@ -77,9 +146,10 @@ impl fmt::Display for HttpResponse {
         // Status line
         writeln!(
             f,
-            "HTTP/1.1 {} {}",
+            "{} {} {}",
+            self.version,
             self.status.code(),
-            self.status.reason_phrase()
+            self.reason_phrase()
         )?;
 
         // Headers
@@ -90,10 +160,12 @@ impl fmt::Display for HttpResponse {
         writeln!(f)?;
 
         // Body (attempt to display as string, fall back to raw bytes info)
-        if let Ok(body_str) = String::from_utf8(self.body.clone()) {
-            write!(f, "{}", body_str)
-        } else {
-            write!(f, "[Binary data: {} bytes]", self.body.len())
+        match self.body.as_bytes() {
+            Some(bytes) => match std::str::from_utf8(bytes) {
+                Ok(body_str) => write!(f, "{}", body_str),
+                Err(_) => write!(f, "[Binary data: {} bytes]", bytes.len()),
+            },
+            None => write!(f, "[Streamed body]"),
         }
     }
 }
\ No newline at end of file
@@ -1,6 +1,9 @@
 // src/http/util/builder.rs
 
-use crate::http::enums::HttpStatus;
+use crate::http::body::{Body, Trailers};
+use crate::http::enums::{HttpStatus, HttpVersion};
+use crate::http::headers::HttpHeaders;
+use crate::http::request::HttpRequest;
 use crate::http::response::HttpResponse;
 
 /// Create an error response with a specific status and message 
@@ -9,6 +12,71 @@ pub fn create_error_response(status: HttpStatus, message: impl Into<String>) ->
         .with_header("Connection", "close")
 }
 
+/// Create an error response for a specific request, showing its request ID
+/// so the failure can be traced through the logs
+pub fn create_request_error_response(req: &HttpRequest, status: HttpStatus, message: impl Into<String>) -> HttpResponse {
+    if req.is_grpc() {
+        let message = match req.request_id() {
+            Some(id) => format!("{} (request ID {})", message.into(), id),
+            None => message.into(),
+        };
+        return create_grpc_error_response(status, &message);
+    }
+    create_error_response_with_id(status, message, req.request_id())
+}
+
+/// Create an error response for a gRPC call: `200 OK` with the failure in
+/// `grpc-status` and `grpc-message` trailers, as gRPC clients expect it
+pub fn create_grpc_error_response(status: HttpStatus, message: &str) -> HttpResponse {
+    // gRPC's mapping of HTTP statuses to its own codes
+    let code = match status.code() {
+        400 => 13,                   // INTERNAL
+        401 => 16,                   // UNAUTHENTICATED
+        403 => 7,                    // PERMISSION_DENIED
+        404 => 12,                   // UNIMPLEMENTED
+        429 | 502 | 503 | 504 => 14, // UNAVAILABLE
+        _ => 2,                      // UNKNOWN
+    };
+    let mut trailers = HttpHeaders::new();
+    trailers.insert("grpc-status".to_string(), code.to_string());
+    trailers.insert("grpc-message".to_string(), grpc_percent_encode(message));
+
+    let mut headers = HttpHeaders::new();
+    headers.insert("Content-Type".to_string(), "application/grpc".to_string());
+    headers.insert("Content-Length".to_string(), "0".to_string());
+    headers.insert("Server".to_string(), "Orion/1.0".to_string());
+    HttpResponse {
+        version: HttpVersion::HTTP1_1,
+        status: HttpStatus::Ok,
+        reason: None,
+        headers,
+        body: Body::empty(),
+        trailers: Trailers::with(trailers),
+    }
+}
+
+/// `grpc-message` is percent-encoded UTF-8: anything outside printable
+/// ASCII, and `%` itself
+fn grpc_percent_encode(message: &str) -> String {
+    let mut encoded = String::with_capacity(message.len());
+    for byte in message.bytes() {
+        match byte {
+            b' '..=b'~' if byte != b'%' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Same as `create_request_error_response`, for failures that happen before
+/// an `HttpRequest` exists (e.g. the request could not be parsed)
+pub fn create_error_response_with_id(status: HttpStatus, message: impl Into<String>, request_id: Option<&str>) -> HttpResponse {
+    match request_id {
+        Some(id) => create_error_response(status, format!("{}\nRequest ID: {}", message.into(), id)),
+        None => create_error_response(status, message),
+    }
+}
+
 /// Create a successful responsee with a specific body (keep-alive)
 pub fn create_success_response(body: impl Into<String>) -> HttpResponse {
     HttpResponse::text(HttpStatus::Ok, body)
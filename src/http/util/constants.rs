@@ -7,4 +7,5 @@ impl HttpLimits {
     pub const MAX_QUERY_PARAMS:    usize  = 100; 
     pub const MAX_URL_LENGTH:      usize  = 2048; // Maximum URL length
     pub const MAX_BODY_SIZE:       usize  = 10485760; // 10 MB
+    pub const MAX_HEAD_SIZE:       usize  = 65536; // Request line + headers, 64 KB
 }
\ No newline at end of file
@@ -0,0 +1,82 @@
+/// HTTP dates (RFC 9110 §5.6.7) for `Date`, `Expires`, `Last-Modified` and
+/// the conditional request headers.
+/// src/http/util/date.rs
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::logging::timefmt::UtcTime;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+/// `Sun, 06 Nov 1994 08:49:37 GMT`
+pub fn format_http_date(time: SystemTime) -> String {
+    let utc = UtcTime::from_system_time(time);
+    let days = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs() / 86_400;
+    format!(
+        "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        utc.day,
+        MONTHS[(utc.month - 1) as usize],
+        utc.year,
+        utc.hour,
+        utc.minute,
+        utc.second
+    )
+}
+
+/// Parse any of the three formats recipients must accept: IMF-fixdate,
+/// RFC 850 (`Sunday, 06-Nov-94 08:49:37 GMT`) and asctime
+/// (`Sun Nov  6 08:49:37 1994`). The weekday is not checked.
+pub fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let value = value.trim();
+    let (day, month, year, time) = match value.split_once(", ") {
+        Some((_, rest)) if rest.contains('-') => {
+            // RFC 850
+            let (date, time) = rest.split_once(' ')?;
+            let mut parts = date.split('-');
+            let (day, month, year) = (parts.next()?, parts.next()?, parts.next()?);
+            let year: i64 = year.parse().ok()?;
+            // Two-digit years more than 50 years ahead are in the past.
+            let year = if year < 70 { 2000 + year } else if year < 100 { 1900 + year } else { year };
+            (day.parse().ok()?, month, year, time.strip_suffix(" GMT")?)
+        }
+        Some((_, rest)) => {
+            let mut parts = rest.split(' ');
+            let (day, month, year, time) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+            if parts.next() != Some("GMT") {
+                return None;
+            }
+            (day.parse().ok()?, month, year.parse().ok()?, time)
+        }
+        None => {
+            // asctime
+            let mut parts = value.split_ascii_whitespace().skip(1);
+            let (month, day, time, year) = (parts.next()?, parts.next()?, parts.next()?, parts.next()?);
+            (day.parse().ok()?, month, year.parse().ok()?, time)
+        }
+    };
+
+    let month = MONTHS.iter().position(|m| *m == month)? as u32 + 1;
+    let mut clock = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (hour, minute, second) = (clock.next()??, clock.next()??, clock.next()??);
+    if !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    let secs = days * 86_400 + (hour * 3600 + minute * 60 + second) as i64;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+/// (year, month, day) to days since 1970-01-01; the inverse of
+/// `civil_from_days` in the log time formatting.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
@@ -7,10 +7,24 @@ pub enum HttpParseError{
     MalformedRequest(String),
     UnsupportedMethod(String),
     UnsupportedHttpVersion(String),
+    MalformedResponse(String),
 
 }
 
 
+impl HttpParseError {
+    /// Variant name, used as a metrics label
+    pub fn kind(&self) -> &'static str {
+        match self {
+            HttpParseError::EmptyRequest => "EmptyRequest",
+            HttpParseError::MalformedRequest(_) => "MalformedRequest",
+            HttpParseError::UnsupportedMethod(_) => "UnsupportedMethod",
+            HttpParseError::UnsupportedHttpVersion(_) => "UnsupportedHttpVersion",
+            HttpParseError::MalformedResponse(_) => "MalformedResponse",
+        }
+    }
+}
+
 impl fmt::Display for HttpParseError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -18,6 +32,7 @@ impl fmt::Display for HttpParseError {
             HttpParseError::MalformedRequest(http_error) => write!(f, "Malformed HTTP request {}", http_error),
             HttpParseError::UnsupportedMethod(method) => write!(f, "Unsupported HTTP method: {}", method),
             HttpParseError::UnsupportedHttpVersion(version) => write!(f, "Unsupported HTTP version: {}", version),
+            HttpParseError::MalformedResponse(http_error) => write!(f, "Malformed HTTP response {}", http_error),
         }
     }
 }
@@ -6,11 +6,14 @@ pub mod builder;
 pub mod url_lib;
 pub mod errors;
 pub mod constants;
+pub mod date;
 
 
-pub use parser::parse_http_request;
+pub use parser::{parse_http_request, parse_http_response};
 pub use builder::{
     create_error_response,
+    create_request_error_response,
+    create_error_response_with_id,
     create_success_response,
     create_html_response,
     create_json_response,
@@ -19,4 +22,5 @@ pub use builder::{
 pub use validator::verify_http_request;
 pub use url_lib::{url_decode, url_encode};
 pub use errors::HttpParseError;
-pub use constants::HttpLimits;
\ No newline at end of file
+pub use constants::HttpLimits;
+pub use date::{format_http_date, parse_http_date};
\ No newline at end of file
@@ -1,6 +1,7 @@
-use crate::http::enums::{HttpMethod, HttpVersion};
+use crate::http::enums::{HttpMethod, HttpStatus, HttpVersion};
 use crate::http::headers::HttpHeaders;
-use crate::http::request::HttpRequest;
+use crate::http::request::{HttpRequest, RequestTarget};
+use crate::http::response::HttpResponse;
 use crate::http::util::errors::HttpParseError;
 use std::str::FromStr;
 use crate::http::util::url_lib::url_decode;
@@ -50,8 +51,6 @@ pub fn parse_http_request(request: &str) -> Result<HttpRequest, HttpParseError>
     let method = HttpMethod::from_str(method_str)
         .map_err(|_| HttpParseError::UnsupportedMethod("Invalid HTTP method".to_string()))?;
 
-    let path = path_str.to_string();
-
     let version = HttpVersion::from_str(version_str)
         .map_err(|_| HttpParseError::UnsupportedHttpVersion("Invalid HTTP version".to_string()))?;
 
@@ -71,16 +70,23 @@ pub fn parse_http_request(request: &str) -> Result<HttpRequest, HttpParseError>
         }
     }
 
-    let host = headers
-        .get("host")
-        .map(|s| s.as_str())
-        .unwrap_or("127.0.0.1");
-
-    let origin = parse_host_and_port(host);
+    let (target, path, origin) = if method == HttpMethod::CONNECT {
+        (RequestTarget::Authority, path_str.to_string(), parse_authority(path_str)?)
+    } else if let Some((authority, path)) = split_absolute_form(path_str)? {
+        // The URI's authority takes the place of any Host header.
+        headers.insert("host".to_string(), authority.to_string());
+        (RequestTarget::Absolute, path, parse_host_and_port(authority))
+    } else {
+        let host = headers
+            .get("host")
+            .map(|s| s.as_str())
+            .unwrap_or("127.0.0.1");
+        (RequestTarget::Origin, path_str.to_string(), parse_host_and_port(host))
+    };
 
     let body_start = seperator + 4; // Skip the "\r\n\r\n"
     let body = if body_start < request.len() {
-        request[body_start ..].as_bytes().to_vec()
+        request.as_bytes()[body_start ..].to_vec()
     } else {
         Vec::new()
     };
@@ -92,24 +98,128 @@ pub fn parse_http_request(request: &str) -> Result<HttpRequest, HttpParseError>
         version,
         headers,
         origin,
+        target,
         body: Some(body),
+        request_id: None,
+        secure: false,
+        client_cert: None,
+    })
+}
+
+/// Position of the blank line ending the head (index of the "\r\n\r\n")
+pub fn find_head_end(buffer: &[u8]) -> Option<usize> {
+    buffer.windows(4).position(|window| window == b"\r\n\r\n")
+}
+
+/// Parse a raw upstream response (status line, headers and body)
+pub fn parse_http_response(raw: &[u8]) -> Result<HttpResponse, HttpParseError> {
+    if raw.is_empty() {
+        return Err(HttpParseError::EmptyRequest);
+    }
+
+    let seperator = find_head_end(raw)
+        .ok_or_else(|| HttpParseError::MalformedResponse("No headers found".to_string()))?;
+
+    let head = std::str::from_utf8(&raw[..seperator])
+        .map_err(|_| HttpParseError::MalformedResponse("Head is not valid UTF-8".to_string()))?;
+
+    let mut lines = head.lines();
+    let status_line = lines
+        .next()
+        .ok_or_else(|| HttpParseError::MalformedResponse("Missing status line".to_string()))?;
+
+    let mut parts = status_line.splitn(3, ' ');
+    let version_str = parts.next().unwrap_or_default();
+    let version = HttpVersion::from_str(version_str)?;
+
+    let code = parts
+        .next()
+        .and_then(|code| code.trim().parse::<u16>().ok())
+        .ok_or_else(|| HttpParseError::MalformedResponse(format!("Invalid status line: {}", status_line)))?;
+    let status = HttpStatus::from_code(code)
+        .ok_or_else(|| HttpParseError::MalformedResponse(format!("Invalid status code {}", code)))?;
+    // Relayed unless it is empty or would corrupt the status line.
+    let reason = parts
+        .next()
+        .map(str::trim)
+        .filter(|reason| !reason.is_empty() && !reason.chars().any(|c| c.is_control()))
+        .map(str::to_string);
+
+    let mut headers = HttpHeaders::new();
+    for line in lines {
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        let (key, value) = line.split_once(':').ok_or_else(|| {
+            HttpParseError::MalformedResponse(format!("Invalid header line: {}", line))
+        })?;
+        headers.insert(key.trim().to_string(), value.trim().to_string());
+    }
+
+    Ok(HttpResponse {
+        version,
+        status,
+        reason,
+        headers,
+        body: raw[seperator + 4..].to_vec().into(),
+        trailers: Default::default(),
     })
 }
 
-fn parse_host_and_port(host: &str) -> (String, u16) {
+/// Split an absolute-form target (`http://host:port/path`) into its
+/// authority and an origin-form path. `None` for other targets.
+fn split_absolute_form(target: &str) -> Result<Option<(&str, String)>, HttpParseError> {
+    let Some((scheme, rest)) = target.split_once("://") else {
+        return Ok(None);
+    };
+    if target.starts_with('/') {
+        return Ok(None);
+    }
+    if !scheme.eq_ignore_ascii_case("http") {
+        return Err(HttpParseError::MalformedRequest(format!("Unsupported URI scheme '{}'", scheme)));
+    }
+    let end = rest.find(['/', '?']).unwrap_or(rest.len());
+    let (authority, path) = rest.split_at(end);
+    if authority.is_empty() || authority.contains('@') {
+        return Err(HttpParseError::MalformedRequest(format!("Invalid authority in '{}'", target)));
+    }
+    let path = match path.chars().next() {
+        Some('/') => path.to_string(),
+        _ => format!("/{}", path),
+    };
+    Ok(Some((authority, path)))
+}
+
+/// Parse the authority-form target of a CONNECT request, which must name a
+/// port (`host:port`).
+fn parse_authority(target: &str) -> Result<(String, u16), HttpParseError> {
+    let has_port = target
+        .rsplit_once(':')
+        .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok_and(|p| p != 0));
+    if !has_port || target.contains(['/', '@']) {
+        return Err(HttpParseError::MalformedRequest(format!(
+            "CONNECT target '{}' is not host:port",
+            target
+        )));
+    }
+    Ok(parse_host_and_port(target))
+}
+
+pub(crate) fn parse_host_and_port(host: &str) -> (String, u16) {
     // Handle IPv6 addresses in brackets like [::1]:8080
-    if host.starts_with('[') {
-        if let Some(bracket_end) = host.find(']') {
-            let ipv6_part = &host[..bracket_end + 1]; // Include the closing bracket
-
-            // Check if there's a port after the closing bracket
-            if bracket_end + 1 < host.len() && host.chars().nth(bracket_end + 1) == Some(':') {
-                let port_str = &host[bracket_end + 2..];
-                let port = port_str.parse().unwrap_or(80);
-                return (ipv6_part.to_string(), port);
-            } else {
-                return (ipv6_part.to_string(), 80);
-            }
+    if host.starts_with('[')
+        && let Some(bracket_end) = host.find(']')
+    {
+        let ipv6_part = &host[..bracket_end + 1]; // Include the closing bracket
+
+        // Check if there's a port after the closing bracket
+        if bracket_end + 1 < host.len() && host.chars().nth(bracket_end + 1) == Some(':') {
+            let port_str = &host[bracket_end + 2..];
+            let port = port_str.parse().unwrap_or(80);
+            return (ipv6_part.to_string(), port);
+        } else {
+            return (ipv6_part.to_string(), 80);
         }
     }
 
@@ -33,12 +33,12 @@ pub fn verify_http_request(req: &HttpRequest) -> Result<(), HttpResponse> {
         ));
     }
 
-    if req.version != HttpVersion::HTTP1_1 {
+    if !matches!(req.version, HttpVersion::HTTP1_0 | HttpVersion::HTTP1_1 | HttpVersion::HTTP2_0) {
         return Err(HttpResponse::text(
             HttpStatus::HttpVersionNotSupported,
             format!(
                 "HTTP version ({}) is not supported",
-                req.version.to_string()
+                req.version
             ),
         ));
     }
@@ -67,20 +67,21 @@ pub fn verify_http_request(req: &HttpRequest) -> Result<(), HttpResponse> {
 
 
 
-    if req.headers.get("host").is_none() {
+    // HTTP/1.0 predates Host; the server supplies a default one.
+    if req.headers.get("host").is_none() && req.version != HttpVersion::HTTP1_0 {
         return Err(HttpResponse::text(
             HttpStatus::BadRequest,
             "Host header is required".to_string(),
         ));
     }
 
-    if let Some(body) = req.body.as_ref() {
-        if body.len() > HttpLimits::MAX_BODY_SIZE {
-            return Err(HttpResponse::text(
-                HttpStatus::PayloadTooLarge,
-                format!("Request body exceeds maximum size of {} bytes", HttpLimits::MAX_BODY_SIZE),
-            ));
-        }
+    if let Some(body) = req.body.as_ref()
+        && body.len() > HttpLimits::MAX_BODY_SIZE
+    {
+        return Err(HttpResponse::text(
+            HttpStatus::PayloadTooLarge,
+            format!("Request body exceeds maximum size of {} bytes", HttpLimits::MAX_BODY_SIZE),
+        ));
     }
 
     Ok(()) // we wont be responding with an HttpResponse if everything is fine, this will go to the forwarding logic.
@@ -1,2 +1,9 @@
 // src/lib.rs
-pub mod http;
\ No newline at end of file
+pub mod admin;
+pub mod config;
+pub mod http;
+pub mod json;
+pub mod logging;
+pub mod metrics;
+pub mod proxy;
+pub mod util;
@@ -0,0 +1,359 @@
+// src/logging/access.rs
+//
+// Access log: one line per completed request, in Apache common/combined
+// format, JSON lines or a custom `%` format string.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::json::ObjectWriter;
+use crate::logging::UtcTime;
+
+const COMMON: &str = "%h - - %t \"%r\" %>s %b";
+const COMBINED: &str = "%h - - %t \"%r\" %>s %b \"%{Referer}i\" \"%{User-Agent}i\"";
+
+/// Everything recorded about a single request.
+#[derive(Debug, Clone)]
+pub struct AccessLogEntry {
+    pub time: SystemTime,
+    pub client: SocketAddr,
+    pub method: String,
+    pub path: String,
+    pub version: String,
+    pub status: u16,
+    pub bytes_in: usize,
+    pub bytes_out: usize,
+    pub upstream: Option<String>,
+    pub upstream_latency: Option<Duration>,
+    pub total_latency: Duration,
+    pub user_agent: Option<String>,
+    pub referer: Option<String>,
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessLogFormat {
+    Common,
+    Combined,
+    Json,
+    Custom(String),
+}
+
+impl AccessLogFormat {
+    /// `"common"`, `"combined"`, `"json"`, or a custom format containing `%` directives.
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "common" => Some(AccessLogFormat::Common),
+            "combined" => Some(AccessLogFormat::Combined),
+            "json" => Some(AccessLogFormat::Json),
+            custom if custom.contains('%') => Some(AccessLogFormat::Custom(custom.to_string())),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AccessLogTarget {
+    Stdout,
+    File(PathBuf),
+}
+
+enum Sink {
+    Stdout,
+    File(File),
+}
+
+pub struct AccessLog {
+    format: AccessLogFormat,
+    target: AccessLogTarget,
+    sink: Mutex<Sink>,
+}
+
+impl AccessLog {
+    pub fn open(format: AccessLogFormat, target: AccessLogTarget) -> io::Result<Self> {
+        let sink = open_sink(&target)?;
+        Ok(Self {
+            format,
+            target,
+            sink: Mutex::new(sink),
+        })
+    }
+
+    /// Re-open the log file so rotated files are released (SIGUSR1).
+    pub fn reopen(&self) -> io::Result<()> {
+        let sink = open_sink(&self.target)?;
+        *self.sink.lock().unwrap_or_else(|e| e.into_inner()) = sink;
+        Ok(())
+    }
+
+    pub fn log(&self, entry: &AccessLogEntry) {
+        let mut line = self.format_entry(entry);
+        line.push('\n');
+
+        let mut sink = self.sink.lock().unwrap_or_else(|e| e.into_inner());
+        let _ = match &mut *sink {
+            Sink::Stdout => io::stdout().lock().write_all(line.as_bytes()),
+            Sink::File(file) => file.write_all(line.as_bytes()),
+        };
+    }
+
+    pub fn format_entry(&self, entry: &AccessLogEntry) -> String {
+        match &self.format {
+            AccessLogFormat::Common => format_pattern(COMMON, entry),
+            AccessLogFormat::Combined => format_pattern(COMBINED, entry),
+            AccessLogFormat::Json => format_json(entry),
+            AccessLogFormat::Custom(pattern) => format_pattern(pattern, entry),
+        }
+    }
+}
+
+fn open_sink(target: &AccessLogTarget) -> io::Result<Sink> {
+    match target {
+        AccessLogTarget::Stdout => Ok(Sink::Stdout),
+        AccessLogTarget::File(path) => OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map(Sink::File),
+    }
+}
+
+fn format_json(entry: &AccessLogEntry) -> String {
+    ObjectWriter::new()
+        .string("time", &UtcTime::from_system_time(entry.time).rfc3339())
+        .string("client", &entry.client.ip().to_string())
+        .string("method", &entry.method)
+        .string("path", &entry.path)
+        .string("version", &entry.version)
+        .number("status", entry.status)
+        .number("bytes_in", entry.bytes_in)
+        .number("bytes_out", entry.bytes_out)
+        .optional_string("upstream", entry.upstream.as_deref())
+        .raw(
+            "upstream_latency_ms",
+            &entry
+                .upstream_latency
+                .map_or_else(|| "null".to_string(), |d| format!("{:.3}", millis(d))),
+        )
+        .raw("total_latency_ms", &format!("{:.3}", millis(entry.total_latency)))
+        .optional_string("user_agent", entry.user_agent.as_deref())
+        .optional_string("referer", entry.referer.as_deref())
+        .optional_string("request_id", entry.request_id.as_deref())
+        .finish()
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+fn dash(value: Option<&str>) -> &str {
+    value.unwrap_or("-")
+}
+
+/// Append client supplied text the way Apache and nginx log it: `"` and
+/// `\` backslash-escaped, control and non-ASCII bytes as `\xHH`, so a
+/// value can neither end its quoted field nor forge a line.
+fn push_escaped(out: &mut String, value: &str) {
+    for byte in value.bytes() {
+        match byte {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7e => out.push(byte as char),
+            _ => out.push_str(&format!("\\x{:02x}", byte)),
+        }
+    }
+}
+
+/// Expand an Apache-style format string.
+///
+/// Supported directives: `%h`/`%a` client address, `%t` time in brackets,
+/// `%r` request line, `%m` method, `%U` path, `%q` query string, `%H`
+/// protocol, `%s`/`%>s` status, `%b` bytes out (`-` when zero), `%B`/`%O` bytes out, `%I` bytes in,
+/// `%D` total time in microseconds, `%T` total time in seconds,
+/// `%{Referer}i`/`%{User-Agent}i` request headers, and the Orion specific
+/// `%{request_id}x`, `%{upstream}x`, `%{upstream_latency_ms}x` and
+/// `%{total_latency_ms}x`. Values taken from the request are escaped.
+pub fn format_pattern(pattern: &str, entry: &AccessLogEntry) -> String {
+    let mut out = String::with_capacity(pattern.len() * 2);
+    let mut chars = pattern.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+
+        let mut name = None;
+        if chars.peek() == Some(&'{') {
+            chars.next();
+            let mut inner = String::new();
+            for c in chars.by_ref() {
+                if c == '}' {
+                    break;
+                }
+                inner.push(c);
+            }
+            name = Some(inner);
+        }
+        if chars.peek() == Some(&'>') {
+            chars.next();
+        }
+
+        let Some(directive) = chars.next() else {
+            out.push('%');
+            break;
+        };
+
+        let (path, query) = entry
+            .path
+            .split_once('?')
+            .unwrap_or((entry.path.as_str(), ""));
+
+        match (directive, name.as_deref()) {
+            ('%', _) => out.push('%'),
+            ('h', _) | ('a', _) => out.push_str(&entry.client.ip().to_string()),
+            ('t', _) => out.push_str(&format!("[{}]", UtcTime::from_system_time(entry.time).common_log())),
+            ('r', _) => push_escaped(&mut out, &format!("{} {} {}", entry.method, entry.path, entry.version)),
+            ('m', _) => push_escaped(&mut out, &entry.method),
+            ('U', _) => push_escaped(&mut out, path),
+            ('q', _) if !query.is_empty() => {
+                out.push('?');
+                push_escaped(&mut out, query);
+            }
+            ('q', _) => {}
+            ('H', _) => push_escaped(&mut out, &entry.version),
+            ('s', _) => out.push_str(&entry.status.to_string()),
+            ('b', _) if entry.bytes_out == 0 => out.push('-'),
+            ('b', _) | ('B', _) | ('O', _) => out.push_str(&entry.bytes_out.to_string()),
+            ('I', _) => out.push_str(&entry.bytes_in.to_string()),
+            ('D', _) => out.push_str(&entry.total_latency.as_micros().to_string()),
+            ('T', _) => out.push_str(&format!("{:.3}", entry.total_latency.as_secs_f64())),
+            ('i', Some(header)) if header.eq_ignore_ascii_case("referer") => {
+                push_escaped(&mut out, dash(entry.referer.as_deref()))
+            }
+            ('i', Some(header)) if header.eq_ignore_ascii_case("user-agent") => {
+                push_escaped(&mut out, dash(entry.user_agent.as_deref()))
+            }
+            ('x', Some("request_id")) => push_escaped(&mut out, dash(entry.request_id.as_deref())),
+            ('x', Some("upstream")) => out.push_str(dash(entry.upstream.as_deref())),
+            ('x', Some("upstream_latency_ms")) => match entry.upstream_latency {
+                Some(latency) => out.push_str(&format!("{:.3}", millis(latency))),
+                None => out.push('-'),
+            },
+            ('x', Some("total_latency_ms")) => out.push_str(&format!("{:.3}", millis(entry.total_latency))),
+            _ => out.push('-'),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::UNIX_EPOCH;
+
+    fn entry() -> AccessLogEntry {
+        AccessLogEntry {
+            time: UNIX_EPOCH + Duration::from_millis(1_000_000_000_250),
+            client: "192.0.2.7:51234".parse().unwrap(),
+            method: "GET".to_string(),
+            path: "/search?q=orion".to_string(),
+            version: "HTTP/1.1".to_string(),
+            status: 200,
+            bytes_in: 0,
+            bytes_out: 1234,
+            upstream: Some("10.0.0.2:8080".to_string()),
+            upstream_latency: Some(Duration::from_micros(1500)),
+            total_latency: Duration::from_micros(2250),
+            user_agent: Some("curl/8.0".to_string()),
+            referer: None,
+            request_id: Some("abc-123".to_string()),
+        }
+    }
+
+    fn format(format: AccessLogFormat, entry: &AccessLogEntry) -> String {
+        AccessLog::open(format, AccessLogTarget::Stdout).unwrap().format_entry(entry)
+    }
+
+    #[test]
+    fn common_format() {
+        assert_eq!(
+            format(AccessLogFormat::Common, &entry()),
+            "192.0.2.7 - - [09/Sep/2001:01:46:40 +0000] \"GET /search?q=orion HTTP/1.1\" 200 1234"
+        );
+    }
+
+    #[test]
+    fn combined_format() {
+        assert_eq!(
+            format(AccessLogFormat::Combined, &entry()),
+            "192.0.2.7 - - [09/Sep/2001:01:46:40 +0000] \"GET /search?q=orion HTTP/1.1\" 200 1234 \"-\" \"curl/8.0\""
+        );
+    }
+
+    #[test]
+    fn quoted_fields_are_escaped() {
+        let mut entry = entry();
+        entry.path = "/\"a\\b\"".to_string();
+        entry.user_agent = Some("evil\" 500 0\n192.0.2.1 - - \u{7f}é".to_string());
+        entry.referer = Some("tab\there".to_string());
+        assert_eq!(
+            format(AccessLogFormat::Combined, &entry),
+            "192.0.2.7 - - [09/Sep/2001:01:46:40 +0000] \"GET /\\\"a\\\\b\\\" HTTP/1.1\" 200 1234 \
+             \"tab\\x09here\" \"evil\\\" 500 0\\x0a192.0.2.1 - - \\x7f\\xc3\\xa9\""
+        );
+    }
+
+    #[test]
+    fn json_format() {
+        let mut entry = entry();
+        entry.user_agent = Some("say \"hi\"".to_string());
+        assert_eq!(
+            format(AccessLogFormat::Json, &entry),
+            "{\"time\":\"2001-09-09T01:46:40.250Z\",\"client\":\"192.0.2.7\",\"method\":\"GET\",\
+             \"path\":\"/search?q=orion\",\"version\":\"HTTP/1.1\",\"status\":200,\"bytes_in\":0,\
+             \"bytes_out\":1234,\"upstream\":\"10.0.0.2:8080\",\"upstream_latency_ms\":1.500,\
+             \"total_latency_ms\":2.250,\"user_agent\":\"say \\\"hi\\\"\",\"referer\":null,\
+             \"request_id\":\"abc-123\"}"
+        );
+    }
+
+    #[test]
+    fn pattern_directives() {
+        let entry = entry();
+        let expand = |pattern| format_pattern(pattern, &entry);
+
+        assert_eq!(expand("%a %m %U%q %H %s %>s"), "192.0.2.7 GET /search?q=orion HTTP/1.1 200 200");
+        assert_eq!(expand("%b %B %O %I"), "1234 1234 1234 0");
+        assert_eq!(expand("%D %T"), "2250 0.002");
+        assert_eq!(expand("%{Referer}i %{user-agent}i"), "- curl/8.0");
+        assert_eq!(
+            expand("%{request_id}x %{upstream}x %{upstream_latency_ms}x %{total_latency_ms}x"),
+            "abc-123 10.0.0.2:8080 1.500 2.250"
+        );
+        assert_eq!(expand("100%% %t"), "100% [09/Sep/2001:01:46:40 +0000]");
+        // Unknown directives log as `-`; a trailing `%` is kept.
+        assert_eq!(expand("%z %{Cookie}i %{nope}x %"), "- - - %");
+
+        let mut empty = entry.clone();
+        empty.path = "/".to_string();
+        empty.bytes_out = 0;
+        empty.upstream = None;
+        empty.upstream_latency = None;
+        assert_eq!(format_pattern("[%q] %b %{upstream}x %{upstream_latency_ms}x", &empty), "[] - - -");
+    }
+
+    #[test]
+    fn format_names() {
+        assert_eq!(AccessLogFormat::parse("common"), Some(AccessLogFormat::Common));
+        assert_eq!(AccessLogFormat::parse("combined"), Some(AccessLogFormat::Combined));
+        assert_eq!(AccessLogFormat::parse("json"), Some(AccessLogFormat::Json));
+        assert_eq!(AccessLogFormat::parse("%h %s"), Some(AccessLogFormat::Custom("%h %s".to_string())));
+        assert_eq!(AccessLogFormat::parse("apache"), None);
+    }
+}
@@ -0,0 +1,69 @@
+// src/logging/mod.rs
+//
+// Diagnostic logging for the proxy. Every line carries a timestamp, a level
+// and, when one is known, the request ID so lines can be correlated across
+// services.
+
+pub mod access;
+pub mod timefmt;
+
+use std::fmt;
+use std::io::Write;
+
+pub use access::{AccessLog, AccessLogEntry, AccessLogFormat, AccessLogTarget};
+pub use timefmt::UtcTime;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level_str = match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        };
+        write!(f, "{}", level_str)
+    }
+}
+
+pub fn log(level: Level, request_id: Option<&str>, args: fmt::Arguments<'_>) {
+    let timestamp = UtcTime::now().rfc3339();
+    let line = match request_id {
+        Some(id) => format!("{} {} request_id={} {}\n", timestamp, level, id, args),
+        None => format!("{} {} {}\n", timestamp, level, args),
+    };
+    // A single write keeps lines from concurrent connections intact.
+    let _ = std::io::stderr().lock().write_all(line.as_bytes());
+}
+
+/// `log_error!("message {}", x)` or `log_error!(id: request_id, "message")`.
+#[macro_export]
+macro_rules! log_error {
+    (id: $id:expr, $($arg:tt)+) => { $crate::logging::log($crate::logging::Level::Error, $id, format_args!($($arg)+)) };
+    ($($arg:tt)+) => { $crate::logging::log($crate::logging::Level::Error, None, format_args!($($arg)+)) };
+}
+
+#[macro_export]
+macro_rules! log_warn {
+    (id: $id:expr, $($arg:tt)+) => { $crate::logging::log($crate::logging::Level::Warn, $id, format_args!($($arg)+)) };
+    ($($arg:tt)+) => { $crate::logging::log($crate::logging::Level::Warn, None, format_args!($($arg)+)) };
+}
+
+#[macro_export]
+macro_rules! log_info {
+    (id: $id:expr, $($arg:tt)+) => { $crate::logging::log($crate::logging::Level::Info, $id, format_args!($($arg)+)) };
+    ($($arg:tt)+) => { $crate::logging::log($crate::logging::Level::Info, None, format_args!($($arg)+)) };
+}
+
+#[macro_export]
+macro_rules! log_debug {
+    (id: $id:expr, $($arg:tt)+) => { $crate::logging::log($crate::logging::Level::Debug, $id, format_args!($($arg)+)) };
+    ($($arg:tt)+) => { $crate::logging::log($crate::logging::Level::Debug, None, format_args!($($arg)+)) };
+}
@@ -1,3 +1,18 @@
-fn main(){
-    
-}
\ No newline at end of file
+use orion::config::Config;
+use orion::proxy::Server;
+
+fn main() {
+    // Usage: orion [config.toml]
+    let config = match std::env::args().nth(1) {
+        Some(path) => Config::from_file(&path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }),
+        None => Config::default(),
+    };
+
+    if let Err(e) = Server::new(config).and_then(|server| server.run()) {
+        eprintln!("Server error: {}", e);
+        std::process::exit(1);
+    }
+}
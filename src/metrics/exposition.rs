@@ -0,0 +1,98 @@
+// src/metrics/exposition.rs
+//
+// Prometheus text exposition format (version 0.0.4).
+
+use std::fmt::{Display, Write};
+
+use crate::metrics::types::{CounterVec, HistogramVec};
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+#[derive(Debug, Default)]
+pub struct TextEncoder {
+    out: String,
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_float(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+impl TextEncoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn header(&mut self, name: &str, help: &str, kind: &str) {
+        let _ = writeln!(self.out, "# HELP {} {}", name, help);
+        let _ = writeln!(self.out, "# TYPE {} {}", name, kind);
+    }
+
+    pub fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl Display) {
+        self.out.push_str(name);
+        if !labels.is_empty() {
+            self.out.push('{');
+            for (i, (key, value)) in labels.iter().enumerate() {
+                if i > 0 {
+                    self.out.push(',');
+                }
+                let _ = write!(self.out, "{}=\"{}\"", key, escape_label(value));
+            }
+            self.out.push('}');
+        }
+        let _ = writeln!(self.out, " {}", value);
+    }
+
+    pub fn counter_vec(&mut self, name: &str, help: &str, counter: &CounterVec) {
+        self.header(name, help, "counter");
+        for (values, count) in counter.snapshot() {
+            let labels: Vec<(&str, &str)> = counter
+                .label_names()
+                .iter()
+                .copied()
+                .zip(values.iter().map(String::as_str))
+                .collect();
+            self.sample(name, &labels, count);
+        }
+    }
+
+    pub fn histogram_vec(&mut self, name: &str, help: &str, histogram: &HistogramVec) {
+        self.header(name, help, "histogram");
+        let bucket_name = format!("{}_bucket", name);
+        for (values, data) in histogram.snapshot() {
+            let mut labels: Vec<(&str, &str)> = histogram
+                .label_names()
+                .iter()
+                .copied()
+                .zip(values.iter().map(String::as_str))
+                .collect();
+
+            for (bound, count) in histogram.bounds().iter().zip(&data.buckets) {
+                let le = format_float(*bound);
+                let mut bucket_labels = labels.clone();
+                bucket_labels.push(("le", &le));
+                self.sample(&bucket_name, &bucket_labels, count);
+            }
+            labels.push(("le", "+Inf"));
+            self.sample(&bucket_name, &labels, data.count);
+            labels.pop();
+
+            self.sample(&format!("{}_sum", name), &labels, data.sum);
+            self.sample(&format!("{}_count", name), &labels, data.count);
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
@@ -0,0 +1,185 @@
+// src/metrics/mod.rs
+//
+// Process-wide request metrics, rendered in the Prometheus text format by
+// the admin listener's `/metrics` endpoint.
+
+pub mod exposition;
+pub mod types;
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::http::HttpParseError;
+use crate::proxy::upstream::UpstreamPool;
+
+pub use exposition::TextEncoder;
+pub use types::{CounterVec, Gauge, HistogramVec, LATENCY_BUCKETS};
+
+#[derive(Debug)]
+pub struct Metrics {
+    pub requests: CounterVec,
+    pub request_duration: HistogramVec,
+    pub upstream_duration: HistogramVec,
+    pub parse_errors: CounterVec,
+    pub active_connections: Gauge,
+    pub idle_connections: Gauge,
+    pub worker_connections: CounterVec,
+    pub worker_requests: CounterVec,
+    pub cache_requests: CounterVec,
+    pub rate_limited: CounterVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// `"2xx"`, `"4xx"`, ...
+pub fn status_class(code: u16) -> &'static str {
+    match code {
+        100..=199 => "1xx",
+        200..=299 => "2xx",
+        300..=399 => "3xx",
+        400..=499 => "4xx",
+        _ => "5xx",
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self {
+            requests: CounterVec::new(&["route", "method", "status_class"]),
+            request_duration: HistogramVec::new(&["route"], LATENCY_BUCKETS),
+            upstream_duration: HistogramVec::new(&["upstream", "backend"], LATENCY_BUCKETS),
+            parse_errors: CounterVec::new(&["kind"]),
+            active_connections: Gauge::default(),
+            idle_connections: Gauge::default(),
+            worker_connections: CounterVec::new(&["worker"]),
+            worker_requests: CounterVec::new(&["worker"]),
+            cache_requests: CounterVec::new(&["route", "result"]),
+            rate_limited: CounterVec::new(&["route"]),
+        }
+    }
+
+    pub fn record_request(&self, route: &str, method: &str, status: u16, total: Duration) {
+        self.requests.inc(&[route, method, status_class(status)]);
+        self.request_duration.observe(&[route], total.as_secs_f64());
+    }
+
+    pub fn record_upstream(&self, upstream: &str, backend: &str, latency: Duration) {
+        self.upstream_duration
+            .observe(&[upstream, backend], latency.as_secs_f64());
+    }
+
+    pub fn record_parse_error(&self, error: &HttpParseError) {
+        self.parse_errors.inc(&[error.kind()]);
+    }
+
+    pub fn render(&self, upstreams: &[Arc<UpstreamPool>]) -> String {
+        let mut encoder = TextEncoder::new();
+
+        encoder.counter_vec(
+            "orion_requests_total",
+            "Requests handled, by route, method and status class.",
+            &self.requests,
+        );
+        encoder.histogram_vec(
+            "orion_request_duration_seconds",
+            "Time from reading a request to writing its response.",
+            &self.request_duration,
+        );
+        encoder.histogram_vec(
+            "orion_upstream_duration_seconds",
+            "Time spent waiting on upstream servers.",
+            &self.upstream_duration,
+        );
+        encoder.counter_vec(
+            "orion_parse_errors_total",
+            "HTTP messages that failed to parse, by error kind.",
+            &self.parse_errors,
+        );
+
+        encoder.header(
+            "orion_active_connections",
+            "Client connections currently open.",
+            "gauge",
+        );
+        encoder.sample("orion_active_connections", &[], self.active_connections.get());
+
+        encoder.header(
+            "orion_idle_connections",
+            "Keep-alive client connections parked between requests.",
+            "gauge",
+        );
+        encoder.sample("orion_idle_connections", &[], self.idle_connections.get());
+
+        encoder.counter_vec(
+            "orion_worker_connections_total",
+            "Client connections accepted, by worker.",
+            &self.worker_connections,
+        );
+        encoder.counter_vec(
+            "orion_worker_requests_total",
+            "Requests handled, by worker.",
+            &self.worker_requests,
+        );
+        encoder.counter_vec(
+            "orion_cache_requests_total",
+            "Requests on cached routes, by route and cache result.",
+            &self.cache_requests,
+        );
+        encoder.counter_vec(
+            "orion_rate_limited_requests_total",
+            "Requests refused by a rate limit, by route.",
+            &self.rate_limited,
+        );
+
+        encoder.header(
+            "orion_upstream_connections_in_use",
+            "Connections currently open to each backend.",
+            "gauge",
+        );
+        for pool in upstreams {
+            for backend in &pool.backends {
+                encoder.sample(
+                    "orion_upstream_connections_in_use",
+                    &[("upstream", &pool.name), ("backend", &backend.address)],
+                    backend.in_use(),
+                );
+            }
+        }
+
+        encoder.header(
+            "orion_upstream_connections_max",
+            "Connection limit for each backend.",
+            "gauge",
+        );
+        for pool in upstreams {
+            for backend in &pool.backends {
+                encoder.sample(
+                    "orion_upstream_connections_max",
+                    &[("upstream", &pool.name), ("backend", &backend.address)],
+                    backend.max_connections(),
+                );
+            }
+        }
+
+        encoder.header(
+            "orion_backend_healthy",
+            "Health check state of each backend (1 healthy, 0 unhealthy).",
+            "gauge",
+        );
+        for pool in upstreams {
+            for backend in &pool.backends {
+                encoder.sample(
+                    "orion_backend_healthy",
+                    &[("upstream", &pool.name), ("backend", &backend.address)],
+                    u8::from(backend.is_healthy()),
+                );
+            }
+        }
+
+        encoder.finish()
+    }
+}
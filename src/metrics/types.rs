@@ -0,0 +1,121 @@
+// src/metrics/types.rs
+//
+// Metric families keyed by label values. Label sets are small and bounded
+// (routes, methods, status classes), so a mutex-guarded map is plenty.
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicI64, Ordering};
+
+/// Prometheus' default buckets, in seconds.
+pub const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+#[derive(Debug)]
+pub struct CounterVec {
+    label_names: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl CounterVec {
+    pub fn new(label_names: &'static [&'static str]) -> Self {
+        Self {
+            label_names,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn inc(&self, labels: &[&str]) {
+        self.add(labels, 1);
+    }
+
+    pub fn add(&self, labels: &[&str], amount: u64) {
+        debug_assert_eq!(labels.len(), self.label_names.len());
+        let key = labels.iter().map(|l| l.to_string()).collect();
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        *values.entry(key).or_insert(0) += amount;
+    }
+
+    pub fn label_names(&self) -> &'static [&'static str] {
+        self.label_names
+    }
+
+    pub fn snapshot(&self) -> Vec<(Vec<String>, u64)> {
+        let values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        values.iter().map(|(k, v)| (k.clone(), *v)).collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HistogramData {
+    /// Cumulative count per bucket upper bound, as exposed by Prometheus.
+    pub buckets: Vec<u64>,
+    pub sum: f64,
+    pub count: u64,
+}
+
+#[derive(Debug)]
+pub struct HistogramVec {
+    label_names: &'static [&'static str],
+    bounds: &'static [f64],
+    values: Mutex<BTreeMap<Vec<String>, HistogramData>>,
+}
+
+impl HistogramVec {
+    pub fn new(label_names: &'static [&'static str], bounds: &'static [f64]) -> Self {
+        Self {
+            label_names,
+            bounds,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn observe(&self, labels: &[&str], value: f64) {
+        debug_assert_eq!(labels.len(), self.label_names.len());
+        let key = labels.iter().map(|l| l.to_string()).collect();
+        let mut values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        let data = values.entry(key).or_insert_with(|| HistogramData {
+            buckets: vec![0; self.bounds.len()],
+            sum: 0.0,
+            count: 0,
+        });
+        for (bound, bucket) in self.bounds.iter().zip(data.buckets.iter_mut()) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        data.sum += value;
+        data.count += 1;
+    }
+
+    pub fn label_names(&self) -> &'static [&'static str] {
+        self.label_names
+    }
+
+    pub fn bounds(&self) -> &'static [f64] {
+        self.bounds
+    }
+
+    pub fn snapshot(&self) -> Vec<(Vec<String>, HistogramData)> {
+        let values = self.values.lock().unwrap_or_else(|e| e.into_inner());
+        values.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
@@ -0,0 +1,332 @@
+// src/proxy/cache/disk.rs
+//
+// The disk tier: one file per key, holding all its variants, named by a
+// hash of the key. Files survive restarts; the index is rebuilt at startup
+// from the files' keys, oldest modification first, and is bounded the same
+// way as the memory store.
+//
+// Entries are written with a temporary name and renamed into place, so a
+// crash leaves either the old file or the new one. Unreadable files are
+// removed rather than served.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicU64;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::DiskCacheConfig;
+use crate::http::{HttpHeaders, HttpStatus};
+use crate::log_warn;
+use crate::proxy::cache::Entry;
+use crate::proxy::cache::policy::CacheControl;
+use crate::util::lru::Lru;
+
+const MAGIC: &[u8; 4] = b"ORC1";
+const EXTENSION: &str = "entry";
+
+#[derive(Debug)]
+pub struct Disk {
+    dir: PathBuf,
+    index: Mutex<Lru<()>>,
+}
+
+impl Disk {
+    pub fn open(config: &DiskCacheConfig) -> io::Result<Self> {
+        fs::create_dir_all(&config.path)?;
+        let mut files = Vec::new();
+        for file in fs::read_dir(&config.path)? {
+            let path = file?.path();
+            if path.extension().is_none_or(|ext| ext != EXTENSION) {
+                // Left over from an interrupted write.
+                if path.extension().is_some_and(|ext| ext == "tmp") {
+                    let _ = fs::remove_file(&path);
+                }
+                continue;
+            }
+            // Gone since it was listed, say removed by a purge.
+            let Ok(metadata) = fs::metadata(&path) else { continue };
+            let modified = metadata.modified().unwrap_or(UNIX_EPOCH);
+            match read_key(&path) {
+                Ok(key) => files.push((modified, key, metadata.len())),
+                Err(e) => {
+                    log_warn!("Removing unreadable cache file {}: {}", path.display(), e);
+                    let _ = fs::remove_file(&path);
+                }
+            }
+        }
+        files.sort();
+
+        let disk = Self {
+            dir: config.path.clone(),
+            index: Mutex::new(Lru::new(config.max_size)),
+        };
+        for (_, key, size) in files {
+            disk.track(key, size);
+        }
+        Ok(disk)
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{:016x}.{}", fnv1a64(key.as_bytes()), EXTENSION))
+    }
+
+    /// Add `key` to the index, deleting the files of whatever it evicts.
+    /// Returns false when `key` itself did not fit.
+    fn track(&self, key: String, size: u64) -> bool {
+        let evicted = self
+            .index
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key.clone(), (), size);
+        let mut fits = true;
+        for (evicted, ()) in evicted {
+            fits &= evicted != key;
+            let _ = fs::remove_file(self.path(&evicted));
+        }
+        fits
+    }
+
+    pub fn store<E: AsRef<Entry>>(&self, key: &str, variants: &[E]) -> io::Result<()> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        put_str(&mut bytes, key);
+        put_u32(&mut bytes, variants.len() as u32);
+        for entry in variants {
+            encode(&mut bytes, entry.as_ref());
+        }
+        if !self.track(key.to_string(), bytes.len() as u64) {
+            return Ok(());
+        }
+        let path = self.path(key);
+        let tmp = path.with_extension("tmp");
+        let mut file = fs::File::create(&tmp)?;
+        file.write_all(&bytes)?;
+        // On disk before it is renamed, or a crash could leave the new name
+        // on an empty file.
+        file.sync_all()?;
+        fs::rename(&tmp, &path)
+    }
+
+    /// Take the variants of `key` off the disk, to be held in memory.
+    pub fn load(&self, key: &str) -> Option<Vec<Entry>> {
+        self.index.lock().unwrap_or_else(|e| e.into_inner()).remove(key)?;
+        let path = self.path(key);
+        let variants = self.read(key);
+        let _ = fs::remove_file(&path);
+        variants
+    }
+
+    /// Read the variants of `key`, leaving them on disk.
+    pub fn peek(&self, key: &str) -> Option<Vec<Entry>> {
+        self.index.lock().unwrap_or_else(|e| e.into_inner()).peek(key)?;
+        self.read(key)
+    }
+
+    fn read(&self, key: &str) -> Option<Vec<Entry>> {
+        let path = self.path(key);
+        match fs::read(&path).and_then(|bytes| decode(&bytes)) {
+            // Two keys with the same hash share a file; the last stored wins.
+            Ok((stored, variants)) if stored == key => Some(variants),
+            Ok(_) => None,
+            Err(e) => {
+                log_warn!("Discarding unreadable cache file {}: {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    pub fn remove(&self, key: &str) -> bool {
+        let removed = self.index.lock().unwrap_or_else(|e| e.into_inner()).remove(key).is_some();
+        if removed {
+            let _ = fs::remove_file(self.path(key));
+        }
+        removed
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        let index = self.index.lock().unwrap_or_else(|e| e.into_inner());
+        index.iter().map(|(key, ())| key.clone()).collect()
+    }
+}
+
+fn fnv1a64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn put_u32(bytes: &mut Vec<u8>, value: u32) {
+    bytes.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_u64(bytes: &mut Vec<u8>, value: u64) {
+    bytes.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_str(bytes: &mut Vec<u8>, value: &str) {
+    put_u32(bytes, value.len() as u32);
+    bytes.extend_from_slice(value.as_bytes());
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+fn encode(bytes: &mut Vec<u8>, entry: &Entry) {
+    put_u32(bytes, u32::from(entry.status.code()));
+    put_u64(bytes, millis(entry.response_time.duration_since(UNIX_EPOCH).unwrap_or_default()));
+    put_u64(bytes, millis(entry.initial_age));
+    put_u64(bytes, millis(entry.lifetime));
+    put_u64(bytes, entry.hits.load(std::sync::atomic::Ordering::Relaxed));
+    put_u32(bytes, entry.vary.len() as u32);
+    for (field, value) in &entry.vary {
+        put_str(bytes, field);
+        put_str(bytes, value);
+    }
+    let headers: Vec<_> = entry.headers.iter().collect();
+    put_u32(bytes, headers.len() as u32);
+    for (name, value) in headers {
+        put_str(bytes, name);
+        put_str(bytes, value);
+    }
+    put_u64(bytes, entry.body.len() as u64);
+    bytes.extend_from_slice(&entry.body);
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> io::Result<&[u8]> {
+        if self.bytes.len() < n {
+            return Err(invalid("truncated"));
+        }
+        let (taken, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| invalid("invalid UTF-8"))
+    }
+
+    fn header(&mut self) -> io::Result<String> {
+        if self.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("not a cache file"));
+        }
+        self.string()
+    }
+}
+
+fn read_key(path: &std::path::Path) -> io::Result<String> {
+    // The key comes first; no need to read the body.
+    let mut file = fs::File::open(path)?;
+    let mut head = [0u8; 8];
+    file.read_exact(&mut head)?;
+    let len = u32::from_be_bytes(head[4..8].try_into().unwrap()) as usize;
+    if (8 + len) as u64 > file.metadata()?.len() {
+        return Err(invalid("truncated"));
+    }
+    let mut bytes = head.to_vec();
+    bytes.resize(8 + len, 0);
+    file.read_exact(&mut bytes[8..])?;
+    Reader { bytes: &bytes }.header()
+}
+
+fn decode(bytes: &[u8]) -> io::Result<(String, Vec<Entry>)> {
+    let mut reader = Reader { bytes };
+    let key = reader.header()?;
+    let count = reader.u32()?;
+    let mut variants = Vec::new();
+    for _ in 0..count {
+        let status = u16::try_from(reader.u32()?)
+            .ok()
+            .and_then(HttpStatus::from_code)
+            .ok_or_else(|| invalid("unknown status"))?;
+        let response_time = UNIX_EPOCH + Duration::from_millis(reader.u64()?);
+        let initial_age = Duration::from_millis(reader.u64()?);
+        let lifetime = Duration::from_millis(reader.u64()?);
+        let hits = reader.u64()?;
+        let mut vary = Vec::new();
+        for _ in 0..reader.u32()? {
+            vary.push((reader.string()?, reader.string()?));
+        }
+        let mut headers = HttpHeaders::new();
+        for _ in 0..reader.u32()? {
+            let (name, value) = (reader.string()?, reader.string()?);
+            headers.insert(name, value);
+        }
+        let len = reader.u64()? as usize;
+        let body = reader.take(len)?.to_vec();
+        variants.push(Entry {
+            key: key.clone(),
+            status,
+            control: CacheControl::parse(&headers),
+            headers,
+            body,
+            vary,
+            response_time: response_time.min(SystemTime::now()),
+            initial_age,
+            lifetime,
+            hits: AtomicU64::new(hits),
+        });
+    }
+    Ok((key, variants))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn config(name: &str) -> DiskCacheConfig {
+        let path = std::env::temp_dir().join(format!("orion-disk-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        DiskCacheConfig { path, max_size: 1 << 20 }
+    }
+
+    #[test]
+    fn keys_are_indexed_again_on_open() {
+        let config = config("reopen");
+        let disk = Disk::open(&config).unwrap();
+        disk.store::<Arc<Entry>>("GET http://test/a", &[]).unwrap();
+        disk.store::<Arc<Entry>>("GET http://test/b", &[]).unwrap();
+        drop(disk);
+
+        let mut keys = Disk::open(&config).unwrap().keys();
+        keys.sort();
+        assert_eq!(keys, ["GET http://test/a", "GET http://test/b"]);
+        let _ = fs::remove_dir_all(&config.path);
+    }
+
+    #[test]
+    fn files_with_a_bad_key_length_are_removed_on_open() {
+        let config = config("corrupt");
+        fs::create_dir_all(&config.path).unwrap();
+        let huge = config.path.join("huge.entry");
+        fs::write(&huge, [&MAGIC[..], &[0xff; 4], b"key"].concat()).unwrap();
+        let short = config.path.join("short.entry");
+        fs::write(&short, [&MAGIC[..], &[0, 0, 0, 4], b"key"].concat()).unwrap();
+
+        let disk = Disk::open(&config).unwrap();
+        assert!(disk.keys().is_empty());
+        assert!(!huge.exists() && !short.exists());
+        let _ = fs::remove_dir_all(&config.path);
+    }
+}
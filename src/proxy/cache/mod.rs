@@ -0,0 +1,612 @@
+// src/proxy/cache/mod.rs
+//
+// The response cache: stored responses by key, each key holding one entry
+// per variant its responses `Vary` on. Entries live in memory, bounded by
+// size with least-recently-used eviction, and when a disk path is
+// configured, entries evicted from memory move there and come back on use.
+//
+// Deciding whether a stored response can be used (and revalidating it when
+// it cannot) is the server's job; the rules are in `policy`.
+//
+// Concurrent misses on one key are collapsed: the first request for it
+// leads, going to the upstream, while the others wait for it to store the
+// response and are served from the cache. Followers go to the upstream on
+// their own when the leader's response cannot be stored, or fails, or takes
+// longer than the coalescing timeout.
+
+pub mod disk;
+pub mod policy;
+
+use std::borrow::Borrow;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, SystemTime};
+
+use crate::config::{CacheConfig, CacheKeyPart};
+use crate::http::body::Body;
+use crate::http::util::format_http_date;
+use crate::http::{HttpHeaders, HttpRequest, HttpResponse, HttpStatus, HttpVersion};
+use crate::log_warn;
+use crate::proxy::cache::disk::Disk;
+use crate::proxy::cache::policy::CacheControl;
+use crate::util::lru::Lru;
+
+/// Headers describing the stored response rather than the representation;
+/// a 304 does not replace them (RFC 9111 §3.2).
+const NOT_UPDATED_BY_304: [&str; 4] = ["content-length", "content-encoding", "content-range", "transfer-encoding"];
+
+/// Headers about the connection the response arrived on.
+const HOP_BY_HOP: [&str; 6] = ["connection", "keep-alive", "proxy-connection", "trailer", "transfer-encoding", "upgrade"];
+
+/// The cache key of `req` for a route keyed on `parts`. With the default
+/// parts it reads like the request URL.
+pub fn key(parts: &[CacheKeyPart], req: &HttpRequest) -> String {
+    let (path, query) = req.path.split_once('?').unwrap_or((req.path.as_str(), ""));
+    let mut key = String::new();
+    for part in parts {
+        match part {
+            CacheKeyPart::Scheme => {
+                key.push_str(req.scheme());
+                key.push_str("://");
+            }
+            CacheKeyPart::Host => {
+                let host = req.headers.get("host").map_or(req.origin.0.as_str(), |h| h.as_str());
+                key.push_str(&host.to_ascii_lowercase());
+            }
+            CacheKeyPart::Path => key.push_str(path),
+            CacheKeyPart::Query if !query.is_empty() => {
+                key.push('?');
+                key.push_str(query);
+            }
+            CacheKeyPart::Query => {}
+            CacheKeyPart::Header(name) => {
+                let value = req.headers.get(name).map_or("", |v| v.trim());
+                key.push_str(&format!(" {}={}", name, value));
+            }
+            CacheKeyPart::Cookie(name) => {
+                let value = req
+                    .headers
+                    .get("cookie")
+                    .and_then(|cookies| {
+                        cookies
+                            .split(';')
+                            .filter_map(|cookie| cookie.trim().split_once('='))
+                            .find(|(cookie, _)| cookie == name)
+                            .map(|(_, value)| value.to_string())
+                    })
+                    .unwrap_or_default();
+                key.push_str(&format!(" cookie:{}={}", name, value));
+            }
+        }
+    }
+    key
+}
+
+/// A stored response.
+#[derive(Debug)]
+pub struct Entry {
+    pub key: String,
+    pub status: HttpStatus,
+    pub headers: HttpHeaders,
+    pub body: Vec<u8>,
+    /// The request's values for the fields the response varies on, `""`
+    /// when absent.
+    pub vary: Vec<(String, String)>,
+    /// When the response (or its last revalidation) arrived.
+    pub response_time: SystemTime,
+    /// Age on arrival (RFC 9111 §4.2.3).
+    pub initial_age: Duration,
+    pub lifetime: Duration,
+    pub control: CacheControl,
+    pub hits: AtomicU64,
+}
+
+impl Entry {
+    pub fn new(
+        key: String,
+        req: &HttpRequest,
+        response: &HttpResponse,
+        request_time: SystemTime,
+        response_time: SystemTime,
+    ) -> Self {
+        let vary = response
+            .headers
+            .get("vary")
+            .map(|vary| {
+                vary.split(',')
+                    .map(|field| field.trim().to_ascii_lowercase())
+                    .filter(|field| !field.is_empty())
+                    .map(|field| {
+                        let value = req.headers.get(&field).map_or("", |v| v.trim()).to_string();
+                        (field, value)
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+        let mut headers = response.headers.clone();
+        // The body is stored decoded and sent with a length.
+        for name in HOP_BY_HOP {
+            headers.remove(name);
+        }
+        if headers.get("date").is_none() {
+            headers.insert("Date".to_string(), format_http_date(response_time));
+        }
+        Self {
+            key,
+            status: response.status,
+            lifetime: policy::freshness_lifetime(response.status_code(), &headers, response_time),
+            initial_age: policy::initial_age(&headers, request_time, response_time),
+            control: CacheControl::parse(&headers),
+            headers,
+            body: Vec::new(),
+            vary,
+            response_time,
+            hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether this variant was selected by a request like `req`.
+    pub fn matches(&self, req: &HttpRequest) -> bool {
+        self.vary
+            .iter()
+            .all(|(field, value)| req.headers.get(field).map_or("", |v| v.trim()) == value)
+    }
+
+    pub fn age(&self, now: SystemTime) -> Duration {
+        self.initial_age + now.duration_since(self.response_time).unwrap_or_default()
+    }
+
+    /// How long past its freshness lifetime the entry is.
+    pub fn staleness(&self, now: SystemTime) -> Duration {
+        self.age(now).saturating_sub(self.lifetime)
+    }
+
+    /// May it be used without asking the upstream, given the request's own
+    /// directives (§4.2, §5.2.1)?
+    pub fn is_fresh(&self, now: SystemTime, request: &CacheControl) -> bool {
+        let age = self.age(now);
+        !self.control.no_cache && !request.no_cache && age < self.lifetime && request.max_age.is_none_or(|max| age <= max)
+    }
+
+    /// `stale-while-revalidate` (RFC 5861): may it be used while a
+    /// revalidation runs in the background?
+    pub fn usable_while_revalidating(&self, now: SystemTime) -> bool {
+        !self.control.must_revalidate
+            && !self.control.no_cache
+            && self
+                .control
+                .stale_while_revalidate
+                .is_some_and(|window| self.staleness(now) <= window)
+    }
+
+    /// `stale-if-error` (RFC 5861), from the response or the request: may
+    /// it be used when the upstream fails?
+    pub fn usable_on_error(&self, now: SystemTime, request: &CacheControl) -> bool {
+        !self.control.must_revalidate
+            && self
+                .control
+                .stale_if_error
+                .max(request.stale_if_error)
+                .is_some_and(|window| self.staleness(now) <= window)
+    }
+
+    pub fn etag(&self) -> Option<&str> {
+        self.headers.get("etag").map(|v| v.as_str())
+    }
+
+    /// Surrogate keys the upstream tagged the response with, for purging
+    /// related responses together.
+    pub fn tags(&self) -> impl Iterator<Item = &str> {
+        self.headers
+            .get("surrogate-key")
+            .map_or("", |tags| tags.as_str())
+            .split_ascii_whitespace()
+    }
+
+    pub fn last_modified(&self) -> Option<&str> {
+        self.headers.get("last-modified").map(|v| v.as_str())
+    }
+
+    /// Bytes the entry takes, roughly.
+    pub fn size(&self) -> u64 {
+        let fields: usize = self.headers.iter().map(|(k, v)| k.len() + v.len()).sum();
+        let vary: usize = self.vary.iter().map(|(k, v)| k.len() + v.len()).sum();
+        (self.key.len() + fields + vary + self.body.len()) as u64
+    }
+
+    /// The stored response, ready to send; without its body for HEAD.
+    pub fn response(&self, now: SystemTime, head_only: bool) -> HttpResponse {
+        let mut headers = self.headers.clone();
+        // Meant for this cache only.
+        headers.remove("surrogate-key");
+        headers.insert("Age".to_string(), self.age(now).as_secs().to_string());
+        if self.status != HttpStatus::NoContent {
+            headers.insert("Content-Length".to_string(), self.body.len().to_string());
+        }
+        HttpResponse {
+            version: HttpVersion::HTTP1_1,
+            status: self.status,
+            reason: None,
+            headers,
+            body: Body::Buffered(if head_only { Vec::new() } else { self.body.clone() }),
+            trailers: Default::default(),
+        }
+    }
+
+    /// The entry updated by a `304 Not Modified` (§4.3.4).
+    pub fn freshened(&self, not_modified: &HttpHeaders, request_time: SystemTime, response_time: SystemTime) -> Entry {
+        let mut headers = self.headers.clone();
+        for (name, value) in not_modified.iter() {
+            if !NOT_UPDATED_BY_304.contains(&name.as_str()) {
+                headers.insert(name.clone(), value.clone());
+            }
+        }
+        Entry {
+            key: self.key.clone(),
+            status: self.status,
+            lifetime: policy::freshness_lifetime(self.status.code(), &headers, response_time),
+            initial_age: policy::initial_age(not_modified, request_time, response_time),
+            control: CacheControl::parse(&headers),
+            headers,
+            body: self.body.clone(),
+            vary: self.vary.clone(),
+            response_time,
+            hits: AtomicU64::new(self.hits.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+type Variants = Vec<Arc<Entry>>;
+
+/// Which stored responses a purge drops.
+#[derive(Debug, Clone)]
+pub enum Purge {
+    Key(String),
+    Prefix(String),
+    /// Responses tagged with any of these surrogate keys.
+    Tags(Vec<String>),
+}
+
+impl Purge {
+    fn matches_key(&self, key: &str) -> bool {
+        match self {
+            Purge::Key(purged) => key == purged,
+            Purge::Prefix(prefix) => key.starts_with(prefix.as_str()),
+            Purge::Tags(_) => true,
+        }
+    }
+
+    fn matches(&self, variants: &[impl Borrow<Entry>]) -> bool {
+        match self {
+            Purge::Tags(tags) => variants
+                .iter()
+                .any(|entry| entry.borrow().tags().any(|tag| tags.iter().any(|t| t == tag))),
+            _ => true,
+        }
+    }
+}
+
+/// Where a stored response is kept.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tier {
+    Memory,
+    Disk,
+}
+
+impl std::fmt::Display for Tier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Tier::Memory => "memory",
+            Tier::Disk => "disk",
+        })
+    }
+}
+
+pub struct Cache {
+    max_entry_size: u64,
+    memory: Mutex<Lru<Variants>>,
+    disk: Option<Disk>,
+    /// Keys with a background revalidation under way.
+    revalidating: Mutex<HashSet<String>>,
+    /// Keys with a leading request under way, when coalescing.
+    flights: Option<Mutex<HashMap<String, Arc<Flight>>>>,
+}
+
+/// One leading request, waited on by the requests collapsed into it.
+#[derive(Debug, Default)]
+pub struct Flight {
+    landed: Mutex<bool>,
+    signal: Condvar,
+}
+
+impl Flight {
+    /// Wait up to `timeout` for the leader; false when it took too long.
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let landed = self.landed.lock().unwrap_or_else(|e| e.into_inner());
+        let (landed, _) = self
+            .signal
+            .wait_timeout_while(landed, timeout, |landed| !*landed)
+            .unwrap_or_else(|e| e.into_inner());
+        *landed
+    }
+}
+
+/// The leader's claim on a key. Dropping it, once the response is stored
+/// or cannot be, releases the followers.
+#[derive(Debug)]
+pub struct Lead {
+    cache: Arc<Cache>,
+    key: String,
+    flight: Arc<Flight>,
+}
+
+impl Drop for Lead {
+    fn drop(&mut self) {
+        if let Some(flights) = &self.cache.flights {
+            flights.lock().unwrap_or_else(|e| e.into_inner()).remove(&self.key);
+        }
+        *self.flight.landed.lock().unwrap_or_else(|e| e.into_inner()) = true;
+        self.flight.signal.notify_all();
+    }
+}
+
+/// A request's part in coalescing.
+pub enum Join {
+    Lead(Lead),
+    Follow(Arc<Flight>),
+    /// Coalescing is off.
+    Alone,
+}
+
+impl std::fmt::Debug for Cache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let memory = self.memory.lock().unwrap_or_else(|e| e.into_inner());
+        f.debug_struct("Cache")
+            .field("keys", &memory.len())
+            .field("size", &memory.size())
+            .field("disk", &self.disk.is_some())
+            .finish()
+    }
+}
+
+impl Cache {
+    pub fn new(config: &CacheConfig) -> io::Result<Self> {
+        Ok(Self {
+            max_entry_size: config.max_entry_size,
+            memory: Mutex::new(Lru::new(config.max_size)),
+            disk: config.disk.as_ref().map(Disk::open).transpose()?,
+            revalidating: Mutex::new(HashSet::new()),
+            flights: config.coalesce.then(|| Mutex::new(HashMap::new())),
+        })
+    }
+
+    /// The stored variant of `key` that `req` selects, if any.
+    pub fn lookup(&self, key: &str, req: &HttpRequest) -> Option<Arc<Entry>> {
+        {
+            let mut memory = self.memory.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(variants) = memory.get(key) {
+                return variants.iter().find(|entry| entry.matches(req)).cloned();
+            }
+        }
+        // Entries come back into memory from disk when used.
+        let variants: Variants = self.disk.as_ref()?.load(key)?.into_iter().map(Arc::new).collect();
+        let found = variants.iter().find(|entry| entry.matches(req)).cloned();
+        self.put(key.to_string(), variants);
+        found
+    }
+
+    /// Store `entry`, replacing the variant it stands for.
+    pub fn insert(&self, entry: Arc<Entry>) {
+        if entry.body.len() as u64 > self.max_entry_size {
+            return;
+        }
+        let key = entry.key.clone();
+        let mut variants = {
+            let mut memory = self.memory.lock().unwrap_or_else(|e| e.into_inner());
+            memory.remove(&key).unwrap_or_default()
+        };
+        if variants.is_empty()
+            && let Some(disk) = &self.disk
+        {
+            disk.remove(&key);
+        }
+        variants.retain(|stored| stored.vary != entry.vary);
+        variants.push(entry);
+        self.put(key, variants);
+    }
+
+    /// Drop everything stored under `key`.
+    pub fn remove(&self, key: &str) -> bool {
+        let removed = self.memory.lock().unwrap_or_else(|e| e.into_inner()).remove(key).is_some();
+        let removed_from_disk = self.disk.as_ref().is_some_and(|disk| disk.remove(key));
+        removed || removed_from_disk
+    }
+
+    /// Drop the keys `purge` selects, in memory and on disk. Returns how
+    /// many keys were dropped.
+    pub fn purge(&self, purge: &Purge) -> usize {
+        let mut purged = 0;
+        {
+            let mut memory = self.memory.lock().unwrap_or_else(|e| e.into_inner());
+            let keys: Vec<String> = memory
+                .iter()
+                .filter(|(key, variants)| purge.matches_key(key) && purge.matches(variants))
+                .map(|(key, _)| key.clone())
+                .collect();
+            for key in keys {
+                memory.remove(&key);
+                purged += 1;
+            }
+        }
+        if let Some(disk) = &self.disk {
+            for key in disk.keys() {
+                // Tags are only known by reading the entry.
+                let selected = purge.matches_key(&key)
+                    && (!matches!(purge, Purge::Tags(_)) || disk.peek(&key).is_some_and(|v| purge.matches(&v)));
+                if selected && disk.remove(&key) {
+                    purged += 1;
+                }
+            }
+        }
+        purged
+    }
+
+    /// Every stored response, with where it is kept.
+    pub fn entries(&self) -> Vec<(Tier, Arc<Entry>)> {
+        let mut entries: Vec<(Tier, Arc<Entry>)> = {
+            let memory = self.memory.lock().unwrap_or_else(|e| e.into_inner());
+            memory
+                .iter()
+                .flat_map(|(_, variants)| variants.iter().map(|entry| (Tier::Memory, Arc::clone(entry))))
+                .collect()
+        };
+        if let Some(disk) = &self.disk {
+            for key in disk.keys() {
+                let variants = disk.peek(&key).unwrap_or_default();
+                entries.extend(variants.into_iter().map(|entry| (Tier::Disk, Arc::new(entry))));
+            }
+        }
+        entries.sort_by(|(_, a), (_, b)| a.key.cmp(&b.key));
+        entries
+    }
+
+    fn put(&self, key: String, variants: Variants) {
+        let size = variants.iter().map(|entry| entry.size()).sum();
+        let evicted = self
+            .memory
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key, variants, size);
+        if let Some(disk) = &self.disk {
+            for (key, variants) in evicted {
+                if let Err(e) = disk.store(&key, &variants) {
+                    log_warn!("Unable to move cache entry {} to disk: {}", key, e);
+                }
+            }
+        }
+    }
+
+    /// Claim the background revalidation of `key`; false when one is
+    /// already running.
+    pub fn start_revalidation(&self, key: &str) -> bool {
+        self.revalidating
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key.to_string())
+    }
+
+    pub fn finish_revalidation(&self, key: &str) {
+        self.revalidating.lock().unwrap_or_else(|e| e.into_inner()).remove(key);
+    }
+
+    /// Lead the requests for `key`, or follow the request already leading.
+    pub fn join(self: &Arc<Self>, key: &str) -> Join {
+        let Some(flights) = &self.flights else {
+            return Join::Alone;
+        };
+        let mut flights = flights.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(flight) = flights.get(key) {
+            return Join::Follow(Arc::clone(flight));
+        }
+        let flight = Arc::new(Flight::default());
+        flights.insert(key.to_string(), Arc::clone(&flight));
+        Join::Lead(Lead {
+            cache: Arc::clone(self),
+            key: key.to_string(),
+            flight,
+        })
+    }
+
+    /// Arrange for `response` to `req` to be stored under `key` if it may
+    /// be: at once for a buffered body, or as a streamed body is read to its
+    /// end. Returns whether it will be. `lead` is released once it is
+    /// stored, or as soon as it is known it will not be.
+    pub fn record(
+        self: &Arc<Self>,
+        key: String,
+        req: &HttpRequest,
+        request_time: SystemTime,
+        response: &mut HttpResponse,
+        lead: Option<Lead>,
+    ) -> bool {
+        if !policy::is_storable(req, response.status_code(), &response.headers) {
+            return false;
+        }
+        let length = response.headers.get("content-length").and_then(|l| l.trim().parse::<u64>().ok());
+        if length.is_some_and(|length| length > self.max_entry_size) {
+            return false;
+        }
+        let mut entry = Entry::new(key, req, response, request_time, SystemTime::now());
+        match std::mem::take(&mut response.body) {
+            Body::Buffered(bytes) => {
+                entry.body = bytes.clone();
+                response.body = Body::Buffered(bytes);
+                self.insert(Arc::new(entry));
+            }
+            Body::Stream(stream) => {
+                response.body = Body::Stream(Box::new(Recorder {
+                    stream,
+                    pending: Some(Pending {
+                        cache: Arc::clone(self),
+                        entry,
+                        _lead: lead,
+                    }),
+                    limit: self.max_entry_size,
+                    length,
+                }));
+            }
+        }
+        true
+    }
+}
+
+/// A response body passed through to the client and stored once it has
+/// been read to its end, unless it outgrows the entry size limit first.
+struct Recorder {
+    stream: Box<dyn Read + Send>,
+    pending: Option<Pending>,
+    limit: u64,
+    /// The `Content-Length`, if any. A body that reaches it is stored right
+    /// away, before the client could have read all of it and come back.
+    length: Option<u64>,
+}
+
+struct Pending {
+    cache: Arc<Cache>,
+    entry: Entry,
+    _lead: Option<Lead>,
+}
+
+impl Read for Recorder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match self.stream.read(buf) {
+            Ok(n) => n,
+            Err(e) => {
+                self.pending = None;
+                return Err(e);
+            }
+        };
+        if let Some(pending) = &mut self.pending
+            && n > 0
+        {
+            if (pending.entry.body.len() + n) as u64 > self.limit {
+                self.pending = None;
+            } else {
+                pending.entry.body.extend_from_slice(&buf[..n]);
+            }
+        }
+        let complete = match &self.pending {
+            Some(pending) => match self.length {
+                Some(length) => pending.entry.body.len() as u64 >= length,
+                None => n == 0 && !buf.is_empty(),
+            },
+            None => false,
+        };
+        if complete && let Some(pending) = self.pending.take() {
+            pending.cache.insert(Arc::new(pending.entry));
+        }
+        Ok(n)
+    }
+}
@@ -0,0 +1,172 @@
+// src/proxy/cache/policy.rs
+//
+// RFC 9111 as it applies to a shared cache: which responses may be stored,
+// how long they stay fresh, and when a stale one may still be used.
+
+use std::time::{Duration, SystemTime};
+
+use crate::http::util::parse_http_date;
+use crate::http::{HttpHeaders, HttpMethod, HttpRequest};
+
+/// Heuristic freshness (§4.2.2) is never longer than this.
+const MAX_HEURISTIC_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Statuses that may be stored without explicit freshness (§3, RFC 9110
+/// §15.1), excluding 206, which would need range support.
+const HEURISTICALLY_CACHEABLE: [u16; 11] = [200, 203, 204, 300, 301, 308, 404, 405, 410, 414, 501];
+
+/// `Cache-Control` directives of a request or response. Directives with
+/// field names (`no-cache="set-cookie"`) are treated as unqualified, which
+/// RFC 9111 allows.
+#[derive(Debug, Clone, Default)]
+pub struct CacheControl {
+    pub no_store: bool,
+    pub no_cache: bool,
+    pub private: bool,
+    pub public: bool,
+    /// `must-revalidate` or `proxy-revalidate`: never used stale.
+    pub must_revalidate: bool,
+    pub only_if_cached: bool,
+    pub max_age: Option<Duration>,
+    pub s_maxage: Option<Duration>,
+    pub stale_while_revalidate: Option<Duration>,
+    pub stale_if_error: Option<Duration>,
+}
+
+impl CacheControl {
+    pub fn parse(headers: &HttpHeaders) -> Self {
+        let mut control = Self::default();
+        let Some(value) = headers.get("cache-control") else {
+            // HTTP/1.0 caches understood only this.
+            control.no_cache = headers.has_token("pragma", "no-cache");
+            return control;
+        };
+        for directive in split_directives(value) {
+            let (name, argument) = match directive.split_once('=') {
+                Some((name, argument)) => (name.trim(), Some(argument.trim().trim_matches('"'))),
+                None => (directive.trim(), None),
+            };
+            let seconds = || argument.and_then(|a| a.parse::<u64>().ok()).map(Duration::from_secs);
+            match name.to_ascii_lowercase().as_str() {
+                "no-store" => control.no_store = true,
+                "no-cache" => control.no_cache = true,
+                "private" => control.private = true,
+                "public" => control.public = true,
+                "must-revalidate" | "proxy-revalidate" => control.must_revalidate = true,
+                "only-if-cached" => control.only_if_cached = true,
+                "max-age" => control.max_age = seconds(),
+                "s-maxage" => control.s_maxage = seconds(),
+                "stale-while-revalidate" => control.stale_while_revalidate = seconds(),
+                "stale-if-error" => control.stale_if_error = seconds(),
+                _ => {}
+            }
+        }
+        control
+    }
+}
+
+/// Split on commas outside quoted strings.
+fn split_directives(value: &str) -> Vec<&str> {
+    let mut directives = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                directives.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    directives.push(&value[start..]);
+    directives.into_iter().filter(|d| !d.trim().is_empty()).collect()
+}
+
+/// §3: may a shared cache store this response to `req`?
+pub fn is_storable(req: &HttpRequest, status: u16, headers: &HttpHeaders) -> bool {
+    let request = CacheControl::parse(&req.headers);
+    let response = CacheControl::parse(headers);
+    if req.method != HttpMethod::GET || request.no_store || response.no_store || response.private {
+        return false;
+    }
+    // A 304 only updates what is already stored.
+    if !(200..600).contains(&status) || status == 206 || status == 304 {
+        return false;
+    }
+    // Shared caches may reuse responses to authenticated requests only
+    // when told they can (§3.5).
+    if req.headers.get("authorization").is_some()
+        && !(response.public || response.s_maxage.is_some() || response.must_revalidate)
+    {
+        return false;
+    }
+    if headers.get("vary").is_some_and(|vary| vary.split(',').any(|f| f.trim() == "*")) {
+        return false;
+    }
+    // One client's cookies must not be handed to another.
+    if headers.get("set-cookie").is_some() {
+        return false;
+    }
+    response.public
+        || response.max_age.is_some()
+        || response.s_maxage.is_some()
+        || headers.get("expires").is_some()
+        || HEURISTICALLY_CACHEABLE.contains(&status)
+}
+
+/// §4.2.1: how long a response stays fresh, measured from its generation.
+pub fn freshness_lifetime(status: u16, headers: &HttpHeaders, response_time: SystemTime) -> Duration {
+    let control = CacheControl::parse(headers);
+    if let Some(lifetime) = control.s_maxage.or(control.max_age) {
+        return lifetime;
+    }
+    let date = date(headers).unwrap_or(response_time);
+    if let Some(expires) = headers.get("expires") {
+        // An invalid date means already expired.
+        return parse_http_date(expires)
+            .and_then(|expires| expires.duration_since(date).ok())
+            .unwrap_or_default();
+    }
+    // §4.2.2: a tenth of the time since the last modification.
+    match headers.get("last-modified").and_then(|value| parse_http_date(value)) {
+        Some(modified) if HEURISTICALLY_CACHEABLE.contains(&status) => date
+            .duration_since(modified)
+            .map(|age| (age / 10).min(MAX_HEURISTIC_LIFETIME))
+            .unwrap_or_default(),
+        _ => Duration::ZERO,
+    }
+}
+
+/// §4.2.3: the response's age when it was received.
+pub fn initial_age(headers: &HttpHeaders, request_time: SystemTime, response_time: SystemTime) -> Duration {
+    let apparent_age = date(headers)
+        .and_then(|date| response_time.duration_since(date).ok())
+        .unwrap_or_default();
+    let age_value = headers
+        .get("age")
+        .and_then(|age| age.trim().parse::<u64>().ok())
+        .map(Duration::from_secs)
+        .unwrap_or_default();
+    let response_delay = response_time.duration_since(request_time).unwrap_or_default();
+    apparent_age.max(age_value + response_delay)
+}
+
+fn date(headers: &HttpHeaders) -> Option<SystemTime> {
+    headers.get("date").and_then(|value| parse_http_date(value))
+}
+
+/// RFC 9110 §13.1.2: does `If-None-Match` match `etag`? Weak comparison.
+pub fn none_match(if_none_match: &str, etag: &str) -> bool {
+    let opaque = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+    if_none_match.trim() == "*" || if_none_match.split(',').any(|tag| opaque(tag) == opaque(etag))
+}
+
+/// RFC 9110 §13.1.3: has the resource not changed since `If-Modified-Since`?
+pub fn not_modified_since(if_modified_since: &str, last_modified: &str) -> bool {
+    match (parse_http_date(if_modified_since), parse_http_date(last_modified)) {
+        (Some(since), Some(modified)) => modified <= since,
+        _ => false,
+    }
+}
@@ -0,0 +1,180 @@
+// src/proxy/compression.rs
+//
+// Compressing responses on their way to the client. Buffered bodies are
+// compressed at once and keep a `Content-Length`; streamed ones are
+// compressed as they are read and lose it, going out chunked (or, to
+// HTTP/1.0 clients, delimited by the close). Responses that are already
+// encoded, partial, or marked `no-transform` pass through untouched.
+//
+// Routes can also have encoded request bodies decoded before they are
+// forwarded, for upstreams that cannot, and encoded responses decoded for
+// clients that do not accept their coding.
+
+use std::io::{Cursor, Read};
+
+use crate::config::CompressionConfig;
+use crate::http::body::Framing;
+use crate::http::coding::{self, ContentCoding, Decoded, Encoded};
+use crate::http::{Body, HttpLimits, HttpMethod, HttpRequest, HttpResponse, HttpStatus};
+use crate::log_warn;
+
+/// Compress `response` to `req` if the configuration and the client allow.
+pub fn compress(config: &CompressionConfig, req: &HttpRequest, response: &mut HttpResponse) {
+    if !eligible(config, response) {
+        return;
+    }
+    // Whether or not this client gets it compressed, others might.
+    vary_on_accept_encoding(response);
+    let Some(coding) = req
+        .headers
+        .get("accept-encoding")
+        .and_then(|accept| coding::negotiate(accept, &config.algorithms))
+    else {
+        return;
+    };
+
+    match std::mem::take(&mut response.body) {
+        // The length of the compressed body is not known without the body.
+        _ if req.method == HttpMethod::HEAD => {
+            response.headers.remove("content-length");
+        }
+        Body::Buffered(bytes) => match coding.encode(&bytes, config.level) {
+            Ok(encoded) => {
+                response
+                    .headers
+                    .insert("Content-Length".to_string(), encoded.len().to_string());
+                response.body = Body::Buffered(encoded);
+            }
+            Err(e) => {
+                log_warn!(id: req.request_id(), "Unable to {} compress a response: {}", coding, e);
+                response.body = Body::Buffered(bytes);
+                return;
+            }
+        },
+        Body::Stream(stream) => {
+            response.headers.remove("content-length");
+            response.body = Body::Stream(Box::new(Encoded::new(stream, coding, config.level)));
+        }
+    }
+    response
+        .headers
+        .insert("Content-Encoding".to_string(), coding.name().to_string());
+    weaken_etag(response);
+}
+
+fn eligible(config: &CompressionConfig, response: &HttpResponse) -> bool {
+    let status = response.status;
+    if status.is_informational()
+        || matches!(status, HttpStatus::NoContent | HttpStatus::NotModified | HttpStatus::PartialContent)
+    {
+        return false;
+    }
+    let headers = &response.headers;
+    if headers
+        .get("content-encoding")
+        .is_some_and(|encoding| !encoding.trim().eq_ignore_ascii_case("identity"))
+        || headers.has_token("cache-control", "no-transform")
+        || !headers.get("content-type").is_some_and(|t| config.compresses(t))
+    {
+        return false;
+    }
+    let length = match headers.get("content-length").and_then(|l| l.trim().parse::<u64>().ok()) {
+        Some(length) => Some(length),
+        None => response.body.as_bytes().map(|bytes| bytes.len() as u64),
+    };
+    length.is_none_or(|length| length >= config.min_size)
+}
+
+/// The request to forward in place of `req` when its body is encoded: the
+/// same request without `Content-Encoding`, its body of unknown length
+/// until decoded, along with the codings to remove (see `decoded_body`).
+/// `Ok(None)` when there is nothing to decode; `Err` names a coding the
+/// proxy does not know.
+pub fn decode_request(req: &HttpRequest) -> Result<Option<(HttpRequest, Vec<ContentCoding>)>, String> {
+    let Some(encoding) = req.headers.get("content-encoding") else {
+        return Ok(None);
+    };
+    let codings = coding::parse_list(encoding)
+        .map_err(|name| format!("Unsupported request Content-Encoding '{}'", name))?;
+    if codings.is_empty() || Framing::of_request(&req.headers).is_ok_and(|framing| framing == Framing::Empty) {
+        return Ok(None);
+    }
+    let mut decoded = req.clone();
+    decoded.headers.remove("content-encoding");
+    decoded.headers.remove("content-length");
+    decoded
+        .headers
+        .insert("Transfer-Encoding".to_string(), "chunked".to_string());
+    Ok(Some((decoded, codings)))
+}
+
+/// `body` with `codings` removed, last applied first. Each layer may decode
+/// to at most `MAX_BODY_SIZE` bytes.
+pub fn decoded_body<'a>(body: &'a mut dyn Read, codings: &[ContentCoding]) -> Box<dyn Read + 'a> {
+    let limit = HttpLimits::MAX_BODY_SIZE as u64;
+    let mut body: Box<dyn Read + 'a> = Box::new(body);
+    for coding in codings.iter().rev() {
+        body = Box::new(Decoded::new(body, *coding, limit));
+    }
+    body
+}
+
+/// Decode an encoded `response` when `req` does not accept its coding. A
+/// request without `Accept-Encoding` is taken to accept none, as most
+/// clients that leave it out cannot decode anything.
+pub fn decompress(req: &HttpRequest, response: &mut HttpResponse) {
+    if response.status == HttpStatus::PartialContent || response.headers.has_token("cache-control", "no-transform") {
+        return;
+    }
+    let Some(Ok(codings)) = response.headers.get("content-encoding").map(|e| coding::parse_list(e)) else {
+        return;
+    };
+    if codings.is_empty() {
+        return;
+    }
+    vary_on_accept_encoding(response);
+    let accept = req.headers.get("accept-encoding").map_or("", |accept| accept.as_str());
+    if codings
+        .iter()
+        .all(|coding| coding::negotiate(accept, &[*coding]).is_some())
+    {
+        return;
+    }
+
+    response.headers.remove("content-encoding");
+    response.headers.remove("content-length");
+    weaken_etag(response);
+    let mut body: Box<dyn Read + Send> = match std::mem::take(&mut response.body) {
+        Body::Buffered(bytes) if bytes.is_empty() => return,
+        Body::Buffered(bytes) => Box::new(Cursor::new(bytes)),
+        Body::Stream(stream) => stream,
+    };
+    // Streamed to the client as it is decoded, so there is no need to
+    // bound it.
+    for coding in codings.iter().rev() {
+        body = Box::new(Decoded::new(body, *coding, u64::MAX));
+    }
+    response.body = Body::Stream(body);
+}
+
+/// Mark `response` as depending on `Accept-Encoding`.
+fn vary_on_accept_encoding(response: &mut HttpResponse) {
+    match response.headers.get("vary") {
+        Some(vary) if vary.split(',').any(|f| f.trim() == "*" || f.trim().eq_ignore_ascii_case("accept-encoding")) => {}
+        Some(vary) => {
+            let vary = format!("{}, Accept-Encoding", vary);
+            response.headers.insert("Vary".to_string(), vary);
+        }
+        None => response.headers.insert("Vary".to_string(), "Accept-Encoding".to_string()),
+    }
+}
+
+/// The re-encoded bytes differ, so a strong validator no longer holds.
+fn weaken_etag(response: &mut HttpResponse) {
+    if let Some(etag) = response.headers.get("etag")
+        && etag.starts_with('"')
+    {
+        let weak = format!("W/{}", etag);
+        response.headers.insert("ETag".to_string(), weak);
+    }
+}
@@ -0,0 +1,329 @@
+// src/proxy/connection.rs
+//
+// Reading HTTP/1.x requests off a client socket. Shared by the proxy and
+// admin listeners. Request bodies are not read with the head: they stream
+// from the connection through `Connection::body`.
+//
+// `Expect: 100-continue` is answered here rather than upstream: the head
+// has been checked against `HttpLimits` by the time it is returned, and
+// `100 Continue` goes out when something first reads the body. A request
+// answered without reading it leaves the client's body unsent, so the
+// connection is not reused.
+
+use std::io::{self, BufRead, Read, Write};
+use std::net::TcpStream;
+
+use rustls::{ServerConnection, StreamOwned};
+
+use crate::http::body::{BodyError, COPY_BUFFER_SIZE, Decoder, Framing};
+use crate::http::util::parser::find_head_end;
+use crate::http::{HttpLimits, HttpParseError, HttpRequest, HttpResponse, HttpStatus, HttpVersion, h2, parse_http_request};
+
+pub enum ReadError {
+    Closed, // EOF, timeout or I/O error: nothing useful can be sent back
+    Parse(HttpParseError),
+    Rejected(HttpStatus, String),
+}
+
+/// A client socket, either plain TCP or TLS terminated by the proxy.
+pub enum ClientStream {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ServerConnection, TcpStream>>),
+}
+
+impl ClientStream {
+    /// The underlying socket, for addresses, timeouts and shutdown.
+    pub fn tcp(&self) -> &TcpStream {
+        match self {
+            ClientStream::Plain(stream) => stream,
+            ClientStream::Tls(tls) => &tls.sock,
+        }
+    }
+
+    pub fn is_secure(&self) -> bool {
+        matches!(self, ClientStream::Tls(_))
+    }
+
+    /// True when the client picked HTTP/2 through ALPN.
+    pub fn negotiated_h2(&self) -> bool {
+        match self {
+            ClientStream::Plain(_) => false,
+            ClientStream::Tls(tls) => tls.conn.alpn_protocol() == Some(b"h2"),
+        }
+    }
+
+    /// True when data already read off the socket is waiting to be returned,
+    /// so the socket itself may never become readable for it.
+    pub fn has_buffered_data(&mut self) -> bool {
+        match self {
+            ClientStream::Plain(_) => false,
+            ClientStream::Tls(tls) => tls
+                .conn
+                .process_new_packets()
+                .is_ok_and(|state| state.plaintext_bytes_to_read() > 0),
+        }
+    }
+}
+
+impl Drop for ClientStream {
+    /// Tell TLS clients the response is complete rather than truncated.
+    fn drop(&mut self) {
+        if let ClientStream::Tls(tls) = self {
+            tls.conn.send_close_notify();
+            let _ = tls.conn.complete_io(&mut tls.sock);
+        }
+    }
+}
+
+impl From<TcpStream> for ClientStream {
+    fn from(stream: TcpStream) -> Self {
+        ClientStream::Plain(stream)
+    }
+}
+
+impl Read for ClientStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Plain(stream) => stream.read(buf),
+            ClientStream::Tls(tls) => tls.read(buf),
+        }
+    }
+}
+
+impl Write for ClientStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            ClientStream::Plain(stream) => stream.write(buf),
+            ClientStream::Tls(tls) => tls.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            ClientStream::Plain(stream) => stream.flush(),
+            ClientStream::Tls(tls) => tls.flush(),
+        }
+    }
+}
+
+/// A client connection with whatever bytes were read past the previous request.
+pub struct Connection {
+    pub stream: ClientStream,
+    pub buffer: Vec<u8>,
+    body: Decoder,
+    /// The client expects `100 Continue` before it sends the body.
+    expects_continue: bool,
+}
+
+impl Connection {
+    pub fn new(stream: impl Into<ClientStream>) -> Self {
+        Self {
+            stream: stream.into(),
+            buffer: Vec::new(),
+            body: Decoder::finished(),
+            expects_continue: false,
+        }
+    }
+
+    fn fill(&mut self) -> Result<(), ReadError> {
+        let mut chunk = [0u8; 8192];
+        match self.stream.read(&mut chunk) {
+            Ok(0) => Err(ReadError::Closed),
+            Ok(n) => {
+                self.buffer.extend_from_slice(&chunk[..n]);
+                Ok(())
+            }
+            Err(_) => Err(ReadError::Closed),
+        }
+    }
+
+    /// Read the first bytes of a new connection to see whether they are the
+    /// HTTP/2 connection preface, sent by clients that know the server
+    /// speaks h2c. Stops reading as soon as they cannot be.
+    pub fn starts_with_h2_preface(&mut self) -> bool {
+        let preface = &h2::frame::PREFACE[..];
+        loop {
+            let len = self.buffer.len().min(preface.len());
+            if self.buffer[..len] != preface[..len] {
+                return false;
+            }
+            if len == preface.len() {
+                return true;
+            }
+            if self.fill().is_err() {
+                return false;
+            }
+        }
+    }
+
+    /// The stream and whatever was read past the last request, for
+    /// switching the connection to another protocol.
+    pub fn into_parts(self) -> (ClientStream, Vec<u8>) {
+        (self.stream, self.buffer)
+    }
+
+    /// Read the next request head, returning it with the number of bytes it
+    /// used. Whatever the previous request left of its body is discarded
+    /// first.
+    pub fn read_request(&mut self) -> Result<(HttpRequest, usize), ReadError> {
+        if self.expects_continue {
+            // The client may still be waiting to send the last body.
+            return Err(ReadError::Closed);
+        }
+        if !self.body.is_done() {
+            io::copy(&mut self.body(), &mut io::sink()).map_err(|_| ReadError::Closed)?;
+        }
+
+        let head_end = loop {
+            if let Some(end) = find_head_end(&self.buffer) {
+                break end;
+            }
+            if self.buffer.len() > HttpLimits::MAX_HEAD_SIZE {
+                return Err(ReadError::Rejected(
+                    HttpStatus::BadRequest,
+                    "Request header section too large".to_string(),
+                ));
+            }
+            self.fill()?;
+        };
+
+        let head = std::str::from_utf8(&self.buffer[..head_end + 4]).map_err(|_| {
+            ReadError::Parse(HttpParseError::MalformedRequest("Request head is not valid UTF-8".to_string()))
+        })?;
+        let mut req = parse_http_request(head).map_err(ReadError::Parse)?;
+        if req.version == HttpVersion::HTTP2_0 {
+            return Err(ReadError::Rejected(
+                HttpStatus::HttpVersionNotSupported,
+                "HTTP/2 requests must use HTTP/2 framing".to_string(),
+            ));
+        }
+        req.secure = self.stream.is_secure();
+        req.body = None;
+
+        let framing = Framing::of_request(&req.headers).map_err(|e| ReadError::Rejected(e.status(), e.to_string()))?;
+        let limit = HttpLimits::MAX_BODY_SIZE as u64;
+        if let Framing::Length(length) = framing
+            && length > limit
+        {
+            return Err(ReadError::Rejected(
+                HttpStatus::PayloadTooLarge,
+                BodyError::TooLarge(limit).to_string(),
+            ));
+        }
+
+        // HTTP/1.0 clients cannot expect anything (RFC 9110 §10.1.1).
+        if req.version == HttpVersion::HTTP1_1
+            && let Some(expect) = req.headers.remove("expect")
+        {
+            if !expect.trim().eq_ignore_ascii_case("100-continue") {
+                return Err(ReadError::Rejected(
+                    HttpStatus::ExpectationFailed,
+                    format!("Unsupported expectation '{}'", expect),
+                ));
+            }
+            self.expects_continue = framing != Framing::Empty;
+        }
+
+        let head_len = head_end + 4;
+        self.buffer.drain(..head_len);
+        self.body = Decoder::new(framing, limit);
+        Ok((req, head_len))
+    }
+
+    /// The body of the request last returned by `read_request`, decoded as
+    /// it is read. Errors carry a `BodyError` when the body itself is at
+    /// fault (too large, badly chunked).
+    pub fn body(&mut self) -> RequestBody<'_> {
+        RequestBody {
+            source: Source {
+                stream: &mut self.stream,
+                buffer: &mut self.buffer,
+                expects_continue: &mut self.expects_continue,
+            },
+            decoder: &mut self.body,
+        }
+    }
+
+    /// True when the client was never told to send the body it announced
+    /// with `Expect: 100-continue`; the connection cannot be reused.
+    pub fn awaits_continue(&self) -> bool {
+        self.expects_continue
+    }
+
+    /// Read the whole body of the current request into memory.
+    pub fn read_body(&mut self) -> Result<Vec<u8>, ReadError> {
+        let mut body = Vec::new();
+        self.body().read_to_end(&mut body).map_err(|e| match BodyError::from_io(&e) {
+            Some(error) => ReadError::Rejected(error.status(), error.to_string()),
+            None => ReadError::Closed,
+        })?;
+        Ok(body)
+    }
+
+    /// True when nothing of the next request has been read yet: the
+    /// connection can wait for the socket to become readable.
+    pub fn is_idle(&mut self) -> bool {
+        self.buffer.is_empty() && !self.stream.has_buffered_data()
+    }
+
+    /// Bytes of the current request body read off the wire so far.
+    pub fn body_bytes_read(&self) -> usize {
+        self.body.bytes_consumed() as usize
+    }
+
+    /// True once reading the current request body has failed; the
+    /// connection cannot be reused.
+    pub fn body_failed(&self) -> bool {
+        self.body.has_failed()
+    }
+}
+
+/// A streaming request body; see `Connection::body`.
+pub struct RequestBody<'a> {
+    source: Source<'a>,
+    decoder: &'a mut Decoder,
+}
+
+impl Read for RequestBody<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.decoder.read(&mut self.source, buf)
+    }
+}
+
+/// The connection's read buffer in front of its stream.
+struct Source<'a> {
+    stream: &'a mut ClientStream,
+    buffer: &'a mut Vec<u8>,
+    expects_continue: &'a mut bool,
+}
+
+impl Read for Source<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.fill_buf()?;
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        self.consume(n);
+        Ok(n)
+    }
+}
+
+impl BufRead for Source<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if *self.expects_continue {
+            *self.expects_continue = false;
+            let interim = HttpResponse::informational(HttpStatus::Continue);
+            self.stream.write_all(&interim.head_to_bytes())?;
+            self.stream.flush()?;
+        }
+        if self.buffer.is_empty() {
+            let mut chunk = [0u8; COPY_BUFFER_SIZE];
+            let n = self.stream.read(&mut chunk)?;
+            self.buffer.extend_from_slice(&chunk[..n]);
+        }
+        Ok(self.buffer)
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.buffer.drain(..amount);
+    }
+}
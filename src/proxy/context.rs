@@ -0,0 +1,70 @@
+// src/proxy/context.rs
+
+use std::io;
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::logging::AccessLog;
+use crate::metrics::Metrics;
+use crate::proxy::cache::Cache;
+use crate::proxy::rate_limit::RateLimiter;
+use crate::proxy::router::Router;
+use crate::proxy::shutdown::Shutdown;
+use crate::proxy::tls::TlsAcceptor;
+use crate::proxy::upstream::UpstreamPool;
+
+/// State shared by every connection handler.
+pub struct ProxyContext {
+    pub config: Config,
+    pub access_log: Option<AccessLog>,
+    pub upstreams: Vec<Arc<UpstreamPool>>,
+    pub router: Router,
+    pub metrics: Metrics,
+    pub shutdown: Arc<Shutdown>,
+    /// Handshake settings for the HTTPS listener.
+    pub tls: Option<Arc<TlsAcceptor>>,
+    /// Stored responses, when any route is cached.
+    pub cache: Option<Arc<Cache>>,
+    /// Token buckets, when any route is rate limited.
+    pub rate_limiter: Option<RateLimiter>,
+}
+
+impl ProxyContext {
+    pub fn new(config: Config) -> io::Result<Self> {
+        let access_log = if config.access_log.enabled {
+            Some(AccessLog::open(
+                config.access_log.format.clone(),
+                config.access_log.target.clone(),
+            )?)
+        } else {
+            None
+        };
+
+        let upstreams: Vec<Arc<UpstreamPool>> = config
+            .upstreams
+            .iter()
+            .map(|upstream| UpstreamPool::new(upstream).map(Arc::new))
+            .collect::<io::Result<_>>()?;
+        let router = Router::new(&config.routes, &upstreams);
+        let tls = config
+            .tls
+            .as_ref()
+            .map(|tls| TlsAcceptor::new(tls, config.server.http2))
+            .transpose()?
+            .map(Arc::new);
+        let cache = config.cache.as_ref().map(Cache::new).transpose()?.map(Arc::new);
+        let rate_limiter = config.rate_limit.as_ref().map(RateLimiter::new);
+
+        Ok(Self {
+            config,
+            access_log,
+            upstreams,
+            router,
+            metrics: Metrics::new(),
+            shutdown: Arc::new(Shutdown::new()),
+            tls,
+            cache,
+            rate_limiter,
+        })
+    }
+}
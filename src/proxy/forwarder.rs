@@ -1,26 +1,368 @@
 // src/proxy/forwader.rs
 
-use std::net::{TcpListener, TcpStream};
-use std::io::{Read, Write};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::IpAddr;
+use std::time::Duration;
 
-use crate::enums::{HttpRequest, HttpResponse};
+use crate::http::body::{self, Body, BodyError, BodyReader, COPY_BUFFER_SIZE, CopyError, Decoder, Framing};
+use crate::http::h2::ErrorCode;
+use crate::http::util::parser::find_head_end;
+use crate::http::{
+    HttpHeaders, HttpLimits, HttpParseError, HttpRequest, HttpResponse, HttpStatus, HttpVersion, parse_http_response,
+};
+use crate::proxy::h2;
+use crate::proxy::transport::{Connector, Transport};
+use crate::proxy::upstream::Backend;
 
-pub fn forward_to_upstream(req: &HttpRequest) -> Result<HttpResponse, HttpResponse> {
-    let (host, port) = ("127.0.0.1", 8081); // Default upstream server
-    let mut upstream = TcpStream::connect((host, port))
-    .map_err(|e| HttpResponse::new(500, format!("Bad Gateway {}", e)))?;
+/// Connection-level headers that must not be forwarded (RFC 9110 §7.6.1)
+const HOP_BY_HOP_HEADERS: [&str; 7] = [
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
 
-    upstream.write_all(req.to_string().as_bytes())
-    .map_err(|e| HttpResponse::new(500, format!("Bad Gateway {}", e)))?;
+#[derive(Debug)]
+pub enum ForwardError {
+    Resolve(String),
+    Connect(io::Error),
+    Tls(io::Error),
+    Io(io::Error),
+    InvalidResponse(HttpParseError),
+    /// The client's request body could not be read.
+    RequestBody(io::Error),
+}
+
+impl ForwardError {
+    pub fn parse_error(&self) -> Option<&HttpParseError> {
+        match self {
+            ForwardError::InvalidResponse(e) => Some(e),
+            _ => None,
+        }
+    }
 
+    /// The status to answer the client with instead of 502, when the fault
+    /// is with the client's request rather than the upstream.
+    pub fn client_status(&self) -> Option<HttpStatus> {
+        match self {
+            ForwardError::RequestBody(e) => Some(BodyError::from_io(e).map_or(HttpStatus::BadRequest, BodyError::status)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for ForwardError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ForwardError::Resolve(address) => write!(f, "Unable to resolve upstream {}", address),
+            ForwardError::Connect(e) => write!(f, "Unable to connect to upstream: {}", e),
+            ForwardError::Tls(e) => write!(f, "TLS handshake with upstream failed: {}", e),
+            ForwardError::Io(e) => write!(f, "Upstream I/O error: {}", e),
+            ForwardError::InvalidResponse(e) => write!(f, "Invalid response from upstream: {}", e),
+            ForwardError::RequestBody(e) => write!(f, "Unable to read request body: {}", e),
+        }
+    }
+}
 
-    let mut response_buffer = Vec::new();
-    upstream.read_to_end(&mut response_buffer)
-        .map_err(|e| HttpResponse::new(502, format!("Bad Gateway {}", e)))?;
+impl std::error::Error for ForwardError {}
+
+/// Send `req` to `address` and return the upstream's response with its body
+/// still streaming from the upstream connection. The request body is read
+/// from `body` as it is sent, framed by the request's own headers.
+pub fn forward_to_upstream(
+    req: &HttpRequest,
+    body: &mut dyn Read,
+    address: &str,
+    connector: &Connector,
+    timeout: Duration,
+    client: IpAddr,
+) -> Result<HttpResponse, ForwardError> {
+    let mut upstream = connector.connect(address, timeout)?;
+    send_request(req, body, &mut upstream, client, None)?;
+    let (response, upstream) = receive_head(upstream)?;
+    if response.status == HttpStatus::SwitchingProtocols {
+        return Err(ForwardError::InvalidResponse(HttpParseError::MalformedResponse(
+            "101 Switching Protocols without an upgrade request".to_string(),
+        )));
+    }
+    finish_response(req, response, upstream)
+}
+
+/// An upstream connection that switched protocols, with whatever the
+/// upstream sent past its `101` response head.
+pub struct Upgraded {
+    pub transport: Box<dyn Transport>,
+    pub buffered: Vec<u8>,
+}
 
-    let response_string = String::from_utf8_lossy(&response_buffer).to_string();
-    print!("response_string: {}", response_string);
+/// Forward an upgrade request (see `HttpRequest::upgrade`). When the
+/// upstream answers `101 Switching Protocols` for the requested protocol,
+/// the connection is returned for tunneling along with the response;
+/// otherwise the response is an ordinary one.
+pub fn forward_upgrade(
+    req: &HttpRequest,
+    body: &mut dyn Read,
+    address: &str,
+    connector: &Connector,
+    timeout: Duration,
+    client: IpAddr,
+) -> Result<(HttpResponse, Option<Upgraded>), ForwardError> {
+    let protocol = req.upgrade().unwrap_or_default();
+    let mut upstream = connector.connect(address, timeout)?;
+    send_request(req, body, &mut upstream, client, Some(protocol))?;
+    let (mut response, upstream) = receive_head(upstream)?;
+    if response.status != HttpStatus::SwitchingProtocols {
+        return Ok((finish_response(req, response, upstream)?, None));
+    }
 
+    let accepted = response.headers.get("upgrade").cloned().unwrap_or_default();
+    if !accepted.trim().eq_ignore_ascii_case(protocol) {
+        return Err(ForwardError::InvalidResponse(HttpParseError::MalformedResponse(format!(
+            "101 Switching Protocols to '{}', '{}' was requested",
+            accepted, protocol
+        ))));
+    }
+    for header in HOP_BY_HOP_HEADERS {
+        response.headers.remove(header);
+    }
+    response
+        .headers
+        .insert("Connection".to_string(), "Upgrade".to_string());
+    response.headers.insert("Upgrade".to_string(), accepted);
+    response.body = Body::empty();
+
+    let buffered = upstream.buffer().to_vec();
+    let transport = upstream.into_inner().0;
+    Ok((response, Some(Upgraded { transport, buffered })))
+}
+
+/// Write the request head with proxy headers, then its body. `upgrade`
+/// carries the protocol of an upgrade request; other requests ask the
+/// upstream to close the connection after responding.
+fn send_request(
+    req: &HttpRequest,
+    body: &mut dyn Read,
+    upstream: &mut Box<dyn Transport>,
+    client: IpAddr,
+    upgrade: Option<&str>,
+) -> Result<(), ForwardError> {
+    let mut upstream_req = req.clone();
+    // This connection speaks HTTP/1.1 whatever the client used.
+    upstream_req.version = HttpVersion::HTTP1_1;
+    upstream_req.headers = proxy_headers(req, client);
+    match upgrade {
+        Some(protocol) => {
+            upstream_req
+                .headers
+                .insert("Connection".to_string(), "Upgrade".to_string());
+            upstream_req
+                .headers
+                .insert("Upgrade".to_string(), protocol.to_string());
+        }
+        // One request per upstream connection.
+        None => {
+            upstream_req
+                .headers
+                .insert("Connection".to_string(), "close".to_string());
+        }
+    }
+
+    let chunked = body::is_chunked(&req.headers);
+    if chunked {
+        upstream_req
+            .headers
+            .insert("Transfer-Encoding".to_string(), "chunked".to_string());
+    }
+
+    upstream
+        .write_all(&upstream_req.head_to_bytes())
+        .map_err(ForwardError::Io)?;
+    body::copy_body(body, upstream, chunked, None).map_err(|e| match e {
+        CopyError::Read(e) => ForwardError::RequestBody(e),
+        CopyError::Write(e) => ForwardError::Io(e),
+    })?;
+    Ok(())
+}
+
+/// The request's end-to-end headers, with what the proxy adds.
+fn proxy_headers(req: &HttpRequest, client: IpAddr) -> HttpHeaders {
+    let mut headers = req.headers.clone();
+    for header in HOP_BY_HOP_HEADERS {
+        headers.remove(header);
+    }
+    let forwarded_for = match req.headers.get("x-forwarded-for") {
+        Some(existing) => format!("{}, {}", existing, client),
+        None => client.to_string(),
+    };
+    headers.insert("X-Forwarded-For".to_string(), forwarded_for);
+    headers.insert("X-Forwarded-Proto".to_string(), req.scheme().to_string());
+    // Only the proxy vouches for client certificates; never pass on a
+    // client's own claim.
+    headers.remove("x-client-cert-subject");
+    headers.remove("x-client-cert-san");
+    if let Some(cert) = &req.client_cert {
+        headers.insert("X-Client-Cert-Subject".to_string(), cert.subject.clone());
+        if !cert.sans.is_empty() {
+            headers.insert("X-Client-Cert-San".to_string(), cert.sans.join(", "));
+        }
+    }
+    headers
+}
+
+/// Send `req` on a stream of one of `backend`'s HTTP/2 connections and
+/// return the response, its body and trailers still streaming in.
+pub fn forward_http2(
+    req: &HttpRequest,
+    body: &mut dyn Read,
+    backend: &Backend,
+    connector: &Connector,
+    timeout: Duration,
+    client: IpAddr,
+) -> Result<HttpResponse, ForwardError> {
+    let framing = Framing::of_request(&req.headers).map_err(|e| ForwardError::RequestBody(e.into()))?;
+    let has_body = framing != Framing::Empty;
+    let method = req.method.to_string();
+    let scheme = if connector.is_tls() { "https" } else { "http" };
+    let authority = req.headers.get("host").map_or(backend.address.as_str(), |host| host.as_str());
+    let headers = proxy_headers(req, client);
+    let mut fields = vec![
+        (":method", method.as_str()),
+        (":scheme", scheme),
+        (":authority", authority),
+        (":path", req.path.as_str()),
+    ];
+    fields.extend(
+        headers
+            .iter()
+            .filter(|(name, _)| *name != "host")
+            .map(|(name, value)| (name.as_str(), value.as_str())),
+    );
+    // gRPC servers insist on it.
+    if req.headers.has_token("te", "trailers") {
+        fields.push(("te", "trailers"));
+    }
+
+    let stream = backend.h2.open(connector, &backend.address, timeout, &fields, !has_body)?;
+    if has_body {
+        let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+        loop {
+            let n = match body.read(&mut buffer) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    stream.reset(ErrorCode::CANCEL);
+                    return Err(ForwardError::RequestBody(e));
+                }
+            };
+            stream.send_data(&buffer[..n], n == 0).map_err(ForwardError::Io)?;
+            if n == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut response = h2::client::receive_response(stream, &req.method)?;
+    if req.secure {
+        rewrite_location(req, &mut response);
+    }
+    Ok(response)
+}
+
+type UpstreamBuffer = BufReader<UpstreamReader>;
+
+/// Read and parse the response head, leaving the body on the connection.
+/// Interim responses other than `101 Switching Protocols` are skipped.
+fn receive_head(upstream: Box<dyn Transport>) -> Result<(HttpResponse, UpstreamBuffer), ForwardError> {
+    let mut upstream = BufReader::with_capacity(COPY_BUFFER_SIZE, UpstreamReader(upstream));
+    loop {
+        let head = read_head(&mut upstream)?;
+        let response = parse_http_response(&head).map_err(ForwardError::InvalidResponse)?;
+        if !response.status.is_informational() || response.status == HttpStatus::SwitchingProtocols {
+            return Ok((response, upstream));
+        }
+    }
+}
+
+/// Attach the body still on `upstream` to `response` and make the headers
+/// fit for the client.
+fn finish_response(req: &HttpRequest, mut response: HttpResponse, upstream: UpstreamBuffer) -> Result<HttpResponse, ForwardError> {
+    let framing = Framing::of_response(&req.method, response.status_code(), &response.headers)
+        .map_err(|e| ForwardError::InvalidResponse(HttpParseError::MalformedResponse(e.to_string())))?;
+    response.body = match framing {
+        Framing::Empty => Body::empty(),
+        framing => {
+            let decoder = Decoder::new(framing, u64::MAX).keep_trailers(response.trailers.clone());
+            Body::Stream(Box::new(BodyReader::new(upstream, decoder)))
+        }
+    };
+
+    for header in HOP_BY_HOP_HEADERS {
+        response.headers.remove(header);
+    }
+    if req.secure {
+        rewrite_location(req, &mut response);
+    }
+
+    Ok(response)
+}
+
+/// Read the response head, up to and including the blank line.
+fn read_head(upstream: &mut impl BufRead) -> Result<Vec<u8>, ForwardError> {
+    let mut head = Vec::new();
+    loop {
+        let data = upstream.fill_buf().map_err(ForwardError::Io)?;
+        if data.is_empty() {
+            // Let the parser say what is missing.
+            return Ok(head);
+        }
+        let searched = head.len().saturating_sub(3);
+        let read = data.len();
+        head.extend_from_slice(data);
+        if let Some(end) = find_head_end(&head[searched..]) {
+            let end = searched + end + 4;
+            upstream.consume(read - (head.len() - end));
+            head.truncate(end);
+            return Ok(head);
+        }
+        upstream.consume(read);
+        if head.len() > HttpLimits::MAX_HEAD_SIZE {
+            return Err(ForwardError::InvalidResponse(HttpParseError::MalformedResponse(
+                "Response header section too large".to_string(),
+            )));
+        }
+    }
+}
+
+/// Reads from an upstream connection. TLS servers that close without
+/// close_notify end the stream like plain TCP does; the body framing still
+/// catches a response that was cut short.
+struct UpstreamReader(Box<dyn Transport>);
+
+impl Read for UpstreamReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.0.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof && self.0.is_secure() => Ok(0),
+            result => result,
+        }
+    }
+}
 
-    Ok(HttpResponse::new(200, response_string).with_header("X-Forwarded-For", format!("{}:{}", host, port)) )
+/// Backends behind TLS termination see plain HTTP and may redirect to
+/// `http://` URLs for the client's own host; point those back at HTTPS.
+fn rewrite_location(req: &HttpRequest, response: &mut HttpResponse) {
+    let (Some(host), Some(location)) = (req.headers.get("host"), response.headers.get("location")) else {
+        return;
+    };
+    let Some(rest) = location.strip_prefix("http://") else {
+        return;
+    };
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    if authority.eq_ignore_ascii_case(host) {
+        let rewritten = format!("https://{}", rest);
+        response.headers.insert("Location".to_string(), rewritten);
+    }
 }
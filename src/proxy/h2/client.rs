@@ -0,0 +1,189 @@
+// src/proxy/h2/client.rs
+//
+// The client side of HTTP/2, for upstreams that speak it: a few connections
+// per server, each carrying many requests at once, and responses read off
+// streams as `HttpResponse`s whose body and trailers arrive as they come.
+
+use std::io::{self, Read};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::http::body::{Body, Trailers};
+use crate::http::h2::Header;
+use crate::http::{HttpHeaders, HttpMethod, HttpParseError, HttpResponse, HttpStatus, HttpVersion};
+use crate::log_debug;
+use crate::proxy::forwarder::ForwardError;
+use crate::proxy::h2::{Connection, Role, Stream};
+use crate::proxy::transport::Connector;
+
+/// How long an upstream connection is kept without any streams.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// HTTP/2 connections to one server, shared by the requests sent to it.
+#[derive(Default)]
+pub struct Connections {
+    open: Mutex<Vec<Connection>>,
+    /// Held while connecting, so requests arriving together share the new
+    /// connection instead of each opening one.
+    dialing: Mutex<()>,
+}
+
+impl Connections {
+    /// Open a stream with a request head on a connection that has room for
+    /// it, connecting anew only when every connection is full.
+    pub fn open(
+        &self,
+        connector: &Connector,
+        address: &str,
+        timeout: Duration,
+        headers: &[(&str, &str)],
+        end_stream: bool,
+    ) -> Result<Stream, ForwardError> {
+        if let Some(stream) = self.open_on_existing(headers, end_stream) {
+            return Ok(stream);
+        }
+        let _dialing = self.dialing.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(stream) = self.open_on_existing(headers, end_stream) {
+            return Ok(stream);
+        }
+
+        let conn = connect(connector, address, timeout, IDLE_TIMEOUT)?;
+        let stream = conn
+            .open(headers.iter().copied(), end_stream)
+            .map_err(ForwardError::Io)?;
+        self.open
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(conn);
+        Ok(stream)
+    }
+
+    fn open_on_existing(&self, headers: &[(&str, &str)], end_stream: bool) -> Option<Stream> {
+        let mut open = self.open.lock().unwrap_or_else(|e| e.into_inner());
+        open.retain(|conn| !conn.is_finished());
+        open.iter().find_map(|conn| conn.open(headers.iter().copied(), end_stream).ok())
+    }
+
+    /// Connections currently open.
+    pub fn count(&self) -> usize {
+        let open = self.open.lock().unwrap_or_else(|e| e.into_inner());
+        open.iter().filter(|conn| !conn.is_finished()).count()
+    }
+}
+
+impl std::fmt::Debug for Connections {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Connections({})", self.count())
+    }
+}
+
+/// Connect to `address` and drive the connection from a thread of its own
+/// until it has been idle for `idle_timeout`.
+fn connect(connector: &Connector, address: &str, timeout: Duration, idle_timeout: Duration) -> Result<Connection, ForwardError> {
+    let mut transport = connector.connect_h2(address, timeout)?;
+    let conn = Connection::new(Role::Client, timeout).map_err(ForwardError::Io)?;
+    let driver = conn.clone();
+    let address = address.to_string();
+    thread::spawn(move || {
+        let never = || false;
+        if let Err(e) = driver.run(&mut transport, Vec::new(), idle_timeout, &never, &mut |_, _, _| {}) {
+            log_debug!("HTTP/2 connection to upstream {} closed: {}", address, e);
+        }
+    });
+    Ok(conn)
+}
+
+/// Wait for the response on `stream`. Its body and trailers are read from
+/// the stream as the caller reads the body.
+pub fn receive_response(stream: Stream, method: &HttpMethod) -> Result<HttpResponse, ForwardError> {
+    let head = stream.head().map_err(ForwardError::Io)?;
+    let code = status(&head).ok_or_else(|| malformed("response without a valid :status".to_string()))?;
+    let status = HttpStatus::from_code(code).ok_or_else(|| malformed(format!("Invalid status code {}", code)))?;
+
+    let mut headers = fields(head);
+    let trailers = Trailers::default();
+    let body = if *method == HttpMethod::HEAD || code == 204 || code == 304 {
+        Body::empty()
+    } else if stream.is_drained() {
+        // The head ended the stream: gRPC's trailers-only responses, say.
+        if headers.get("content-length").is_none() {
+            headers.insert("content-length".to_string(), "0".to_string());
+        }
+        Body::empty()
+    } else {
+        Body::Stream(Box::new(ResponseBody {
+            stream,
+            trailers: trailers.clone(),
+        }))
+    };
+    Ok(HttpResponse {
+        version: HttpVersion::HTTP2_0,
+        status,
+        reason: None,
+        headers,
+        body,
+        trailers,
+    })
+}
+
+/// The status of a GET for `path`, sent on a connection of its own; for
+/// health checks.
+pub fn probe(connector: &Connector, address: &str, path: &str, timeout: Duration) -> Option<u16> {
+    let conn = connect(connector, address, timeout, timeout).ok()?;
+    let headers = [
+        (":method", "GET"),
+        (":scheme", if connector.is_tls() { "https" } else { "http" }),
+        (":authority", address),
+        (":path", path),
+        ("user-agent", "Orion-HealthCheck"),
+    ];
+    let stream = conn.open(headers, true).ok()?;
+    status(&stream.head().ok()?)
+}
+
+fn status(head: &[Header]) -> Option<u16> {
+    head.iter()
+        .find(|(name, _)| name == ":status")
+        .and_then(|(_, value)| value.parse().ok())
+}
+
+fn malformed(message: String) -> ForwardError {
+    ForwardError::InvalidResponse(HttpParseError::MalformedResponse(message))
+}
+
+/// Regular fields of a header block; repeated ones are folded into one.
+fn fields(block: Vec<Header>) -> HttpHeaders {
+    let mut fields = HttpHeaders::new();
+    for (name, value) in block {
+        if name.starts_with(':') {
+            continue;
+        }
+        let value = match fields.get(&name) {
+            Some(existing) => format!("{}, {}", existing, value),
+            None => value,
+        };
+        fields.insert(name, value);
+    }
+    fields
+}
+
+/// A response body read off its stream; the stream's trailers are handed
+/// over once the body has been read to its end.
+struct ResponseBody {
+    stream: Stream,
+    trailers: Trailers,
+}
+
+impl Read for ResponseBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stream.read(buf)?;
+        if n == 0
+            && !buf.is_empty()
+            && let Some(trailers) = self.stream.trailers()
+        {
+            self.trailers.set(fields(trailers));
+        }
+        Ok(n)
+    }
+}
@@ -0,0 +1,984 @@
+// src/proxy/h2/mod.rs
+//
+// HTTP/2 connections (RFC 9113). One I/O thread per connection owns the
+// socket: it reads and dispatches frames, and writes the frames stream
+// threads have queued, which wake it through an eventfd. Each stream is
+// served by its own thread through a `Stream` handle, so streams proceed
+// independently over the one connection.
+//
+// Flow control: a peer may send up to STREAM_WINDOW bytes on a stream ahead
+// of the stream's thread reading them, and window is handed back as they are
+// read. Connection-level window is handed back as data arrives, so a stream
+// nobody reads cannot stall the others. Sending waits for the peer's window
+// and for room in the outgoing queue.
+
+pub mod client;
+pub mod server;
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use crate::http::HttpLimits;
+use crate::http::body::COPY_BUFFER_SIZE;
+use crate::http::h2::frame::{self, ErrorCode, Frame};
+use crate::http::h2::hpack::{self, DecodeError, Header};
+use crate::proxy::transport::Endpoint;
+
+/// Streams a peer may have open at once.
+pub const MAX_CONCURRENT_STREAMS: u32 = 100;
+
+/// Stream identifiers are 31 bits.
+const MAX_STREAM_ID: u32 = (1 << 31) - 1;
+
+/// Receive window of each stream: how far a peer may send ahead of us.
+const STREAM_WINDOW: u32 = 1 << 20;
+
+/// Receive window of the connection as a whole.
+const CONNECTION_WINDOW: u32 = 16 << 20;
+
+/// Largest header list accepted, as SETTINGS_MAX_HEADER_LIST_SIZE counts.
+const MAX_HEADER_LIST_SIZE: usize = HttpLimits::MAX_HEAD_SIZE;
+
+/// Queued outgoing bytes past which senders wait for the I/O thread.
+const MAX_QUEUED: usize = 256 * 1024;
+
+/// How often the I/O thread checks for shutdown and idleness.
+const TICK: Duration = Duration::from_millis(250);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Role {
+    Client,
+    Server,
+}
+
+/// Why a connection had to be torn down; sent to the peer in GOAWAY.
+#[derive(Debug)]
+pub struct ConnectionError {
+    pub code: ErrorCode,
+    pub reason: String,
+}
+
+impl ConnectionError {
+    fn new(code: ErrorCode, reason: impl Into<String>) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+        }
+    }
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HTTP/2 {}: {}", self.code, self.reason)
+    }
+}
+
+impl From<ErrorCode> for ConnectionError {
+    fn from(code: ErrorCode) -> Self {
+        Self::new(code, "invalid frame")
+    }
+}
+
+/// A handle on an HTTP/2 connection, shared by its I/O thread and the
+/// threads serving its streams.
+#[derive(Clone)]
+pub struct Connection {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    role: Role,
+    state: Mutex<State>,
+    /// Signalled whenever stream state, windows or the queue change.
+    changed: Condvar,
+    waker: Waker,
+    /// Longest a stream thread waits for the peer.
+    timeout: Duration,
+}
+
+/// Settings the peer sent that govern what we send.
+struct PeerSettings {
+    initial_window: u32,
+    max_frame_size: u32,
+    max_concurrent_streams: u32,
+}
+
+struct State {
+    streams: HashMap<u32, StreamState>,
+    /// Encoded frames waiting for the I/O thread.
+    outgoing: Vec<u8>,
+    peer: PeerSettings,
+    /// What the peer still lets us send on the connection.
+    send_window: i64,
+    /// What we still let the peer send, and what it has used since we last
+    /// handed window back.
+    recv_window: i64,
+    recv_unacked: u32,
+    /// Highest stream opened by the peer.
+    last_peer_stream: u32,
+    /// Next stream we open (client role).
+    next_stream: u32,
+    /// GOAWAY sent or received: no new streams.
+    going_away: bool,
+    /// Set when the connection is gone, with the reason.
+    closed: Option<String>,
+}
+
+struct StreamState {
+    send_window: i64,
+    recv_window: i64,
+    /// Bytes read by the stream's thread and not yet handed back as window.
+    recv_unacked: u32,
+    data: VecDeque<u8>,
+    /// A response head received and not yet taken (client role).
+    head: Option<Vec<Header>>,
+    got_head: bool,
+    trailers: Option<Vec<Header>>,
+    /// END_STREAM received.
+    remote_done: bool,
+    /// END_STREAM sent.
+    local_done: bool,
+    reset: Option<ErrorCode>,
+}
+
+impl StreamState {
+    fn new(initial_window: u32) -> Self {
+        Self {
+            send_window: initial_window as i64,
+            recv_window: STREAM_WINDOW as i64,
+            recv_unacked: 0,
+            data: VecDeque::new(),
+            head: None,
+            got_head: false,
+            trailers: None,
+            remote_done: false,
+            local_done: false,
+            reset: None,
+        }
+    }
+}
+
+/// Connection-level reading state, owned by the I/O thread.
+struct Reader {
+    input: Vec<u8>,
+    decoder: hpack::Decoder,
+    /// A header block still waiting for CONTINUATION frames: stream, whether
+    /// it ends the stream, and the fragments so far.
+    continuation: Option<(u32, bool, Vec<u8>)>,
+    /// Servers expect the client preface first, then SETTINGS.
+    awaiting_preface: bool,
+    awaiting_settings: bool,
+}
+
+impl Connection {
+    pub fn new(role: Role, timeout: Duration) -> io::Result<Self> {
+        let state = State {
+            streams: HashMap::new(),
+            outgoing: Vec::new(),
+            peer: PeerSettings {
+                initial_window: frame::DEFAULT_WINDOW_SIZE,
+                max_frame_size: frame::DEFAULT_MAX_FRAME_SIZE,
+                max_concurrent_streams: u32::MAX,
+            },
+            send_window: frame::DEFAULT_WINDOW_SIZE as i64,
+            recv_window: frame::DEFAULT_WINDOW_SIZE as i64,
+            recv_unacked: 0,
+            last_peer_stream: 0,
+            next_stream: 1,
+            going_away: false,
+            closed: None,
+        };
+        Ok(Self {
+            shared: Arc::new(Shared {
+                role,
+                state: Mutex::new(state),
+                changed: Condvar::new(),
+                waker: Waker::new()?,
+                timeout,
+            }),
+        })
+    }
+
+    /// Apply the settings a client sent in the `HTTP2-Settings` header of an
+    /// `Upgrade: h2c` request.
+    pub fn apply_settings(&self, payload: &[u8]) -> Result<(), ConnectionError> {
+        let mut state = self.lock();
+        apply_settings(&mut state, payload)
+    }
+
+    /// Open stream 1 for the request that carried an `Upgrade: h2c`; its
+    /// request is complete, so only the response goes on the stream.
+    pub fn upgraded_stream(&self) -> Stream {
+        let mut state = self.lock();
+        let mut stream = StreamState::new(state.peer.initial_window);
+        stream.remote_done = true;
+        state.streams.insert(1, stream);
+        state.last_peer_stream = 1;
+        Stream {
+            conn: self.clone(),
+            id: 1,
+        }
+    }
+
+    /// Open a stream by sending a request head (client role). Fails when
+    /// the connection is going away or has no room for another stream.
+    pub fn open<'a>(&self, headers: impl IntoIterator<Item = (&'a str, &'a str)>, end_stream: bool) -> io::Result<Stream> {
+        let block = hpack::encode(headers);
+        let mut state = self.lock();
+        if let Some(reason) = &state.closed {
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted, reason.clone()));
+        }
+        if !has_room(&state) {
+            return Err(io::Error::new(io::ErrorKind::WouldBlock, "no room for another HTTP/2 stream"));
+        }
+        let id = state.next_stream;
+        state.next_stream += 2;
+        let mut stream = StreamState::new(state.peer.initial_window);
+        stream.local_done = end_stream;
+        state.streams.insert(id, stream);
+        let max_frame_size = state.peer.max_frame_size;
+        frame::encode_headers(&mut state.outgoing, id, &block, end_stream, max_frame_size);
+        drop(state);
+        self.wake();
+        Ok(Stream { conn: self.clone(), id })
+    }
+
+    /// True while another stream could be opened on the connection.
+    pub fn has_room(&self) -> bool {
+        has_room(&self.lock())
+    }
+
+    /// True once the connection is closed or going away.
+    pub fn is_finished(&self) -> bool {
+        let state = self.lock();
+        state.closed.is_some() || state.going_away
+    }
+
+    /// Run the connection until it closes: send our preface, then read and
+    /// dispatch frames and write queued ones. `input` holds bytes already
+    /// read off the socket. Servers pass each new stream to `accept` with
+    /// its request head and whether the head ended the stream. Once `stop`
+    /// returns true, or the connection is idle for `idle_timeout`, the peer
+    /// is told to go away and the connection closes as soon as its open
+    /// streams finish.
+    pub fn run(
+        &self,
+        io: &mut dyn Endpoint,
+        input: Vec<u8>,
+        idle_timeout: Duration,
+        stop: &dyn Fn() -> bool,
+        accept: &mut dyn FnMut(Stream, Vec<Header>, bool),
+    ) -> Result<(), ConnectionError> {
+        let role = self.shared.role;
+        let mut reader = Reader {
+            input,
+            decoder: hpack::Decoder::default(),
+            continuation: None,
+            awaiting_preface: role == Role::Server,
+            awaiting_settings: true,
+        };
+        {
+            let mut state = self.lock();
+            let mut preamble = Vec::new();
+            if role == Role::Client {
+                preamble.extend_from_slice(frame::PREFACE);
+            }
+            let mut settings = vec![
+                (frame::SETTINGS_INITIAL_WINDOW_SIZE, STREAM_WINDOW),
+                (frame::SETTINGS_MAX_HEADER_LIST_SIZE, MAX_HEADER_LIST_SIZE as u32),
+            ];
+            match role {
+                Role::Server => settings.push((frame::SETTINGS_MAX_CONCURRENT_STREAMS, MAX_CONCURRENT_STREAMS)),
+                Role::Client => settings.push((frame::SETTINGS_ENABLE_PUSH, 0)),
+            }
+            frame::encode_settings(&mut preamble, &settings);
+            frame::encode_window_update(&mut preamble, 0, CONNECTION_WINDOW - frame::DEFAULT_WINDOW_SIZE);
+            state.recv_window = CONNECTION_WINDOW as i64;
+            // Anything queued already (an upgraded stream's response) goes
+            // after the preface.
+            preamble.append(&mut state.outgoing);
+            state.outgoing = preamble;
+        }
+
+        let result = self.drive(io, &mut reader, idle_timeout, stop, accept);
+        let reason = match &result {
+            Ok(()) => "connection closed".to_string(),
+            Err(e) => e.to_string(),
+        };
+        if let Err(e) = &result {
+            let last_stream = self.lock().last_peer_stream;
+            let mut goaway = Vec::new();
+            frame::encode_goaway(&mut goaway, last_stream, e.code, &e.reason);
+            let _ = io.write_all(&goaway).and_then(|()| io.flush());
+        }
+        let mut state = self.lock();
+        state.closed = Some(reason);
+        state.going_away = true;
+        self.shared.changed.notify_all();
+        result
+    }
+
+    fn drive(
+        &self,
+        io: &mut dyn Endpoint,
+        reader: &mut Reader,
+        idle_timeout: Duration,
+        stop: &dyn Fn() -> bool,
+        accept: &mut dyn FnMut(Stream, Vec<Header>, bool),
+    ) -> Result<(), ConnectionError> {
+        let io_error = |e: io::Error| ConnectionError::new(ErrorCode::INTERNAL_ERROR, e.to_string());
+        let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+        let mut last_active = Instant::now();
+        loop {
+            self.process(reader, accept)?;
+
+            let outgoing = std::mem::take(&mut self.lock().outgoing);
+            if !outgoing.is_empty() {
+                io.write_all(&outgoing).and_then(|()| io.flush()).map_err(io_error)?;
+                self.shared.changed.notify_all();
+            }
+
+            {
+                let mut state = self.lock();
+                if !state.streams.is_empty() {
+                    last_active = Instant::now();
+                }
+                let idle = state.streams.is_empty() && last_active.elapsed() >= idle_timeout;
+                if (stop() || idle) && !state.going_away {
+                    state.going_away = true;
+                    let last_stream = state.last_peer_stream;
+                    frame::encode_goaway(&mut state.outgoing, last_stream, ErrorCode::NO_ERROR, "");
+                    continue;
+                }
+                if state.going_away && state.streams.is_empty() && state.outgoing.is_empty() {
+                    return Ok(());
+                }
+            }
+
+            let [socket_ready, woken] = if io.has_buffered_data() {
+                [true, false]
+            } else {
+                wait([io.socket().as_raw_fd(), self.shared.waker.fd()], TICK).map_err(io_error)?
+            };
+            if woken {
+                self.shared.waker.drain();
+            }
+            if socket_ready {
+                let n = match io.read(&mut buf) {
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+                    Err(e) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => continue,
+                    Err(e) => return Err(io_error(e)),
+                };
+                if n == 0 {
+                    return Ok(());
+                }
+                reader.input.extend_from_slice(&buf[..n]);
+                last_active = Instant::now();
+            }
+        }
+    }
+
+    /// Handle every complete frame in the input buffer.
+    fn process(&self, reader: &mut Reader, accept: &mut dyn FnMut(Stream, Vec<Header>, bool)) -> Result<(), ConnectionError> {
+        let mut used = 0;
+        if reader.awaiting_preface {
+            let len = reader.input.len().min(frame::PREFACE.len());
+            if reader.input[..len] != frame::PREFACE[..len] {
+                return Err(ConnectionError::new(ErrorCode::PROTOCOL_ERROR, "invalid connection preface"));
+            }
+            if len < frame::PREFACE.len() {
+                return Ok(());
+            }
+            reader.awaiting_preface = false;
+            used = len;
+        }
+        let result = loop {
+            match Frame::parse(&reader.input[used..], frame::DEFAULT_MAX_FRAME_SIZE) {
+                Ok(Some((frame, len))) => {
+                    used += len;
+                    if let Err(e) = self.handle(reader, frame, accept) {
+                        break Err(e);
+                    }
+                }
+                Ok(None) => break Ok(()),
+                Err(code) => break Err(ConnectionError::new(code, "frame larger than SETTINGS_MAX_FRAME_SIZE")),
+            }
+        };
+        reader.input.drain(..used);
+        result
+    }
+
+    fn handle(&self, reader: &mut Reader, frame: Frame, accept: &mut dyn FnMut(Stream, Vec<Header>, bool)) -> Result<(), ConnectionError> {
+        if reader.awaiting_settings {
+            if frame.kind != frame::SETTINGS || frame.has(frame::ACK) {
+                return Err(ConnectionError::new(ErrorCode::PROTOCOL_ERROR, "expected SETTINGS first"));
+            }
+            reader.awaiting_settings = false;
+        }
+        if let Some((id, _, _)) = &reader.continuation
+            && (frame.kind != frame::CONTINUATION || frame.stream != *id)
+        {
+            return Err(ConnectionError::new(ErrorCode::PROTOCOL_ERROR, "expected CONTINUATION"));
+        }
+        let stream_frame = !matches!(frame.kind, frame::SETTINGS | frame::PING | frame::GOAWAY | frame::WINDOW_UPDATE);
+        if stream_frame && frame.stream == 0 && frame.kind <= frame::CONTINUATION {
+            return Err(ConnectionError::new(ErrorCode::PROTOCOL_ERROR, "stream frame on stream 0"));
+        }
+
+        match frame.kind {
+            frame::DATA => self.on_data(&frame),
+            frame::HEADERS => {
+                let block = frame.header_block()?;
+                let end_stream = frame.has(frame::END_STREAM);
+                if frame.has(frame::END_HEADERS) {
+                    self.on_headers(reader, frame.stream, end_stream, block, accept)
+                } else {
+                    reader.continuation = Some((frame.stream, end_stream, block.to_vec()));
+                    Ok(())
+                }
+            }
+            frame::CONTINUATION => {
+                let Some((id, end_stream, mut block)) = reader.continuation.take() else {
+                    return Err(ConnectionError::new(ErrorCode::PROTOCOL_ERROR, "unexpected CONTINUATION"));
+                };
+                block.extend_from_slice(&frame.payload);
+                if block.len() > MAX_HEADER_LIST_SIZE * 2 {
+                    return Err(ConnectionError::new(ErrorCode::ENHANCE_YOUR_CALM, "header block too large"));
+                }
+                if frame.has(frame::END_HEADERS) {
+                    self.on_headers(reader, id, end_stream, &block, accept)
+                } else {
+                    reader.continuation = Some((id, end_stream, block));
+                    Ok(())
+                }
+            }
+            frame::RST_STREAM => {
+                let code = frame::read_u32(&frame.payload)
+                    .filter(|_| frame.payload.len() == 4)
+                    .ok_or(ErrorCode::FRAME_SIZE_ERROR)?;
+                let mut state = self.lock();
+                if let Some(stream) = state.streams.get_mut(&frame.stream) {
+                    stream.reset = Some(ErrorCode(code));
+                }
+                self.shared.changed.notify_all();
+                Ok(())
+            }
+            frame::SETTINGS => {
+                if frame.stream != 0 {
+                    return Err(ConnectionError::new(ErrorCode::PROTOCOL_ERROR, "SETTINGS on a stream"));
+                }
+                if frame.has(frame::ACK) {
+                    return match frame.payload.is_empty() {
+                        true => Ok(()),
+                        false => Err(ErrorCode::FRAME_SIZE_ERROR.into()),
+                    };
+                }
+                let mut state = self.lock();
+                apply_settings(&mut state, &frame.payload)?;
+                frame::encode(&mut state.outgoing, frame::SETTINGS, frame::ACK, 0, &[]);
+                self.shared.changed.notify_all();
+                Ok(())
+            }
+            frame::PUSH_PROMISE => Err(ConnectionError::new(ErrorCode::PROTOCOL_ERROR, "PUSH_PROMISE is not enabled")),
+            frame::PING => {
+                if frame.stream != 0 {
+                    return Err(ConnectionError::new(ErrorCode::PROTOCOL_ERROR, "PING on a stream"));
+                }
+                if frame.payload.len() != 8 {
+                    return Err(ErrorCode::FRAME_SIZE_ERROR.into());
+                }
+                if !frame.has(frame::ACK) {
+                    let mut state = self.lock();
+                    frame::encode(&mut state.outgoing, frame::PING, frame::ACK, 0, &frame.payload);
+                }
+                Ok(())
+            }
+            frame::GOAWAY => {
+                let last_stream = frame::read_u32(&frame.payload).ok_or(ErrorCode::FRAME_SIZE_ERROR)? & frame::MAX_WINDOW_SIZE;
+                let mut state = self.lock();
+                state.going_away = true;
+                // Streams we opened that the peer never processed.
+                let local = |id: u32| (id % 2 == 1) == (self.shared.role == Role::Client);
+                for (id, stream) in state.streams.iter_mut() {
+                    if local(*id) && *id > last_stream && stream.reset.is_none() {
+                        stream.reset = Some(ErrorCode::REFUSED_STREAM);
+                    }
+                }
+                self.shared.changed.notify_all();
+                Ok(())
+            }
+            frame::WINDOW_UPDATE => self.on_window_update(&frame),
+            // PRIORITY is advisory, and unknown frame types are ignored.
+            _ => Ok(()),
+        }
+    }
+
+    fn on_data(&self, frame: &Frame) -> Result<(), ConnectionError> {
+        let data = frame.unpadded()?;
+        let len = frame.payload.len() as u32;
+        let mut state = self.lock();
+        let state = &mut *state;
+
+        state.recv_window -= len as i64;
+        if state.recv_window < 0 {
+            return Err(ConnectionError::new(ErrorCode::FLOW_CONTROL_ERROR, "connection window exceeded"));
+        }
+        state.recv_unacked += len;
+        if state.recv_unacked >= CONNECTION_WINDOW / 2 {
+            frame::encode_window_update(&mut state.outgoing, 0, state.recv_unacked);
+            state.recv_window += state.recv_unacked as i64;
+            state.recv_unacked = 0;
+        }
+
+        let id = frame.stream;
+        let Some(stream) = state.streams.get_mut(&id) else {
+            if id > state.last_peer_stream && self.shared.role == Role::Server {
+                return Err(ConnectionError::new(ErrorCode::PROTOCOL_ERROR, "DATA on an idle stream"));
+            }
+            // A stream that already ended; its window is returned above.
+            return Ok(());
+        };
+        if stream.reset.is_some() {
+            return Ok(());
+        }
+        if stream.remote_done {
+            reset_stream(&mut state.outgoing, id, stream, ErrorCode::STREAM_CLOSED);
+        } else {
+            stream.recv_window -= len as i64;
+            if stream.recv_window < 0 {
+                reset_stream(&mut state.outgoing, id, stream, ErrorCode::FLOW_CONTROL_ERROR);
+            } else {
+                stream.data.extend(data);
+                // Padding counts against the window but is never read.
+                stream.recv_unacked += len - data.len() as u32;
+                stream.remote_done = frame.has(frame::END_STREAM);
+            }
+        }
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    fn on_headers(
+        &self,
+        reader: &mut Reader,
+        id: u32,
+        end_stream: bool,
+        block: &[u8],
+        accept: &mut dyn FnMut(Stream, Vec<Header>, bool),
+    ) -> Result<(), ConnectionError> {
+        // Every block is decoded, wanted or not, to keep the table in step.
+        let decoded = reader.decoder.decode(block, MAX_HEADER_LIST_SIZE);
+        let headers = match decoded {
+            Ok(headers) => Some(headers),
+            Err(DecodeError::TooLarge) => None,
+            Err(e @ DecodeError::Compression(_)) => {
+                return Err(ConnectionError::new(ErrorCode::COMPRESSION_ERROR, e.to_string()));
+            }
+        };
+
+        let mut guard = self.lock();
+        let state = &mut *guard;
+        if let Some(stream) = state.streams.get_mut(&id) {
+            if stream.reset.is_some() {
+                return Ok(());
+            }
+            let Some(headers) = headers.filter(|_| !stream.remote_done) else {
+                reset_stream(&mut state.outgoing, id, stream, ErrorCode::PROTOCOL_ERROR);
+                self.shared.changed.notify_all();
+                return Ok(());
+            };
+            let interim = headers
+                .first()
+                .is_some_and(|(name, value)| name == ":status" && value.starts_with('1'));
+            if self.shared.role == Role::Client && !stream.got_head {
+                if !interim {
+                    stream.head = Some(headers);
+                    stream.got_head = true;
+                }
+            } else if end_stream {
+                stream.trailers = Some(headers);
+            } else {
+                // Trailers must end the stream.
+                reset_stream(&mut state.outgoing, id, stream, ErrorCode::PROTOCOL_ERROR);
+            }
+            stream.remote_done |= end_stream;
+            self.shared.changed.notify_all();
+            return Ok(());
+        }
+
+        if self.shared.role == Role::Client || id <= state.last_peer_stream {
+            // A stream that already ended.
+            return Ok(());
+        }
+        if id.is_multiple_of(2) {
+            return Err(ConnectionError::new(ErrorCode::PROTOCOL_ERROR, "client opened an even stream"));
+        }
+        state.last_peer_stream = id;
+        let refused = if state.going_away || state.streams.len() >= MAX_CONCURRENT_STREAMS as usize {
+            Some(ErrorCode::REFUSED_STREAM)
+        } else if headers.is_none() {
+            Some(ErrorCode::PROTOCOL_ERROR)
+        } else {
+            None
+        };
+        if let Some(code) = refused {
+            frame::encode_rst_stream(&mut state.outgoing, id, code);
+            return Ok(());
+        }
+        let mut stream = StreamState::new(state.peer.initial_window);
+        stream.remote_done = end_stream;
+        state.streams.insert(id, stream);
+        drop(guard);
+
+        let stream = Stream {
+            conn: self.clone(),
+            id,
+        };
+        accept(stream, headers.unwrap_or_default(), end_stream);
+        Ok(())
+    }
+
+    fn on_window_update(&self, frame: &Frame) -> Result<(), ConnectionError> {
+        let increment = frame::read_u32(&frame.payload)
+            .filter(|_| frame.payload.len() == 4)
+            .ok_or(ErrorCode::FRAME_SIZE_ERROR)?
+            & frame::MAX_WINDOW_SIZE;
+        let mut state = self.lock();
+        let state = &mut *state;
+        if frame.stream == 0 {
+            if increment == 0 {
+                return Err(ConnectionError::new(ErrorCode::PROTOCOL_ERROR, "zero window increment"));
+            }
+            state.send_window += increment as i64;
+            if state.send_window > frame::MAX_WINDOW_SIZE as i64 {
+                return Err(ConnectionError::new(ErrorCode::FLOW_CONTROL_ERROR, "connection window overflow"));
+            }
+        } else if let Some(stream) = state.streams.get_mut(&frame.stream) {
+            stream.send_window += increment as i64;
+            if increment == 0 {
+                reset_stream(&mut state.outgoing, frame.stream, stream, ErrorCode::PROTOCOL_ERROR);
+            } else if stream.send_window > frame::MAX_WINDOW_SIZE as i64 {
+                reset_stream(&mut state.outgoing, frame.stream, stream, ErrorCode::FLOW_CONTROL_ERROR);
+            }
+        }
+        self.shared.changed.notify_all();
+        Ok(())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.shared.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Wait for a change, up to the connection's timeout.
+    fn wait<'a>(&self, state: MutexGuard<'a, State>, deadline: Instant) -> io::Result<MutexGuard<'a, State>> {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::Error::new(io::ErrorKind::TimedOut, "HTTP/2 peer did not respond in time"));
+        }
+        let (state, _) = self
+            .shared
+            .changed
+            .wait_timeout(state, remaining)
+            .unwrap_or_else(|e| e.into_inner());
+        Ok(state)
+    }
+
+    /// Signal the I/O thread that there are frames queued for it to write.
+    fn wake(&self) {
+        self.shared.waker.wake();
+    }
+}
+
+fn apply_settings(state: &mut State, payload: &[u8]) -> Result<(), ConnectionError> {
+    for (id, value) in frame::parse_settings(payload)? {
+        match id {
+            frame::SETTINGS_INITIAL_WINDOW_SIZE => {
+                if value > frame::MAX_WINDOW_SIZE {
+                    return Err(ConnectionError::new(ErrorCode::FLOW_CONTROL_ERROR, "initial window too large"));
+                }
+                let delta = value as i64 - state.peer.initial_window as i64;
+                for stream in state.streams.values_mut() {
+                    stream.send_window += delta;
+                }
+                state.peer.initial_window = value;
+            }
+            frame::SETTINGS_MAX_FRAME_SIZE => {
+                if !(frame::DEFAULT_MAX_FRAME_SIZE..=frame::LARGEST_MAX_FRAME_SIZE).contains(&value) {
+                    return Err(ConnectionError::new(ErrorCode::PROTOCOL_ERROR, "invalid max frame size"));
+                }
+                state.peer.max_frame_size = value;
+            }
+            frame::SETTINGS_MAX_CONCURRENT_STREAMS => state.peer.max_concurrent_streams = value,
+            frame::SETTINGS_ENABLE_PUSH if value > 1 => {
+                return Err(ConnectionError::new(ErrorCode::PROTOCOL_ERROR, "invalid ENABLE_PUSH"));
+            }
+            // Our encoder never uses the dynamic table, so the peer's table
+            // size is of no concern; unknown settings are ignored.
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn has_room(state: &State) -> bool {
+    let limit = state.peer.max_concurrent_streams.min(MAX_CONCURRENT_STREAMS) as usize;
+    !state.going_away && state.closed.is_none() && state.streams.len() < limit && state.next_stream <= MAX_STREAM_ID
+}
+
+fn reset_stream(outgoing: &mut Vec<u8>, id: u32, stream: &mut StreamState, code: ErrorCode) {
+    frame::encode_rst_stream(outgoing, id, code);
+    stream.reset = Some(code);
+}
+
+/// One stream of a connection, used by the thread serving it. Reading
+/// returns the data the peer sends on the stream. Dropping the handle
+/// resets the stream if either side has not finished with it.
+pub struct Stream {
+    conn: Connection,
+    id: u32,
+}
+
+impl Stream {
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Send a header block: a head, or trailers when it ends the stream
+    /// after data. Returns the size of the encoded block.
+    pub fn send_headers<'a>(
+        &self,
+        headers: impl IntoIterator<Item = (&'a str, &'a str)>,
+        end_stream: bool,
+    ) -> io::Result<usize> {
+        let block = hpack::encode(headers);
+        let conn = &self.conn;
+        let mut state = self.writable(conn.lock())?;
+        let max_frame_size = state.peer.max_frame_size;
+        frame::encode_headers(&mut state.outgoing, self.id, &block, end_stream, max_frame_size);
+        if end_stream && let Some(stream) = state.streams.get_mut(&self.id) {
+            stream.local_done = true;
+        }
+        drop(state);
+        conn.wake();
+        Ok(block.len())
+    }
+
+    /// Send `data`, split into frames as flow control and the peer's frame
+    /// size allow, waiting for window as needed.
+    pub fn send_data(&self, mut data: &[u8], end_stream: bool) -> io::Result<()> {
+        let conn = &self.conn;
+        let deadline = || Instant::now() + conn.shared.timeout;
+        let mut waiting_since = deadline();
+        let mut state = conn.lock();
+        loop {
+            state = self.writable(state)?;
+            let state_ref = &mut *state;
+            let stream = state_ref.streams.get_mut(&self.id).expect("checked by writable");
+            let window = state_ref.send_window.min(stream.send_window).max(0) as usize;
+            let room = MAX_QUEUED.saturating_sub(state_ref.outgoing.len());
+            let len = data.len().min(window).min(room).min(state_ref.peer.max_frame_size as usize);
+            if len == 0 && !data.is_empty() {
+                state = conn.wait(state, waiting_since)?;
+                continue;
+            }
+
+            let last = len == data.len();
+            let flags = if last && end_stream { frame::END_STREAM } else { 0 };
+            frame::encode(&mut state_ref.outgoing, frame::DATA, flags, self.id, &data[..len]);
+            state_ref.send_window -= len as i64;
+            stream.send_window -= len as i64;
+            stream.local_done |= flags != 0;
+            data = &data[len..];
+            conn.wake();
+            if last {
+                return Ok(());
+            }
+            waiting_since = deadline();
+        }
+    }
+
+    /// Reset the stream with `code`.
+    pub fn reset(&self, code: ErrorCode) {
+        let mut state = self.conn.lock();
+        let state = &mut *state;
+        if let Some(stream) = state.streams.get_mut(&self.id)
+            && stream.reset.is_none()
+        {
+            reset_stream(&mut state.outgoing, self.id, stream, code);
+            self.conn.shared.changed.notify_all();
+            self.conn.wake();
+        }
+    }
+
+    /// Wait for the peer's response head (client role).
+    pub fn head(&self) -> io::Result<Vec<Header>> {
+        let conn = &self.conn;
+        let deadline = Instant::now() + conn.shared.timeout;
+        let mut state = conn.lock();
+        loop {
+            let stream = self.readable(&mut state)?;
+            if let Some(head) = stream.head.take() {
+                return Ok(head);
+            }
+            if stream.remote_done {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "HTTP/2 stream ended without a response"));
+            }
+            state = conn.wait(state, deadline)?;
+        }
+    }
+
+    /// True once the peer has ended the stream and all it sent has been
+    /// taken, as when a response head ends the stream.
+    pub fn is_drained(&self) -> bool {
+        let state = self.conn.lock();
+        state
+            .streams
+            .get(&self.id)
+            .is_none_or(|stream| stream.remote_done && stream.data.is_empty() && stream.trailers.is_none())
+    }
+
+    /// Trailers the peer ended the stream with, once they have arrived.
+    pub fn trailers(&self) -> Option<Vec<Header>> {
+        let mut state = self.conn.lock();
+        state.streams.get_mut(&self.id)?.trailers.take()
+    }
+
+    /// The stream, if data can still be sent on it.
+    fn writable<'a>(&self, state: MutexGuard<'a, State>) -> io::Result<MutexGuard<'a, State>> {
+        if let Some(reason) = &state.closed {
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted, reason.clone()));
+        }
+        match state.streams.get(&self.id) {
+            Some(stream) if stream.reset.is_some() => Err(reset_error(stream.reset)),
+            Some(stream) if stream.local_done => Err(io::Error::other("HTTP/2 stream already ended")),
+            Some(_) => Ok(state),
+            None => Err(reset_error(None)),
+        }
+    }
+
+    fn readable<'a>(&self, state: &'a mut MutexGuard<'_, State>) -> io::Result<&'a mut StreamState> {
+        let closed = state.closed.clone();
+        let stream = state.streams.get_mut(&self.id).ok_or_else(|| reset_error(None))?;
+        if stream.data.is_empty() && stream.reset.is_some() && !stream.remote_done {
+            return Err(reset_error(stream.reset));
+        }
+        if let Some(reason) = closed
+            && stream.data.is_empty()
+            && !stream.remote_done
+        {
+            return Err(io::Error::new(io::ErrorKind::ConnectionAborted, reason));
+        }
+        Ok(stream)
+    }
+}
+
+fn reset_error(code: Option<ErrorCode>) -> io::Error {
+    let code = code.unwrap_or(ErrorCode::STREAM_CLOSED);
+    io::Error::new(io::ErrorKind::ConnectionReset, format!("HTTP/2 stream reset ({})", code))
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let conn = &self.conn;
+        let deadline = Instant::now() + conn.shared.timeout;
+        let mut state = conn.lock();
+        loop {
+            let stream = self.readable(&mut state)?;
+            if !stream.data.is_empty() {
+                let n = buf.len().min(stream.data.len());
+                for (slot, byte) in buf.iter_mut().zip(stream.data.drain(..n)) {
+                    *slot = byte;
+                }
+                stream.recv_unacked += n as u32;
+                if stream.recv_unacked >= STREAM_WINDOW / 2 && !stream.remote_done {
+                    let increment = std::mem::take(&mut stream.recv_unacked);
+                    stream.recv_window += increment as i64;
+                    frame::encode_window_update(&mut state.outgoing, self.id, increment);
+                    conn.wake();
+                }
+                return Ok(n);
+            }
+            if stream.remote_done {
+                return Ok(0);
+            }
+            state = conn.wait(state, deadline)?;
+        }
+    }
+}
+
+impl Drop for Stream {
+    fn drop(&mut self) {
+        let mut state = self.conn.lock();
+        let state = &mut *state;
+        let Some(stream) = state.streams.remove(&self.id) else { return };
+        if stream.reset.is_none() && state.closed.is_none() {
+            // A response sent in full ends the exchange even if the request
+            // body was never read (RFC 9113 §8.1); a client that stops
+            // reading a response cancels it.
+            let client = self.conn.shared.role == Role::Client;
+            if !stream.local_done || (client && !stream.remote_done) {
+                frame::encode_rst_stream(&mut state.outgoing, self.id, ErrorCode::CANCEL);
+            } else if !stream.remote_done {
+                frame::encode_rst_stream(&mut state.outgoing, self.id, ErrorCode::NO_ERROR);
+            }
+        }
+        self.conn.shared.changed.notify_all();
+        self.conn.wake();
+    }
+}
+
+/// An eventfd stream threads use to wake the I/O thread out of `poll`.
+struct Waker(OwnedFd);
+
+impl Waker {
+    fn new() -> io::Result<Self> {
+        // SAFETY: eventfd has no memory-safety preconditions.
+        let fd = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC | libc::EFD_NONBLOCK) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: `fd` is a freshly created descriptor owned by nobody else.
+        Ok(Self(unsafe { OwnedFd::from_raw_fd(fd) }))
+    }
+
+    fn fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+    }
+
+    fn wake(&self) {
+        let one = 1u64.to_ne_bytes();
+        // SAFETY: writes 8 bytes from a live buffer. A full counter fails
+        // with EAGAIN, which still leaves the eventfd readable.
+        unsafe { libc::write(self.fd(), one.as_ptr().cast(), one.len()) };
+    }
+
+    fn drain(&self) {
+        let mut count = [0u8; 8];
+        // SAFETY: reads at most 8 bytes into a live buffer.
+        unsafe { libc::read(self.fd(), count.as_mut_ptr().cast(), count.len()) };
+    }
+}
+
+/// Wait up to `timeout` for either descriptor to become readable.
+fn wait(fds: [RawFd; 2], timeout: Duration) -> io::Result<[bool; 2]> {
+    let mut pollfds = fds.map(|fd| libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    });
+    // SAFETY: `pollfds` is a valid array of pollfds for the duration of the call.
+    let ready = unsafe { libc::poll(pollfds.as_mut_ptr(), pollfds.len() as libc::nfds_t, timeout.as_millis() as libc::c_int) };
+    if ready < 0 {
+        let err = io::Error::last_os_error();
+        return match err.kind() {
+            io::ErrorKind::Interrupted => Ok([false, false]),
+            _ => Err(err),
+        };
+    }
+    Ok([pollfds[0].revents != 0, pollfds[1].revents != 0])
+}
@@ -0,0 +1,243 @@
+// src/proxy/h2/server.rs
+//
+// The server side of HTTP/2: requests arriving on streams become
+// `HttpRequest`s for the usual routing and forwarding, and `HttpResponse`s
+// go back as HEADERS and DATA frames. Also the pieces of an `Upgrade: h2c`
+// handshake, which starts out as an HTTP/1.1 request.
+
+use std::io::{self, Read};
+
+use crate::http::body::{Body, BodyError, COPY_BUFFER_SIZE};
+use crate::http::h2::{ErrorCode, Header};
+use crate::http::util::parser::parse_host_and_port;
+use crate::http::{HttpHeaders, HttpLimits, HttpMethod, HttpRequest, HttpResponse, HttpStatus, HttpVersion, RequestTarget};
+use crate::proxy::h2::Stream;
+
+/// Headers that are specific to an HTTP/1.1 connection: malformed in
+/// HTTP/2 requests (RFC 9113 §8.2.2) and dropped from responses.
+const CONNECTION_SPECIFIC: [&str; 5] = ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+/// Build the request carried by a stream's header block. The request body,
+/// if `end_stream` is false, is read from the stream. On failure, returns
+/// the status to answer with and why.
+pub fn request_from_head(
+    headers: Vec<Header>,
+    end_stream: bool,
+    secure: bool,
+) -> Result<(HttpRequest, usize), (HttpStatus, String)> {
+    let malformed = |message: String| (HttpStatus::BadRequest, message);
+    let mut method = None;
+    let mut path = None;
+    let mut scheme = None;
+    let mut authority = None;
+    let mut fields = HttpHeaders::new();
+    let mut head_len = 0;
+    let mut regular_seen = false;
+
+    for (name, value) in headers {
+        head_len += name.len() + value.len() + 4;
+        if let Some(pseudo) = name.strip_prefix(':') {
+            let slot = match pseudo {
+                "method" => &mut method,
+                "path" => &mut path,
+                "scheme" => &mut scheme,
+                "authority" => &mut authority,
+                _ => return Err(malformed(format!("Unknown pseudo-header '{}'", name))),
+            };
+            if regular_seen || slot.is_some() {
+                return Err(malformed(format!("Misplaced or repeated pseudo-header '{}'", name)));
+            }
+            *slot = Some(value);
+            continue;
+        }
+        regular_seen = true;
+        if name.bytes().any(|b| b.is_ascii_uppercase()) {
+            return Err(malformed(format!("Header name '{}' is not lowercase", name)));
+        }
+        if CONNECTION_SPECIFIC.contains(&name.as_str()) || (name == "te" && value != "trailers") {
+            return Err(malformed(format!("Connection-specific header '{}' in an HTTP/2 request", name)));
+        }
+        // Repeated fields are folded into one, as HTTP/1.1 would list them;
+        // cookies are split into separate fields by HTTP/2 clients.
+        let separator = if name == "cookie" { "; " } else { ", " };
+        let value = match fields.get(&name) {
+            Some(existing) => format!("{}{}{}", existing, separator, value),
+            None => value,
+        };
+        fields.insert(name, value);
+    }
+
+    let method = method.ok_or_else(|| malformed("Missing :method".to_string()))?;
+    let method: HttpMethod = method
+        .parse()
+        .map_err(|_| (HttpStatus::NotImplemented, format!("Unsupported method '{}'", method)))?;
+    if method == HttpMethod::CONNECT {
+        return Err((HttpStatus::NotImplemented, "CONNECT is not supported over HTTP/2".to_string()));
+    }
+    let path = path.filter(|p| !p.is_empty()).ok_or_else(|| malformed("Missing :path".to_string()))?;
+    if scheme.is_none() {
+        return Err(malformed("Missing :scheme".to_string()));
+    }
+    if let Some(authority) = authority {
+        fields.insert("host".to_string(), authority);
+    }
+    let origin = parse_host_and_port(fields.get("host").map(|h| h.as_str()).unwrap_or("127.0.0.1"));
+
+    if let Some(length) = fields.get("content-length") {
+        let length: u64 = length
+            .trim()
+            .parse()
+            .map_err(|_| malformed("Invalid Content-Length".to_string()))?;
+        let limit = HttpLimits::MAX_BODY_SIZE as u64;
+        if length > limit {
+            return Err((HttpStatus::PayloadTooLarge, BodyError::TooLarge(limit).to_string()));
+        }
+    } else if !end_stream {
+        // DATA frames carry a body of unknown length; HTTP/1.1 upstreams
+        // get it chunked.
+        fields.insert("transfer-encoding".to_string(), "chunked".to_string());
+    }
+
+    let req = HttpRequest {
+        method,
+        path,
+        version: HttpVersion::HTTP2_0,
+        headers: fields,
+        origin,
+        target: RequestTarget::Origin,
+        body: None,
+        request_id: None,
+        secure,
+        client_cert: None,
+    };
+    Ok((req, head_len))
+}
+
+/// A request body read from a stream, limited to `MAX_BODY_SIZE`.
+pub struct RequestBody<'a> {
+    stream: &'a mut Stream,
+    read: u64,
+}
+
+impl<'a> RequestBody<'a> {
+    pub fn new(stream: &'a mut Stream) -> Self {
+        Self { stream, read: 0 }
+    }
+
+    pub fn bytes_read(&self) -> u64 {
+        self.read
+    }
+}
+
+impl Read for RequestBody<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.stream.read(buf)?;
+        self.read += n as u64;
+        let limit = HttpLimits::MAX_BODY_SIZE as u64;
+        if self.read > limit {
+            return Err(BodyError::TooLarge(limit).into());
+        }
+        Ok(n)
+    }
+}
+
+/// Send `response` on `stream`, streaming its body as DATA frames and
+/// ending with its trailers, if any. Returns the number of header block and
+/// body bytes sent. A body that fails part way resets the stream.
+pub fn send_response(stream: &Stream, response: &mut HttpResponse) -> io::Result<u64> {
+    let status = response.status_code().to_string();
+    let fields = std::iter::once((":status", status.as_str())).chain(
+        response
+            .headers
+            .iter()
+            .filter(|(name, _)| !CONNECTION_SPECIFIC.contains(&name.as_str()))
+            .map(|(name, value)| (name.as_str(), value.as_str())),
+    );
+
+    let mut sent = match &response.body {
+        Body::Buffered(bytes) if bytes.is_empty() && response.trailers.is_empty() => {
+            return stream.send_headers(fields, true).map(|n| n as u64);
+        }
+        _ => stream.send_headers(fields, false)? as u64,
+    };
+    match &mut response.body {
+        Body::Buffered(bytes) => {
+            if !bytes.is_empty() {
+                stream.send_data(bytes, false)?;
+                sent += bytes.len() as u64;
+            }
+        }
+        Body::Stream(body) => {
+            let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+            loop {
+                let n = match body.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => {
+                        stream.reset(ErrorCode::INTERNAL_ERROR);
+                        return Err(e);
+                    }
+                };
+                stream.send_data(&buf[..n], false)?;
+                sent += n as u64;
+            }
+        }
+    }
+    match response.trailers.take() {
+        Some(trailers) => {
+            let fields = trailers
+                .iter()
+                .filter(|(name, _)| !CONNECTION_SPECIFIC.contains(&name.as_str()))
+                .map(|(name, value)| (name.as_str(), value.as_str()));
+            sent += stream.send_headers(fields, true)? as u64;
+        }
+        None => stream.send_data(&[], true)?,
+    }
+    Ok(sent)
+}
+
+/// The SETTINGS payload from `HTTP2-Settings` when `req` asks to upgrade
+/// to h2c (RFC 7540 §3.2) in a form the proxy accepts: no request body, so
+/// the connection can switch protocols as soon as the head is read.
+pub fn h2c_upgrade(req: &HttpRequest) -> Option<Vec<u8>> {
+    if req.version != HttpVersion::HTTP1_1
+        || !req.headers.has_token("upgrade", "h2c")
+        || !req.headers.has_token("connection", "upgrade")
+        || !req.headers.has_token("connection", "http2-settings")
+    {
+        return None;
+    }
+    let has_body = req.headers.get("transfer-encoding").is_some()
+        || req.headers.get("content-length").is_some_and(|len| len.trim() != "0");
+    if has_body {
+        return None;
+    }
+    decode_base64url(req.headers.get("http2-settings")?.trim())
+}
+
+/// Decode unpadded base64url, as used by `HTTP2-Settings`.
+fn decode_base64url(input: &str) -> Option<Vec<u8>> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'-' => Some(62),
+        b'_' => Some(63),
+        _ => None,
+    };
+    let input = input.trim_end_matches('=').as_bytes();
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let mut bits = 0u32;
+    let mut count = 0;
+    for &c in input {
+        bits = (bits << 6) | value(c)? as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+            bits &= (1 << count) - 1;
+        }
+    }
+    Some(out)
+}
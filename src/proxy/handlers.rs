@@ -0,0 +1,104 @@
+// src/proxy/handlers.rs
+//
+// A bounded pool of handler threads for connections the reactor resumes.
+// Threads are reused from one resumed request to the next. Up to `max` run
+// at once; past that, connections queue until a thread is free. Threads
+// left idle for `idle_timeout` exit, so a quiet worker gives them back.
+
+use std::collections::VecDeque;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+use crate::log_error;
+
+pub struct HandlerPool<T> {
+    name: String,
+    max: usize,
+    idle_timeout: Duration,
+    handler: Box<dyn Fn(T) + Send + Sync>,
+    state: Mutex<State<T>>,
+    ready: Condvar,
+}
+
+struct State<T> {
+    queue: VecDeque<T>,
+    threads: usize,
+    /// Threads waiting for a job.
+    idle: usize,
+}
+
+impl<T: Send + 'static> HandlerPool<T> {
+    /// A pool running `handler` on at most `max` threads named `name`.
+    pub fn new(name: &str, max: usize, idle_timeout: Duration, handler: impl Fn(T) + Send + Sync + 'static) -> Arc<Self> {
+        Arc::new(Self {
+            name: name.to_string(),
+            max: max.max(1),
+            idle_timeout,
+            handler: Box::new(handler),
+            state: Mutex::new(State {
+                queue: VecDeque::new(),
+                threads: 0,
+                idle: 0,
+            }),
+            ready: Condvar::new(),
+        })
+    }
+
+    /// Run `handler` on `job` on an idle thread, a new one if none is idle
+    /// and the pool is below its size, or else once a thread is free.
+    pub fn submit(self: &Arc<Self>, job: T) {
+        let mut state = self.lock();
+        state.queue.push_back(job);
+        if state.idle >= state.queue.len() {
+            self.ready.notify_one();
+            return;
+        }
+        if state.threads < self.max {
+            let pool = Arc::clone(self);
+            let spawned = thread::Builder::new()
+                .name(self.name.clone())
+                .spawn(move || pool.work());
+            match spawned {
+                Ok(_) => state.threads += 1,
+                // The job stays queued for a thread already running.
+                Err(e) => log_error!("Failed to start a handler thread: {}", e),
+            }
+        }
+    }
+
+    /// Threads currently running, idle or not.
+    pub fn threads(&self) -> usize {
+        self.lock().threads
+    }
+
+    fn work(&self) {
+        let mut state = self.lock();
+        loop {
+            if let Some(job) = state.queue.pop_front() {
+                drop(state);
+                if panic::catch_unwind(AssertUnwindSafe(|| (self.handler)(job))).is_err() {
+                    log_error!("Handler thread panicked; the connection was dropped");
+                }
+                state = self.lock();
+                continue;
+            }
+            state.idle += 1;
+            let (guard, wait) = self
+                .ready
+                .wait_timeout(state, self.idle_timeout)
+                .unwrap_or_else(|e| e.into_inner());
+            state = guard;
+            state.idle -= 1;
+            if wait.timed_out() && state.queue.is_empty() {
+                state.threads -= 1;
+                return;
+            }
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
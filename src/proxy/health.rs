@@ -0,0 +1,85 @@
+// src/proxy/health.rs
+//
+// Active health checks: each upstream with a `health_check` section gets a
+// thread that periodically requests the check path from every backend and
+// flips the backend in or out of rotation after enough consecutive results.
+// HTTP/2 upstreams are checked over HTTP/2.
+
+use std::io::{Read, Write};
+use std::sync::Arc;
+use std::thread;
+
+use crate::config::HealthCheckConfig;
+use crate::http::util::parser::find_head_end;
+use crate::http::{HttpLimits, parse_http_response};
+use crate::proxy::h2;
+use crate::proxy::transport::Connector;
+use crate::proxy::upstream::{Backend, UpstreamPool};
+use crate::{log_info, log_warn};
+
+pub fn spawn_health_checks(upstreams: &[Arc<UpstreamPool>]) {
+    for pool in upstreams {
+        if let Some(check) = pool.health_check.clone() {
+            let pool = Arc::clone(pool);
+            thread::spawn(move || run(pool, check));
+        }
+    }
+}
+
+fn run(pool: Arc<UpstreamPool>, check: HealthCheckConfig) {
+    // (consecutive successes, consecutive failures) per backend
+    let mut streaks = vec![(0u32, 0u32); pool.backends.len()];
+
+    loop {
+        for (backend, streak) in pool.backends.iter().zip(streaks.iter_mut()) {
+            let passed = match pool.http2 {
+                true => h2::client::probe(&pool.connector, &backend.address, &check.path, check.timeout)
+                    .is_some_and(|status| (200..=399).contains(&status)),
+                false => probe(&pool.connector, backend, &check),
+            };
+            if passed {
+                *streak = (streak.0.saturating_add(1), 0);
+                if !backend.is_healthy() && streak.0 >= check.healthy_threshold {
+                    backend.set_healthy(true);
+                    log_info!("Upstream {} backend {} is healthy", pool.name, backend.address);
+                }
+            } else {
+                *streak = (0, streak.1.saturating_add(1));
+                if backend.is_healthy() && streak.1 >= check.unhealthy_threshold {
+                    backend.set_healthy(false);
+                    log_warn!("Upstream {} backend {} is unhealthy", pool.name, backend.address);
+                }
+            }
+        }
+        thread::sleep(check.interval);
+    }
+}
+
+/// A backend passes when it answers the check path with a 2xx or 3xx status.
+fn probe(connector: &Connector, backend: &Backend, check: &HealthCheckConfig) -> bool {
+    let Ok(mut stream) = connector.connect(&backend.address, check.timeout) else {
+        return false;
+    };
+
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nUser-Agent: Orion-HealthCheck\r\nConnection: close\r\n\r\n",
+        check.path, backend.address
+    );
+    if stream.write_all(request.as_bytes()).is_err() {
+        return false;
+    }
+
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 1024];
+    while find_head_end(&buffer).is_none() {
+        if buffer.len() > HttpLimits::MAX_HEAD_SIZE {
+            return false;
+        }
+        match stream.read(&mut chunk) {
+            Ok(0) | Err(_) => return false,
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+        }
+    }
+
+    parse_http_response(&buffer).is_ok_and(|response| (200..=399).contains(&response.status.code()))
+}
@@ -0,0 +1,28 @@
+// src/proxy/mod.rs
+
+pub mod cache;
+pub mod connection;
+pub mod compression;
+pub mod context;
+pub mod forwarder;
+pub mod h2;
+pub mod handlers;
+pub mod health;
+pub mod rate_limit;
+pub mod reactor;
+pub mod request_id;
+pub mod router;
+pub mod server;
+pub mod shutdown;
+pub mod signals;
+pub mod tls;
+pub mod transport;
+pub mod tunnel;
+pub mod upgrade;
+pub mod upstream;
+pub mod worker;
+pub mod x509;
+
+pub use context::ProxyContext;
+pub use forwarder::{ForwardError, forward_to_upstream, forward_upgrade};
+pub use server::Server;
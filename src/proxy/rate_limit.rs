@@ -0,0 +1,103 @@
+// src/proxy/rate_limit.rs
+//
+// Token buckets for the routes' rate limits, one per key. The buckets live
+// in a bounded LRU, so keys that have gone quiet make room for active ones.
+
+use std::fmt::Write;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{RateLimit, RateLimitConfig, RateLimitKeyPart};
+use crate::http::{HttpHeaders, HttpRequest};
+use crate::util::lru::Lru;
+
+pub struct RateLimiter {
+    buckets: Mutex<Lru<Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Where a bucket stands after a request has tried to take a token.
+#[derive(Debug, Clone)]
+pub struct Quota {
+    pub limit: u64,
+    pub remaining: u64,
+    /// Until the bucket is full again.
+    pub reset: Duration,
+    /// Until the next token, when the request found none.
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimiter {
+    pub fn new(config: &RateLimitConfig) -> Self {
+        Self {
+            buckets: Mutex::new(Lru::new(config.max_keys)),
+        }
+    }
+
+    /// Take a token from the bucket for `key` under `limit`, refilled for
+    /// the time since it was last used.
+    pub fn take(&self, limit: &RateLimit, key: String, now: Instant) -> Quota {
+        let burst = limit.burst as f64;
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        let mut bucket = buckets.remove(&key).unwrap_or(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.rate).min(burst);
+        bucket.updated = now;
+
+        let retry_after = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - bucket.tokens) / limit.rate))
+        };
+        let quota = Quota {
+            limit: limit.burst,
+            remaining: bucket.tokens as u64,
+            reset: Duration::from_secs_f64((burst - bucket.tokens) / limit.rate),
+            retry_after,
+        };
+        buckets.insert(key, bucket, 1);
+        quota
+    }
+}
+
+/// The bucket key for `req` on the route named `route` under `limit`.
+pub fn key(limit: &RateLimit, req: &HttpRequest, route: &str, client: IpAddr) -> String {
+    let mut key = limit.scope.clone().unwrap_or_default();
+    for part in &limit.key {
+        // Field values cannot hold a line break, so keys cannot run together.
+        key.push('\n');
+        let _ = match part {
+            RateLimitKeyPart::ClientIp => write!(key, "{}", client),
+            RateLimitKeyPart::Route => write!(key, "{}", route),
+            RateLimitKeyPart::Header(name) => write!(key, "{}", req.headers.get(name).map_or("", |v| v.as_str())),
+        };
+    }
+    key
+}
+
+impl Quota {
+    /// `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset`, plus
+    /// `Retry-After` for a refused request; times in whole seconds, rounded
+    /// up.
+    pub fn apply(&self, headers: &mut HttpHeaders) {
+        headers.insert("RateLimit-Limit".to_string(), self.limit.to_string());
+        headers.insert("RateLimit-Remaining".to_string(), self.remaining.to_string());
+        headers.insert("RateLimit-Reset".to_string(), seconds(self.reset).to_string());
+        if let Some(retry_after) = self.retry_after {
+            headers.insert("Retry-After".to_string(), seconds(retry_after).max(1).to_string());
+        }
+    }
+}
+
+fn seconds(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
@@ -0,0 +1,168 @@
+// src/proxy/reactor.rs
+//
+// An epoll reactor for connections waiting on their client. Requests are
+// still served by blocking handler threads, but a connection waiting for
+// its first request, the next flight of its TLS handshake, or its next
+// keep-alive request is parked here instead of holding a thread in `read`:
+// one reactor thread waits on every parked socket and hands each back when
+// the client sends something (or closes). Connections left idle past their
+// timeout are dropped, which closes them.
+
+use std::collections::HashMap;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::ptr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+const MAX_EVENTS: usize = 256;
+
+/// How often parked connections are checked against their idle timeout.
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+pub struct Reactor<T> {
+    epoll: OwnedFd,
+    next_token: AtomicU64,
+    parked: Mutex<HashMap<u64, Parked<T>>>,
+}
+
+struct Parked<T> {
+    fd: RawFd,
+    deadline: Instant,
+    item: T,
+}
+
+impl<T: Send> Reactor<T> {
+    pub fn new() -> io::Result<Self> {
+        // SAFETY: epoll_create1 takes no pointers; failure is checked below.
+        let fd = unsafe { libc::epoll_create1(libc::EPOLL_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            // SAFETY: `fd` is a new, valid descriptor that nothing else owns.
+            epoll: unsafe { OwnedFd::from_raw_fd(fd) },
+            next_token: AtomicU64::new(0),
+            parked: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Connections currently parked.
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Hold `item` until `fd` becomes readable or hangs up, then pass it to
+    /// the `resume` callback of `run`. Gives the item back if `fd` cannot be
+    /// watched.
+    pub fn park(&self, fd: RawFd, item: T, idle_timeout: Duration) -> Result<(), T> {
+        let token = self.next_token.fetch_add(1, Ordering::Relaxed);
+        // Insert before watching, so an event can never arrive for an
+        // unknown token.
+        let mut parked = self.lock();
+        parked.insert(
+            token,
+            Parked {
+                fd,
+                deadline: Instant::now() + idle_timeout,
+                item,
+            },
+        );
+        let mut event = libc::epoll_event {
+            events: (libc::EPOLLIN | libc::EPOLLRDHUP | libc::EPOLLONESHOT) as u32,
+            u64: token,
+        };
+        // SAFETY: `event` is a valid epoll_event that outlives the call, and
+        // the kernel copies it. A bad `fd` is reported as an error.
+        let rc = unsafe { libc::epoll_ctl(self.epoll.as_raw_fd(), libc::EPOLL_CTL_ADD, fd, &mut event) };
+        if rc < 0 {
+            return match parked.remove(&token) {
+                Some(entry) => Err(entry.item),
+                None => Ok(()),
+            };
+        }
+        Ok(())
+    }
+
+    /// Wait for parked connections to become ready and hand each one to
+    /// `resume`, dropping those idle past their timeout. Only returns if
+    /// waiting fails.
+    pub fn run(&self, resume: impl Fn(T)) -> io::Result<()> {
+        let mut events = [libc::epoll_event { events: 0, u64: 0 }; MAX_EVENTS];
+        let mut next_sweep = Instant::now() + SWEEP_INTERVAL;
+        loop {
+            let timeout = next_sweep.saturating_duration_since(Instant::now()).as_millis() as libc::c_int;
+            // SAFETY: `events` is writable for MAX_EVENTS entries, the count
+            // passed, and the kernel fills at most that many.
+            let n = unsafe {
+                libc::epoll_wait(
+                    self.epoll.as_raw_fd(),
+                    events.as_mut_ptr(),
+                    MAX_EVENTS as libc::c_int,
+                    timeout,
+                )
+            };
+            if n < 0 {
+                let e = io::Error::last_os_error();
+                if e.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(e);
+            }
+
+            for event in &events[..n as usize] {
+                let token = event.u64;
+                if let Some(item) = self.unpark(token) {
+                    resume(item);
+                }
+            }
+
+            if Instant::now() >= next_sweep {
+                self.expire();
+                next_sweep = Instant::now() + SWEEP_INTERVAL;
+            }
+        }
+    }
+
+    fn unpark(&self, token: u64) -> Option<T> {
+        let entry = self.lock().remove(&token)?;
+        self.unwatch(entry.fd);
+        Some(entry.item)
+    }
+
+    /// Drop every connection whose idle timeout has passed.
+    fn expire(&self) {
+        let now = Instant::now();
+        let expired: Vec<Parked<T>> = {
+            let mut parked = self.lock();
+            let tokens: Vec<u64> = parked
+                .iter()
+                .filter(|(_, entry)| entry.deadline <= now)
+                .map(|(token, _)| *token)
+                .collect();
+            tokens.iter().filter_map(|token| parked.remove(token)).collect()
+        };
+        for entry in &expired {
+            self.unwatch(entry.fd);
+        }
+    }
+
+    /// Stop watching `fd`. Needed even for one-shot events: the socket may
+    /// have duplicates (see `Shutdown::register`) that keep it registered.
+    fn unwatch(&self, fd: RawFd) {
+        // SAFETY: EPOLL_CTL_DEL ignores the event pointer, so null is allowed
+        // (since Linux 2.6.9). A stale `fd` is reported as an error.
+        unsafe {
+            libc::epoll_ctl(self.epoll.as_raw_fd(), libc::EPOLL_CTL_DEL, fd, ptr::null_mut());
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<u64, Parked<T>>> {
+        self.parked.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
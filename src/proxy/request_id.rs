@@ -0,0 +1,116 @@
+// src/proxy/request_id.rs
+//
+// Correlation IDs for requests passing through the proxy. IDs only need to be
+// unique, not unpredictable, so a per-thread SplitMix64 generator seeded from
+// the std hasher keys is used instead of an RNG crate.
+
+use std::cell::Cell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::IpAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{RequestIdConfig, RequestIdFormat};
+use crate::http::HttpRequest;
+
+/// Longest client supplied ID we are willing to reuse.
+const MAX_INCOMING_ID_LEN: usize = 128;
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+thread_local! {
+    static STATE: Cell<u64> = Cell::new(seed());
+}
+
+fn seed() -> u64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u128(
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos(),
+    );
+    hasher.finish()
+}
+
+fn next_u64() -> u64 {
+    STATE.with(|state| {
+        let next = state.get().wrapping_add(0x9E37_79B9_7F4A_7C15);
+        state.set(next);
+        let mut z = next;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    })
+}
+
+pub fn generate(format: RequestIdFormat) -> String {
+    match format {
+        RequestIdFormat::Uuid => uuid_v4(),
+        RequestIdFormat::Ulid => ulid(),
+    }
+}
+
+/// Random UUID (version 4, RFC 4122 variant) in hyphenated form.
+pub fn uuid_v4() -> String {
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&next_u64().to_be_bytes());
+    bytes[8..].copy_from_slice(&next_u64().to_be_bytes());
+    bytes[6] = (bytes[6] & 0x0F) | 0x40;
+    bytes[8] = (bytes[8] & 0x3F) | 0x80;
+
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// ULID: 48 bit millisecond timestamp followed by 80 random bits,
+/// Crockford base32 encoded (26 characters, lexically sortable by time).
+pub fn ulid() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+        & ((1 << 48) - 1);
+    let random = ((next_u64() as u128) << 16 | (next_u64() & 0xFFFF) as u128) & ((1 << 80) - 1);
+    let mut value = millis << 80 | random;
+
+    let mut out = [0u8; 26];
+    for slot in out.iter_mut().rev() {
+        *slot = CROCKFORD[(value & 0x1F) as usize];
+        value >>= 5;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Client supplied IDs end up in headers and log lines, so only a
+/// conservative character set is accepted.
+fn is_valid_incoming(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_INCOMING_ID_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':'))
+}
+
+/// Pick the ID for `req`: reuse a trusted incoming one, otherwise generate a
+/// fresh one. The ID is stored on the request and in its headers so it is
+/// forwarded upstream.
+pub fn assign(req: &mut HttpRequest, client: IpAddr, config: &RequestIdConfig) -> String {
+    let incoming = req
+        .headers
+        .get(&config.header)
+        .filter(|id| config.trusts(client) && is_valid_incoming(id))
+        .cloned();
+
+    let id = incoming.unwrap_or_else(|| generate(config.format));
+    req.headers.insert(config.header.clone(), id.clone());
+    req.request_id = Some(id.clone());
+    id
+}
@@ -0,0 +1,87 @@
+// src/proxy/router.rs
+
+use std::sync::Arc;
+
+use crate::config::{CacheKeyPart, RateLimit, RouteConfig};
+use crate::http::{ClientCertificate, HttpRequest};
+use crate::http::util::parser::extract_query_params;
+use crate::proxy::upstream::UpstreamPool;
+
+#[derive(Debug)]
+pub struct Route {
+    pub name: String,
+    pub host: Option<String>,
+    pub prefix: String,
+    pub client_subjects: Vec<String>,
+    pub upstream: Arc<UpstreamPool>,
+    /// Set when the route's responses are cached.
+    pub cache_key: Option<Vec<CacheKeyPart>>,
+    pub decompress_requests: bool,
+    pub decompress_responses: bool,
+    pub rate_limit: Option<RateLimit>,
+}
+
+impl Route {
+    fn matches(&self, host: &str, path: &str, client_cert: Option<&ClientCertificate>) -> bool {
+        self.host.as_ref().is_none_or(|h| h.eq_ignore_ascii_case(host))
+            && path_has_prefix(path, &self.prefix)
+            && (self.client_subjects.is_empty()
+                || client_cert.is_some_and(|cert| self.client_subjects.iter().any(|s| cert.matches(s))))
+    }
+}
+
+/// `/api` matches `/api` and `/api/users` but not `/apiary`.
+fn path_has_prefix(path: &str, prefix: &str) -> bool {
+    match path.strip_prefix(prefix) {
+        Some(rest) => prefix.ends_with('/') || rest.is_empty() || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+#[derive(Debug)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    /// Build the routing table. Routes are tried host-specific first, then by
+    /// longest prefix, then client-certificate-specific, so the most specific
+    /// match always wins.
+    pub fn new(configs: &[RouteConfig], upstreams: &[Arc<UpstreamPool>]) -> Self {
+        let mut routes: Vec<Route> = configs
+            .iter()
+            .filter_map(|config| {
+                let upstream = upstreams.iter().find(|u| u.name == config.upstream)?;
+                Some(Route {
+                    name: config.name.clone(),
+                    host: config.host.clone(),
+                    prefix: config.prefix.clone(),
+                    client_subjects: config.client_subjects.clone(),
+                    upstream: Arc::clone(upstream),
+                    cache_key: config.cache_key.clone(),
+                    decompress_requests: config.decompress_requests,
+                    decompress_responses: config.decompress_responses,
+                    rate_limit: config.rate_limit.clone(),
+                })
+            })
+            .collect();
+        routes.sort_by(|a, b| {
+            b.host
+                .is_some()
+                .cmp(&a.host.is_some())
+                .then(b.prefix.len().cmp(&a.prefix.len()))
+                .then(a.client_subjects.is_empty().cmp(&b.client_subjects.is_empty()))
+        });
+        Self { routes }
+    }
+
+    pub fn route(&self, req: &HttpRequest) -> Option<&Route> {
+        let (path, _) = extract_query_params(&req.path);
+        let host = req.origin.0.as_str();
+        self.routes.iter().find(|route| route.matches(host, &path, req.client_cert.as_ref()))
+    }
+
+    pub fn routes(&self) -> &[Route] {
+        &self.routes
+    }
+}
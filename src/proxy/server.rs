@@ -1 +1,1449 @@
-// This will serve as the entry point for the reverse proxy server
\ No newline at end of file
+// This will serve as the entry point for the reverse proxy server
+// src/proxy/server.rs
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::os::fd::AsRawFd;
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+
+use crate::admin;
+use crate::config::{CacheKeyPart, Config, ForwardProxyConfig};
+use crate::http::util::{create_error_response_with_id, create_request_error_response};
+use crate::http::util::parser::parse_host_and_port;
+use crate::http::{
+    Body, HttpHeaders, HttpMethod, HttpRequest, HttpResponse, HttpStatus, HttpVersion, RequestTarget, verify_http_request,
+};
+use crate::json::ObjectWriter;
+use crate::logging::AccessLogEntry;
+use crate::metrics::{Gauge, Metrics};
+use crate::proxy::cache::policy::{self, CacheControl};
+use crate::proxy::cache::{self, Cache, Entry, Join, Purge};
+use crate::proxy::connection::{ClientStream, Connection, ReadError};
+use crate::proxy::context::ProxyContext;
+use crate::proxy::forwarder::{Upgraded, forward_http2, forward_to_upstream, forward_upgrade};
+use crate::proxy::handlers::HandlerPool;
+use crate::proxy::reactor::Reactor;
+use crate::proxy::router::Route;
+use crate::proxy::shutdown::TrackedConnection;
+use crate::proxy::transport::Connector;
+use crate::proxy::tunnel::Relayed;
+use crate::proxy::upstream::{ConnectionSlot, UpstreamPool};
+use crate::proxy::upgrade::{self, Inherited};
+use crate::proxy::tls::{ClientIdentity, Handshake, TlsAcceptor};
+use crate::proxy::{compression, h2, health, rate_limit, request_id, signals, tls, tunnel, worker};
+use crate::{log_debug, log_error, log_info, log_warn};
+
+/// Handler threads per worker for connections resumed by its reactor.
+const MAX_RESUME_HANDLERS: usize = 256;
+/// How long a handler thread waits for another connection before exiting.
+const HANDLER_IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+
+pub struct Server {
+    context: Arc<ProxyContext>,
+}
+
+impl Server {
+    pub fn new(config: Config) -> io::Result<Self> {
+        Ok(Self {
+            context: Arc::new(ProxyContext::new(config)?),
+        })
+    }
+
+    /// Bind the listeners and serve connections until a graceful shutdown
+    /// has completed. Each of `server.workers` accept threads has its own
+    /// listeners and reactor; requests are served on handler threads, and
+    /// connections waiting for a request (or their next TLS handshake
+    /// flight) wait in the reactor without one. HTTP/2 connections, tunnels
+    /// and upstream requests still hold a thread each while they last.
+    ///
+    /// With `server.upgrade_socket` configured, listeners are taken over from
+    /// a running instance when there is one, and the previous instance is
+    /// told to drain once this one is accepting.
+    pub fn run(&self) -> io::Result<()> {
+        let config = &self.context.config;
+        let mut inherited = match &config.server.upgrade_socket {
+            Some(path) => upgrade::inherit(path)?,
+            None => None,
+        };
+
+        let mut workers = Vec::new();
+        let mut handoff = Vec::new();
+        for index in 0..config.server.workers {
+            let mut listeners = Vec::new();
+            let name = listener_name("proxy", index);
+            let listener = bind_listener(inherited.as_mut(), &name, config.server.listen)?;
+            if index == 0 {
+                log_info!("Listening on {}", listener.local_addr()?);
+            }
+            listeners.push(Listener { name, listener, tls: None });
+            if let (Some(tls), Some(acceptor)) = (&config.tls, &self.context.tls) {
+                let name = listener_name("tls", index);
+                let listener = bind_listener(inherited.as_mut(), &name, tls.listen)?;
+                if index == 0 {
+                    log_info!("Listening for HTTPS on {}", listener.local_addr()?);
+                }
+                listeners.push(Listener { name, listener, tls: Some(Arc::clone(acceptor)) });
+            }
+            workers.push(listeners);
+        }
+        if let Some(inherited) = inherited.as_mut() {
+            adopt_extra_listeners(inherited, &mut workers, config, self.context.tls.as_ref())?;
+        }
+        for listener in workers.iter().flatten() {
+            listener.listener.set_nonblocking(true)?;
+            handoff.push((listener.name.clone(), listener.listener.as_raw_fd()));
+        }
+
+        signals::install_handlers()?;
+        let context = Arc::clone(&self.context);
+        thread::spawn(move || watch_signals(context));
+
+        health::spawn_health_checks(&self.context.upstreams);
+        if let (Some(interval), Some(acceptor)) = (config.tls.as_ref().and_then(|t| t.reload_interval), &self.context.tls) {
+            tls::spawn_reloader(Arc::clone(acceptor), interval);
+        }
+        if let Some(admin) = &config.admin {
+            let admin_listener = bind_listener(inherited.as_mut(), "admin", admin.listen)?;
+            handoff.push(("admin".to_string(), admin_listener.as_raw_fd()));
+            admin::spawn(admin_listener, Arc::clone(&self.context))?;
+        }
+
+        let cpus = if config.server.pin_workers {
+            worker::allowed_cpus()?
+        } else {
+            Vec::new()
+        };
+        let mut handles = Vec::new();
+        for (index, listeners) in workers.into_iter().enumerate() {
+            let reactor = Arc::new(Reactor::new()?);
+            let cpu = (!cpus.is_empty()).then(|| cpus[index % cpus.len()]);
+            let context = Arc::clone(&self.context);
+            let handle = thread::Builder::new()
+                .name(format!("worker-{}", index))
+                .spawn(move || run_worker(index, listeners, cpu, reactor, context))?;
+            handles.push(handle);
+        }
+
+        if let Some(path) = &config.server.upgrade_socket {
+            upgrade::spawn_handoff_server(path.clone(), handoff, Arc::clone(&self.context))?;
+        }
+        if let Some(previous) = inherited {
+            previous.ready()?;
+            log_info!("Took over listeners from the previous process");
+        }
+
+        // Workers return once shutdown is requested, closing their listeners:
+        // new connections are refused from here on.
+        let mut result = Ok(());
+        for handle in handles {
+            match handle.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => result = Err(e),
+                Err(_) => result = Err(io::Error::other("worker thread panicked")),
+            }
+        }
+
+        let shutdown = &self.context.shutdown;
+        let grace = self.context.config.server.shutdown_grace;
+        log_info!(
+            "Shutting down, waiting up to {:?} for {} in-flight requests",
+            grace,
+            shutdown.in_flight()
+        );
+        if shutdown.drain(grace) {
+            log_info!("Shutdown complete");
+        } else {
+            log_warn!(
+                "Grace period expired with {} requests in flight on {} open connections",
+                shutdown.in_flight(),
+                shutdown.open_connections()
+            );
+        }
+        result
+    }
+}
+
+/// Accept connections on one worker's listeners until shutdown is
+/// requested. A failing worker requests shutdown for the whole process.
+fn run_worker(
+    index: usize,
+    listeners: Vec<Listener>,
+    cpu: Option<usize>,
+    reactor: Arc<Reactor<Waiting>>,
+    context: Arc<ProxyContext>,
+) -> io::Result<()> {
+    if let Some(cpu) = cpu {
+        match worker::pin_to_cpu(cpu) {
+            Ok(()) => log_debug!("Worker {} pinned to CPU {}", index, cpu),
+            Err(e) => log_warn!("Could not pin worker {} to CPU {}: {}", index, cpu, e),
+        }
+    }
+    let handlers = spawn_reactor(index, Arc::clone(&reactor), Arc::clone(&context));
+
+    let label = index.to_string();
+    let shutdown = &context.shutdown;
+    while !shutdown.is_requested() {
+        let ready = match wait_readable(&listeners, Duration::from_millis(250)) {
+            Ok(ready) => ready,
+            Err(e) => {
+                log_error!("Worker {} stopped accepting: {}", index, e);
+                shutdown.request();
+                return Err(e);
+            }
+        };
+        for ready in ready {
+            let listener = &listeners[ready];
+            match listener.listener.accept() {
+                Ok((stream, _)) => {
+                    let _ = stream.set_nonblocking(false);
+                    shutdown.accepted();
+                    context.metrics.worker_connections.inc(&[&label]);
+                    let Some(accepted) = accept(stream, listener.tls.as_ref(), &label, &context) else { continue };
+                    // Nothing is read until the client has sent something.
+                    let fd = accepted.transport.tcp().as_raw_fd();
+                    if let Err(waiting) = reactor.park(fd, Waiting::New(accepted), context.config.server.read_timeout) {
+                        handlers.submit(waiting);
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                Err(e) => log_warn!("Failed to accept connection: {}", e),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Handoff name of a worker's listener: `proxy`, `proxy.1`, ... The first
+/// worker keeps the plain name, so upgrades from single-worker processes
+/// still find it.
+fn listener_name(kind: &str, worker: usize) -> String {
+    match worker {
+        0 => kind.to_string(),
+        n => format!("{}.{}", kind, n),
+    }
+}
+
+/// Reuse the listener inherited under `name` when it is still bound to
+/// `addr`; otherwise bind a new one.
+fn bind_listener(inherited: Option<&mut Inherited>, name: &str, addr: SocketAddr) -> io::Result<TcpListener> {
+    if let Some(listener) = inherited.and_then(|i| i.take(name)) {
+        let bound = listener.local_addr()?;
+        if bound == addr {
+            return Ok(listener);
+        }
+        log_warn!("Inherited {} listener is bound to {}, binding {} instead", name, bound, addr);
+    }
+    worker::bind_reuseport(addr)
+}
+
+/// Share out among `workers` the inherited listeners of workers the previous
+/// process had beyond this one's, round-robin. Closing them would reset the
+/// connections queued on them; they are handed on again at the next upgrade.
+fn adopt_extra_listeners(
+    inherited: &mut Inherited,
+    workers: &mut [Vec<Listener>],
+    config: &Config,
+    acceptor: Option<&Arc<TlsAcceptor>>,
+) -> io::Result<()> {
+    let mut extra: Vec<(String, TcpListener, SocketAddr, Option<Arc<TlsAcceptor>>)> = inherited
+        .take_extra("proxy")
+        .into_iter()
+        .map(|(name, listener)| (name, listener, config.server.listen, None))
+        .collect();
+    if let (Some(tls), Some(acceptor)) = (&config.tls, acceptor) {
+        for (name, listener) in inherited.take_extra("tls") {
+            extra.push((name, listener, tls.listen, Some(Arc::clone(acceptor))));
+        }
+    }
+
+    for (index, (name, listener, addr, tls)) in extra.into_iter().enumerate() {
+        let bound = listener.local_addr()?;
+        if bound != addr {
+            log_warn!("Closing inherited {} listener, bound to {} rather than {}", name, bound, addr);
+            continue;
+        }
+        let worker = index % workers.len();
+        log_info!("Worker {} takes over inherited listener {}", worker, name);
+        workers[worker].push(Listener { name, listener, tls });
+    }
+    Ok(())
+}
+
+/// A bound client listener; `tls` is set for HTTPS listeners.
+struct Listener {
+    name: String,
+    listener: TcpListener,
+    tls: Option<Arc<TlsAcceptor>>,
+}
+
+/// Wait up to `timeout` for pending connections, returning the indices of
+/// the listeners that have one.
+fn wait_readable(listeners: &[Listener], timeout: Duration) -> io::Result<Vec<usize>> {
+    let mut fds: Vec<libc::pollfd> = listeners
+        .iter()
+        .map(|l| libc::pollfd {
+            fd: l.listener.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        })
+        .collect();
+    // SAFETY: `fds` is a valid array of pollfds for the duration of the call.
+    let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout.as_millis() as libc::c_int) };
+    if ready < 0 {
+        let err = io::Error::last_os_error();
+        return match err.kind() {
+            io::ErrorKind::Interrupted => Ok(Vec::new()),
+            _ => Err(err),
+        };
+    }
+    Ok(fds
+        .iter()
+        .enumerate()
+        .filter(|(_, fd)| fd.revents != 0)
+        .map(|(index, _)| index)
+        .collect())
+}
+
+/// Act on signals recorded by the handlers in `signals`.
+fn watch_signals(context: Arc<ProxyContext>) {
+    loop {
+        thread::sleep(Duration::from_millis(250));
+        if signals::terminate_requested() && !context.shutdown.is_requested() {
+            log_info!("Received termination signal");
+            context.shutdown.request();
+        }
+        if signals::take_reopen_request()
+            && let Some(access_log) = &context.access_log
+        {
+            match access_log.reopen() {
+                Ok(()) => log_info!("Reopened access log"),
+                Err(e) => log_error!("Failed to reopen access log: {}", e),
+            }
+        }
+    }
+}
+
+/// Run the reactor on its own thread. Resumed connections are served on
+/// the worker's pool of handler threads, which is returned, rather than a
+/// thread each; only waiting connections are without a thread, requests
+/// still block theirs.
+fn spawn_reactor(
+    index: usize,
+    reactor: Arc<Reactor<Waiting>>,
+    context: Arc<ProxyContext>,
+) -> Arc<HandlerPool<Waiting>> {
+    let handlers = {
+        let (context, reactor) = (Arc::clone(&context), Arc::clone(&reactor));
+        HandlerPool::new(
+            &format!("handler-{}", index),
+            MAX_RESUME_HANDLERS,
+            HANDLER_IDLE_TIMEOUT,
+            move |waiting| match waiting {
+                Waiting::New(accepted) => open(accepted, Arc::clone(&context), Arc::clone(&reactor)),
+                Waiting::Idle(session) => serve(session, Arc::clone(&context), Arc::clone(&reactor)),
+            },
+        )
+    };
+    let pool = Arc::clone(&handlers);
+    thread::spawn(move || {
+        if let Err(e) = reactor.run(|waiting| pool.submit(waiting)) {
+            log_error!("Connection reactor stopped: {}", e);
+        }
+    });
+    handlers
+}
+
+/// Keeps a connection gauge accurate however the connection ends.
+struct Counted {
+    context: Arc<ProxyContext>,
+    gauge: fn(&Metrics) -> &Gauge,
+}
+
+impl Counted {
+    fn new(context: &Arc<ProxyContext>, gauge: fn(&Metrics) -> &Gauge) -> Self {
+        gauge(&context.metrics).inc();
+        Self {
+            context: Arc::clone(context),
+            gauge,
+        }
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        (self.gauge)(&self.context.metrics).dec();
+    }
+}
+
+/// Which backend served a request and how long it took.
+struct UpstreamTiming {
+    backend: String,
+    latency: Duration,
+}
+
+/// A connection waiting in the reactor.
+pub(crate) enum Waiting {
+    /// Accepted, and yet to send its first request or finish its TLS
+    /// handshake.
+    New(Accepted),
+    /// Idle between requests.
+    Idle(Session),
+}
+
+/// A connection before its first request.
+pub(crate) struct Accepted {
+    transport: Transport,
+    peer: SocketAddr,
+    tracked: TrackedConnection,
+    worker: String,
+    active: Counted,
+}
+
+enum Transport {
+    Plain(TcpStream),
+    Handshaking(Arc<TlsAcceptor>, Box<Handshake>),
+}
+
+impl Transport {
+    fn tcp(&self) -> &TcpStream {
+        match self {
+            Transport::Plain(stream) => stream,
+            Transport::Handshaking(_, handshake) => handshake.tcp(),
+        }
+    }
+}
+
+/// An open client connection, handed between handler threads and the
+/// reactor.
+pub(crate) struct Session {
+    conn: Connection,
+    peer: SocketAddr,
+    identity: ClientIdentity,
+    tracked: TrackedConnection,
+    /// Metrics label of the worker that accepted the connection.
+    worker: String,
+    _active: Counted,
+    /// Set while parked in the reactor.
+    idle: Option<Counted>,
+}
+
+/// Track a newly accepted connection and, on an HTTPS listener, start its
+/// TLS handshake.
+fn accept(
+    stream: TcpStream,
+    tls: Option<&Arc<TlsAcceptor>>,
+    worker: &str,
+    context: &Arc<ProxyContext>,
+) -> Option<Accepted> {
+    let tracked = context.shutdown.register(&stream);
+    let peer = stream.peer_addr().ok()?;
+    let active = Counted::new(context, |m| &m.active_connections);
+    let _ = stream.set_read_timeout(Some(context.config.server.read_timeout));
+    let transport = match tls {
+        Some(acceptor) => match acceptor.start(stream) {
+            Ok(handshake) => Transport::Handshaking(Arc::clone(acceptor), Box::new(handshake)),
+            Err(e) => {
+                log_debug!("TLS handshake with {} failed: {}", peer, e);
+                return None;
+            }
+        },
+        None => Transport::Plain(stream),
+    };
+    Some(Accepted {
+        transport,
+        peer,
+        tracked,
+        worker: worker.to_string(),
+        active,
+    })
+}
+
+/// Take a connection the reactor resumed before its first request through
+/// its TLS handshake, parking it again while it waits for the client's next
+/// flight, then serve it.
+fn open(mut accepted: Accepted, context: Arc<ProxyContext>, reactor: Arc<Reactor<Waiting>>) {
+    let peer = accepted.peer;
+    let (stream, identity) = loop {
+        match accepted.transport {
+            Transport::Plain(stream) => break (ClientStream::Plain(stream), Ok(None)),
+            Transport::Handshaking(acceptor, mut handshake) => match handshake.advance() {
+                Ok(true) => break acceptor.finish(*handshake),
+                Ok(false) => {
+                    accepted.transport = Transport::Handshaking(acceptor, handshake);
+                    let fd = accepted.transport.tcp().as_raw_fd();
+                    // If the connection cannot be parked, wait for the flight here.
+                    match reactor.park(fd, Waiting::New(accepted), context.config.server.read_timeout) {
+                        Err(Waiting::New(returned)) => accepted = returned,
+                        _ => return,
+                    }
+                }
+                Err(e) => {
+                    log_debug!("TLS handshake with {} failed: {}", peer, e);
+                    return;
+                }
+            },
+        }
+    };
+
+    let http2 = context.config.server.http2;
+    let negotiated_h2 = http2 && stream.negotiated_h2();
+    let mut conn = Connection::new(stream);
+    // Cleartext clients with prior knowledge open with the HTTP/2 preface.
+    let prior_knowledge = http2 && !conn.stream.is_secure() && conn.starts_with_h2_preface();
+    let session = Session {
+        conn,
+        peer,
+        identity,
+        tracked: accepted.tracked,
+        worker: accepted.worker,
+        _active: accepted.active,
+        idle: None,
+    };
+    if negotiated_h2 || prior_knowledge {
+        thread::spawn(move || serve_http2(session, context, None));
+        return;
+    }
+    // A finished handshake need not have a request behind it yet.
+    let session = match session.conn.stream.is_secure() {
+        true => match park(session, &context, &reactor) {
+            Some(session) => session,
+            None => return,
+        },
+        false => session,
+    };
+    serve(session, context, reactor);
+}
+
+/// Serve requests until the connection closes. Whenever it goes idle between
+/// requests it is parked in the reactor, which resumes it on a new thread.
+/// Tunnels and h2c connections last as long as the client likes, so they
+/// leave for a thread of their own rather than hold a handler thread.
+fn serve(mut session: Session, context: Arc<ProxyContext>, reactor: Arc<Reactor<Waiting>>) {
+    session.idle = None;
+    loop {
+        match serve_request(&mut session, &context) {
+            Next::KeepAlive => {}
+            Next::Close => return,
+            Next::Tunnel(opened) => {
+                thread::spawn(move || relay(session, &context, *opened));
+                return;
+            }
+            Next::Http2(upgrade) => {
+                thread::spawn(move || serve_http2(session, context, Some(*upgrade)));
+                return;
+            }
+        }
+        session = match park(session, &context, &reactor) {
+            Some(session) => session,
+            None => return,
+        };
+    }
+}
+
+/// Park `session` in the reactor if it is idle. Gives it back if it has a
+/// request waiting, or cannot be parked.
+fn park(mut session: Session, context: &Arc<ProxyContext>, reactor: &Reactor<Waiting>) -> Option<Session> {
+    if !session.conn.is_idle() {
+        return Some(session);
+    }
+    session.idle = Some(Counted::new(context, |m| &m.idle_connections));
+    let fd = session.conn.stream.tcp().as_raw_fd();
+    match reactor.park(fd, Waiting::Idle(session), context.config.server.read_timeout) {
+        Err(Waiting::Idle(mut returned)) => {
+            returned.idle = None;
+            Some(returned)
+        }
+        _ => None,
+    }
+}
+
+/// What happens to a connection after a request.
+enum Next {
+    KeepAlive,
+    Close,
+    /// The response switched protocols; relay the tunnel behind it.
+    Tunnel(Box<OpenedTunnel>),
+    /// The client asked to switch to h2c.
+    Http2(Box<H2cUpgrade>),
+}
+
+/// A request answered by opening a tunnel, recorded once the tunnel closes.
+struct OpenedTunnel {
+    req: HttpRequest,
+    answer: Answer,
+    head_len: usize,
+    written: usize,
+}
+
+/// An HTTP/1.1 request with `Upgrade: h2c`, answered on stream 1 once the
+/// connection has switched.
+struct H2cUpgrade {
+    req: HttpRequest,
+    head_len: usize,
+    settings: Vec<u8>,
+}
+
+/// Read and answer one request.
+fn serve_request(session: &mut Session, context: &ProxyContext) -> Next {
+    let config = &context.config;
+    let peer = session.peer;
+    let (mut req, head_len) = match session.conn.read_request() {
+        Ok(read) => read,
+        Err(ReadError::Closed) => return Next::Close,
+        Err(ReadError::Parse(e)) => {
+            context.metrics.record_parse_error(&e);
+            reject(&mut session.conn, context, peer, HttpStatus::BadRequest, e.to_string());
+            return Next::Close;
+        }
+        Err(ReadError::Rejected(status, message)) => {
+            reject(&mut session.conn, context, peer, status, message);
+            return Next::Close;
+        }
+    };
+    if req.version == HttpVersion::HTTP1_0 && req.headers.get("host").is_none() {
+        let host = default_host(config, &session.conn.stream);
+        // Routing matches on the origin, which the parser took from the
+        // Host it did not have.
+        req.origin = parse_host_and_port(&host);
+        req.headers.insert("Host".to_string(), host);
+    }
+    if config.server.http2
+        && !session.conn.stream.is_secure()
+        && let Some(settings) = h2::server::h2c_upgrade(&req)
+    {
+        return Next::Http2(Box::new(H2cUpgrade { req, head_len, settings }));
+    }
+
+    let _in_flight = context.shutdown.begin_request();
+    let mut answer = answer(context, &mut req, &session.identity, &mut session.conn.body(), peer);
+    let response = &mut answer.response;
+
+    let mut keep_alive = keep_alive(&req, response)
+        && !context.shutdown.is_requested()
+        && !session.conn.body_failed()
+        && !session.conn.awaits_continue();
+    // Whatever the upstream spoke, the client hears its own version.
+    response.version = match req.version {
+        HttpVersion::HTTP1_0 => HttpVersion::HTTP1_0,
+        _ => HttpVersion::HTTP1_1,
+    };
+    if req.version == HttpVersion::HTTP1_0 {
+        // No chunked coding in HTTP/1.0: such bodies end with the connection.
+        if response.headers.remove("transfer-encoding").is_some() {
+            response.headers.remove("trailer");
+            keep_alive = false;
+        }
+    }
+    if response.body.is_stream() && response.headers.get("content-length").is_none() {
+        if req.version == HttpVersion::HTTP1_1 {
+            response
+                .headers
+                .insert("Transfer-Encoding".to_string(), "chunked".to_string());
+        } else {
+            // The body ends when the connection does.
+            keep_alive = false;
+        }
+    }
+    if !keep_alive && answer.tunnel.is_none() {
+        response
+            .headers
+            .insert("Connection".to_string(), "close".to_string());
+    } else if keep_alive && req.version == HttpVersion::HTTP1_0 {
+        response
+            .headers
+            .insert("Connection".to_string(), "keep-alive".to_string());
+    }
+
+    let written = response.write_to(&mut session.conn.stream);
+    if let Err(e) = &written {
+        log_warn!(id: Some(&answer.id), "Response to {} was cut short: {}", peer, e);
+    }
+    if let (Ok(n), Some(_)) = (&written, &answer.tunnel) {
+        let written = *n as usize;
+        return Next::Tunnel(Box::new(OpenedTunnel { req, answer, head_len, written }));
+    }
+    session.tracked.served();
+    let bytes_in = head_len + session.conn.body_bytes_read();
+    let bytes_out = written.as_ref().map_or(0, |n| *n as usize);
+    record(context, &req, &answer, peer, &session.worker, bytes_in, bytes_out);
+
+    match written.is_ok() && keep_alive && answer.tunnel.is_none() {
+        true => Next::KeepAlive,
+        false => Next::Close,
+    }
+}
+
+/// The response to a request, with what it took to produce it.
+struct Answer {
+    id: String,
+    response: HttpResponse,
+    upstream: Option<UpstreamTiming>,
+    tunnel: Option<Tunnel>,
+    route: String,
+    time: SystemTime,
+    started: Instant,
+}
+
+/// Route `req` and produce its response, reading the request body from
+/// `body`. Shared by HTTP/1.1 connections and HTTP/2 streams.
+fn answer(
+    context: &ProxyContext,
+    req: &mut HttpRequest,
+    identity: &ClientIdentity,
+    body: &mut dyn Read,
+    peer: SocketAddr,
+) -> Answer {
+    let config = &context.config;
+    let started = Instant::now();
+    let time = SystemTime::now();
+    let id = request_id::assign(req, peer.ip(), &config.request_id);
+    if let Ok(client_cert) = identity {
+        req.client_cert = client_cert.clone();
+    }
+    let req = &*req;
+    let route = context.router.route(req);
+    // CONNECT and absolute-form requests skip the routes in forward-proxy mode.
+    let forward = config.forward_proxy.as_ref().filter(|_| req.target != RequestTarget::Origin);
+
+    let mut upstream = None;
+    let mut tunnel = None;
+    let mut quota = None;
+    let mut response = match (identity, verify_http_request(req), route) {
+        (Err(message), _, _) => {
+            log_warn!(id: Some(&id), "Rejected request from {}: {}", peer, message);
+            create_request_error_response(req, HttpStatus::Forbidden, message.clone())
+        }
+        (Ok(_), Ok(()), _) if let Some(forward) = forward => {
+            let (response, timing, opened) = forward_request(context, forward, req, body, peer.ip());
+            upstream = timing;
+            tunnel = opened;
+            response
+        }
+        (Ok(_), Ok(()), _) if req.method == HttpMethod::CONNECT => {
+            create_request_error_response(req, HttpStatus::MethodNotAllowed, "CONNECT is not enabled")
+        }
+        (Ok(_), Ok(()), Some(route)) => {
+            // Only requests that get this far use up quota.
+            quota = context
+                .rate_limiter
+                .as_ref()
+                .zip(route.rate_limit.as_ref())
+                .map(|(limiter, limit)| {
+                    limiter.take(limit, rate_limit::key(limit, req, &route.name, peer.ip()), started)
+                });
+            if quota.as_ref().is_some_and(|quota| quota.retry_after.is_some()) {
+                context.metrics.rate_limited.inc(&[&route.name]);
+                let mut refused = create_request_error_response(req, HttpStatus::TooManyRequests, "Too Many Requests");
+                // The client is only asked to slow down; its connection stays open.
+                refused.headers.remove("connection");
+                refused
+            } else if req.method == HttpMethod::PURGE {
+                purge_request(context, req, route, peer.ip())
+            } else {
+                let decoded = match route.decompress_requests {
+                    true => compression::decode_request(req),
+                    false => Ok(None),
+                };
+                let (response, timing, upgraded) = match decoded {
+                    Ok(Some((decoded, codings))) => {
+                        let mut body = compression::decoded_body(body, &codings);
+                        route_request(context, &decoded, &mut body, route, peer.ip())
+                    }
+                    Ok(None) => route_request(context, req, body, route, peer.ip()),
+                    Err(message) => {
+                        log_warn!(id: Some(&id), "Rejected request from {}: {}", peer, message);
+                        (create_request_error_response(req, HttpStatus::UnsupportedMediaType, message), None, None)
+                    }
+                };
+                upstream = timing;
+                tunnel = upgraded;
+                response
+            }
+        }
+        (Ok(_), Ok(()), None) => {
+            create_request_error_response(req, HttpStatus::NotFound, "No route matches this request")
+        }
+        (Ok(_), Err(resp), _) => {
+            log_warn!(id: Some(&id), "Rejected request from {}: {}", peer, resp.status.code());
+            let message = resp.body_as_string().unwrap_or_default();
+            create_request_error_response(req, resp.status, message)
+        }
+    };
+    if let Some(quota) = &quota {
+        quota.apply(&mut response.headers);
+    }
+    if route.is_some_and(|route| route.decompress_responses) && forward.is_none() && tunnel.is_none() {
+        compression::decompress(req, &mut response);
+    }
+    if let Some(compression) = &config.compression
+        && tunnel.is_none()
+    {
+        compression::compress(compression, req, &mut response);
+    }
+    response
+        .headers
+        .insert(config.request_id.header.clone(), id.clone());
+
+    let route = match (forward, route) {
+        (Some(_), _) => "forward_proxy",
+        (None, Some(route)) => route.name.as_str(),
+        (None, None) => "unmatched",
+    };
+    Answer {
+        id,
+        response,
+        upstream,
+        tunnel,
+        route: route.to_string(),
+        time,
+        started,
+    }
+}
+
+/// Count an answered request in the metrics and the access log.
+fn record(
+    context: &ProxyContext,
+    req: &HttpRequest,
+    answer: &Answer,
+    peer: SocketAddr,
+    worker: &str,
+    bytes_in: usize,
+    bytes_out: usize,
+) {
+    let total_latency = answer.started.elapsed();
+    let status = answer.response.status_code();
+    context
+        .metrics
+        .record_request(&answer.route, &req.method.to_string(), status, total_latency);
+    context.metrics.worker_requests.inc(&[worker]);
+
+    if let Some(access_log) = &context.access_log {
+        let upstream = answer.upstream.as_ref();
+        access_log.log(&AccessLogEntry {
+            time: answer.time,
+            client: peer,
+            method: req.method.to_string(),
+            path: req.path.clone(),
+            version: req.version.to_string(),
+            status,
+            bytes_in,
+            bytes_out,
+            upstream: upstream.map(|u| u.backend.clone()),
+            upstream_latency: upstream.map(|u| u.latency),
+            total_latency,
+            user_agent: req.headers.get("user-agent").cloned(),
+            referer: req.headers.get("referer").cloned(),
+            request_id: Some(answer.id.clone()),
+        });
+    }
+}
+
+/// What the streams of one HTTP/2 connection share.
+struct H2Session {
+    peer: SocketAddr,
+    identity: ClientIdentity,
+    secure: bool,
+    tracked: TrackedConnection,
+    worker: String,
+}
+
+/// Serve a connection that speaks HTTP/2, each stream on its own thread,
+/// until the connection closes. `upgrade` is the request that switched an
+/// HTTP/1.1 connection to h2c.
+fn serve_http2(session: Session, context: Arc<ProxyContext>, upgrade: Option<H2cUpgrade>) {
+    let Session {
+        conn,
+        peer,
+        identity,
+        tracked,
+        worker,
+        _active,
+        ..
+    } = session;
+    let (mut stream, input) = conn.into_parts();
+    let timeout = context.config.server.read_timeout;
+    let h2 = match h2::Connection::new(h2::Role::Server, timeout) {
+        Ok(h2) => h2,
+        Err(e) => {
+            log_error!("Cannot serve HTTP/2 to {}: {}", peer, e);
+            return;
+        }
+    };
+    let shared = Arc::new(H2Session {
+        peer,
+        identity,
+        secure: stream.is_secure(),
+        tracked,
+        worker,
+    });
+
+    if let Some(upgrade) = upgrade {
+        if let Err(e) = h2.apply_settings(&upgrade.settings) {
+            log_debug!("Refused h2c upgrade from {}: {}", peer, e);
+            return;
+        }
+        let switching = b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: h2c\r\n\r\n";
+        if stream.write_all(switching).and_then(|()| stream.flush()).is_err() {
+            return;
+        }
+        let first = h2.upgraded_stream();
+        let request = Ok((upgrade.req, upgrade.head_len));
+        let (shared, context) = (Arc::clone(&shared), Arc::clone(&context));
+        thread::spawn(move || serve_stream(first, request, &shared, &context));
+    }
+
+    let stop = || context.shutdown.is_requested();
+    let mut accept = |stream: h2::Stream, head, end_stream| {
+        let request = h2::server::request_from_head(head, end_stream, shared.secure);
+        let (shared, context) = (Arc::clone(&shared), Arc::clone(&context));
+        thread::spawn(move || serve_stream(stream, request, &shared, &context));
+    };
+    if let Err(e) = h2.run(&mut stream, input, timeout, &stop, &mut accept) {
+        log_debug!("HTTP/2 connection with {} closed: {}", peer, e);
+    }
+}
+
+/// Answer the request on one HTTP/2 stream.
+fn serve_stream(
+    mut stream: h2::Stream,
+    request: Result<(HttpRequest, usize), (HttpStatus, String)>,
+    session: &H2Session,
+    context: &ProxyContext,
+) {
+    let _in_flight = context.shutdown.begin_request();
+    let (mut req, head_len) = match request {
+        Ok(request) => request,
+        Err((status, message)) => {
+            let (mut response, id) = rejection(context, session.peer, status, message);
+            let sent = h2::server::send_response(&stream, &mut response).unwrap_or(0);
+            log_rejected(context, session.peer, status, 0, sent as usize, id);
+            return;
+        }
+    };
+
+    let mut body = h2::server::RequestBody::new(&mut stream);
+    let mut answer = answer(context, &mut req, &session.identity, &mut body, session.peer);
+    let body_read = body.bytes_read() as usize;
+    let written = h2::server::send_response(&stream, &mut answer.response);
+    if let Err(e) = &written {
+        log_warn!(id: Some(&answer.id), "Response to {} was cut short: {}", session.peer, e);
+    }
+    session.tracked.served();
+    let bytes_out = written.map_or(0, |n| n as usize);
+    record(context, &req, &answer, session.peer, &session.worker, head_len + body_read, bytes_out);
+}
+
+/// Relay a tunnel until it closes, then record the request that opened it.
+fn relay(mut session: Session, context: &ProxyContext, opened: OpenedTunnel) {
+    let OpenedTunnel { req, mut answer, head_len, written } = opened;
+    let _in_flight = context.shutdown.begin_request();
+    let relayed = match &mut answer.tunnel {
+        Some(tunnel) => serve_tunnel(&mut session, context, tunnel, &answer.id),
+        None => Relayed::default(),
+    };
+    session.tracked.served();
+    let bytes_in = head_len + session.conn.body_bytes_read() + relayed.to_upstream as usize;
+    let bytes_out = written + relayed.to_client as usize;
+    record(context, &req, &answer, session.peer, &session.worker, bytes_in, bytes_out);
+}
+
+/// Relay bytes between the client and an upgraded upstream connection
+/// until either side is done with it.
+fn serve_tunnel(session: &mut Session, context: &ProxyContext, tunnel: &mut Tunnel, id: &str) -> Relayed {
+    let upgraded = &mut tunnel.upgraded;
+    let client = &mut session.conn;
+    // Either side may have sent data right behind the handshake.
+    let early = std::mem::take(&mut client.buffer);
+    let mut relayed = Relayed {
+        to_upstream: early.len() as u64,
+        to_client: upgraded.buffered.len() as u64,
+    };
+    let result = upgraded
+        .transport
+        .write_all(&early)
+        .and_then(|()| upgraded.transport.flush())
+        .and_then(|()| client.stream.write_all(&upgraded.buffered))
+        .and_then(|()| client.stream.flush())
+        .and_then(|()| {
+            let idle_timeout = context.config.server.tunnel_idle_timeout;
+            tunnel::relay(&mut client.stream, &mut upgraded.transport, idle_timeout, &mut relayed)
+        });
+    if let Err(e) = result {
+        log_debug!(id: Some(id), "Tunnel for {} closed: {}", session.peer, e);
+    }
+    relayed
+}
+
+/// Send `req` to its route's upstream, through the cache when the route
+/// is cached.
+fn route_request(
+    context: &ProxyContext,
+    req: &HttpRequest,
+    body: &mut dyn Read,
+    route: &Route,
+    client: IpAddr,
+) -> (HttpResponse, Option<UpstreamTiming>, Option<Tunnel>) {
+    match (&context.cache, &route.cache_key) {
+        (Some(cache), Some(parts)) if !is_websocket_upgrade(req) => {
+            cached_request(context, cache, parts, req, body, route, client)
+        }
+        _ => proxy_request(context, req, body, route, client),
+    }
+}
+
+/// Send `req` to a backend of the route's upstream group.
+fn proxy_request(
+    context: &ProxyContext,
+    req: &HttpRequest,
+    body: &mut dyn Read,
+    route: &Route,
+    client: IpAddr,
+) -> (HttpResponse, Option<UpstreamTiming>, Option<Tunnel>) {
+    let pool = &route.upstream;
+
+    let Some(backend) = pool.select() else {
+        log_warn!(id: req.request_id(), "No healthy backend in upstream {}", pool.name);
+        return (
+            create_request_error_response(req, HttpStatus::ServiceUnavailable, "Service Unavailable"),
+            None,
+            None,
+        );
+    };
+    let Some(slot) = backend.acquire() else {
+        log_warn!(id: req.request_id(), "Upstream {} backend {} is at its connection limit", pool.name, backend.address);
+        return (
+            create_request_error_response(req, HttpStatus::ServiceUnavailable, "Service Unavailable"),
+            None,
+            None,
+        );
+    };
+
+    let started = Instant::now();
+    let result = if is_websocket_upgrade(req) {
+        forward_upgrade(req, body, &backend.address, &pool.connector, pool.timeout, client)
+    } else if pool.http2 {
+        forward_http2(req, body, &backend, &pool.connector, pool.timeout, client).map(|r| (r, None))
+    } else {
+        forward_to_upstream(req, body, &backend.address, &pool.connector, pool.timeout, client).map(|r| (r, None))
+    };
+    let latency = started.elapsed();
+    context
+        .metrics
+        .record_upstream(&pool.name, &backend.address, latency);
+
+    let mut tunnel = None;
+    let response = match result {
+        Ok((response, Some(upgraded))) => {
+            // The upstream connection stays open for the tunnel's lifetime.
+            tunnel = Some(Tunnel {
+                upgraded,
+                _slot: Some(slot),
+            });
+            response
+        }
+        Ok((mut response, None)) => {
+            // The upstream connection stays open until the body is read.
+            if let Body::Stream(stream) = std::mem::take(&mut response.body) {
+                response.body = Body::Stream(Box::new(SlotHeld { stream, _slot: slot }));
+            }
+            response
+        }
+        Err(e) => {
+            if let Some(status) = e.client_status() {
+                log_warn!(id: req.request_id(), "{}", e);
+                let message = e.to_string();
+                return (create_request_error_response(req, status, message), None, None);
+            }
+            if let Some(parse_error) = e.parse_error() {
+                context.metrics.record_parse_error(parse_error);
+            }
+            log_error!(id: req.request_id(), "{} (upstream {}, backend {})", e, pool.name, backend.address);
+            create_request_error_response(req, HttpStatus::BadGateway, "Bad Gateway")
+        }
+    };
+
+    (
+        response,
+        Some(UpstreamTiming {
+            backend: backend.address.clone(),
+            latency,
+        }),
+        tunnel,
+    )
+}
+
+/// Answer a request on a cached route from the cache where RFC 9111
+/// allows, otherwise from the upstream, storing what may be stored.
+fn cached_request(
+    context: &ProxyContext,
+    cache: &Arc<Cache>,
+    parts: &[CacheKeyPart],
+    req: &HttpRequest,
+    body: &mut dyn Read,
+    route: &Route,
+    client: IpAddr,
+) -> (HttpResponse, Option<UpstreamTiming>, Option<Tunnel>) {
+    let key = cache::key(parts, req);
+    let count = |result: &str| context.metrics.cache_requests.inc(&[&route.name, result]);
+
+    if !matches!(req.method, HttpMethod::GET | HttpMethod::HEAD) {
+        let (response, timing, tunnel) = proxy_request(context, req, body, route, client);
+        // A successful unsafe request makes what is stored out of date (§4.4).
+        if req.method != HttpMethod::OPTIONS && (200..400).contains(&response.status_code())
+        {
+            cache.remove(&key);
+        }
+        return (response, timing, tunnel);
+    }
+
+    let request_time = SystemTime::now();
+    let request = CacheControl::parse(&req.headers);
+    let stored = cache.lookup(&key, req);
+    if let Some(entry) = &stored {
+        if entry.is_fresh(request_time, &request) {
+            count("hit");
+            return (serve_entry(entry, req, request_time, "HIT"), None, None);
+        }
+        if entry.usable_while_revalidating(request_time) {
+            revalidate_in_background(cache, &route.upstream, req, entry, client);
+            count("stale");
+            return (serve_entry(entry, req, request_time, "STALE"), None, None);
+        }
+    }
+    if request.only_if_cached {
+        count("miss");
+        return (create_request_error_response(req, HttpStatus::GatewayTimeout, "Not cached"), None, None);
+    }
+
+    // One request per key goes to the upstream; the rest wait for what it
+    // stores, or go on their own if nothing usable turns up in time.
+    let mut lead = None;
+    if req.method == HttpMethod::GET {
+        match cache.join(&key) {
+            Join::Lead(claim) => lead = Some(claim),
+            Join::Follow(flight) => {
+                let timeout = context.config.cache.as_ref().map_or(Duration::ZERO, |c| c.coalesce_timeout);
+                let landed = flight.wait(timeout);
+                let now = SystemTime::now();
+                if landed
+                    && let Some(entry) = cache.lookup(&key, req)
+                    && entry.is_fresh(now, &request)
+                {
+                    count("coalesced");
+                    return (serve_entry(&entry, req, now, "COALESCED"), None, None);
+                }
+            }
+            Join::Alone => {}
+        }
+    }
+
+    let Some(entry) = stored else {
+        let (mut response, timing, tunnel) = proxy_request(context, req, body, route, client);
+        let result = match cache.record(key, req, request_time, &mut response, lead) {
+            true => "miss",
+            false => "bypass",
+        };
+        count(result);
+        response.headers.remove("surrogate-key");
+        response.headers.insert("X-Cache".to_string(), result.to_uppercase());
+        return (response, timing, tunnel);
+    };
+
+    let conditional = conditional_request(req, &entry);
+    let (mut response, timing, tunnel) = proxy_request(context, &conditional, &mut io::empty(), route, client);
+    let now = SystemTime::now();
+    if response.status == HttpStatus::NotModified {
+        let fresh = Arc::new(entry.freshened(&response.headers, request_time, now));
+        cache.insert(Arc::clone(&fresh));
+        count("revalidated");
+        return (serve_entry(&fresh, req, now, "REVALIDATED"), timing, None);
+    }
+    if response.status_code() >= 500 && entry.usable_on_error(now, &request) {
+        count("stale");
+        return (serve_entry(&entry, req, now, "STALE"), timing, None);
+    }
+    if !cache.record(key.clone(), req, request_time, &mut response, lead) {
+        cache.remove(&key);
+    }
+    count("expired");
+    response.headers.remove("surrogate-key");
+    response.headers.insert("X-Cache".to_string(), "EXPIRED".to_string());
+    (response, timing, tunnel)
+}
+
+/// Drop the stored responses a `PURGE` request names: its own key, keys
+/// starting with it when the path ends in `*`, or responses tagged with the
+/// surrogate keys in its `Surrogate-Key` header.
+fn purge_request(context: &ProxyContext, req: &HttpRequest, route: &Route, client: IpAddr) -> HttpResponse {
+    let (Some(cache), Some(config), Some(parts)) = (&context.cache, &context.config.cache, &route.cache_key) else {
+        return create_request_error_response(req, HttpStatus::MethodNotAllowed, "This route is not cached");
+    };
+    if !config.purge_clients.contains(&client) {
+        log_warn!(id: req.request_id(), "Refused PURGE from {}", client);
+        return create_request_error_response(req, HttpStatus::Forbidden, "PURGE is not allowed from this address");
+    }
+    let purge = if let Some(tags) = req.headers.get("surrogate-key") {
+        Purge::Tags(tags.split_ascii_whitespace().map(str::to_string).collect())
+    } else if let Some(path) = req.path.strip_suffix('*') {
+        let mut prefix = req.clone();
+        prefix.path = path.to_string();
+        Purge::Prefix(cache::key(parts, &prefix))
+    } else {
+        Purge::Key(cache::key(parts, req))
+    };
+    let purged = cache.purge(&purge);
+    log_info!(id: req.request_id(), "PURGE from {} dropped {} cached responses ({:?})", client, purged, purge);
+    HttpResponse::json(HttpStatus::Ok, ObjectWriter::new().number("purged", purged).finish())
+}
+
+/// `req` made conditional on the stored entry, so an unchanged resource
+/// costs the upstream a 304. The client's own conditions are answered by
+/// the proxy, from the entry.
+fn conditional_request(req: &HttpRequest, entry: &Entry) -> HttpRequest {
+    let mut conditional = req.clone();
+    conditional.headers.remove("if-none-match");
+    conditional.headers.remove("if-modified-since");
+    if let Some(etag) = entry.etag() {
+        conditional.headers.insert("If-None-Match".to_string(), etag.to_string());
+    } else if let Some(modified) = entry.last_modified() {
+        conditional.headers.insert("If-Modified-Since".to_string(), modified.to_string());
+    }
+    conditional
+}
+
+/// The stored response, or a 304 when the client already has it.
+fn serve_entry(entry: &Entry, req: &HttpRequest, now: SystemTime, result: &str) -> HttpResponse {
+    entry.hits.fetch_add(1, Ordering::Relaxed);
+    let unchanged = match (req.headers.get("if-none-match"), req.headers.get("if-modified-since")) {
+        (Some(tags), _) => entry.etag().is_some_and(|etag| policy::none_match(tags, etag)),
+        (None, Some(since)) => entry.last_modified().is_some_and(|modified| policy::not_modified_since(since, modified)),
+        (None, None) => false,
+    };
+    let mut response = entry.response(now, req.method == HttpMethod::HEAD);
+    if unchanged {
+        response.status = HttpStatus::NotModified;
+        response.headers.remove("content-length");
+        response.body = Body::empty();
+    }
+    response.headers.insert("X-Cache".to_string(), result.to_string());
+    response
+}
+
+/// Revalidate a stale entry off the request path, at most once per key at
+/// a time, while the stale entry is served.
+fn revalidate_in_background(cache: &Arc<Cache>, pool: &Arc<UpstreamPool>, req: &HttpRequest, entry: &Arc<Entry>, client: IpAddr) {
+    if !cache.start_revalidation(&entry.key) {
+        return;
+    }
+    let mut conditional = conditional_request(req, entry);
+    conditional.method = HttpMethod::GET;
+    let key = entry.key.clone();
+    let spawned = thread::Builder::new().name("revalidate".to_string()).spawn({
+        let (cache, pool, entry, key) = (Arc::clone(cache), Arc::clone(pool), Arc::clone(entry), key.clone());
+        move || {
+            let request_time = SystemTime::now();
+            match fetch(&pool, &conditional, client) {
+                Ok(response) if response.status == HttpStatus::NotModified => {
+                    let fresh = entry.freshened(&response.headers, request_time, SystemTime::now());
+                    cache.insert(Arc::new(fresh));
+                }
+                Ok(mut response) if response.status_code() < 500 => {
+                    if !cache.record(key.clone(), &conditional, request_time, &mut response, None) {
+                        cache.remove(&key);
+                    } else if let Body::Stream(mut stream) = response.body {
+                        // Reading the body to its end stores it.
+                        let _ = io::copy(&mut stream, &mut io::sink());
+                    }
+                }
+                Ok(response) => {
+                    log_warn!(id: conditional.request_id(), "Revalidating {} failed with {}", key, response.status_code());
+                }
+                Err(e) => log_warn!(id: conditional.request_id(), "Revalidating {} failed: {}", key, e),
+            }
+            cache.finish_revalidation(&key);
+        }
+    });
+    if spawned.is_err() {
+        cache.finish_revalidation(&key);
+    }
+}
+
+/// Send a request of the proxy's own, without a body, to a backend of `pool`.
+fn fetch(pool: &UpstreamPool, req: &HttpRequest, client: IpAddr) -> Result<HttpResponse, String> {
+    let backend = pool.select().ok_or("no healthy backend")?;
+    let slot = backend.acquire().ok_or("backend at its connection limit")?;
+    let result = if pool.http2 {
+        forward_http2(req, &mut io::empty(), &backend, &pool.connector, pool.timeout, client)
+    } else {
+        forward_to_upstream(req, &mut io::empty(), &backend.address, &pool.connector, pool.timeout, client)
+    };
+    let mut response = result.map_err(|e| e.to_string())?;
+    if let Body::Stream(stream) = std::mem::take(&mut response.body) {
+        response.body = Body::Stream(Box::new(SlotHeld { stream, _slot: slot }));
+    }
+    Ok(response)
+}
+
+/// WebSocket handshakes are forwarded as upgrades; other `Upgrade` requests
+/// are forwarded as plain requests, without the header.
+fn is_websocket_upgrade(req: &HttpRequest) -> bool {
+    req.upgrade().is_some_and(|protocol| protocol.eq_ignore_ascii_case("websocket"))
+}
+
+/// An upstream connection that switched protocols (or a CONNECT tunnel),
+/// holding its backend's connection slot if it has one.
+struct Tunnel {
+    upgraded: Upgraded,
+    _slot: Option<ConnectionSlot>,
+}
+
+/// Serve a CONNECT or absolute-form request in forward-proxy mode: open a
+/// tunnel to, or forward the request to, the destination it names.
+fn forward_request(
+    context: &ProxyContext,
+    config: &ForwardProxyConfig,
+    req: &HttpRequest,
+    body: &mut dyn Read,
+    client: IpAddr,
+) -> (HttpResponse, Option<UpstreamTiming>, Option<Tunnel>) {
+    let (host, port) = &req.origin;
+    if !config.allows(host, *port) {
+        log_warn!(id: req.request_id(), "Forward proxy destination {}:{} is not allowed", host, port);
+        return (
+            create_request_error_response(req, HttpStatus::Forbidden, "Destination not allowed"),
+            None,
+            None,
+        );
+    }
+
+    let address = format!("{}:{}", host, port);
+    let connector = Connector::plain();
+    let started = Instant::now();
+    let result = if req.method == HttpMethod::CONNECT {
+        connector.connect(&address, config.connect_timeout).map(|transport| {
+            let established = HttpResponse {
+                version: HttpVersion::HTTP1_1,
+                status: HttpStatus::Ok,
+                reason: None,
+                headers: HttpHeaders::new(),
+                body: Body::empty(),
+                trailers: Default::default(),
+            };
+            let upgraded = Upgraded {
+                transport,
+                buffered: Vec::new(),
+            };
+            (established, Some(upgraded))
+        })
+    } else {
+        forward_to_upstream(req, body, &address, &connector, config.connect_timeout, client).map(|r| (r, None))
+    };
+    let latency = started.elapsed();
+
+    let timing = Some(UpstreamTiming {
+        backend: address.clone(),
+        latency,
+    });
+    match result {
+        Ok((response, upgraded)) => (
+            response,
+            timing,
+            upgraded.map(|upgraded| Tunnel { upgraded, _slot: None }),
+        ),
+        Err(e) => {
+            if let Some(status) = e.client_status() {
+                log_warn!(id: req.request_id(), "{}", e);
+                let message = e.to_string();
+                return (create_request_error_response(req, status, message), None, None);
+            }
+            if let Some(parse_error) = e.parse_error() {
+                context.metrics.record_parse_error(parse_error);
+            }
+            log_warn!(id: req.request_id(), "{} (forward proxy destination {})", e, address);
+            (
+                create_request_error_response(req, HttpStatus::BadGateway, "Bad Gateway"),
+                timing,
+                None,
+            )
+        }
+    }
+}
+
+/// A streamed response body that keeps its backend's connection slot.
+struct SlotHeld {
+    stream: Box<dyn Read + Send>,
+    _slot: ConnectionSlot,
+}
+
+impl Read for SlotHeld {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+/// Respond to a request that could not be read and log it with a fresh ID.
+fn reject(conn: &mut Connection, context: &ProxyContext, peer: SocketAddr, status: HttpStatus, message: String) {
+    let (response, id) = rejection(context, peer, status, message);
+    let bytes = response.to_bytes();
+    let _ = conn.stream.write_all(&bytes).and_then(|()| conn.stream.flush());
+    log_rejected(context, peer, status, conn.buffer.len(), bytes.len(), id);
+}
+
+/// The error response for a request that could not be read, and the fresh
+/// ID it was given.
+fn rejection(context: &ProxyContext, peer: SocketAddr, status: HttpStatus, message: String) -> (HttpResponse, String) {
+    let config = &context.config;
+    let id = request_id::generate(config.request_id.format);
+    log_warn!(id: Some(&id), "Rejected request from {}: {}", peer, message);
+    let response = create_error_response_with_id(status, message, Some(&id))
+        .with_header(config.request_id.header.clone(), id.clone());
+    (response, id)
+}
+
+fn log_rejected(context: &ProxyContext, peer: SocketAddr, status: HttpStatus, bytes_in: usize, bytes_out: usize, id: String) {
+    if let Some(access_log) = &context.access_log {
+        access_log.log(&AccessLogEntry {
+            time: SystemTime::now(),
+            client: peer,
+            method: "-".to_string(),
+            path: "-".to_string(),
+            version: "-".to_string(),
+            status: status.code(),
+            bytes_in,
+            bytes_out,
+            upstream: None,
+            upstream_latency: None,
+            total_latency: Duration::ZERO,
+            user_agent: None,
+            referer: None,
+            request_id: Some(id),
+        });
+    }
+}
+
+fn keep_alive(req: &HttpRequest, response: &HttpResponse) -> bool {
+    let closes = |value: Option<&String>| value.is_some_and(|v| v.eq_ignore_ascii_case("close"));
+    let persistent = match req.version {
+        HttpVersion::HTTP1_1 => !closes(req.headers.get("connection")),
+        // HTTP/1.0 connections close after one request unless asked not to.
+        _ => req.headers.has_token("connection", "keep-alive"),
+    };
+    persistent && !closes(response.headers.get("connection"))
+}
+
+/// The Host for an HTTP/1.0 request that came without one: the listener's
+/// `default_host`, or else the address the connection was accepted on.
+fn default_host(config: &Config, stream: &ClientStream) -> String {
+    let configured = match stream.is_secure() {
+        true => config.tls.as_ref().and_then(|tls| tls.default_host.clone()),
+        false => config.server.default_host.clone(),
+    };
+    configured.unwrap_or_else(|| match stream.tcp().local_addr() {
+        Ok(address) => address.to_string(),
+        Err(_) => "localhost".to_string(),
+    })
+}
@@ -0,0 +1,203 @@
+// src/proxy/shutdown.rs
+//
+// Graceful shutdown bookkeeping. Once shutdown starts the listener stops
+// accepting, requests already being handled run to completion, and every
+// keep-alive connection is closed after its next response. Connections idle
+// between requests are closed once nothing is in flight; freshly accepted
+// connections get until the end of the grace period to send their first
+// request, since the client has no way to know it was accepted by a process
+// that is about to exit (see `upgrade`).
+
+use std::collections::HashMap;
+use std::net::{Shutdown as SocketShutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    requested: AtomicBool,
+    in_flight: AtomicUsize,
+    /// Accepted connections whose handler has not registered them yet.
+    pending: AtomicUsize,
+    next_id: AtomicU64,
+    /// Tracked streams, and whether each has served a response yet.
+    connections: Mutex<HashMap<u64, (TcpStream, bool)>>,
+}
+
+/// Registered client connection; unregisters itself when dropped.
+pub struct TrackedConnection {
+    shutdown: Arc<Shutdown>,
+    id: u64,
+}
+
+/// A request being handled; shutdown waits for these to finish.
+pub struct InFlightRequest<'a> {
+    shutdown: &'a Shutdown,
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_requested(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+
+    pub fn request(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Note an accepted connection, so shutdown does not finish before it
+    /// has been registered.
+    pub fn accepted(&self) {
+        self.pending.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Track `stream` so it can be closed if it is still idle at shutdown.
+    /// Must be called once for every `accepted` connection.
+    pub fn register(self: &Arc<Self>, stream: &TcpStream) -> TrackedConnection {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        if let Ok(clone) = stream.try_clone() {
+            self.connections
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .insert(id, (clone, false));
+        }
+        self.pending.fetch_sub(1, Ordering::SeqCst);
+        TrackedConnection {
+            shutdown: Arc::clone(self),
+            id,
+        }
+    }
+
+    pub fn begin_request(&self) -> InFlightRequest<'_> {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlightRequest { shutdown: self }
+    }
+
+    pub fn open_connections(&self) -> usize {
+        self.connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .len()
+    }
+
+    /// Close every tracked connection that has served a response. Handlers
+    /// blocked reading their next request see EOF and exit.
+    fn close_idle_connections(&self) {
+        for (stream, served) in self
+            .connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .values()
+        {
+            if *served {
+                let _ = stream.shutdown(SocketShutdown::Both);
+            }
+        }
+    }
+
+    /// Wait for in-flight requests to finish, then close idle connections
+    /// and wait for the rest to finish their first request.
+    /// Returns false if the grace period ran out with connections still open.
+    pub fn drain(&self, grace: Duration) -> bool {
+        let deadline = Instant::now() + grace;
+        while self.in_flight() > 0 || self.pending.load(Ordering::SeqCst) > 0 {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            thread::sleep(Duration::from_millis(50));
+        }
+
+        self.close_idle_connections();
+        while self.open_connections() > 0 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        self.open_connections() == 0
+    }
+}
+
+impl TrackedConnection {
+    /// Record that a response was sent; the connection now counts as idle
+    /// between requests.
+    pub fn served(&self) {
+        if let Some(entry) = self
+            .shutdown
+            .connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .get_mut(&self.id)
+        {
+            entry.1 = true;
+        }
+    }
+}
+
+impl Drop for TrackedConnection {
+    fn drop(&mut self) {
+        self.shutdown
+            .connections
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&self.id);
+    }
+}
+
+impl Drop for InFlightRequest<'_> {
+    fn drop(&mut self) {
+        self.shutdown.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    fn connection() -> (TcpStream, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        (listener.accept().unwrap().0, client)
+    }
+
+    #[test]
+    fn drain_fails_while_a_connection_is_still_open() {
+        let shutdown = Arc::new(Shutdown::new());
+        let (server, _client) = connection();
+        shutdown.accepted();
+        let tracked = shutdown.register(&server);
+
+        // No request yet, so the connection is not closed as idle.
+        assert!(!shutdown.drain(Duration::from_millis(50)));
+        assert_eq!(shutdown.open_connections(), 1);
+
+        drop(tracked);
+        assert!(shutdown.drain(Duration::from_millis(50)));
+    }
+
+    #[test]
+    fn drain_closes_idle_connections() {
+        let shutdown = Arc::new(Shutdown::new());
+        let (server, mut client) = connection();
+        shutdown.accepted();
+        let tracked = shutdown.register(&server);
+        tracked.served();
+
+        // The handler sees EOF and drops its connection.
+        let handler = thread::spawn(move || {
+            let _ = (&server).read(&mut [0u8; 1]);
+            drop(tracked);
+        });
+        assert!(shutdown.drain(Duration::from_secs(5)));
+        handler.join().unwrap();
+        assert_eq!(client.read(&mut [0u8; 1]).unwrap(), 0);
+    }
+}
@@ -0,0 +1,52 @@
+// src/proxy/signals.rs
+//
+// Process signal handling. Handlers only flip atomics; the work happens on a
+// regular thread that polls them, so nothing async-signal-unsafe runs inside
+// the handler.
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static REOPEN_LOGS: AtomicBool = AtomicBool::new(false);
+static TERMINATE: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn on_reopen(_: libc::c_int) {
+    REOPEN_LOGS.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn on_terminate(_: libc::c_int) {
+    TERMINATE.store(true, Ordering::SeqCst);
+}
+
+fn install(signal: libc::c_int, handler: extern "C" fn(libc::c_int)) -> io::Result<()> {
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe,
+    // and `action` is fully initialised before being passed to sigaction.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as *const () as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        if libc::sigaction(signal, &action, std::ptr::null_mut()) != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// Install the proxy's signal handlers (SIGUSR1: reopen log files,
+/// SIGTERM/SIGINT: graceful shutdown).
+pub fn install_handlers() -> io::Result<()> {
+    install(libc::SIGUSR1, on_reopen)?;
+    install(libc::SIGTERM, on_terminate)?;
+    install(libc::SIGINT, on_terminate)
+}
+
+/// True once SIGTERM or SIGINT has been received.
+pub fn terminate_requested() -> bool {
+    TERMINATE.load(Ordering::SeqCst)
+}
+
+/// True once per received SIGUSR1.
+pub fn take_reopen_request() -> bool {
+    REOPEN_LOGS.swap(false, Ordering::SeqCst)
+}
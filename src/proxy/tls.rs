@@ -0,0 +1,399 @@
+// src/proxy/tls.rs
+//
+// TLS termination for the HTTPS listener, using rustls with the ring
+// provider. Certificates are picked per handshake from the SNI name, and
+// reloaded from disk when their files change.
+//
+// Client certificates are requested during the handshake but verified only
+// once it completes, so a client without a valid one still gets an HTTP 403
+// explaining why instead of an opaque handshake failure.
+
+use std::io;
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use rustls::client::danger::HandshakeSignatureValid;
+use rustls::crypto::{CryptoProvider, ring};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, UnixTime};
+use rustls::server::danger::{ClientCertVerified, ClientCertVerifier};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{DigitallySignedStruct, DistinguishedName, RootCertStore, ServerConfig, ServerConnection, SignatureScheme, StreamOwned};
+
+use crate::config::{CertificateConfig, ClientAuthConfig, TlsConfig, TlsVersion};
+use crate::http::ClientCertificate;
+use crate::proxy::connection::ClientStream;
+use crate::proxy::x509;
+use crate::{log_error, log_info};
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+/// The ring provider restricted to the configured cipher suites.
+fn provider(config: &TlsConfig) -> io::Result<CryptoProvider> {
+    let mut provider = ring::default_provider();
+    if config.cipher_suites.is_empty() {
+        return Ok(provider);
+    }
+
+    let mut suites = Vec::with_capacity(config.cipher_suites.len());
+    for name in &config.cipher_suites {
+        let suite = provider
+            .cipher_suites
+            .iter()
+            .find(|s| s.suite().as_str() == Some(name.as_str()))
+            .ok_or_else(|| invalid(format!("tls.cipher_suites: unsupported cipher suite '{}'", name)))?;
+        suites.push(*suite);
+    }
+    provider.cipher_suites = suites;
+    Ok(provider)
+}
+
+/// Handshake settings and client certificate policy of the HTTPS listener.
+pub struct TlsAcceptor {
+    config: Arc<ServerConfig>,
+    client_auth: Option<ClientAuth>,
+    resolver: Arc<CertResolver>,
+    certificates: Vec<CertificateConfig>,
+    provider: Arc<CryptoProvider>,
+}
+
+struct ClientAuth {
+    verifier: Arc<dyn ClientCertVerifier>,
+    required: bool,
+}
+
+/// The client identity of a TLS connection: `Ok(None)` when no certificate
+/// was presented and none is required, `Err` with the reason requests on the
+/// connection must be refused.
+pub type ClientIdentity = Result<Option<ClientCertificate>, String>;
+
+impl TlsAcceptor {
+    /// With `http2`, clients may pick h2 over http/1.1 through ALPN.
+    pub fn new(config: &TlsConfig, http2: bool) -> io::Result<Self> {
+        let provider = Arc::new(provider(config)?);
+
+        let versions: &[&'static rustls::SupportedProtocolVersion] = match config.min_version {
+            TlsVersion::Tls12 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+            TlsVersion::Tls13 => &[&rustls::version::TLS13],
+        };
+        if !provider
+            .cipher_suites
+            .iter()
+            .any(|s| versions.iter().any(|v| s.version() == *v))
+        {
+            return Err(invalid(format!(
+                "tls.cipher_suites: no suite usable with TLS {} or later",
+                config.min_version.as_str()
+            )));
+        }
+
+        let resolver = Arc::new(CertResolver::new(load_certificates(&config.certificates, &provider)?));
+
+        let client_auth = config
+            .client_auth
+            .as_ref()
+            .map(|auth| client_auth(auth, &provider))
+            .transpose()?;
+
+        let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_protocol_versions(versions)
+            .map_err(|e| invalid(format!("tls: {}", e)))?;
+        let mut server = match &client_auth {
+            Some(auth) => builder.with_client_cert_verifier(Arc::new(DeferredVerification(Arc::clone(&auth.verifier)))),
+            None => builder.with_no_client_auth(),
+        }
+        .with_cert_resolver(resolver.clone());
+        server.alpn_protocols = match http2 {
+            true => vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+            false => vec![b"http/1.1".to_vec()],
+        };
+
+        Ok(Self {
+            config: Arc::new(server),
+            client_auth,
+            resolver,
+            certificates: config.certificates.clone(),
+            provider,
+        })
+    }
+
+    /// Load the certificate files again and use them for new handshakes.
+    /// If any of them fails to load, the current certificates stay.
+    pub fn reload(&self) -> io::Result<()> {
+        let certificates = load_certificates(&self.certificates, &self.provider)?;
+        self.resolver.replace(certificates);
+        Ok(())
+    }
+
+    /// Modification time and size of every certificate and key file, to
+    /// notice when they are rewritten.
+    fn fingerprint(&self) -> Vec<Option<(SystemTime, u64)>> {
+        self.certificates
+            .iter()
+            .flat_map(|cert| [&cert.cert, &cert.key])
+            .map(|path| {
+                let metadata = std::fs::metadata(path).ok()?;
+                Some((metadata.modified().ok()?, metadata.len()))
+            })
+            .collect()
+    }
+
+    /// Start the server side of a handshake on an accepted connection. Drive
+    /// it with `Handshake::advance` as the client's flights arrive.
+    pub fn start(&self, stream: TcpStream) -> io::Result<Handshake> {
+        let conn = ServerConnection::new(Arc::clone(&self.config)).map_err(io::Error::other)?;
+        Ok(Handshake { conn, sock: stream })
+    }
+
+    /// The stream of a completed handshake, and the client's identity from
+    /// the certificate it presented.
+    pub fn finish(&self, handshake: Handshake) -> (ClientStream, ClientIdentity) {
+        let identity = match &self.client_auth {
+            Some(auth) => verify_client(auth, handshake.conn.peer_certificates()),
+            None => Ok(None),
+        };
+        let tls = StreamOwned::new(handshake.conn, handshake.sock);
+        (ClientStream::Tls(Box::new(tls)), identity)
+    }
+}
+
+/// A server handshake in progress. It advances one client flight at a time,
+/// so the connection can wait for the next one in the reactor rather than
+/// on a thread.
+pub struct Handshake {
+    conn: ServerConnection,
+    sock: TcpStream,
+}
+
+impl Handshake {
+    pub fn tcp(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    /// Read what the client has sent, which must already be waiting on the
+    /// socket, and answer it. True once the handshake is complete.
+    pub fn advance(&mut self) -> io::Result<bool> {
+        if self.conn.read_tls(&mut self.sock)? == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let processed = self.conn.process_new_packets();
+        // Sends the alert too when the client's flight was refused.
+        while self.conn.wants_write() {
+            self.conn.write_tls(&mut self.sock)?;
+        }
+        processed.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        Ok(!self.conn.is_handshaking())
+    }
+}
+
+/// Check the certificate files every `interval` and reload them when they
+/// change.
+pub fn spawn_reloader(acceptor: Arc<TlsAcceptor>, interval: Duration) {
+    thread::spawn(move || {
+        let mut last = acceptor.fingerprint();
+        loop {
+            thread::sleep(interval);
+            let current = acceptor.fingerprint();
+            if current == last {
+                continue;
+            }
+            // Remember the attempt either way: a half-written rotation is
+            // retried once the remaining files change.
+            last = current;
+            match acceptor.reload() {
+                Ok(()) => log_info!("Reloaded TLS certificates"),
+                Err(e) => log_error!("Keeping the current TLS certificates: {}", e),
+            }
+        }
+    });
+}
+
+fn load_certificates(
+    certificates: &[CertificateConfig],
+    provider: &CryptoProvider,
+) -> io::Result<Vec<(Vec<String>, Arc<CertifiedKey>)>> {
+    let mut loaded = Vec::with_capacity(certificates.len());
+    for cert in certificates {
+        let key = load_certified_key(&cert.cert, &cert.key, provider)?;
+        loaded.push((cert.server_names.clone(), Arc::new(key)));
+    }
+    Ok(loaded)
+}
+
+fn client_auth(config: &ClientAuthConfig, provider: &Arc<CryptoProvider>) -> io::Result<ClientAuth> {
+    let mut roots = RootCertStore::empty();
+    for cert in load_certs(&config.ca_file)? {
+        roots
+            .add(cert)
+            .map_err(|e| invalid(format!("{}: {}", config.ca_file.display(), e)))?;
+    }
+    let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), Arc::clone(provider))
+        .build()
+        .map_err(|e| invalid(format!("{}: {}", config.ca_file.display(), e)))?;
+    Ok(ClientAuth {
+        verifier,
+        required: config.required,
+    })
+}
+
+fn verify_client(auth: &ClientAuth, certs: Option<&[CertificateDer<'static>]>) -> ClientIdentity {
+    let Some((end_entity, intermediates)) = certs.and_then(|certs| certs.split_first()) else {
+        return match auth.required {
+            true => Err("Client certificate required".to_string()),
+            false => Ok(None),
+        };
+    };
+    auth.verifier
+        .verify_client_cert(end_entity, intermediates, UnixTime::now())
+        .map_err(|e| format!("Invalid client certificate: {}", e))?;
+    x509::client_certificate(end_entity)
+        .map(Some)
+        .ok_or_else(|| "Unreadable client certificate".to_string())
+}
+
+/// Requests a client certificate and checks the handshake signatures made
+/// with it, but leaves chain verification to `verify_client`.
+#[derive(Debug)]
+struct DeferredVerification(Arc<dyn ClientCertVerifier>);
+
+impl ClientCertVerifier for DeferredVerification {
+    fn client_auth_mandatory(&self) -> bool {
+        false
+    }
+
+    fn root_hint_subjects(&self) -> &[DistinguishedName] {
+        self.0.root_hint_subjects()
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _now: UnixTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        Ok(ClientCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+/// Read a PEM certificate chain and private key and check that they match.
+pub fn load_certified_key(cert: &Path, key: &Path, provider: &CryptoProvider) -> io::Result<CertifiedKey> {
+    let chain = load_certs(cert)?;
+    let private_key = load_private_key(key)?;
+    CertifiedKey::from_der(chain, private_key, provider)
+        .map_err(|e| invalid(format!("{}: {}", cert.display(), e)))
+}
+
+/// All certificates in a PEM file; an empty file is an error.
+pub fn load_certs(path: &Path) -> io::Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+    if certs.is_empty() {
+        return Err(invalid(format!("{}: no certificates found", path.display())));
+    }
+    Ok(certs)
+}
+
+/// The first private key in a PEM file.
+pub fn load_private_key(path: &Path) -> io::Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path).map_err(|e| invalid(format!("{}: {}", path.display(), e)))
+}
+
+/// Picks a certificate by SNI name: exact names first, then `*.` wildcards
+/// covering a single label, then the default certificate.
+#[derive(Debug)]
+pub struct CertResolver {
+    certificates: RwLock<Certificates>,
+}
+
+#[derive(Debug)]
+struct Certificates {
+    entries: Vec<(Vec<String>, Arc<CertifiedKey>)>,
+    default: usize,
+}
+
+impl Certificates {
+    fn new(entries: Vec<(Vec<String>, Arc<CertifiedKey>)>) -> Self {
+        let default = entries.iter().position(|(names, _)| names.is_empty()).unwrap_or(0);
+        Self { entries, default }
+    }
+}
+
+impl CertResolver {
+    pub fn new(certificates: Vec<(Vec<String>, Arc<CertifiedKey>)>) -> Self {
+        Self {
+            certificates: RwLock::new(Certificates::new(certificates)),
+        }
+    }
+
+    /// Swap in a new set of certificates; handshakes already past
+    /// certificate selection keep the old one.
+    pub fn replace(&self, certificates: Vec<(Vec<String>, Arc<CertifiedKey>)>) {
+        let certificates = Certificates::new(certificates);
+        *self.certificates.write().unwrap_or_else(|e| e.into_inner()) = certificates;
+    }
+
+    pub fn select(&self, server_name: Option<&str>) -> Option<Arc<CertifiedKey>> {
+        let certificates = self.certificates.read().unwrap_or_else(|e| e.into_inner());
+        if let Some(name) = server_name.map(|n| n.to_ascii_lowercase()) {
+            let exact = certificates.entries.iter().find(|(names, _)| names.contains(&name));
+            let wildcard = || {
+                certificates
+                    .entries
+                    .iter()
+                    .find(|(names, _)| names.iter().any(|pattern| wildcard_matches(pattern, &name)))
+            };
+            if let Some((_, key)) = exact.or_else(wildcard) {
+                return Some(Arc::clone(key));
+            }
+        }
+        certificates
+            .entries
+            .get(certificates.default)
+            .map(|(_, key)| Arc::clone(key))
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        self.select(client_hello.server_name())
+    }
+}
+
+fn wildcard_matches(pattern: &str, name: &str) -> bool {
+    let Some(suffix) = pattern.strip_prefix("*.") else {
+        return false;
+    };
+    match name.strip_suffix(suffix).and_then(|rest| rest.strip_suffix('.')) {
+        Some(label) => !label.is_empty() && !label.contains('.'),
+        None => false,
+    }
+}
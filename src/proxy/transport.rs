@@ -0,0 +1,277 @@
+// src/proxy/transport.rs
+//
+// Connections to upstream servers. Plain TCP and TLS connections both
+// implement `Transport`, so the forwarder and health checks are written once;
+// each upstream's `Connector` decides which kind a new connection gets.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{WebPkiSupportedAlgorithms, ring};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, DigitallySignedStruct, RootCertStore, SignatureScheme, StreamOwned};
+
+use crate::config::UpstreamTlsConfig;
+use crate::proxy::connection::ClientStream;
+use crate::proxy::forwarder::ForwardError;
+use crate::proxy::tls;
+
+/// A connection to an upstream server.
+pub trait Transport: Read + Write + Send {
+    /// The underlying socket, for timeouts and addresses.
+    fn socket(&self) -> &TcpStream;
+
+    fn is_secure(&self) -> bool {
+        false
+    }
+
+    /// True when data already read off the socket is waiting to be returned;
+    /// see `ClientStream::has_buffered_data`.
+    fn has_buffered_data(&mut self) -> bool {
+        false
+    }
+}
+
+impl Transport for TcpStream {
+    fn socket(&self) -> &TcpStream {
+        self
+    }
+}
+
+impl Transport for StreamOwned<ClientConnection, TcpStream> {
+    fn socket(&self) -> &TcpStream {
+        &self.sock
+    }
+
+    fn is_secure(&self) -> bool {
+        true
+    }
+
+    fn has_buffered_data(&mut self) -> bool {
+        self.conn
+            .process_new_packets()
+            .is_ok_and(|state| state.plaintext_bytes_to_read() > 0)
+    }
+}
+
+/// Either side of a proxied connection, for code that polls sockets
+/// directly (tunnels, HTTP/2).
+pub trait Endpoint: Read + Write + Send {
+    fn socket(&self) -> &TcpStream;
+    fn has_buffered_data(&mut self) -> bool;
+}
+
+impl Endpoint for ClientStream {
+    fn socket(&self) -> &TcpStream {
+        self.tcp()
+    }
+
+    fn has_buffered_data(&mut self) -> bool {
+        ClientStream::has_buffered_data(self)
+    }
+}
+
+impl Endpoint for Box<dyn Transport> {
+    fn socket(&self) -> &TcpStream {
+        self.as_ref().socket()
+    }
+
+    fn has_buffered_data(&mut self) -> bool {
+        self.as_mut().has_buffered_data()
+    }
+}
+
+/// Opens connections to the servers of one upstream group.
+pub struct Connector {
+    tls: Option<TlsConnector>,
+}
+
+struct TlsConnector {
+    config: Arc<ClientConfig>,
+    /// The same, offering only `h2` in ALPN.
+    h2_config: Arc<ClientConfig>,
+    server_name: Option<ServerName<'static>>,
+}
+
+impl fmt::Debug for Connector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Connector")
+            .field("tls", &self.tls.is_some())
+            .finish()
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+impl Connector {
+    pub fn plain() -> Self {
+        Self { tls: None }
+    }
+
+    pub fn new(tls: Option<&UpstreamTlsConfig>) -> io::Result<Self> {
+        let Some(tls) = tls else {
+            return Ok(Self::plain());
+        };
+        let provider = Arc::new(ring::default_provider());
+
+        let builder = ClientConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| invalid(e.to_string()))?;
+        let builder = if tls.insecure_skip_verify {
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(SkipVerification(
+                    provider.signature_verification_algorithms,
+                )))
+        } else {
+            builder.with_root_certificates(root_store(tls)?)
+        };
+        let config = match (&tls.client_cert, &tls.client_key) {
+            (Some(cert), Some(key)) => builder
+                .with_client_auth_cert(tls::load_certs(cert)?, tls::load_private_key(key)?)
+                .map_err(|e| invalid(format!("{}: {}", cert.display(), e)))?,
+            _ => builder.with_no_client_auth(),
+        };
+
+        let server_name = match &tls.server_name {
+            Some(name) => Some(
+                ServerName::try_from(name.clone())
+                    .map_err(|_| invalid(format!("'{}' is not a valid TLS server name", name)))?,
+            ),
+            None => None,
+        };
+
+        let mut h2_config = config.clone();
+        h2_config.alpn_protocols = vec![b"h2".to_vec()];
+        Ok(Self {
+            tls: Some(TlsConnector {
+                config: Arc::new(config),
+                h2_config: Arc::new(h2_config),
+                server_name,
+            }),
+        })
+    }
+
+    pub fn is_tls(&self) -> bool {
+        self.tls.is_some()
+    }
+
+    /// Connect to `address` (`host:port`), completing the TLS handshake when
+    /// the upstream uses TLS. `timeout` also applies to reads and writes.
+    pub fn connect(&self, address: &str, timeout: Duration) -> Result<Box<dyn Transport>, ForwardError> {
+        self.open(address, timeout, false)
+    }
+
+    /// Connect to `address` to speak HTTP/2: over TLS the server must agree
+    /// to `h2` in ALPN; plain connections assume it (prior knowledge).
+    pub fn connect_h2(&self, address: &str, timeout: Duration) -> Result<Box<dyn Transport>, ForwardError> {
+        self.open(address, timeout, true)
+    }
+
+    fn open(&self, address: &str, timeout: Duration, h2: bool) -> Result<Box<dyn Transport>, ForwardError> {
+        let addr = address
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| ForwardError::Resolve(address.to_string()))?;
+
+        let stream = TcpStream::connect_timeout(&addr, timeout).map_err(ForwardError::Connect)?;
+        let _ = stream.set_read_timeout(Some(timeout));
+        let _ = stream.set_write_timeout(Some(timeout));
+
+        let Some(tls) = &self.tls else {
+            return Ok(Box::new(stream));
+        };
+        let server_name = match &tls.server_name {
+            Some(name) => name.clone(),
+            None => host_name(address)?,
+        };
+        let config = if h2 { &tls.h2_config } else { &tls.config };
+        let conn = ClientConnection::new(Arc::clone(config), server_name)
+            .map_err(|e| ForwardError::Tls(io::Error::other(e)))?;
+        let mut stream = StreamOwned::new(conn, stream);
+        while stream.conn.is_handshaking() {
+            stream
+                .conn
+                .complete_io(&mut stream.sock)
+                .map_err(ForwardError::Tls)?;
+        }
+        if h2 && stream.conn.alpn_protocol() != Some(b"h2") {
+            return Err(ForwardError::Tls(io::Error::other("upstream did not negotiate h2")));
+        }
+        Ok(Box::new(stream))
+    }
+}
+
+/// The host part of `host:port` (or `[v6]:port`) as a TLS server name.
+fn host_name(address: &str) -> Result<ServerName<'static>, ForwardError> {
+    let host = match address.rsplit_once(':') {
+        Some((host, _)) => host,
+        None => address,
+    };
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+    ServerName::try_from(host.to_string()).map_err(|_| {
+        ForwardError::Tls(invalid(format!("'{}' is not a valid TLS server name", host)))
+    })
+}
+
+fn root_store(tls: &UpstreamTlsConfig) -> io::Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    match &tls.ca_file {
+        Some(path) => {
+            for cert in tls::load_certs(path)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| invalid(format!("{}: {}", path.display(), e)))?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    Ok(roots)
+}
+
+/// `insecure_skip_verify`: accepts any certificate, but still checks that
+/// the server holds the key for the certificate it presented.
+#[derive(Debug)]
+struct SkipVerification(WebPkiSupportedAlgorithms);
+
+impl ServerCertVerifier for SkipVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.0)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.0)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_schemes()
+    }
+}
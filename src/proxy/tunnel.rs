@@ -0,0 +1,112 @@
+// src/proxy/tunnel.rs
+//
+// Relaying raw bytes between a client and an upstream once the connection
+// has stopped speaking HTTP (after `101 Switching Protocols`). One thread
+// polls both sockets and copies whatever arrives to the other side. When one
+// side finishes sending, the other side's write half is shut down and the
+// remaining direction carries on until it finishes too.
+
+use std::io;
+use std::net::{Shutdown, TcpStream};
+use std::os::fd::AsRawFd;
+use std::time::Duration;
+
+use crate::http::body::COPY_BUFFER_SIZE;
+use crate::proxy::connection::ClientStream;
+use crate::proxy::transport::{Endpoint, Transport};
+
+/// Bytes carried in each direction.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Relayed {
+    pub to_upstream: u64,
+    pub to_client: u64,
+}
+
+/// Copy bytes between `client` and `upstream` until both have finished
+/// sending, either fails, or neither sends anything for `idle_timeout`.
+/// Bytes carried are added to `relayed` as they go, so it is accurate even
+/// when the tunnel ends in an error.
+pub fn relay(
+    client: &mut ClientStream,
+    upstream: &mut Box<dyn Transport>,
+    idle_timeout: Duration,
+    relayed: &mut Relayed,
+) -> io::Result<()> {
+    // Whether each direction (client to upstream, upstream to client) is
+    // still open.
+    let mut open = [true, true];
+    let mut buf = vec![0u8; COPY_BUFFER_SIZE];
+
+    while open[0] || open[1] {
+        // Bytes TLS has already decrypted never show up in poll.
+        let buffered = [open[0] && client.has_buffered_data(), open[1] && upstream.has_buffered_data()];
+        let ready = if buffered.contains(&true) {
+            buffered
+        } else {
+            match wait([client.socket(), upstream.socket()], open, idle_timeout)? {
+                Some(ready) => ready,
+                None => break,
+            }
+        };
+
+        if ready[0] {
+            match pump(client, upstream, &mut buf)? {
+                0 => open[0] = false,
+                n => relayed.to_upstream += n as u64,
+            }
+        }
+        if ready[1] {
+            match pump(upstream, client, &mut buf)? {
+                0 => open[1] = false,
+                n => relayed.to_client += n as u64,
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Move one read's worth of bytes from `from` to `to`. At end of stream,
+/// tells `to` nothing more is coming and returns 0.
+fn pump(from: &mut dyn Endpoint, to: &mut dyn Endpoint, buf: &mut [u8]) -> io::Result<usize> {
+    let n = match from.read(buf) {
+        Ok(n) => n,
+        // TLS peers that close without close_notify end the stream too.
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+        Err(e) => return Err(e),
+    };
+    if n == 0 {
+        let _ = to.flush();
+        let _ = to.socket().shutdown(Shutdown::Write);
+        return Ok(0);
+    }
+    to.write_all(&buf[..n])?;
+    to.flush()?;
+    Ok(n)
+}
+
+/// Wait for either socket of an open direction to become readable. `None`
+/// when the timeout passes first.
+fn wait(sockets: [&TcpStream; 2], open: [bool; 2], timeout: Duration) -> io::Result<Option<[bool; 2]>> {
+    let mut fds = [0, 1].map(|i| libc::pollfd {
+        // poll skips negative descriptors.
+        fd: if open[i] { sockets[i].as_raw_fd() } else { -1 },
+        events: libc::POLLIN,
+        revents: 0,
+    });
+    let timeout = timeout.as_millis().min(libc::c_int::MAX as u128) as libc::c_int;
+    loop {
+        // SAFETY: `fds` is a valid array of pollfds for the duration of the call.
+        let ready = unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout) };
+        if ready < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        if ready == 0 {
+            return Ok(None);
+        }
+        return Ok(Some([fds[0].revents != 0, fds[1].revents != 0]));
+    }
+}
@@ -0,0 +1,258 @@
+// src/proxy/upgrade.rs
+//
+// Zero-downtime binary upgrades. A running proxy with `server.upgrade_socket`
+// set listens on that Unix socket. A new process started with the same
+// setting connects to it, receives the listening sockets over SCM_RIGHTS,
+// starts accepting on them and replies READY. Only then does the old process
+// begin its graceful shutdown, so the listening sockets are never closed and
+// no connection attempt is refused during the upgrade. With several
+// workers, the listeners of workers after the first are named `proxy.1`,
+// `tls.1` and so on; a process with fewer workers than its predecessor
+// shares the extra ones out among its own. The handoff socket is created
+// with mode 0600, as whoever can connect to it can take the listeners.
+//
+//   new -> old   LISTENERS\n
+//   old -> new   proxy proxy.1 admin\n  (+ one fd per name)
+//   new -> old   READY\n
+
+use std::fs::Permissions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::mem;
+use std::net::TcpListener;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::MAX_WORKERS;
+use crate::proxy::context::ProxyContext;
+use crate::{log_error, log_info, log_warn};
+
+/// A proxy and an HTTPS listener per worker, plus the admin listener.
+const MAX_FDS: usize = 2 * MAX_WORKERS + 1;
+/// How long the old process waits for its successor to become ready.
+const READY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Listening sockets received from the previous process.
+pub struct Inherited {
+    listeners: Vec<(String, TcpListener)>,
+    handoff: UnixStream,
+}
+
+impl Inherited {
+    /// Take the inherited listener registered under `name`.
+    pub fn take(&mut self, name: &str) -> Option<TcpListener> {
+        let index = self.listeners.iter().position(|(n, _)| n == name)?;
+        Some(self.listeners.remove(index).1)
+    }
+
+    /// Take the listeners of `kind` left after each worker took its own:
+    /// those of workers the previous process had and this one does not.
+    pub fn take_extra(&mut self, kind: &str) -> Vec<(String, TcpListener)> {
+        let prefix = format!("{}.", kind);
+        let (extra, rest) = mem::take(&mut self.listeners)
+            .into_iter()
+            .partition(|(name, _)| name.starts_with(&prefix));
+        self.listeners = rest;
+        extra
+    }
+
+    /// Tell the previous process we are accepting; it will start draining.
+    pub fn ready(mut self) -> io::Result<()> {
+        self.handoff.write_all(b"READY\n")
+    }
+}
+
+/// Ask a running process for its listeners. Returns `None` when nothing is
+/// listening on `path`, i.e. this is a fresh start rather than an upgrade.
+pub fn inherit(path: &Path) -> io::Result<Option<Inherited>> {
+    let mut stream = match UnixStream::connect(path) {
+        Ok(stream) => stream,
+        Err(e) if matches!(e.kind(), io::ErrorKind::NotFound | io::ErrorKind::ConnectionRefused) => {
+            return Ok(None);
+        }
+        Err(e) => return Err(e),
+    };
+    stream.set_read_timeout(Some(READY_TIMEOUT))?;
+    stream.write_all(b"LISTENERS\n")?;
+
+    let (data, fds) = recv_fds(&stream)?;
+    let names = String::from_utf8_lossy(&data);
+    let names: Vec<&str> = names.split_whitespace().collect();
+    if names.len() != fds.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("received {} listener names but {} sockets", names.len(), fds.len()),
+        ));
+    }
+
+    let listeners = names
+        .into_iter()
+        .zip(fds)
+        .map(|(name, fd)| (name.to_string(), TcpListener::from(fd)))
+        .collect();
+    Ok(Some(Inherited {
+        listeners,
+        handoff: stream,
+    }))
+}
+
+/// Serve handoff requests on `path` for the lifetime of the process.
+pub fn spawn_handoff_server(
+    path: PathBuf,
+    listeners: Vec<(String, RawFd)>,
+    context: Arc<ProxyContext>,
+) -> io::Result<()> {
+    // The previous process (if any) has handed over already; its socket
+    // file is replaced so the next upgrade reaches this process.
+    let server = bind_private(&path)?;
+    log_info!("Accepting upgrade handoffs on {}", path.display());
+
+    thread::spawn(move || {
+        for stream in server.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log_warn!("Failed to accept upgrade connection: {}", e);
+                    continue;
+                }
+            };
+            match hand_off(stream, &listeners) {
+                Ok(()) => {
+                    log_info!("New process is ready, draining this one");
+                    context.shutdown.request();
+                    return;
+                }
+                Err(e) => log_error!("Upgrade handoff failed, continuing to serve: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Bind a Unix socket at `path` that only this user can connect to. It is
+/// bound under a temporary name and renamed into place once its mode is
+/// set, so it is never reachable with the default permissions.
+fn bind_private(path: &Path) -> io::Result<UnixListener> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(".{}", std::process::id()));
+    let temporary = PathBuf::from(temporary);
+    let _ = std::fs::remove_file(&temporary);
+
+    let listener = UnixListener::bind(&temporary)?;
+    let moved = std::fs::set_permissions(&temporary, Permissions::from_mode(0o600))
+        .and_then(|()| std::fs::rename(&temporary, path));
+    if let Err(e) = moved {
+        let _ = std::fs::remove_file(&temporary);
+        return Err(e);
+    }
+    Ok(listener)
+}
+
+fn hand_off(stream: UnixStream, listeners: &[(String, RawFd)]) -> io::Result<()> {
+    stream.set_read_timeout(Some(READY_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if line.trim() != "LISTENERS" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unexpected request {:?}", line.trim())));
+    }
+
+    let names: Vec<&str> = listeners.iter().map(|(name, _)| name.as_str()).collect();
+    let fds: Vec<RawFd> = listeners.iter().map(|(_, fd)| *fd).collect();
+    send_fds(&stream, format!("{}\n", names.join(" ")).as_bytes(), &fds)?;
+    log_info!("Handed listeners ({}) to new process", names.join(", "));
+
+    line.clear();
+    reader.read_line(&mut line)?;
+    if line.trim() != "READY" {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "new process exited before becoming ready"));
+    }
+    Ok(())
+}
+
+/// Control message buffer, aligned for `cmsghdr`.
+fn control_buffer(fd_count: usize) -> Vec<u64> {
+    // SAFETY: CMSG_SPACE is a pure size computation.
+    let space = unsafe { libc::CMSG_SPACE((fd_count * mem::size_of::<RawFd>()) as u32) } as usize;
+    vec![0u64; space.div_ceil(mem::size_of::<u64>())]
+}
+
+fn send_fds(stream: &UnixStream, data: &[u8], fds: &[RawFd]) -> io::Result<()> {
+    let mut control = control_buffer(fds.len());
+    let payload = mem::size_of_val(fds) as u32;
+    let mut iov = libc::iovec {
+        iov_base: data.as_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+
+    // SAFETY: every pointer in `msg` refers to a live local buffer of the
+    // stated length, and the control message is written within `control`.
+    unsafe {
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = libc::CMSG_SPACE(payload) as _;
+
+        let cmsg = libc::CMSG_FIRSTHDR(&msg);
+        (*cmsg).cmsg_level = libc::SOL_SOCKET;
+        (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+        (*cmsg).cmsg_len = libc::CMSG_LEN(payload) as _;
+        ptr::copy_nonoverlapping(fds.as_ptr(), libc::CMSG_DATA(cmsg) as *mut RawFd, fds.len());
+
+        if libc::sendmsg(stream.as_raw_fd(), &msg, 0) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+fn recv_fds(stream: &UnixStream) -> io::Result<(Vec<u8>, Vec<OwnedFd>)> {
+    let mut data = vec![0u8; 4096];
+    let mut control = control_buffer(MAX_FDS);
+    let mut iov = libc::iovec {
+        iov_base: data.as_mut_ptr() as *mut libc::c_void,
+        iov_len: data.len(),
+    };
+    let mut fds = Vec::new();
+
+    // SAFETY: as in `send_fds`; received descriptors are owned by us and
+    // wrapped in `OwnedFd` immediately so they are closed on error.
+    let received = unsafe {
+        let mut msg: libc::msghdr = mem::zeroed();
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = (control.len() * mem::size_of::<u64>()) as _;
+
+        let received = libc::recvmsg(stream.as_raw_fd(), &mut msg, libc::MSG_CMSG_CLOEXEC);
+        if received < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+                let payload = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                let data = libc::CMSG_DATA(cmsg) as *const RawFd;
+                for i in 0..payload / mem::size_of::<RawFd>() {
+                    fds.push(OwnedFd::from_raw_fd(ptr::read_unaligned(data.add(i))));
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+        if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "too many listeners in handoff"));
+        }
+        received as usize
+    };
+
+    data.truncate(received);
+    Ok((data, fds))
+}
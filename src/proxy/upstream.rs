@@ -0,0 +1,172 @@
+// src/proxy/upstream.rs
+//
+// Runtime state for upstream groups: the servers behind each group, their
+// health, and how many connections each one is currently serving.
+
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::io;
+use std::time::Duration;
+
+use crate::config::{HealthCheckConfig, UpstreamConfig};
+use crate::proxy::h2::client::Connections;
+use crate::proxy::transport::Connector;
+
+/// A single upstream server.
+#[derive(Debug)]
+pub struct Backend {
+    pub address: String,
+    weight: AtomicU32,
+    healthy: AtomicBool,
+    /// Drained backends finish in-flight requests but receive no new ones.
+    drained: AtomicBool,
+    in_use: AtomicUsize,
+    max_connections: usize,
+    /// Shared connections, when the upstream speaks HTTP/2.
+    pub h2: Connections,
+}
+
+/// Holds one of a backend's connection slots until dropped.
+#[derive(Debug)]
+pub struct ConnectionSlot {
+    backend: Arc<Backend>,
+}
+
+impl Backend {
+    pub fn new(address: impl Into<String>, weight: u32, max_connections: usize) -> Self {
+        Self {
+            address: address.into(),
+            weight: AtomicU32::new(weight),
+            healthy: AtomicBool::new(true),
+            drained: AtomicBool::new(false),
+            in_use: AtomicUsize::new(0),
+            max_connections,
+            h2: Connections::default(),
+        }
+    }
+
+    pub fn weight(&self) -> u32 {
+        self.weight.load(Ordering::Relaxed)
+    }
+
+    pub fn set_weight(&self, weight: u32) {
+        self.weight.store(weight, Ordering::Relaxed);
+    }
+
+    pub fn is_drained(&self) -> bool {
+        self.drained.load(Ordering::Relaxed)
+    }
+
+    pub fn set_drained(&self, drained: bool) {
+        self.drained.store(drained, Ordering::Relaxed);
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Relaxed)
+    }
+
+    pub fn set_healthy(&self, healthy: bool) {
+        self.healthy.store(healthy, Ordering::Relaxed);
+    }
+
+    /// Connections currently open to this backend.
+    pub fn in_use(&self) -> usize {
+        self.in_use.load(Ordering::Relaxed)
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.max_connections
+    }
+
+    fn available(&self) -> bool {
+        self.is_healthy() && !self.is_drained() && self.weight() > 0
+    }
+
+    /// Reserve a connection slot, or `None` when the backend is saturated.
+    pub fn acquire(self: &Arc<Self>) -> Option<ConnectionSlot> {
+        self.in_use
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| {
+                (n < self.max_connections).then_some(n + 1)
+            })
+            .ok()
+            .map(|_| ConnectionSlot {
+                backend: Arc::clone(self),
+            })
+    }
+}
+
+impl ConnectionSlot {
+    pub fn backend(&self) -> &Arc<Backend> {
+        &self.backend
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.backend.in_use.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// A named group of backends sharing load.
+#[derive(Debug)]
+pub struct UpstreamPool {
+    pub name: String,
+    pub backends: Vec<Arc<Backend>>,
+    pub timeout: Duration,
+    pub health_check: Option<HealthCheckConfig>,
+    /// Plain or TLS connections, per the upstream's `tls` section.
+    pub connector: Connector,
+    /// Requests go out over HTTP/2 rather than HTTP/1.1.
+    pub http2: bool,
+    /// Smooth weighted round-robin state, one entry per backend.
+    current_weights: Mutex<Vec<i64>>,
+}
+
+impl UpstreamPool {
+    pub fn new(config: &UpstreamConfig) -> io::Result<Self> {
+        let backends: Vec<Arc<Backend>> = config
+            .servers
+            .iter()
+            .map(|server| Arc::new(Backend::new(&server.address, server.weight, config.max_connections)))
+            .collect();
+
+        Ok(Self {
+            name: config.name.clone(),
+            current_weights: Mutex::new(vec![0; backends.len()]),
+            backends,
+            timeout: config.timeout,
+            health_check: config.health_check.clone(),
+            connector: Connector::new(config.tls.as_ref())?,
+            http2: config.http2,
+        })
+    }
+
+    pub fn backend(&self, address: &str) -> Option<&Arc<Backend>> {
+        self.backends.iter().find(|b| b.address == address)
+    }
+
+    /// Pick the next healthy backend using smooth weighted round-robin
+    /// (the same scheme nginx uses), so heavier backends are interleaved
+    /// rather than hit in bursts.
+    pub fn select(&self) -> Option<Arc<Backend>> {
+        let mut current = self.current_weights.lock().unwrap_or_else(|e| e.into_inner());
+        let mut total = 0i64;
+        let mut best: Option<usize> = None;
+
+        for (i, backend) in self.backends.iter().enumerate() {
+            if !backend.available() {
+                continue;
+            }
+            let weight = i64::from(backend.weight());
+            current[i] += weight;
+            total += weight;
+            if best.is_none_or(|b| current[i] > current[b]) {
+                best = Some(i);
+            }
+        }
+
+        let best = best?;
+        current[best] -= total;
+        Some(Arc::clone(&self.backends[best]))
+    }
+}
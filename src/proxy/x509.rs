@@ -0,0 +1,218 @@
+// src/proxy/x509.rs
+//
+// Just enough DER to pull the subject and subject alternative names out of
+// a client certificate. The certificate has already been verified by rustls;
+// this only reads it.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use crate::http::ClientCertificate;
+
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const OID: u8 = 0x06;
+const BOOLEAN: u8 = 0x01;
+const OCTET_STRING: u8 = 0x04;
+const VERSION: u8 = 0xa0; // [0] EXPLICIT
+const EXTENSIONS: u8 = 0xa3; // [3] EXPLICIT
+
+const SUBJECT_ALT_NAME: &[u8] = &[0x55, 0x1d, 0x11]; // 2.5.29.17
+
+/// A cursor over a run of DER elements.
+struct Der<'a> {
+    input: &'a [u8],
+}
+
+impl<'a> Der<'a> {
+    fn new(input: &'a [u8]) -> Self {
+        Self { input }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.input.is_empty()
+    }
+
+    fn peek_tag(&self) -> Option<u8> {
+        self.input.first().copied()
+    }
+
+    /// The next element as (tag, contents).
+    fn next(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = self.input.split_first()?;
+        let (&first, mut rest) = rest.split_first()?;
+        let len = if first < 0x80 {
+            first as usize
+        } else {
+            let count = (first & 0x7f) as usize;
+            if count == 0 || count > 4 || rest.len() < count {
+                return None;
+            }
+            let len = rest[..count].iter().fold(0usize, |acc, &b| (acc << 8) | b as usize);
+            rest = &rest[count..];
+            len
+        };
+        if rest.len() < len {
+            return None;
+        }
+        let (contents, rest) = rest.split_at(len);
+        self.input = rest;
+        Some((tag, contents))
+    }
+
+    fn expect(&mut self, expected: u8) -> Option<&'a [u8]> {
+        match self.next()? {
+            (tag, contents) if tag == expected => Some(contents),
+            _ => None,
+        }
+    }
+}
+
+/// Subject and SANs of a DER certificate, or `None` if it cannot be read.
+pub fn client_certificate(der: &[u8]) -> Option<ClientCertificate> {
+    let certificate = Der::new(der).expect(SEQUENCE)?;
+    let mut tbs = Der::new(Der::new(certificate).expect(SEQUENCE)?);
+
+    if tbs.peek_tag() == Some(VERSION) {
+        tbs.next()?;
+    }
+    tbs.next()?; // serialNumber
+    tbs.expect(SEQUENCE)?; // signature
+    tbs.expect(SEQUENCE)?; // issuer
+    tbs.expect(SEQUENCE)?; // validity
+    let subject = tbs.expect(SEQUENCE)?;
+    tbs.expect(SEQUENCE)?; // subjectPublicKeyInfo
+
+    let mut sans = Vec::new();
+    while let Some((tag, contents)) = tbs.next() {
+        if tag == EXTENSIONS {
+            sans = subject_alt_names(Der::new(contents).expect(SEQUENCE)?)?;
+        }
+    }
+
+    Some(ClientCertificate {
+        subject: distinguished_name(subject)?,
+        sans,
+    })
+}
+
+/// RFC 4514 string form of a Name: most specific RDN first, e.g.
+/// `CN=client,O=Example`.
+fn distinguished_name(name: &[u8]) -> Option<String> {
+    let mut rdns = Vec::new();
+    let mut der = Der::new(name);
+    while !der.is_empty() {
+        let mut set = Der::new(der.expect(SET)?);
+        let mut attributes = Vec::new();
+        while !set.is_empty() {
+            let mut attribute = Der::new(set.expect(SEQUENCE)?);
+            let oid = attribute.expect(OID)?;
+            let (_, value) = attribute.next()?;
+            attributes.push(format!(
+                "{}={}",
+                attribute_name(oid),
+                escape_value(&String::from_utf8_lossy(value))
+            ));
+        }
+        rdns.push(attributes.join("+"));
+    }
+    rdns.reverse();
+    Some(rdns.join(","))
+}
+
+fn attribute_name(oid: &[u8]) -> String {
+    match oid {
+        [0x55, 0x04, 0x03] => "CN".to_string(),
+        [0x55, 0x04, 0x05] => "serialNumber".to_string(),
+        [0x55, 0x04, 0x06] => "C".to_string(),
+        [0x55, 0x04, 0x07] => "L".to_string(),
+        [0x55, 0x04, 0x08] => "ST".to_string(),
+        [0x55, 0x04, 0x09] => "STREET".to_string(),
+        [0x55, 0x04, 0x0a] => "O".to_string(),
+        [0x55, 0x04, 0x0b] => "OU".to_string(),
+        [0x09, 0x92, 0x26, 0x89, 0x93, 0xf2, 0x2c, 0x64, 0x01, 0x01] => "UID".to_string(),
+        [0x09, 0x92, 0x26, 0x89, 0x93, 0xf2, 0x2c, 0x64, 0x01, 0x19] => "DC".to_string(),
+        [0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x01] => "emailAddress".to_string(),
+        other => dotted_oid(other),
+    }
+}
+
+fn dotted_oid(oid: &[u8]) -> String {
+    let Some((&first, rest)) = oid.split_first() else {
+        return String::new();
+    };
+    let (arc1, arc2) = if first < 80 { (first / 40, first % 40) } else { (2, first - 80) };
+    let mut parts = vec![arc1 as u64, arc2 as u64];
+    let mut value = 0u64;
+    for &byte in rest {
+        value = (value << 7) | (byte & 0x7f) as u64;
+        if byte & 0x80 == 0 {
+            parts.push(value);
+            value = 0;
+        }
+    }
+    parts
+        .iter()
+        .map(|p| p.to_string())
+        .collect::<Vec<_>>()
+        .join(".")
+}
+
+fn escape_value(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    let last = value.chars().count().saturating_sub(1);
+    for (i, c) in value.chars().enumerate() {
+        let special = matches!(c, ',' | '+' | '"' | '\\' | '<' | '>' | ';')
+            || (i == 0 && (c == '#' || c == ' '))
+            || (i == last && c == ' ');
+        if c.is_control() {
+            // Never let certificate contents break a forwarded header line.
+            out.push_str(&format!("\\{:02x}", c as u32 & 0xff));
+            continue;
+        }
+        if special {
+            out.push('\\');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// SANs from the extension list, in OpenSSL's `DNS:`/`IP:`/`URI:`/`email:` form.
+fn subject_alt_names(extensions: &[u8]) -> Option<Vec<String>> {
+    let mut der = Der::new(extensions);
+    while !der.is_empty() {
+        let mut extension = Der::new(der.expect(SEQUENCE)?);
+        let oid = extension.expect(OID)?;
+        if extension.peek_tag() == Some(BOOLEAN) {
+            extension.next()?;
+        }
+        let value = extension.expect(OCTET_STRING)?;
+        if oid != SUBJECT_ALT_NAME {
+            continue;
+        }
+
+        let mut names = Vec::new();
+        let mut general_names = Der::new(Der::new(value).expect(SEQUENCE)?);
+        while let Some((tag, contents)) = general_names.next() {
+            let text = || {
+                String::from_utf8_lossy(contents)
+                    .chars()
+                    .map(|c| if c.is_control() { '?' } else { c })
+                    .collect::<String>()
+            };
+            match tag {
+                0x81 => names.push(format!("email:{}", text())),
+                0x82 => names.push(format!("DNS:{}", text())),
+                0x86 => names.push(format!("URI:{}", text())),
+                0x87 => match contents.len() {
+                    4 => names.push(format!("IP:{}", Ipv4Addr::from(<[u8; 4]>::try_from(contents).ok()?))),
+                    16 => names.push(format!("IP:{}", Ipv6Addr::from(<[u8; 16]>::try_from(contents).ok()?))),
+                    _ => {}
+                },
+                _ => {}
+            }
+        }
+        return Some(names);
+    }
+    Some(Vec::new())
+}
@@ -0,0 +1,94 @@
+// src/util/lru.rs
+//
+// Size-bounded least-recently-used bookkeeping, shared by the cache's memory
+// store and disk index and by the rate limiter's buckets.
+
+use std::collections::{BTreeMap, HashMap};
+
+#[derive(Debug)]
+pub struct Lru<V> {
+    entries: HashMap<String, Slot<V>>,
+    /// Keys by the tick of their last use, oldest first.
+    order: BTreeMap<u64, String>,
+    tick: u64,
+    size: u64,
+    max_size: u64,
+}
+
+#[derive(Debug)]
+struct Slot<V> {
+    value: V,
+    size: u64,
+    tick: u64,
+}
+
+impl<V> Lru<V> {
+    pub fn new(max_size: u64) -> Self {
+        Self {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            size: 0,
+            max_size,
+        }
+    }
+
+    /// Look up `key`, marking it as just used.
+    pub fn get(&mut self, key: &str) -> Option<&V> {
+        let slot = self.entries.get_mut(key)?;
+        self.order.remove(&slot.tick);
+        self.tick += 1;
+        slot.tick = self.tick;
+        self.order.insert(self.tick, key.to_string());
+        Some(&slot.value)
+    }
+
+    /// Look up `key` without counting it as a use.
+    pub fn peek(&self, key: &str) -> Option<&V> {
+        self.entries.get(key).map(|slot| &slot.value)
+    }
+
+    /// Insert or replace `key`, then evict the least recently used entries
+    /// until the total fits. Returns what was evicted; a value larger than
+    /// the whole store is evicted straight away.
+    pub fn insert(&mut self, key: String, value: V, size: u64) -> Vec<(String, V)> {
+        self.remove(&key);
+        self.tick += 1;
+        self.order.insert(self.tick, key.clone());
+        self.entries.insert(key, Slot { value, size, tick: self.tick });
+        self.size += size;
+
+        let mut evicted = Vec::new();
+        while self.size > self.max_size {
+            let Some((_, oldest)) = self.order.pop_first() else { break };
+            if let Some(slot) = self.entries.remove(&oldest) {
+                self.size -= slot.size;
+                evicted.push((oldest, slot.value));
+            }
+        }
+        evicted
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        let slot = self.entries.remove(key)?;
+        self.order.remove(&slot.tick);
+        self.size -= slot.size;
+        Some(slot.value)
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &V)> {
+        self.entries.iter().map(|(key, slot)| (key, &slot.value))
+    }
+}
@@ -0,0 +1,5 @@
+// src/util/mod.rs
+//
+// Data structures shared by more than one part of the proxy.
+
+pub mod lru;
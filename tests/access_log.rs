@@ -0,0 +1,76 @@
+// The access log file: reopened on SIGUSR1 so rotated files are released.
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{ProxyProcess, Upstream, free_port, temp_dir};
+
+fn get(proxy: SocketAddr, path: &str) {
+    let mut stream = TcpStream::connect(proxy).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+}
+
+/// Wait for `path` to hold `expected`: lines are written as requests end,
+/// which may be just after the client has its response.
+fn assert_contents(path: &Path, expected: &str) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    loop {
+        let contents = std::fs::read_to_string(path).unwrap_or_default();
+        if contents == expected {
+            return;
+        }
+        assert!(Instant::now() < deadline, "{} holds {:?}", path.display(), contents);
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn sigusr1_reopens_the_log_file() {
+    let upstream = Upstream::spawn();
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let dir = temp_dir("access-log");
+    let log = dir.join("access.log");
+    let rotated = dir.join("access.log.1");
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+
+[upstream]
+address = "{upstream}"
+
+[access_log]
+format = "%U %s"
+path = "{log}"
+"#,
+        upstream = upstream.address,
+        log = log.display()
+    );
+    let proxy = ProxyProcess::spawn(&config, listen);
+
+    get(listen, "/before");
+    assert_contents(&log, "/before 200\n");
+    std::fs::rename(&log, &rotated).unwrap();
+    // Until it is reopened, the log is still the rotated file.
+    get(listen, "/rotated");
+    assert_contents(&rotated, "/before 200\n/rotated 200\n");
+
+    proxy.signal(libc::SIGUSR1);
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !log.exists() {
+        assert!(Instant::now() < deadline, "log file was not reopened");
+        thread::sleep(Duration::from_millis(20));
+    }
+    get(listen, "/after");
+    assert_contents(&log, "/after 200\n");
+    assert_contents(&rotated, "/before 200\n/rotated 200\n");
+}
@@ -0,0 +1,277 @@
+// The admin listener's JSON API: reading routes, upstreams, connections and
+// the configuration; draining backends and changing their weights; and the
+// answers to bad requests, unknown names and missing tokens.
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use common::{Upstream, free_port, header, start_proxy};
+use orion::json::{self, JsonValue};
+
+struct Admin {
+    proxy: SocketAddr,
+    admin: SocketAddr,
+    one: Upstream,
+    two: Upstream,
+}
+
+/// A proxy routing `/` to an upstream group "app" of two servers, with
+/// `admin` added to its `[admin]` section.
+fn start(admin_settings: &str) -> Admin {
+    let (one, two) = (Upstream::spawn(), Upstream::spawn());
+    let proxy: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let admin: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{proxy}"
+
+[admin]
+listen = "{admin}"
+{admin_settings}
+
+[upstreams.app]
+servers = ["{one}", {{ address = "{two}", weight = 3 }}]
+
+[[routes]]
+name = "app"
+host = "app.test"
+prefix = "/"
+upstream = "app"
+
+[access_log]
+enabled = false
+"#,
+        one = one.address,
+        two = two.address
+    );
+    start_proxy(&config, proxy);
+    Admin { proxy, admin, one, two }
+}
+
+/// Send a request to the admin listener; the status and the response.
+fn call(admin: SocketAddr, method: &str, path: &str, headers: &str, body: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(admin).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: admin\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        method,
+        path,
+        headers,
+        body.len(),
+        body
+    )
+    .unwrap();
+    // The admin listener keeps the connection open, so read by length.
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap() == 1 {
+        head.push(byte[0]);
+    }
+    let mut response = String::from_utf8(head).unwrap();
+    let length = header(&response, "content-length").map_or(0, |l| l.parse().unwrap());
+    let mut body = vec![0u8; length];
+    stream.read_exact(&mut body).unwrap();
+    response.push_str(&String::from_utf8(body).unwrap());
+    let status = response.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, response)
+}
+
+fn get_json(admin: SocketAddr, path: &str) -> JsonValue {
+    let (status, response) = call(admin, "GET", path, "", "");
+    assert_eq!(status, 200, "{}", response);
+    assert_eq!(header(&response, "content-type").as_deref(), Some("application/json"));
+    json::parse(response.split_once("\r\n\r\n").unwrap().1).unwrap()
+}
+
+fn error_of(response: &str) -> String {
+    let body = json::parse(response.split_once("\r\n\r\n").unwrap().1).unwrap();
+    body.get("error").and_then(JsonValue::as_str).unwrap().to_string()
+}
+
+fn field<'a>(value: &'a JsonValue, path: &[&str]) -> &'a JsonValue {
+    path.iter()
+        .fold(value, |value, key| value.get(key).unwrap_or_else(|| panic!("no {}", key)))
+}
+
+fn backend(upstream: &JsonValue, address: SocketAddr) -> &JsonValue {
+    field(upstream, &["backends"])
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|b| b.get("address").and_then(JsonValue::as_str) == Some(address.to_string().as_str()))
+        .unwrap()
+}
+
+fn proxy_get(proxy: SocketAddr) {
+    let mut stream = TcpStream::connect(proxy).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: app.test\r\nConnection: close\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+}
+
+#[test]
+fn routes_upstreams_connections_and_config_are_listed() {
+    let admin = start("").admin;
+
+    let routes = get_json(admin, "/routes");
+    let route = &routes.as_array().unwrap()[0];
+    assert_eq!(field(route, &["name"]).as_str(), Some("app"));
+    assert_eq!(field(route, &["host"]).as_str(), Some("app.test"));
+    assert_eq!(field(route, &["prefix"]).as_str(), Some("/"));
+    assert_eq!(field(route, &["upstream"]).as_str(), Some("app"));
+
+    let upstreams = get_json(admin, "/upstreams");
+    assert_eq!(upstreams.as_array().unwrap().len(), 1);
+    let app = get_json(admin, "/upstreams/app");
+    assert_eq!(field(&app, &["name"]).as_str(), Some("app"));
+    let backends = field(&app, &["backends"]).as_array().unwrap();
+    assert_eq!(backends.len(), 2);
+    assert_eq!(field(&backends[1], &["weight"]).as_f64(), Some(3.0));
+    assert_eq!(field(&backends[1], &["drained"]).as_bool(), Some(false));
+
+    let connections = get_json(admin, "/connections");
+    assert!(field(&connections, &["client_connections"]).as_f64().is_some());
+    let pools = field(&connections, &["upstreams"]).as_array().unwrap();
+    assert_eq!(field(&pools[0], &["name"]).as_str(), Some("app"));
+
+    let config = get_json(admin, "/config");
+    assert_eq!(field(&config, &["admin", "listen"]).as_str(), Some(admin.to_string().as_str()));
+    assert_eq!(field(&config, &["admin", "token_required"]).as_bool(), Some(false));
+    assert_eq!(field(&config, &["routes"]).as_array().unwrap().len(), 1);
+    let servers = field(&config, &["upstreams"]).as_array().unwrap()[0].get("servers").unwrap();
+    assert_eq!(servers.as_array().unwrap().len(), 2);
+}
+
+#[test]
+fn drained_backends_receive_no_requests() {
+    let Admin { proxy, admin, one, two } = start("");
+    let path = format!("/upstreams/app/backends/{}/drain", two.address);
+
+    let (status, response) = call(admin, "POST", &path, "", "");
+    assert_eq!(status, 200, "{}", response);
+    let drained = json::parse(response.split_once("\r\n\r\n").unwrap().1).unwrap();
+    assert_eq!(field(&drained, &["drained"]).as_bool(), Some(true));
+    assert_eq!(field(backend(&get_json(admin, "/upstreams/app"), two.address), &["drained"]).as_bool(), Some(true));
+    let config = get_json(admin, "/config");
+    let servers = field(&config, &["upstreams"]).as_array().unwrap()[0].get("servers").unwrap();
+    assert_eq!(field(&servers.as_array().unwrap()[1], &["drained"]).as_bool(), Some(true));
+
+    for _ in 0..6 {
+        proxy_get(proxy);
+    }
+    assert_eq!(one.requests.lock().unwrap().len(), 6);
+    assert_eq!(two.requests.lock().unwrap().len(), 0);
+
+    let (status, _) = call(admin, "POST", &path.replace("/drain", "/undrain"), "", "");
+    assert_eq!(status, 200);
+    for _ in 0..8 {
+        proxy_get(proxy);
+    }
+    assert!(!two.requests.lock().unwrap().is_empty());
+}
+
+#[test]
+fn weights_can_be_changed() {
+    let Admin { proxy, admin, one, two } = start("");
+    let path = format!("/upstreams/app/backends/{}/weight", one.address);
+
+    let (status, response) = call(admin, "PUT", &path, "", "{\"weight\": 0}");
+    assert_eq!(status, 200, "{}", response);
+    assert_eq!(field(backend(&get_json(admin, "/upstreams/app"), one.address), &["weight"]).as_f64(), Some(0.0));
+    for _ in 0..4 {
+        proxy_get(proxy);
+    }
+    assert_eq!(one.requests.lock().unwrap().len(), 0);
+    assert_eq!(two.requests.lock().unwrap().len(), 4);
+
+    // POST is accepted too.
+    let (status, _) = call(admin, "POST", &path, "", "{\"weight\": 5}");
+    assert_eq!(status, 200);
+    assert_eq!(field(backend(&get_json(admin, "/upstreams/app"), one.address), &["weight"]).as_f64(), Some(5.0));
+}
+
+#[test]
+fn bad_requests_are_refused() {
+    let Admin { admin, one, .. } = start("");
+    let weight = format!("/upstreams/app/backends/{}/weight", one.address);
+
+    for (body, message) in [
+        ("not json", "Invalid JSON body"),
+        ("{}", "Expected a numeric \"weight\" field"),
+        ("{\"weight\": -1}", "Weight must be a non-negative integer"),
+        ("{\"weight\": 1.5}", "Weight must be a non-negative integer"),
+    ] {
+        let (status, response) = call(admin, "PUT", &weight, "", body);
+        assert_eq!(status, 400, "{}", body);
+        assert!(error_of(&response).starts_with(message), "{}", response);
+    }
+    assert_eq!(field(backend(&get_json(admin, "/upstreams/app"), one.address), &["weight"]).as_f64(), Some(1.0));
+
+    for (method, path, message) in [
+        ("GET", "/upstreams/none".to_string(), "Unknown upstream"),
+        ("POST", format!("/upstreams/none/backends/{}/drain", one.address), "Unknown upstream"),
+        ("POST", "/upstreams/app/backends/10.9.9.9:1/drain".to_string(), "Unknown backend"),
+        ("GET", "/cache".to_string(), "Caching is not enabled"),
+        ("POST", "/cache/purge".to_string(), "Caching is not enabled"),
+        ("GET", "/nothing".to_string(), "Not Found"),
+    ] {
+        let (status, response) = call(admin, method, &path, "", "");
+        assert_eq!(status, 404, "{} {}", method, path);
+        assert_eq!(error_of(&response), message);
+    }
+
+    for (method, path) in [("DELETE", "/routes"), ("POST", "/config"), ("PUT", "/upstreams/app"), ("POST", "/connections")] {
+        let (status, _) = call(admin, method, path, "", "");
+        assert_eq!(status, 405, "{} {}", method, path);
+    }
+    let (status, _) = call(admin, "POST", "/metrics", "", "");
+    assert_eq!(status, 405);
+}
+
+#[test]
+fn a_configured_token_is_required() {
+    let Admin { admin, two, .. } = start("token = \"s3cret-token\"");
+    let drain = format!("/upstreams/app/backends/{}/drain", two.address);
+
+    for headers in ["", "Authorization: Bearer wrong\r\n", "Authorization: Basic s3cret-token\r\n"] {
+        for (method, path) in [("GET", "/routes"), ("GET", "/metrics"), ("POST", drain.as_str()), ("GET", "/nothing")] {
+            let (status, response) = call(admin, method, path, headers, "");
+            assert_eq!(status, 401, "{} {} with {:?}", method, path, headers);
+            assert_eq!(header(&response, "www-authenticate").as_deref(), Some("Bearer realm=\"orion-admin\""));
+        }
+    }
+    assert_eq!(field(backend(&get_json_with_token(admin, "/upstreams/app"), two.address), &["drained"]).as_bool(), Some(false));
+
+    let token = "Authorization: Bearer s3cret-token\r\n";
+    let (status, response) = call(admin, "POST", &drain, token, "");
+    assert_eq!(status, 200, "{}", response);
+    let (status, _) = call(admin, "GET", "/metrics", token, "");
+    assert_eq!(status, 200);
+    // The token itself is never shown.
+    let (_, response) = call(admin, "GET", "/config", token, "");
+    assert!(!response.contains("s3cret"), "{}", response);
+    let config = get_json_with_token(admin, "/config");
+    assert_eq!(field(&config, &["admin", "token_required"]).as_bool(), Some(true));
+}
+
+/// `get_json`, authorised; the scheme is matched case-insensitively.
+fn get_json_with_token(admin: SocketAddr, path: &str) -> JsonValue {
+    let (status, response) = call(admin, "GET", path, "authorization: bearer s3cret-token\r\n", "");
+    assert_eq!(status, 200, "{}", response);
+    json::parse(response.split_once("\r\n\r\n").unwrap().1).unwrap()
+}
+
+#[test]
+fn invalid_tokens_are_rejected_by_the_config() {
+    for token in ["\"\"", "\"has space\""] {
+        let config = format!("[upstream]\naddress = \"127.0.0.1:8081\"\n[admin]\nlisten = \"127.0.0.1:9901\"\ntoken = {}", token);
+        assert!(orion::config::Config::parse(&config).is_err(), "{}", token);
+    }
+}
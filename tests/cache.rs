@@ -0,0 +1,447 @@
+// The response cache: fresh responses served without the upstream, stale
+// ones revalidated, variants kept apart, and entries evicted from memory
+// found again on disk; purging, over PURGE requests and the admin API; and
+// concurrent misses collapsed into one upstream request.
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use common::{free_port, header, start_proxy, temp_dir};
+
+type Handler = dyn Fn(&str, usize) -> String + Send + Sync;
+
+/// An upstream whose responses are written by `handler`, given the request
+/// head and how many requests came before it.
+struct Scripted {
+    address: SocketAddr,
+    requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl Scripted {
+    fn spawn(handler: impl Fn(&str, usize) -> String + Send + Sync + 'static) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+        let handler: Arc<Handler> = Arc::new(handler);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let (recorded, handler) = (Arc::clone(&recorded), Arc::clone(&handler));
+                thread::spawn(move || {
+                    let mut head = Vec::new();
+                    let mut byte = [0u8; 1];
+                    while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                        head.push(byte[0]);
+                    }
+                    let head = String::from_utf8_lossy(&head).to_string();
+                    let length: usize = header(&head, "content-length").and_then(|l| l.parse().ok()).unwrap_or(0);
+                    let mut body = vec![0u8; length];
+                    let _ = stream.read_exact(&mut body);
+                    let seen = {
+                        let mut requests = recorded.lock().unwrap();
+                        requests.push(head.clone());
+                        requests.len() - 1
+                    };
+                    let _ = stream.write_all(handler(&head, seen).as_bytes());
+                });
+            }
+        });
+
+        Self { address, requests }
+    }
+
+    fn count(&self) -> usize {
+        self.requests.lock().unwrap().len()
+    }
+
+    fn last_request(&self) -> String {
+        self.requests.lock().unwrap().last().cloned().unwrap_or_default()
+    }
+}
+
+fn response(status: &str, headers: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        headers,
+        body.len(),
+        body
+    )
+}
+
+fn start(upstream: SocketAddr, cache: &str) -> SocketAddr {
+    start_with_admin(upstream, cache).0
+}
+
+/// The proxy and its admin listener.
+fn start_with_admin(upstream: SocketAddr, cache: &str) -> (SocketAddr, SocketAddr) {
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let admin: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+
+[admin]
+listen = "{admin}"
+
+[upstream]
+address = "{upstream}"
+
+[cache]
+all_routes = true
+{cache}
+
+[access_log]
+enabled = false
+"#
+    );
+    start_proxy(&config, listen);
+    (listen, admin)
+}
+
+fn request(proxy: SocketAddr, method: &str, path: &str, extra: &str) -> String {
+    send(proxy, method, path, extra, "")
+}
+
+fn send(address: SocketAddr, method: &str, path: &str, extra: &str, body: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let length = match (method, body) {
+        ("GET" | "HEAD", "") => String::new(),
+        _ => format!("Content-Length: {}\r\n", body.len()),
+    };
+    write!(
+        stream,
+        "{} {} HTTP/1.1\r\nHost: cache.test\r\n{}{}Connection: close\r\n\r\n{}",
+        method, path, extra, length, body
+    )
+    .unwrap();
+    // The admin listener keeps the connection open, so read by length.
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap() == 1 {
+        head.push(byte[0]);
+    }
+    let mut response = String::from_utf8(head).unwrap();
+    let mut body = Vec::new();
+    match header(&response, "content-length").and_then(|l| l.parse::<usize>().ok()) {
+        _ if method == "HEAD" => {}
+        Some(length) => {
+            body.resize(length, 0);
+            stream.read_exact(&mut body).unwrap();
+        }
+        None => {
+            stream.read_to_end(&mut body).unwrap();
+        }
+    }
+    response.push_str(&String::from_utf8(body).unwrap());
+    response
+}
+
+fn body(response: &str) -> &str {
+    response.split_once("\r\n\r\n").map_or("", |(_, body)| body)
+}
+
+#[test]
+fn fresh_responses_are_served_from_the_cache() {
+    let upstream = Scripted::spawn(|_, _| response("200 OK", "Cache-Control: max-age=60\r\n", "stored"));
+    let proxy = start(upstream.address, "");
+
+    let first = request(proxy, "GET", "/page", "");
+    assert_eq!(header(&first, "x-cache").as_deref(), Some("MISS"));
+    let second = request(proxy, "GET", "/page", "");
+    assert!(second.starts_with("HTTP/1.1 200"), "{}", second);
+    assert_eq!(header(&second, "x-cache").as_deref(), Some("HIT"));
+    assert!(header(&second, "age").is_some());
+    assert_eq!(body(&second), "stored");
+
+    let head = request(proxy, "HEAD", "/page", "");
+    assert_eq!(header(&head, "x-cache").as_deref(), Some("HIT"));
+    assert_eq!(header(&head, "content-length").as_deref(), Some("6"));
+    assert_eq!(body(&head), "");
+
+    // A different query is a different key.
+    request(proxy, "GET", "/page?v=2", "");
+    assert_eq!(upstream.count(), 2);
+}
+
+#[test]
+fn responses_that_forbid_storing_are_passed_through() {
+    let upstream = Scripted::spawn(|head, _| {
+        let control = if head.starts_with("GET /private") { "private" } else { "no-store" };
+        response("200 OK", &format!("Cache-Control: {}\r\n", control), "fresh")
+    });
+    let proxy = start(upstream.address, "");
+
+    for path in ["/secret", "/secret", "/private", "/private"] {
+        let reply = request(proxy, "GET", path, "");
+        assert_eq!(header(&reply, "x-cache").as_deref(), Some("BYPASS"));
+    }
+    assert_eq!(upstream.count(), 4);
+}
+
+#[test]
+fn any_status_is_relayed_with_the_upstream_reason() {
+    let upstream = Scripted::spawn(|head, _| {
+        let path = head.split(' ').nth(1).unwrap_or_default();
+        let status = match path {
+            "/teapot" => "418 I'm a teapot",
+            "/legal" => "451 Unavailable For Legal Reasons",
+            "/bare" => "421 ",
+            "/choices" => "300 Pick One",
+            _ => "203 Non-Authoritative Information",
+        };
+        response(status, "Last-Modified: Thu, 01 Jan 2015 00:00:00 GMT\r\n", path)
+    });
+    let proxy = start(upstream.address, "");
+
+    let teapot = request(proxy, "GET", "/teapot", "");
+    assert!(teapot.starts_with("HTTP/1.1 418 I'm a teapot\r\n"), "{}", teapot);
+    assert_eq!(body(&teapot), "/teapot");
+    let legal = request(proxy, "GET", "/legal", "");
+    assert!(legal.starts_with("HTTP/1.1 451 Unavailable For Legal Reasons\r\n"), "{}", legal);
+    let bare = request(proxy, "GET", "/bare", "");
+    assert!(bare.starts_with("HTTP/1.1 421 \r\n"), "{}", bare);
+
+    // 203 and 300 are stored without explicit freshness; 418 is not.
+    for path in ["/copy", "/choices"] {
+        request(proxy, "GET", path, "");
+        let hit = request(proxy, "GET", path, "");
+        assert_eq!(header(&hit, "x-cache").as_deref(), Some("HIT"), "{}", hit);
+        assert_eq!(body(&hit), path);
+    }
+    let teapot = request(proxy, "GET", "/teapot", "");
+    assert_eq!(header(&teapot, "x-cache").as_deref(), Some("BYPASS"));
+    assert_eq!(upstream.count(), 6);
+}
+
+#[test]
+fn variants_are_stored_apart() {
+    let upstream = Scripted::spawn(|head, _| {
+        let language = header(head, "accept-language").unwrap_or_default();
+        response("200 OK", "Cache-Control: max-age=60\r\nVary: Accept-Language\r\n", &language)
+    });
+    let proxy = start(upstream.address, "");
+
+    assert_eq!(body(&request(proxy, "GET", "/", "Accept-Language: en\r\n")), "en");
+    assert_eq!(body(&request(proxy, "GET", "/", "Accept-Language: fr\r\n")), "fr");
+    let english = request(proxy, "GET", "/", "Accept-Language: en\r\n");
+    assert_eq!(header(&english, "x-cache").as_deref(), Some("HIT"));
+    assert_eq!(body(&english), "en");
+    assert_eq!(upstream.count(), 2);
+}
+
+#[test]
+fn stale_responses_are_revalidated() {
+    let upstream = Scripted::spawn(|head, _| match header(head, "if-none-match") {
+        Some(tag) if tag == "\"v1\"" => response("304 Not Modified", "Cache-Control: max-age=60\r\nETag: \"v1\"\r\n", ""),
+        _ => response("200 OK", "Cache-Control: max-age=0\r\nETag: \"v1\"\r\n", "version one"),
+    });
+    let proxy = start(upstream.address, "");
+
+    request(proxy, "GET", "/doc", "");
+    let revalidated = request(proxy, "GET", "/doc", "");
+    assert_eq!(header(&upstream.last_request(), "if-none-match").as_deref(), Some("\"v1\""));
+    assert_eq!(header(&revalidated, "x-cache").as_deref(), Some("REVALIDATED"));
+    assert_eq!(body(&revalidated), "version one");
+
+    // The 304 made it fresh for a minute.
+    let hit = request(proxy, "GET", "/doc", "");
+    assert_eq!(header(&hit, "x-cache").as_deref(), Some("HIT"));
+    assert_eq!(upstream.count(), 2);
+
+    // The client's own validators are answered from the cache.
+    let unchanged = request(proxy, "GET", "/doc", "If-None-Match: \"v1\"\r\n");
+    assert!(unchanged.starts_with("HTTP/1.1 304"), "{}", unchanged);
+    assert_eq!(upstream.count(), 2);
+}
+
+#[test]
+fn stale_responses_are_served_when_the_upstream_fails() {
+    let upstream = Scripted::spawn(|_, seen| match seen {
+        0 => response("200 OK", "Cache-Control: max-age=0, stale-if-error=60\r\n", "last good"),
+        _ => response("503 Service Unavailable", "", "down"),
+    });
+    let proxy = start(upstream.address, "");
+
+    request(proxy, "GET", "/status", "");
+    let stale = request(proxy, "GET", "/status", "");
+    assert!(stale.starts_with("HTTP/1.1 200"), "{}", stale);
+    assert_eq!(header(&stale, "x-cache").as_deref(), Some("STALE"));
+    assert_eq!(body(&stale), "last good");
+    assert_eq!(upstream.count(), 2);
+}
+
+#[test]
+fn unsafe_requests_invalidate_the_stored_response() {
+    let upstream = Scripted::spawn(|_, _| response("200 OK", "Cache-Control: max-age=60\r\n", "item"));
+    let proxy = start(upstream.address, "");
+
+    request(proxy, "GET", "/item", "");
+    request(proxy, "POST", "/item", "");
+    let refetched = request(proxy, "GET", "/item", "");
+    assert_eq!(header(&refetched, "x-cache").as_deref(), Some("MISS"));
+    assert_eq!(upstream.count(), 3);
+}
+
+#[test]
+fn only_if_cached_misses_get_a_504() {
+    let upstream = Scripted::spawn(|_, _| response("200 OK", "Cache-Control: max-age=60\r\n", "item"));
+    let proxy = start(upstream.address, "");
+
+    let reply = request(proxy, "GET", "/absent", "Cache-Control: only-if-cached\r\n");
+    assert!(reply.starts_with("HTTP/1.1 504"), "{}", reply);
+    assert_eq!(upstream.count(), 0);
+}
+
+#[test]
+fn entries_evicted_from_memory_move_to_disk() {
+    let upstream = Scripted::spawn(|head, _| {
+        let path = head.split(' ').nth(1).unwrap_or_default().to_string();
+        response("200 OK", "Cache-Control: max-age=60\r\n", &path.repeat(100))
+    });
+    let dir = temp_dir("cache");
+    let proxy = start(
+        upstream.address,
+        &format!("max_size = 400\ndisk_path = \"{}\"", dir.display()),
+    );
+
+    request(proxy, "GET", "/a", "");
+    request(proxy, "GET", "/b", "");
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1, "/a went to disk");
+
+    let from_disk = request(proxy, "GET", "/a", "");
+    assert_eq!(header(&from_disk, "x-cache").as_deref(), Some("HIT"));
+    assert_eq!(body(&from_disk), "/a".repeat(100));
+    assert_eq!(upstream.count(), 2);
+}
+
+#[test]
+fn purge_requests_drop_stored_responses() {
+    let upstream = Scripted::spawn(|head, _| {
+        let tags = if head.starts_with("GET /news/") { "Surrogate-Key: news front\r\n" } else { "" };
+        response("200 OK", &format!("Cache-Control: max-age=60\r\n{}", tags), "page")
+    });
+    let proxy = start(upstream.address, "");
+    for path in ["/a", "/static/1", "/static/2", "/news/1", "/news/2"] {
+        let reply = request(proxy, "GET", path, "");
+        assert!(header(&reply, "surrogate-key").is_none(), "{}", reply);
+    }
+
+    let purged = request(proxy, "PURGE", "/a", "");
+    assert!(purged.starts_with("HTTP/1.1 200"), "{}", purged);
+    assert_eq!(body(&purged), r#"{"purged":1}"#);
+    assert_eq!(header(&request(proxy, "GET", "/a", ""), "x-cache").as_deref(), Some("MISS"));
+
+    assert_eq!(body(&request(proxy, "PURGE", "/static/*", "")), r#"{"purged":2}"#);
+    assert_eq!(body(&request(proxy, "PURGE", "/", "Surrogate-Key: front\r\n")), r#"{"purged":2}"#);
+    assert_eq!(header(&request(proxy, "GET", "/news/1", ""), "x-cache").as_deref(), Some("MISS"));
+}
+
+#[test]
+fn purge_requests_are_limited_to_purge_clients() {
+    let upstream = Scripted::spawn(|_, _| response("200 OK", "Cache-Control: max-age=60\r\n", "page"));
+    let proxy = start(upstream.address, r#"purge_clients = ["192.0.2.1"]"#);
+
+    request(proxy, "GET", "/a", "");
+    let refused = request(proxy, "PURGE", "/a", "");
+    assert!(refused.starts_with("HTTP/1.1 403"), "{}", refused);
+    assert_eq!(header(&request(proxy, "GET", "/a", ""), "x-cache").as_deref(), Some("HIT"));
+}
+
+#[test]
+fn the_admin_api_lists_and_purges_entries() {
+    let upstream = Scripted::spawn(|_, _| {
+        response("200 OK", "Cache-Control: max-age=60\r\nSurrogate-Key: docs\r\n", "page")
+    });
+    let (proxy, admin) = start_with_admin(upstream.address, "");
+    request(proxy, "GET", "/one", "");
+    request(proxy, "GET", "/one", "");
+    request(proxy, "GET", "/two", "");
+
+    let listed = send(admin, "GET", "/cache", "", "");
+    assert!(listed.starts_with("HTTP/1.1 200"), "{}", listed);
+    let listed = body(&listed);
+    assert!(listed.contains(r#""key":"http://cache.test/one","tier":"memory","status":200"#), "{}", listed);
+    assert!(listed.contains(r#""hits":1"#), "{}", listed);
+    assert!(listed.contains(r#""tags":["docs"]"#), "{}", listed);
+
+    let purged = send(admin, "POST", "/cache/purge", "", r#"{"key": "http://cache.test/one"}"#);
+    assert_eq!(body(&purged), r#"{"purged":1}"#);
+    let purged = send(admin, "POST", "/cache/purge", "", r#"{"tags": ["docs"]}"#);
+    assert_eq!(body(&purged), r#"{"purged":1}"#);
+
+    request(proxy, "GET", "/three", "");
+    assert_eq!(body(&send(admin, "DELETE", "/cache", "", "")), r#"{"purged":1}"#);
+    assert_eq!(body(&send(admin, "GET", "/cache", "", "")), "[]");
+}
+
+/// Send `count` requests for `path` at once; their `X-Cache` values, sorted.
+fn concurrently(proxy: SocketAddr, path: &'static str, count: usize) -> Vec<String> {
+    let handles: Vec<_> = (0..count)
+        .map(|_| {
+            thread::spawn(move || {
+                let reply = request(proxy, "GET", path, "");
+                assert!(reply.starts_with("HTTP/1.1 200"), "{}", reply);
+                header(&reply, "x-cache").unwrap_or_default()
+            })
+        })
+        .collect();
+    let mut results: Vec<String> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    results.sort();
+    results
+}
+
+#[test]
+fn concurrent_misses_are_collapsed_into_one_upstream_request() {
+    let upstream = Scripted::spawn(|_, _| {
+        thread::sleep(Duration::from_millis(300));
+        response("200 OK", "Cache-Control: max-age=60\r\n", "slow")
+    });
+    let proxy = start(upstream.address, "");
+
+    let results = concurrently(proxy, "/slow", 5);
+    assert_eq!(results, ["COALESCED", "COALESCED", "COALESCED", "COALESCED", "MISS"]);
+    assert_eq!(upstream.count(), 1);
+}
+
+#[test]
+fn collapsed_requests_go_alone_when_the_leader_stores_nothing() {
+    let upstream = Scripted::spawn(|_, _| {
+        thread::sleep(Duration::from_millis(200));
+        response("200 OK", "Cache-Control: no-store\r\n", "personal")
+    });
+    let proxy = start(upstream.address, "");
+
+    let results = concurrently(proxy, "/personal", 4);
+    assert_eq!(results, ["BYPASS", "BYPASS", "BYPASS", "BYPASS"]);
+    assert_eq!(upstream.count(), 4);
+}
+
+#[test]
+fn collapsed_requests_stop_waiting_after_the_timeout() {
+    let upstream = Scripted::spawn(|_, seen| {
+        if seen == 0 {
+            thread::sleep(Duration::from_millis(1500));
+        }
+        response("200 OK", "Cache-Control: max-age=60\r\n", "eventually")
+    });
+    let proxy = start(upstream.address, "coalesce_timeout_ms = 100");
+
+    let leader = thread::spawn(move || request(proxy, "GET", "/late", ""));
+    thread::sleep(Duration::from_millis(100));
+    let started = std::time::Instant::now();
+    let follower = request(proxy, "GET", "/late", "");
+    assert!(started.elapsed() < Duration::from_millis(1000), "{:?}", started.elapsed());
+    assert_eq!(header(&follower, "x-cache").as_deref(), Some("MISS"));
+    assert_eq!(header(&leader.join().unwrap(), "x-cache").as_deref(), Some("MISS"));
+    assert_eq!(upstream.count(), 2);
+}
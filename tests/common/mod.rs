@@ -0,0 +1,471 @@
+// Shared helpers for the integration tests: recording and echoing
+// upstreams, a proxy in this process or in one of its own, plain HTTP/1.1
+// client helpers, and TLS clients trusting locally generated certificates.
+#![allow(dead_code)]
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use orion::config::Config;
+use orion::proxy::Server;
+use rustls::pki_types::{CertificateDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+/// A scratch directory unique to this test process and call.
+pub fn temp_dir(name: &str) -> PathBuf {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!(
+        "orion-test-{}-{}-{}",
+        name,
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
+/// An upstream that records each request head. `/redirect` answers with a
+/// redirect to `http://{Host}/next`; everything else gets `200 ok`.
+pub struct Upstream {
+    pub address: SocketAddr,
+    pub requests: Arc<Mutex<Vec<String>>>,
+}
+
+impl Upstream {
+    pub fn spawn() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let recorded = Arc::clone(&requests);
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let mut head = Vec::new();
+                let mut byte = [0u8; 1];
+                while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                    head.push(byte[0]);
+                }
+                let head = String::from_utf8_lossy(&head).to_string();
+                let response = if head.starts_with("GET /redirect ") {
+                    let host = header(&head, "host").unwrap_or_default();
+                    format!(
+                        "HTTP/1.1 302 Found\r\nLocation: http://{}/next\r\nContent-Length: 0\r\n\r\n",
+                        host
+                    )
+                } else {
+                    "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok".to_string()
+                };
+                recorded.lock().unwrap().push(head);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+
+        Self { address, requests }
+    }
+
+    pub fn last_request(&self) -> String {
+        self.requests.lock().unwrap().last().cloned().unwrap_or_default()
+    }
+}
+
+/// Case-insensitive header lookup in a raw message head.
+pub fn header(head: &str, name: &str) -> Option<String> {
+    head.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.trim()
+            .eq_ignore_ascii_case(name)
+            .then(|| value.trim().to_string())
+    })
+}
+
+/// Start a proxy from a TOML config and wait until `ready` accepts.
+pub fn start_proxy(config: &str, ready: SocketAddr) {
+    let config = Config::parse(config).expect("valid config");
+    let server = Server::new(config).expect("server starts");
+    thread::spawn(move || server.run());
+    wait_for_listener(ready);
+}
+
+fn wait_for_listener(ready: SocketAddr) {
+    for _ in 0..100 {
+        if TcpStream::connect(ready).is_ok() {
+            return;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    panic!("proxy did not start listening on {}", ready);
+}
+
+/// Start a proxy in front of one upstream, with `server` added to its
+/// `[server]` table, and return its address.
+pub fn proxy_to(upstream: SocketAddr, server: &str) -> SocketAddr {
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+{server}
+
+[upstream]
+address = "{upstream}"
+
+[access_log]
+enabled = false
+"#
+    );
+    start_proxy(&config, listen);
+    listen
+}
+
+/// An upstream that answers every request with its request line and body,
+/// reading bodies framed either way, and records each request head.
+pub fn spawn_echo_upstream() -> (SocketAddr, Arc<Mutex<Vec<String>>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let heads = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&heads);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let recorded = Arc::clone(&recorded);
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let head = read_head(&mut reader);
+                if !head.ends_with("\r\n\r\n") {
+                    return;
+                }
+                let mut body = Vec::new();
+                if let Some(length) = header(&head, "content-length") {
+                    body.resize(length.parse().unwrap(), 0);
+                    reader.read_exact(&mut body).unwrap();
+                } else if header(&head, "transfer-encoding").is_some() {
+                    loop {
+                        let mut size = String::new();
+                        reader.read_line(&mut size).unwrap();
+                        let size = usize::from_str_radix(size.trim(), 16).unwrap();
+                        let mut chunk = vec![0u8; size + 2];
+                        reader.read_exact(&mut chunk).unwrap();
+                        if size == 0 {
+                            break;
+                        }
+                        body.extend_from_slice(&chunk[..size]);
+                    }
+                }
+                let request_line = head.lines().next().unwrap_or_default().to_string();
+                recorded.lock().unwrap().push(head);
+                let mut content = request_line.into_bytes();
+                content.push(b'\n');
+                content.extend(body);
+                let mut stream = stream;
+                let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", content.len());
+                let _ = stream.write_all(response.as_bytes());
+                let _ = stream.write_all(&content);
+            });
+        }
+    });
+
+    (address, heads)
+}
+
+/// Open a connection with a read timeout, so a missing response fails the
+/// test instead of hanging it.
+pub fn connect(address: SocketAddr) -> TcpStream {
+    let stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream
+}
+
+/// `connect`, with a buffered reader on the same connection.
+pub fn connect_buffered(address: SocketAddr) -> (TcpStream, BufReader<TcpStream>) {
+    let stream = connect(address);
+    let reader = BufReader::new(stream.try_clone().unwrap());
+    (stream, reader)
+}
+
+/// Read one message head, returning it as text. Short if the connection
+/// ends first.
+pub fn read_head(reader: &mut impl BufRead) -> String {
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        if reader.read_line(&mut head).unwrap_or(0) == 0 {
+            break;
+        }
+    }
+    head
+}
+
+/// Remove chunked transfer coding from a complete body.
+pub fn dechunk(mut input: &[u8]) -> Vec<u8> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = input.windows(2).position(|w| w == b"\r\n").unwrap();
+        let size = usize::from_str_radix(std::str::from_utf8(&input[..line_end]).unwrap().trim(), 16).unwrap();
+        input = &input[line_end + 2..];
+        if size == 0 {
+            return body;
+        }
+        body.extend_from_slice(&input[..size]);
+        input = &input[size + 2..];
+    }
+}
+
+/// The `orion` binary running in a process of its own, for tests that send
+/// it signals. Killed when dropped.
+pub struct ProxyProcess {
+    pub child: Child,
+}
+
+impl ProxyProcess {
+    /// Run the binary with `config` and wait until `ready` accepts.
+    pub fn spawn(config: &str, ready: SocketAddr) -> Self {
+        let path = temp_dir("process").join("orion.toml");
+        std::fs::write(&path, config).unwrap();
+        let child = Command::new(env!("CARGO_BIN_EXE_orion"))
+            .arg(&path)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .unwrap();
+        let process = Self { child };
+        wait_for_listener(ready);
+        process
+    }
+
+    pub fn pid(&self) -> libc::pid_t {
+        self.child.id() as libc::pid_t
+    }
+
+    pub fn signal(&self, signal: libc::c_int) {
+        // SAFETY: kill has no memory-safety preconditions.
+        assert_eq!(unsafe { libc::kill(self.pid(), signal) }, 0);
+    }
+
+    /// The exit status, if the process exits within `timeout`.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> Option<ExitStatus> {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if let Some(status) = self.child.try_wait().unwrap() {
+                return Some(status);
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        None
+    }
+}
+
+impl Drop for ProxyProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+pub fn client_config(roots: &[&CertificateDer<'static>]) -> ClientConfig {
+    client_config_with_versions(roots, rustls::DEFAULT_VERSIONS)
+}
+
+pub fn client_config_with_versions(
+    roots: &[&CertificateDer<'static>],
+    versions: &[&'static rustls::SupportedProtocolVersion],
+) -> ClientConfig {
+    let mut store = RootCertStore::empty();
+    for root in roots {
+        store.add((*root).clone()).unwrap();
+    }
+    ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_protocol_versions(versions)
+        .unwrap()
+        .with_root_certificates(store)
+        .with_no_client_auth()
+}
+
+pub type TlsStream = StreamOwned<ClientConnection, TcpStream>;
+
+/// Open a TLS connection, completing the handshake.
+pub fn connect_tls(address: SocketAddr, config: ClientConfig, server_name: &str) -> std::io::Result<TlsStream> {
+    let name = ServerName::try_from(server_name.to_string()).unwrap();
+    let conn = ClientConnection::new(Arc::new(config), name).unwrap();
+    let socket = TcpStream::connect(address)?;
+    socket.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut stream = StreamOwned::new(conn, socket);
+    while stream.conn.is_handshaking() {
+        stream.conn.complete_io(&mut stream.sock)?;
+    }
+    Ok(stream)
+}
+
+/// Send a `Connection: close` GET and return the whole response.
+pub fn get(stream: &mut impl ReadWrite, host: &str, path: &str) -> String {
+    let request = format!("GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host);
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    String::from_utf8_lossy(&response).to_string()
+}
+
+pub trait ReadWrite: Read + Write {}
+impl<T: Read + Write> ReadWrite for T {}
+
+/// An HTTPS upstream answering every request with `200` and a body of
+/// `client-cert` or `no-client-cert`, depending on what the proxy presented.
+pub fn spawn_tls_upstream(config: rustls::ServerConfig) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let config = Arc::new(config);
+
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let Ok(conn) = rustls::ServerConnection::new(Arc::clone(&config)) else { continue };
+            let mut tls = StreamOwned::new(conn, stream);
+            let mut head = Vec::new();
+            let mut byte = [0u8; 1];
+            while !head.ends_with(b"\r\n\r\n") && tls.read(&mut byte).unwrap_or(0) == 1 {
+                head.push(byte[0]);
+            }
+            if head.is_empty() {
+                continue;
+            }
+            let body = match tls.conn.peer_certificates() {
+                Some(_) => "client-cert",
+                None => "no-client-cert",
+            };
+            let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}", body.len(), body);
+            let _ = tls.write_all(response.as_bytes());
+            tls.conn.send_close_notify();
+            let _ = tls.flush();
+        }
+    });
+
+    address
+}
+
+/// Send a `Connection: close` GET over plain TCP.
+pub fn get_plain(address: SocketAddr, host: &str, path: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    get(&mut stream, host, path)
+}
+
+/// A locally generated certificate authority.
+pub struct TestCa {
+    pub cert: rcgen::Certificate,
+    key: rcgen::KeyPair,
+}
+
+/// A certificate issued by a `TestCa`, with its key.
+pub struct Issued {
+    pub cert: rcgen::Certificate,
+    pub key: rcgen::KeyPair,
+}
+
+impl Issued {
+    /// Write `{name}.pem` and `{name}.key` into `dir`, returning their paths.
+    pub fn write(&self, dir: &std::path::Path, name: &str) -> (String, String) {
+        let cert = dir.join(format!("{}.pem", name));
+        let key = dir.join(format!("{}.key", name));
+        std::fs::write(&cert, self.cert.pem()).unwrap();
+        std::fs::write(&key, self.key.serialize_pem()).unwrap();
+        (cert.display().to_string(), key.display().to_string())
+    }
+
+    pub fn chain(&self) -> Vec<CertificateDer<'static>> {
+        vec![self.cert.der().clone()]
+    }
+
+    pub fn private_key(&self) -> rustls::pki_types::PrivateKeyDer<'static> {
+        rustls::pki_types::PrivatePkcs8KeyDer::from(self.key.serialize_der()).into()
+    }
+}
+
+impl TestCa {
+    pub fn new(name: &str) -> Self {
+        let mut params = rcgen::CertificateParams::new(Vec::<String>::new()).unwrap();
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, name);
+        params.key_usages = vec![
+            rcgen::KeyUsagePurpose::KeyCertSign,
+            rcgen::KeyUsagePurpose::CrlSign,
+            rcgen::KeyUsagePurpose::DigitalSignature,
+        ];
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.self_signed(&key).unwrap();
+        Self { cert, key }
+    }
+
+    /// Issue a certificate with common name `common_name` and DNS SANs `names`.
+    pub fn issue(&self, common_name: &str, names: &[&str]) -> Issued {
+        let names: Vec<String> = names.iter().map(|n| n.to_string()).collect();
+        let mut params = rcgen::CertificateParams::new(names).unwrap();
+        params
+            .distinguished_name
+            .push(rcgen::DnType::CommonName, common_name);
+        let key = rcgen::KeyPair::generate().unwrap();
+        let cert = params.signed_by(&key, &self.cert, &self.key).unwrap();
+        Issued { cert, key }
+    }
+
+    pub fn write(&self, dir: &std::path::Path) -> String {
+        let path = dir.join("ca.pem");
+        std::fs::write(&path, self.cert.pem()).unwrap();
+        path.display().to_string()
+    }
+
+    pub fn roots(&self) -> Arc<RootCertStore> {
+        let mut roots = RootCertStore::empty();
+        roots.add(self.cert.der().clone()).unwrap();
+        Arc::new(roots)
+    }
+}
+
+/// Fetch `/metrics` from an admin listener. The admin listener keeps
+/// connections open, so the body is read by its Content-Length.
+pub fn fetch_metrics(admin: SocketAddr) -> String {
+    let mut stream = TcpStream::connect(admin).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = Vec::new();
+    let mut chunk = [0u8; 4096];
+    loop {
+        let n = stream.read(&mut chunk).unwrap();
+        response.extend_from_slice(&chunk[..n]);
+        let text = String::from_utf8_lossy(&response);
+        if let Some((head, body)) = text.split_once("\r\n\r\n") {
+            let length: usize = header(head, "content-length").map_or(0, |l| l.parse().unwrap());
+            if body.len() >= length || n == 0 {
+                return body.to_string();
+            }
+        }
+        if n == 0 {
+            return String::new();
+        }
+    }
+}
+
+/// The value of an unlabelled sample in a metrics exposition.
+pub fn metric_value(metrics: &str, name: &str) -> Option<f64> {
+    metrics.lines().find_map(|line| {
+        let value = line.strip_prefix(name)?.strip_prefix(' ')?;
+        value.trim().parse().ok()
+    })
+}
@@ -0,0 +1,194 @@
+// Response compression: negotiated from Accept-Encoding, limited to the
+// configured types and sizes, and applied to buffered and streamed bodies.
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use common::{dechunk, free_port, header, start_proxy};
+
+fn page() -> String {
+    "<p>All work and no play makes Jack a dull boy.</p>\n".repeat(80)
+}
+
+/// An upstream with a few fixed resources; none of them compressed by it
+/// except `/encoded`.
+fn spawn_upstream() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut head = Vec::new();
+            let mut byte = [0u8; 1];
+            while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                head.push(byte[0]);
+            }
+            let head = String::from_utf8_lossy(&head).to_string();
+            let path = head.split(' ').nth(1).unwrap_or_default().to_string();
+            let with_length = |content_type: &str, extra: &str, body: &[u8]| {
+                let mut response = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    content_type,
+                    extra,
+                    body.len()
+                )
+                .into_bytes();
+                response.extend_from_slice(body);
+                response
+            };
+            let response = match path.as_str() {
+                "/page" => with_length("text/html; charset=utf-8", "ETag: \"page-1\"\r\nCache-Control: max-age=60\r\n", page().as_bytes()),
+                "/small" => with_length("text/plain", "", b"tiny"),
+                "/image" => with_length("image/png", "", page().as_bytes()),
+                "/encoded" => with_length("text/plain", "Content-Encoding: gzip\r\n", b"\x1f\x8b already"),
+                "/raw" => with_length("text/plain", "Cache-Control: no-transform\r\n", page().as_bytes()),
+                _ => {
+                    let half = page();
+                    format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nTransfer-Encoding: chunked\r\nConnection: close\r\n\r\n{:x}\r\n{}\r\n{:x}\r\n{}\r\n0\r\n\r\n",
+                        half.len(),
+                        half,
+                        half.len(),
+                        half
+                    )
+                    .into_bytes()
+                }
+            };
+            let _ = stream.write_all(&response);
+        }
+    });
+    address
+}
+
+fn start(compression: &str) -> SocketAddr {
+    let upstream = spawn_upstream();
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+
+[upstream]
+address = "{upstream}"
+
+[compression]
+{compression}
+
+[access_log]
+enabled = false
+"#
+    );
+    start_proxy(&config, listen);
+    listen
+}
+
+/// The response head and its body, with any chunked coding removed.
+fn fetch(proxy: SocketAddr, path: &str, accept_encoding: Option<&str>) -> (String, Vec<u8>) {
+    let mut stream = TcpStream::connect(proxy).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    let accept = accept_encoding.map_or(String::new(), |a| format!("Accept-Encoding: {}\r\n", a));
+    write!(stream, "GET {} HTTP/1.1\r\nHost: test\r\n{}Connection: close\r\n\r\n", path, accept).unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let head = String::from_utf8_lossy(&response[..split]).to_string();
+    let mut body = response[split..].to_vec();
+    if header(&head, "transfer-encoding").is_some() {
+        body = dechunk(&body);
+    }
+    (head, body)
+}
+
+fn decode(coding: &str, body: &[u8]) -> String {
+    let mut decoded = String::new();
+    match coding {
+        "gzip" => flate2::read::GzDecoder::new(body).read_to_string(&mut decoded),
+        "br" => brotli::Decompressor::new(body, 4096).read_to_string(&mut decoded),
+        "zstd" => zstd::stream::read::Decoder::new(body).unwrap().read_to_string(&mut decoded),
+        other => panic!("unexpected coding {}", other),
+    }
+    .unwrap();
+    decoded
+}
+
+#[test]
+fn responses_are_compressed_with_the_negotiated_coding() {
+    let proxy = start("");
+
+    for (accept, expected) in [
+        ("gzip", "gzip"),
+        ("gzip, deflate, br", "br"),
+        ("gzip, br, zstd", "zstd"),
+        ("zstd;q=0.5, br;q=0.8, gzip", "gzip"),
+        ("*", "zstd"),
+    ] {
+        let (head, body) = fetch(proxy, "/page", Some(accept));
+        assert_eq!(header(&head, "content-encoding").as_deref(), Some(expected), "{}", accept);
+        assert_eq!(header(&head, "transfer-encoding").as_deref(), Some("chunked"));
+        assert!(body.len() < page().len() / 4, "{} bytes", body.len());
+        assert_eq!(decode(expected, &body), page());
+        assert_eq!(header(&head, "vary").as_deref(), Some("Accept-Encoding"));
+        assert_eq!(header(&head, "etag").as_deref(), Some("W/\"page-1\""));
+    }
+}
+
+#[test]
+fn buffered_responses_get_the_compressed_length() {
+    // Cache hits are sent from memory.
+    let proxy = start("[cache]\nall_routes = true");
+
+    fetch(proxy, "/page", None);
+    let (head, body) = fetch(proxy, "/page", Some("br"));
+    assert_eq!(header(&head, "x-cache").as_deref(), Some("HIT"));
+    assert_eq!(header(&head, "content-encoding").as_deref(), Some("br"));
+    assert_eq!(header(&head, "content-length"), Some(body.len().to_string()));
+    assert_eq!(decode("br", &body), page());
+}
+
+#[test]
+fn configured_algorithms_and_types_limit_compression() {
+    let proxy = start("algorithms = [\"gzip\"]\ncontent_types = [\"image/*\"]");
+
+    let (head, _) = fetch(proxy, "/page", Some("br, gzip"));
+    assert_eq!(header(&head, "content-encoding"), None);
+    let (head, body) = fetch(proxy, "/image", Some("br, gzip"));
+    assert_eq!(header(&head, "content-encoding").as_deref(), Some("gzip"));
+    assert_eq!(decode("gzip", &body), page());
+}
+
+#[test]
+fn ineligible_responses_pass_through() {
+    let proxy = start("");
+
+    let (head, body) = fetch(proxy, "/page", None);
+    assert_eq!(header(&head, "content-encoding"), None);
+    assert_eq!(header(&head, "vary").as_deref(), Some("Accept-Encoding"));
+    assert_eq!(body, page().as_bytes());
+
+    for path in ["/small", "/image", "/raw"] {
+        let (head, _) = fetch(proxy, path, Some("gzip"));
+        assert_eq!(header(&head, "content-encoding"), None, "{}", path);
+    }
+
+    let (head, body) = fetch(proxy, "/encoded", Some("br"));
+    assert_eq!(header(&head, "content-encoding").as_deref(), Some("gzip"));
+    assert_eq!(body, b"\x1f\x8b already");
+
+    let (head, _) = fetch(proxy, "/page", Some("gzip;q=0, identity"));
+    assert_eq!(header(&head, "content-encoding"), None);
+}
+
+#[test]
+fn streamed_responses_are_compressed_as_they_arrive() {
+    let proxy = start("");
+
+    let (head, body) = fetch(proxy, "/stream", Some("gzip"));
+    assert_eq!(header(&head, "content-encoding").as_deref(), Some("gzip"));
+    assert_eq!(header(&head, "transfer-encoding").as_deref(), Some("chunked"));
+    assert_eq!(header(&head, "content-length"), None);
+    assert_eq!(decode("gzip", &body), page().repeat(2));
+}
@@ -0,0 +1,239 @@
+// Decoding on routes that ask for it: encoded request bodies before they
+// are forwarded, within the body size limit, and encoded responses for
+// clients that do not accept their coding.
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use common::{dechunk, free_port, header, start_proxy};
+
+fn page() -> String {
+    "<p>All work and no play makes Jack a dull boy.</p>\n".repeat(80)
+}
+
+fn encode(coding: &str, data: &[u8]) -> Vec<u8> {
+    match coding {
+        "gzip" => {
+            let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data).unwrap();
+            encoder.finish().unwrap()
+        }
+        "br" => {
+            let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+            encoder.write_all(data).unwrap();
+            encoder.into_inner()
+        }
+        "zstd" => zstd::encode_all(data, 3).unwrap(),
+        other => panic!("unexpected coding {}", other),
+    }
+}
+
+/// Request heads and bodies as the upstream received them.
+type Received = Arc<Mutex<Vec<(String, Vec<u8>)>>>;
+
+/// An upstream that records each request with its body. `/encoded` answers
+/// with a gzip-encoded page; everything else with `200 ok`.
+fn spawn_upstream() -> (SocketAddr, Received) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let received: Received = Arc::default();
+    let recorded = Arc::clone(&received);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let recorded = Arc::clone(&recorded);
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut head = String::new();
+                while !head.ends_with("\r\n\r\n") {
+                    if reader.read_line(&mut head).unwrap_or(0) == 0 {
+                        return;
+                    }
+                }
+                let Some(body) = read_body(&mut reader, &head) else { return };
+                let response = if head.starts_with("GET /encoded ") || head.starts_with("GET /decoded/encoded ") {
+                    let body = encode("gzip", page().as_bytes());
+                    let mut response = format!(
+                        "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Encoding: gzip\r\nETag: \"page-1\"\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                        body.len()
+                    )
+                    .into_bytes();
+                    response.extend_from_slice(&body);
+                    response
+                } else {
+                    b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok".to_vec()
+                };
+                recorded.lock().unwrap().push((head, body));
+                let _ = (&stream).write_all(&response);
+            });
+        }
+    });
+    (address, received)
+}
+
+/// The request body, or `None` if the connection ended before it did.
+fn read_body(reader: &mut impl BufRead, head: &str) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    if header(head, "transfer-encoding").is_some() {
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).ok()?;
+            let size = usize::from_str_radix(line.trim(), 16).ok()?;
+            let mut chunk = vec![0u8; size + 2];
+            reader.read_exact(&mut chunk).ok()?;
+            if size == 0 {
+                return Some(body);
+            }
+            body.extend_from_slice(&chunk[..size]);
+        }
+    }
+    let length = header(head, "content-length").map_or(0, |l| l.parse().unwrap());
+    body.resize(length, 0);
+    reader.read_exact(&mut body).ok()?;
+    Some(body)
+}
+
+fn start() -> (SocketAddr, Received) {
+    let (upstream, received) = spawn_upstream();
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+
+[upstreams.app]
+servers = ["{upstream}"]
+
+[[routes]]
+prefix = "/decoded"
+upstream = "app"
+decompress_requests = true
+decompress_responses = true
+
+[[routes]]
+prefix = "/"
+upstream = "app"
+
+[access_log]
+enabled = false
+"#
+    );
+    start_proxy(&config, listen);
+    (listen, received)
+}
+
+/// Send a request and return the response head and body.
+fn send(proxy: SocketAddr, head: &str, body: &[u8]) -> (String, Vec<u8>) {
+    let mut stream = TcpStream::connect(proxy).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream.write_all(head.as_bytes()).unwrap();
+    // The proxy may answer before taking the whole body.
+    let _ = stream.write_all(body);
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let head = String::from_utf8_lossy(&response[..split]).to_string();
+    let mut body = response[split..].to_vec();
+    if header(&head, "transfer-encoding").is_some() {
+        body = dechunk(&body);
+    }
+    (head, body)
+}
+
+fn post(proxy: SocketAddr, path: &str, content_encoding: &str, body: &[u8]) -> (String, Vec<u8>) {
+    let head = format!(
+        "POST {} HTTP/1.1\r\nHost: test\r\nContent-Encoding: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        content_encoding,
+        body.len()
+    );
+    send(proxy, &head, body)
+}
+
+fn get(proxy: SocketAddr, path: &str, accept_encoding: Option<&str>) -> (String, Vec<u8>) {
+    let accept = accept_encoding.map_or(String::new(), |a| format!("Accept-Encoding: {}\r\n", a));
+    let head = format!("GET {} HTTP/1.1\r\nHost: test\r\n{}Connection: close\r\n\r\n", path, accept);
+    send(proxy, &head, b"")
+}
+
+#[test]
+fn encoded_request_bodies_are_forwarded_decoded() {
+    let (proxy, received) = start();
+
+    for coding in ["gzip", "br", "zstd", "gzip, br"] {
+        let mut body = page().into_bytes();
+        for layer in coding.split(", ") {
+            body = encode(layer, &body);
+        }
+        let (head, _) = post(proxy, "/decoded/upload", coding, &body);
+        assert!(head.starts_with("HTTP/1.1 200"), "{}: {}", coding, head);
+
+        let (upstream_head, upstream_body) = received.lock().unwrap().last().cloned().unwrap();
+        assert_eq!(header(&upstream_head, "content-encoding"), None, "{}", coding);
+        assert_eq!(header(&upstream_head, "content-length"), None);
+        assert_eq!(header(&upstream_head, "transfer-encoding").as_deref(), Some("chunked"));
+        assert_eq!(upstream_body, page().as_bytes(), "{}", coding);
+    }
+}
+
+#[test]
+fn other_routes_forward_request_bodies_as_they_are() {
+    let (proxy, received) = start();
+
+    let body = encode("gzip", page().as_bytes());
+    let (head, _) = post(proxy, "/upload", "gzip", &body);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    let (upstream_head, upstream_body) = received.lock().unwrap().last().cloned().unwrap();
+    assert_eq!(header(&upstream_head, "content-encoding").as_deref(), Some("gzip"));
+    assert_eq!(upstream_body, body);
+}
+
+#[test]
+fn request_bodies_that_decode_past_the_limit_are_refused() {
+    let (proxy, _) = start();
+
+    // About 10 KB that would expand to 11 MB.
+    let bomb = encode("gzip", &vec![0u8; 11 * 1024 * 1024]);
+    assert!(bomb.len() < 64 * 1024);
+    let (head, _) = post(proxy, "/decoded/upload", "gzip", &bomb);
+    assert!(head.starts_with("HTTP/1.1 413"), "{}", head);
+}
+
+#[test]
+fn request_bodies_that_cannot_be_decoded_are_refused() {
+    let (proxy, received) = start();
+
+    let (head, _) = post(proxy, "/decoded/upload", "deflate", b"anything");
+    assert!(head.starts_with("HTTP/1.1 415"), "{}", head);
+    assert!(received.lock().unwrap().is_empty());
+
+    let (head, _) = post(proxy, "/decoded/upload", "gzip", b"not gzip at all");
+    assert!(head.starts_with("HTTP/1.1 400"), "{}", head);
+}
+
+#[test]
+fn encoded_responses_are_decoded_for_clients_that_do_not_accept_them() {
+    let (proxy, _) = start();
+
+    for accept in [None, Some("br"), Some("gzip;q=0")] {
+        let (head, body) = get(proxy, "/decoded/encoded", accept);
+        assert_eq!(header(&head, "content-encoding"), None, "{:?}", accept);
+        assert_eq!(header(&head, "vary").as_deref(), Some("Accept-Encoding"));
+        assert_eq!(header(&head, "etag").as_deref(), Some("W/\"page-1\""));
+        assert_eq!(body, page().as_bytes());
+    }
+
+    let (head, body) = get(proxy, "/decoded/encoded", Some("gzip, br"));
+    assert_eq!(header(&head, "content-encoding").as_deref(), Some("gzip"));
+    assert_eq!(header(&head, "etag").as_deref(), Some("\"page-1\""));
+    assert_eq!(body, encode("gzip", page().as_bytes()));
+
+    // Routes without the option pass encoded responses on regardless.
+    let (head, _) = get(proxy, "/encoded", None);
+    assert_eq!(header(&head, "content-encoding").as_deref(), Some("gzip"));
+}
@@ -0,0 +1,77 @@
+// `Expect: 100-continue`: the proxy answers the expectation itself once the
+// request head checks out, and only when the body is actually wanted.
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+
+use common::{connect_buffered, free_port, header, proxy_to, read_head, spawn_echo_upstream};
+
+#[test]
+fn continue_is_sent_before_the_body_is_read() {
+    let (upstream, heads) = spawn_echo_upstream();
+    let proxy = proxy_to(upstream, "");
+
+    let (mut stream, mut reader) = connect_buffered(proxy);
+    stream
+        .write_all(b"POST /upload HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n")
+        .unwrap();
+    assert_eq!(read_head(&mut reader), "HTTP/1.1 100 Continue\r\n\r\n");
+
+    stream.write_all(b"hello").unwrap();
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    let mut body = vec![0u8; header(&head, "content-length").unwrap().parse().unwrap()];
+    reader.read_exact(&mut body).unwrap();
+    assert_eq!(body, b"POST /upload HTTP/1.1\nhello");
+
+    // The expectation was met here; the upstream is not asked again.
+    let forwarded = heads.lock().unwrap().last().cloned().unwrap();
+    assert_eq!(header(&forwarded, "expect"), None);
+
+    // The connection is still good for another request.
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n").unwrap();
+    assert!(read_head(&mut reader).starts_with("HTTP/1.1 200"));
+}
+
+#[test]
+fn oversized_bodies_are_refused_without_continue() {
+    let (upstream, _) = spawn_echo_upstream();
+    let proxy = proxy_to(upstream, "");
+
+    let (mut stream, mut reader) = connect_buffered(proxy);
+    stream
+        .write_all(b"PUT /big HTTP/1.1\r\nHost: x\r\nContent-Length: 99999999999\r\nExpect: 100-continue\r\n\r\n")
+        .unwrap();
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 413"), "{}", head);
+}
+
+#[test]
+fn unknown_expectations_fail() {
+    let (upstream, _) = spawn_echo_upstream();
+    let proxy = proxy_to(upstream, "");
+
+    let (mut stream, mut reader) = connect_buffered(proxy);
+    stream
+        .write_all(b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 1\r\nExpect: the-unexpected\r\n\r\n")
+        .unwrap();
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 417 Expectation Failed"), "{}", head);
+}
+
+#[test]
+fn requests_refused_before_the_body_close_the_connection() {
+    // Nothing listens upstream, so the body is never wanted.
+    let upstream: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let proxy = proxy_to(upstream, "");
+
+    let (mut stream, mut reader) = connect_buffered(proxy);
+    stream
+        .write_all(b"POST / HTTP/1.1\r\nHost: x\r\nContent-Length: 5\r\nExpect: 100-continue\r\n\r\n")
+        .unwrap();
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 502"), "{}", head);
+    assert_eq!(header(&head, "connection").as_deref(), Some("close"));
+}
@@ -0,0 +1,133 @@
+// Forward-proxy mode: CONNECT tunnels and absolute-form requests, limited
+// to allowlisted destinations.
+
+mod common;
+
+use std::io::{BufRead, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::thread;
+
+use common::{Upstream, connect_buffered, free_port, header, read_head, start_proxy};
+
+/// A TCP server echoing bytes until its client is done sending.
+fn spawn_echo() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            thread::spawn(move || {
+                let mut reader = stream.try_clone().unwrap();
+                let _ = std::io::copy(&mut reader, &mut stream);
+            });
+        }
+    });
+    address
+}
+
+fn start(forward_proxy: &str) -> SocketAddr {
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+
+[upstream]
+address = "127.0.0.1:1"
+
+[access_log]
+enabled = false
+
+{forward_proxy}
+"#
+    );
+    start_proxy(&config, listen);
+    listen
+}
+
+fn send_connect(client: &mut TcpStream, reader: &mut impl BufRead, target: &str) -> String {
+    let request = format!("CONNECT {} HTTP/1.1\r\nHost: {}\r\n\r\n", target, target);
+    client.write_all(request.as_bytes()).unwrap();
+    read_head(reader)
+}
+
+#[test]
+fn connect_opens_a_tunnel_to_an_allowed_destination() {
+    let echo = spawn_echo();
+    let proxy = start(&format!("[forward_proxy]\nallow = [\"127.0.0.1:{}\"]", echo.port()));
+
+    let (mut client, mut reader) = connect_buffered(proxy);
+    let head = send_connect(&mut client, &mut reader, &echo.to_string());
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert_eq!(header(&head, "content-length"), None);
+    assert_eq!(header(&head, "transfer-encoding"), None);
+
+    client.write_all(b"through the tunnel").unwrap();
+    let mut echoed = [0u8; 18];
+    reader.read_exact(&mut echoed).unwrap();
+    assert_eq!(&echoed, b"through the tunnel");
+
+    client.shutdown(Shutdown::Write).unwrap();
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn destinations_outside_the_allowlist_are_forbidden() {
+    let echo = spawn_echo();
+    let proxy = start("[forward_proxy]\nallow = [\"*.example.com:443\"]");
+
+    let (mut client, mut reader) = connect_buffered(proxy);
+    let head = send_connect(&mut client, &mut reader, &echo.to_string());
+    assert!(head.starts_with("HTTP/1.1 403"), "{}", head);
+}
+
+#[test]
+fn absolute_form_requests_go_to_the_named_host() {
+    let upstream = Upstream::spawn();
+    let proxy = start(&format!("[forward_proxy]\nallow = [\"127.0.0.1:{}\"]", upstream.address.port()));
+
+    let (mut client, mut reader) = connect_buffered(proxy);
+    let request = format!(
+        "GET http://{}/packages/index.json?v=2 HTTP/1.1\r\nHost: ignored.example\r\nProxy-Connection: keep-alive\r\n\r\n",
+        upstream.address
+    );
+    client.write_all(request.as_bytes()).unwrap();
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+
+    let forwarded = upstream.last_request();
+    assert!(forwarded.starts_with("GET /packages/index.json?v=2 HTTP/1.1\r\n"), "{}", forwarded);
+    assert_eq!(header(&forwarded, "host"), Some(upstream.address.to_string()));
+    assert_eq!(header(&forwarded, "proxy-connection"), None);
+}
+
+#[test]
+fn unreachable_destination_is_a_bad_gateway() {
+    let closed = free_port();
+    let proxy = start(&format!("[forward_proxy]\nallow = [\"127.0.0.1:{}\"]", closed));
+
+    let (mut client, mut reader) = connect_buffered(proxy);
+    let head = send_connect(&mut client, &mut reader, &format!("127.0.0.1:{}", closed));
+    assert!(head.starts_with("HTTP/1.1 502"), "{}", head);
+}
+
+#[test]
+fn connect_is_refused_without_forward_proxy_mode() {
+    let echo = spawn_echo();
+    let proxy = start("");
+
+    let (mut client, mut reader) = connect_buffered(proxy);
+    let head = send_connect(&mut client, &mut reader, &echo.to_string());
+    assert!(head.starts_with("HTTP/1.1 405"), "{}", head);
+}
+
+#[test]
+fn connect_target_needs_a_port() {
+    let proxy = start("[forward_proxy]\nallow = [\"*:*\"]");
+
+    let (mut client, mut reader) = connect_buffered(proxy);
+    let head = send_connect(&mut client, &mut reader, "example.com");
+    assert!(head.starts_with("HTTP/1.1 400"), "{}", head);
+}
@@ -0,0 +1,137 @@
+// Active health checks: a backend failing its check path is taken out of
+// rotation, and put back once it passes again.
+
+mod common;
+
+use std::io::{BufReader, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{Upstream, free_port, get_plain, read_head, start_proxy};
+
+/// A backend whose `/health` answers `200` (with no reason phrase) while
+/// `healthy` is set and `503` otherwise. Other requests get `200 ok` and are
+/// counted in `served`.
+struct Backend {
+    address: SocketAddr,
+    healthy: Arc<AtomicBool>,
+    served: Arc<AtomicUsize>,
+}
+
+impl Backend {
+    fn spawn() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let healthy = Arc::new(AtomicBool::new(true));
+        let served = Arc::new(AtomicUsize::new(0));
+        let (state, count) = (Arc::clone(&healthy), Arc::clone(&served));
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else { continue };
+                let (state, count) = (Arc::clone(&state), Arc::clone(&count));
+                thread::spawn(move || {
+                    let mut reader = BufReader::new(stream.try_clone().unwrap());
+                    loop {
+                        let head = read_head(&mut reader);
+                        if !head.ends_with("\r\n\r\n") {
+                            return;
+                        }
+                        let response: &[u8] = match head.starts_with("GET /health ") {
+                            true if state.load(Ordering::SeqCst) => b"HTTP/1.1 200\r\nContent-Length: 0\r\n\r\n",
+                            true => b"HTTP/1.1 503 Service Unavailable\r\nContent-Length: 0\r\n\r\n",
+                            false => {
+                                count.fetch_add(1, Ordering::SeqCst);
+                                b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok"
+                            }
+                        };
+                        if stream.write_all(response).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+        Self { address, healthy, served }
+    }
+
+    fn served(&self) -> usize {
+        self.served.load(Ordering::SeqCst)
+    }
+}
+
+fn start(checked: SocketAddr, other: SocketAddr) -> SocketAddr {
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+
+[upstreams.app]
+servers = ["{checked}", "{other}"]
+
+[upstreams.app.health_check]
+path = "/health"
+interval_ms = 50
+timeout_ms = 1000
+healthy_threshold = 2
+unhealthy_threshold = 2
+
+[[routes]]
+prefix = "/"
+upstream = "app"
+
+[access_log]
+enabled = false
+"#
+    );
+    start_proxy(&config, listen);
+    listen
+}
+
+/// Send a few requests; whether `backend` served any of them.
+fn in_rotation(proxy: SocketAddr, backend: &Backend) -> bool {
+    let before = backend.served();
+    for _ in 0..4 {
+        let response = get_plain(proxy, "test", "/");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+    backend.served() > before
+}
+
+/// Wait until `backend` is in rotation or out of it, as `expected` says.
+fn wait_for_rotation(proxy: SocketAddr, backend: &Backend, expected: bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while in_rotation(proxy, backend) != expected {
+        assert!(Instant::now() < deadline, "backend still {} rotation", if expected { "out of" } else { "in" });
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// Requests `upstream` received other than health checks.
+fn proxied(upstream: &Upstream) -> usize {
+    upstream.requests.lock().unwrap().iter().filter(|head| !head.starts_with("GET /health ")).count()
+}
+
+#[test]
+fn backends_leave_and_rejoin_rotation_with_their_checks() {
+    let checked = Backend::spawn();
+    let other = Upstream::spawn();
+    let proxy = start(checked.address, other.address);
+
+    // A status line without a reason phrase still passes, check after check.
+    thread::sleep(Duration::from_millis(400));
+    assert!(in_rotation(proxy, &checked));
+
+    checked.healthy.store(false, Ordering::SeqCst);
+    wait_for_rotation(proxy, &checked, false);
+    // Every request is now served by the other backend.
+    let served = proxied(&other);
+    assert!(!in_rotation(proxy, &checked));
+    assert_eq!(proxied(&other), served + 4);
+
+    checked.healthy.store(true, Ordering::SeqCst);
+    wait_for_rotation(proxy, &checked, true);
+}
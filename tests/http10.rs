@@ -0,0 +1,158 @@
+// HTTP/1.0 clients: no Host header required, connections that close unless
+// asked to stay open, and bodies delimited by the close instead of chunked.
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+
+use common::{Upstream, connect, free_port, header, proxy_to, start_proxy};
+
+/// Send `request` and read until the proxy closes the connection.
+fn exchange(address: SocketAddr, request: &str) -> String {
+    let mut stream = connect(address);
+    stream.write_all(request.as_bytes()).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+/// An upstream that answers every request with a chunked `hello, world`.
+fn spawn_chunked_upstream() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            while reader.read_line(&mut line).unwrap_or(0) > 2 {
+                line.clear();
+            }
+            let _ = stream.write_all(
+                b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n",
+            );
+        }
+    });
+    address
+}
+
+#[test]
+fn requests_without_host_use_the_default_host() {
+    let upstream = Upstream::spawn();
+    let proxy = proxy_to(upstream.address, r#"default_host = "legacy.test""#);
+
+    let response = exchange(proxy, "GET /probe HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{}", response);
+    assert_eq!(header(&response, "connection").as_deref(), Some("close"));
+    assert!(response.ends_with("\r\n\r\nok"), "{}", response);
+
+    let request = upstream.last_request();
+    assert!(request.starts_with("GET /probe HTTP/1.1\r\n"), "{}", request);
+    assert_eq!(header(&request, "host").as_deref(), Some("legacy.test"));
+}
+
+#[test]
+fn the_default_host_selects_host_specific_routes() {
+    let (legacy, other) = (Upstream::spawn(), Upstream::spawn());
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+default_host = "legacy.test:8080"
+
+[upstreams.legacy]
+servers = ["{legacy}"]
+
+[upstreams.other]
+servers = ["{other}"]
+
+[[routes]]
+host = "legacy.test"
+prefix = "/"
+upstream = "legacy"
+
+[[routes]]
+prefix = "/"
+upstream = "other"
+
+[access_log]
+enabled = false
+"#,
+        legacy = legacy.address,
+        other = other.address
+    );
+    start_proxy(&config, listen);
+
+    let response = exchange(listen, "GET /old HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.0 200"), "{}", response);
+    assert_eq!(header(&legacy.last_request(), "host").as_deref(), Some("legacy.test:8080"));
+    assert!(other.requests.lock().unwrap().is_empty());
+
+    exchange(listen, "GET /new HTTP/1.0\r\nHost: new.test\r\n\r\n");
+    assert!(other.last_request().starts_with("GET /new "));
+}
+
+#[test]
+fn the_listener_address_is_the_fallback_host() {
+    let upstream = Upstream::spawn();
+    let proxy = proxy_to(upstream.address, "");
+
+    let response = exchange(proxy, "GET / HTTP/1.0\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.0 200"), "{}", response);
+    assert_eq!(header(&upstream.last_request(), "host"), Some(proxy.to_string()));
+
+    // A Host the client did send is kept.
+    exchange(proxy, "GET / HTTP/1.0\r\nHost: named.test\r\n\r\n");
+    assert_eq!(header(&upstream.last_request(), "host").as_deref(), Some("named.test"));
+}
+
+#[test]
+fn http11_still_requires_host() {
+    let upstream = Upstream::spawn();
+    let proxy = proxy_to(upstream.address, "");
+
+    let response = exchange(proxy, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+}
+
+#[test]
+fn keep_alive_is_honoured_when_asked_for() {
+    let upstream = Upstream::spawn();
+    let proxy = proxy_to(upstream.address, "");
+
+    let mut stream = connect(proxy);
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    for _ in 0..2 {
+        stream
+            .write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+        let mut head = String::new();
+        while !head.ends_with("\r\n\r\n") {
+            assert!(reader.read_line(&mut head).unwrap() > 0, "{}", head);
+        }
+        assert!(head.starts_with("HTTP/1.0 200"), "{}", head);
+        assert_eq!(header(&head, "connection").as_deref(), Some("keep-alive"));
+        let mut body = [0u8; 2];
+        reader.read_exact(&mut body).unwrap();
+        assert_eq!(&body, b"ok");
+    }
+}
+
+#[test]
+fn chunked_responses_are_delimited_by_the_close() {
+    let upstream = spawn_chunked_upstream();
+    let proxy = proxy_to(upstream, "");
+
+    let response = exchange(proxy, "GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n");
+    assert!(response.starts_with("HTTP/1.0 200"), "{}", response);
+    assert_eq!(header(&response, "transfer-encoding"), None);
+    assert_eq!(header(&response, "connection").as_deref(), Some("close"));
+    assert!(response.ends_with("\r\n\r\nhello, world"), "{}", response);
+
+    // HTTP/1.1 clients still get it chunked.
+    let response = exchange(proxy, "GET / HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n");
+    assert_eq!(header(&response, "transfer-encoding").as_deref(), Some("chunked"));
+}
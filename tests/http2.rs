@@ -0,0 +1,510 @@
+// HTTP/2 from clients: prior knowledge and h2c upgrades on the plain
+// listener, ALPN on the TLS listener, forwarded to HTTP/1.1 upstreams; and
+// HTTP/2 to upstreams with `http2 = true`, gRPC trailers included.
+
+mod common;
+
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use common::{
+    ReadWrite, Upstream, client_config, connect, connect_tls, free_port, header, proxy_to, spawn_echo_upstream, start_proxy,
+    temp_dir,
+};
+use orion::http::h2::{Frame, frame, hpack};
+use rcgen::generate_simple_self_signed;
+
+/// A response as an HTTP/2 client saw it.
+#[derive(Debug, Default)]
+struct Response {
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn status(&self) -> &str {
+        self.header(":status").unwrap_or_default()
+    }
+}
+
+/// Just enough of an HTTP/2 client to send requests and collect responses.
+struct Client<S> {
+    io: S,
+    buffer: Vec<u8>,
+    decoder: hpack::Decoder,
+    responses: HashMap<u32, Response>,
+    ended: HashSet<u32>,
+}
+
+impl<S: ReadWrite> Client<S> {
+    /// Send the connection preface and our (default) settings.
+    fn new(mut io: S) -> Self {
+        let mut out = frame::PREFACE.to_vec();
+        frame::encode_settings(&mut out, &[]);
+        io.write_all(&out).unwrap();
+        Self {
+            io,
+            buffer: Vec::new(),
+            decoder: hpack::Decoder::default(),
+            responses: HashMap::new(),
+            ended: HashSet::new(),
+        }
+    }
+
+    fn send(&mut self, stream: u32, method: &str, path: &str, body: Option<&[u8]>) {
+        self.send_with(stream, method, path, &[], body);
+    }
+
+    fn send_with(&mut self, stream: u32, method: &str, path: &str, extra: &[(&str, &str)], body: Option<&[u8]>) {
+        let mut headers = vec![
+            (":method", method),
+            (":scheme", "http"),
+            (":authority", "h2.test"),
+            (":path", path),
+            ("user-agent", "h2-test"),
+        ];
+        headers.extend_from_slice(extra);
+        let mut out = Vec::new();
+        let block = hpack::encode(headers);
+        frame::encode_headers(&mut out, stream, &block, body.is_none(), frame::DEFAULT_MAX_FRAME_SIZE);
+        if let Some(body) = body {
+            let mut chunks = body.chunks(frame::DEFAULT_MAX_FRAME_SIZE as usize).peekable();
+            while let Some(chunk) = chunks.next() {
+                let flags = if chunks.peek().is_none() { frame::END_STREAM } else { 0 };
+                frame::encode(&mut out, frame::DATA, flags, stream, chunk);
+            }
+        }
+        self.io.write_all(&out).unwrap();
+    }
+
+    /// Read frames until `stream` has ended, returning its response. Other
+    /// streams' responses are kept for later.
+    fn response(&mut self, stream: u32) -> Response {
+        while !self.ended.remove(&stream) {
+            let (frame, used) = loop {
+                if let Some(parsed) = Frame::parse(&self.buffer, frame::DEFAULT_MAX_FRAME_SIZE).unwrap() {
+                    break parsed;
+                }
+                let mut chunk = [0u8; 16384];
+                let n = self.io.read(&mut chunk).unwrap();
+                assert!(n > 0, "connection closed before stream {} ended", stream);
+                self.buffer.extend_from_slice(&chunk[..n]);
+            };
+            self.buffer.drain(..used);
+            match frame.kind {
+                frame::SETTINGS if !frame.has(frame::ACK) => {
+                    let mut ack = Vec::new();
+                    frame::encode(&mut ack, frame::SETTINGS, frame::ACK, 0, &[]);
+                    self.io.write_all(&ack).unwrap();
+                }
+                frame::HEADERS => {
+                    let block = frame.unpadded().unwrap().to_vec();
+                    let headers = self.decoder.decode(&block, usize::MAX).unwrap();
+                    self.responses
+                        .entry(frame.stream)
+                        .or_default()
+                        .headers
+                        .extend(headers);
+                }
+                frame::DATA => {
+                    let data = frame.unpadded().unwrap().to_vec();
+                    let mut update = Vec::new();
+                    if !data.is_empty() {
+                        frame::encode_window_update(&mut update, 0, data.len() as u32);
+                        self.io.write_all(&update).unwrap();
+                    }
+                    self.responses.entry(frame.stream).or_default().body.extend(data);
+                }
+                frame::RST_STREAM => panic!("stream {} was reset", frame.stream),
+                frame::GOAWAY => panic!("connection went away: {:?}", frame.payload),
+                _ => {}
+            }
+            if frame.has(frame::END_STREAM) && matches!(frame.kind, frame::HEADERS | frame::DATA) {
+                self.ended.insert(frame.stream);
+            }
+        }
+        self.responses.remove(&stream).unwrap()
+    }
+}
+
+#[test]
+fn prior_knowledge_requests_are_forwarded_as_http1() {
+    let (upstream, heads) = spawn_echo_upstream();
+    let proxy = proxy_to(upstream, "");
+
+    let mut client = Client::new(connect(proxy));
+    client.send(1, "GET", "/hello?x=1", None);
+    let response = client.response(1);
+    assert_eq!(response.status(), "200");
+    assert_eq!(String::from_utf8_lossy(&response.body), "GET /hello?x=1 HTTP/1.1\n");
+    assert!(response.header("x-request-id").is_some());
+
+    let head = heads.lock().unwrap().last().cloned().unwrap();
+    assert_eq!(header(&head, "host").as_deref(), Some("h2.test"));
+    assert_eq!(header(&head, "x-forwarded-proto").as_deref(), Some("http"));
+}
+
+#[test]
+fn streams_are_multiplexed_on_one_connection() {
+    let (upstream, _) = spawn_echo_upstream();
+    let proxy = proxy_to(upstream, "");
+
+    let mut client = Client::new(connect(proxy));
+    for (i, stream) in [1, 3, 5, 7].into_iter().enumerate() {
+        client.send(stream, "GET", &format!("/stream/{}", i), None);
+    }
+    for (i, stream) in [7, 1, 5, 3].into_iter().enumerate() {
+        let response = client.response(stream);
+        assert_eq!(response.status(), "200", "request {}", i);
+        let expected = format!("GET /stream/{} HTTP/1.1\n", (stream - 1) / 2);
+        assert_eq!(String::from_utf8_lossy(&response.body), expected);
+    }
+}
+
+#[test]
+fn request_bodies_reach_the_upstream_chunked() {
+    let (upstream, heads) = spawn_echo_upstream();
+    let proxy = proxy_to(upstream, "");
+
+    // Within the default window the client may use before our SETTINGS.
+    let body = vec![b'x'; 60_000];
+    let mut client = Client::new(connect(proxy));
+    client.send(1, "POST", "/upload", Some(&body));
+    let response = client.response(1);
+    assert_eq!(response.status(), "200");
+    let mut expected = b"POST /upload HTTP/1.1\n".to_vec();
+    expected.extend(&body);
+    assert_eq!(response.body.len(), expected.len());
+    assert!(response.body == expected);
+
+    let head = heads.lock().unwrap().last().cloned().unwrap();
+    assert_eq!(header(&head, "transfer-encoding").as_deref(), Some("chunked"));
+}
+
+#[test]
+fn h2c_upgrade_answers_the_first_request_on_stream_one() {
+    let (upstream, _) = spawn_echo_upstream();
+    let proxy = proxy_to(upstream, "");
+
+    let mut stream = connect(proxy);
+    // SETTINGS_ENABLE_PUSH = 0, base64url-encoded.
+    stream
+        .write_all(
+            b"GET /upgraded HTTP/1.1\r\nHost: h2.test\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+              Upgrade: h2c\r\nHTTP2-Settings: AAIAAAAA\r\n\r\n",
+        )
+        .unwrap();
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+    while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap() == 1 {
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8_lossy(&head).to_string();
+    assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+    assert_eq!(header(&head, "upgrade").as_deref(), Some("h2c"));
+
+    let mut client = Client::new(stream);
+    let response = client.response(1);
+    assert_eq!(response.status(), "200");
+    assert_eq!(String::from_utf8_lossy(&response.body), "GET /upgraded HTTP/1.1\n");
+
+    client.send(3, "GET", "/after", None);
+    assert_eq!(String::from_utf8_lossy(&client.response(3).body), "GET /after HTTP/1.1\n");
+}
+
+#[test]
+fn tls_clients_negotiate_h2_with_alpn() {
+    let dir = temp_dir("http2");
+    let cert = generate_simple_self_signed(vec!["h2.test".to_string()]).unwrap();
+    let cert_path = dir.join("h2.pem");
+    let key_path = dir.join("h2.key");
+    std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+    std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+
+    let upstream = Upstream::spawn();
+    let http: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let https: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{http}"
+
+[upstream]
+address = "{upstream}"
+
+[access_log]
+enabled = false
+
+[tls]
+listen = "{https}"
+
+[[tls.certificates]]
+cert = "{cert}"
+key = "{key}"
+"#,
+        upstream = upstream.address,
+        cert = cert_path.display(),
+        key = key_path.display(),
+    );
+    start_proxy(&config, https);
+
+    let mut tls = client_config(&[cert.cert.der()]);
+    tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    let stream = connect_tls(https, tls, "h2.test").unwrap();
+    assert_eq!(stream.conn.alpn_protocol(), Some(&b"h2"[..]));
+
+    let mut client = Client::new(stream);
+    client.send(1, "GET", "/secure", None);
+    let response = client.response(1);
+    assert_eq!(response.status(), "200");
+    assert_eq!(response.body, b"ok");
+    assert_eq!(header(&upstream.last_request(), "x-forwarded-proto").as_deref(), Some("https"));
+}
+
+#[test]
+fn http2_can_be_disabled() {
+    let (upstream, _) = spawn_echo_upstream();
+    let proxy = proxy_to(upstream, "http2 = false");
+
+    let mut stream = connect(proxy);
+    let mut out = frame::PREFACE.to_vec();
+    frame::encode_settings(&mut out, &[]);
+    stream.write_all(&out).unwrap();
+    let mut response = Vec::new();
+    let _ = stream.read_to_end(&mut response);
+    assert!(response.starts_with(b"HTTP/1.1 "), "{:?}", String::from_utf8_lossy(&response));
+
+    // Upgrade requests are answered in HTTP/1.1.
+    let mut stream = connect(proxy);
+    stream
+        .write_all(
+            b"GET /plain HTTP/1.1\r\nHost: h2.test\r\nConnection: Upgrade, HTTP2-Settings\r\n\
+              Upgrade: h2c\r\nHTTP2-Settings: AAIAAAAA\r\n\r\n",
+        )
+        .unwrap();
+    let mut response = String::new();
+    let mut chunk = [0u8; 1024];
+    while !response.ends_with("GET /plain HTTP/1.1\n") {
+        let n = stream.read(&mut chunk).unwrap();
+        assert!(n > 0, "{}", response);
+        response.push_str(&String::from_utf8_lossy(&chunk[..n]));
+    }
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+}
+
+type Fields = Vec<(String, String)>;
+
+/// A gRPC-ish h2c upstream: each call is answered with its path and body as
+/// the message, then `grpc-status: 0` in trailers. Records request headers
+/// and counts the connections it accepts.
+struct GrpcUpstream {
+    address: SocketAddr,
+    requests: Arc<Mutex<Vec<Fields>>>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl GrpcUpstream {
+    fn spawn() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let connections = Arc::new(AtomicUsize::new(0));
+        let (recorded, accepted) = (Arc::clone(&requests), Arc::clone(&connections));
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                accepted.fetch_add(1, Ordering::Relaxed);
+                let recorded = Arc::clone(&recorded);
+                thread::spawn(move || Self::serve(stream, recorded));
+            }
+        });
+
+        Self {
+            address,
+            requests,
+            connections,
+        }
+    }
+
+    fn serve(mut io: TcpStream, recorded: Arc<Mutex<Vec<Fields>>>) {
+        let mut preface = [0u8; 24];
+        if io.read_exact(&mut preface).is_err() || &preface != frame::PREFACE {
+            return;
+        }
+        let mut out = Vec::new();
+        frame::encode_settings(&mut out, &[]);
+        let _ = io.write_all(&out);
+
+        let mut decoder = hpack::Decoder::default();
+        let mut buffer = Vec::new();
+        let mut calls: HashMap<u32, (Fields, Vec<u8>)> = HashMap::new();
+        loop {
+            let (frame, used) = loop {
+                if let Some(parsed) = Frame::parse(&buffer, frame::DEFAULT_MAX_FRAME_SIZE).unwrap() {
+                    break parsed;
+                }
+                let mut chunk = [0u8; 16384];
+                match io.read(&mut chunk) {
+                    Ok(0) | Err(_) => return,
+                    Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+                }
+            };
+            buffer.drain(..used);
+            let mut out = Vec::new();
+            match frame.kind {
+                frame::SETTINGS if !frame.has(frame::ACK) => {
+                    frame::encode(&mut out, frame::SETTINGS, frame::ACK, 0, &[]);
+                }
+                frame::HEADERS => {
+                    let headers = decoder.decode(frame.unpadded().unwrap(), usize::MAX).unwrap();
+                    calls.entry(frame.stream).or_default().0.extend(headers);
+                }
+                frame::DATA => {
+                    let data = frame.unpadded().unwrap();
+                    if !data.is_empty() {
+                        frame::encode_window_update(&mut out, 0, data.len() as u32);
+                        frame::encode_window_update(&mut out, frame.stream, data.len() as u32);
+                    }
+                    calls.entry(frame.stream).or_default().1.extend_from_slice(data);
+                }
+                _ => {}
+            }
+            if frame.has(frame::END_STREAM)
+                && matches!(frame.kind, frame::HEADERS | frame::DATA)
+                && let Some((headers, body)) = calls.remove(&frame.stream)
+            {
+                let path = headers
+                    .iter()
+                    .find(|(name, _)| name == ":path")
+                    .map(|(_, value)| value.clone())
+                    .unwrap_or_default();
+                let mut message = path.into_bytes();
+                message.push(b'\n');
+                message.extend(body);
+                recorded.lock().unwrap().push(headers);
+
+                let head = hpack::encode([(":status", "200"), ("content-type", "application/grpc")]);
+                frame::encode_headers(&mut out, frame.stream, &head, false, frame::DEFAULT_MAX_FRAME_SIZE);
+                frame::encode(&mut out, frame::DATA, 0, frame.stream, &message);
+                let trailers = hpack::encode([("grpc-status", "0"), ("grpc-message", "done")]);
+                frame::encode_headers(&mut out, frame.stream, &trailers, true, frame::DEFAULT_MAX_FRAME_SIZE);
+            }
+            if !out.is_empty() && io.write_all(&out).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+fn start_with_http2_upstream(upstream: SocketAddr) -> SocketAddr {
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+
+[upstreams.grpc]
+servers = ["{upstream}"]
+http2 = true
+
+[access_log]
+enabled = false
+"#
+    );
+    start_proxy(&config, listen);
+    listen
+}
+
+const GRPC: [(&str, &str); 2] = [("content-type", "application/grpc"), ("te", "trailers")];
+
+#[test]
+fn grpc_calls_keep_their_trailers() {
+    let upstream = GrpcUpstream::spawn();
+    let proxy = start_with_http2_upstream(upstream.address);
+
+    let mut client = Client::new(connect(proxy));
+    client.send_with(1, "POST", "/echo.Echo/Say", &GRPC, Some(b"\0\0\0\0\x02hi"));
+    let response = client.response(1);
+    assert_eq!(response.status(), "200");
+    assert_eq!(response.header("content-type"), Some("application/grpc"));
+    assert_eq!(response.body, b"/echo.Echo/Say\n\0\0\0\0\x02hi");
+    assert_eq!(response.header("grpc-status"), Some("0"));
+    assert_eq!(response.header("grpc-message"), Some("done"));
+
+    let request = upstream.requests.lock().unwrap().last().cloned().unwrap();
+    let value = |name: &str| request.iter().find(|(key, _)| key == name).map(|(_, v)| v.as_str());
+    assert_eq!(value(":method"), Some("POST"));
+    assert_eq!(value(":authority"), Some("h2.test"));
+    assert_eq!(value("te"), Some("trailers"));
+    assert_eq!(value("host"), None);
+}
+
+#[test]
+fn upstream_http2_connections_are_shared() {
+    let upstream = GrpcUpstream::spawn();
+    let proxy = start_with_http2_upstream(upstream.address);
+
+    let mut client = Client::new(connect(proxy));
+    for stream in [1, 3, 5, 7, 9] {
+        client.send_with(stream, "POST", &format!("/call/{}", stream), &GRPC, Some(b"\0\0\0\0\0"));
+    }
+    for stream in [9, 7, 5, 3, 1] {
+        let response = client.response(stream);
+        assert_eq!(response.body, format!("/call/{}\n\0\0\0\0\0", stream).as_bytes());
+        assert_eq!(response.header("grpc-status"), Some("0"));
+    }
+
+    // A second client's calls reuse the same upstream connection.
+    let mut other = Client::new(connect(proxy));
+    other.send_with(1, "POST", "/call/again", &GRPC, Some(b"\0\0\0\0\0"));
+    assert_eq!(other.response(1).header("grpc-status"), Some("0"));
+    assert_eq!(upstream.connections.load(Ordering::Relaxed), 1);
+}
+
+#[test]
+fn http1_clients_get_trailers_in_chunked_coding() {
+    let upstream = GrpcUpstream::spawn();
+    let proxy = start_with_http2_upstream(upstream.address);
+
+    let mut stream = connect(proxy);
+    stream
+        .write_all(b"GET /plain HTTP/1.1\r\nHost: h2.test\r\nTE: trailers\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut response = Vec::new();
+    stream.read_to_end(&mut response).unwrap();
+    let response = String::from_utf8_lossy(&response).to_string();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert_eq!(header(&response, "transfer-encoding").as_deref(), Some("chunked"));
+    let (_, trailers) = response.rsplit_once("\r\n0\r\n").unwrap();
+    assert!(trailers.ends_with("\r\n\r\n"), "{}", response);
+    assert_eq!(header(trailers, "grpc-status").as_deref(), Some("0"));
+    assert_eq!(header(trailers, "grpc-message").as_deref(), Some("done"));
+}
+
+#[test]
+fn grpc_errors_are_reported_in_trailers() {
+    // Nothing listens upstream.
+    let upstream: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let proxy = start_with_http2_upstream(upstream);
+
+    let mut client = Client::new(connect(proxy));
+    client.send_with(1, "POST", "/echo.Echo/Say", &GRPC, Some(b"\0\0\0\0\0"));
+    let response = client.response(1);
+    assert_eq!(response.status(), "200");
+    assert_eq!(response.header("content-type"), Some("application/grpc"));
+    assert!(response.body.is_empty());
+    assert_eq!(response.header("grpc-status"), Some("14"));
+    assert!(response.header("grpc-message").is_some());
+}
@@ -0,0 +1,304 @@
+// Keep-alive connections parked between requests (and new ones until their
+// first) and resumed when the client sends its next one, on a bounded pool
+// of handler threads that long-lived tunnels do not tie up.
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{ProxyProcess, Upstream, fetch_metrics, free_port, header, metric_value, read_head, start_proxy};
+use orion::proxy::handlers::HandlerPool;
+
+struct Proxy {
+    listen: SocketAddr,
+    admin: SocketAddr,
+    _upstream: Upstream,
+}
+
+fn start(read_timeout_ms: u64) -> Proxy {
+    let upstream = Upstream::spawn();
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let admin: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+read_timeout_ms = {read_timeout_ms}
+
+[upstream]
+address = "{upstream}"
+
+[admin]
+listen = "{admin}"
+
+[access_log]
+enabled = false
+"#,
+        upstream = upstream.address,
+    );
+    start_proxy(&config, listen);
+    Proxy {
+        listen,
+        admin,
+        _upstream: upstream,
+    }
+}
+
+impl Proxy {
+    fn idle_connections(&self) -> u64 {
+        metric_value(&fetch_metrics(self.admin), "orion_idle_connections").expect("idle connection gauge") as u64
+    }
+
+    /// Poll the idle gauge until it reaches `expected`.
+    fn wait_for_idle(&self, expected: u64) -> u64 {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let idle = self.idle_connections();
+            if idle == expected || Instant::now() > deadline {
+                return idle;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+/// Read one response with a Content-Length body, returning its head.
+fn read_response(reader: &mut impl BufRead) -> String {
+    let mut head = String::new();
+    while !head.ends_with("\r\n\r\n") {
+        if reader.read_line(&mut head).unwrap() == 0 {
+            break;
+        }
+    }
+    let length: usize = header(&head, "content-length").map_or(0, |l| l.parse().unwrap());
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body).unwrap();
+    head
+}
+
+fn request(stream: &mut TcpStream, reader: &mut impl BufRead) -> String {
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    read_response(reader)
+}
+
+#[test]
+fn idle_connections_are_parked_and_resumed() {
+    let proxy = start(30_000);
+
+    let mut clients = Vec::new();
+    for _ in 0..200 {
+        let mut stream = TcpStream::connect(proxy.listen).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let head = request(&mut stream, &mut reader);
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        clients.push((stream, reader));
+    }
+    assert_eq!(proxy.wait_for_idle(200), 200);
+
+    for (stream, reader) in &mut clients {
+        let head = request(stream, reader);
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    }
+    assert_eq!(proxy.wait_for_idle(200), 200);
+
+    drop(clients);
+    assert_eq!(proxy.wait_for_idle(0), 0);
+}
+
+#[test]
+fn pipelined_requests_are_all_answered() {
+    let proxy = start(30_000);
+
+    let mut stream = TcpStream::connect(proxy.listen).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    stream
+        .write_all(
+            b"GET /a HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              GET /b HTTP/1.1\r\nHost: localhost\r\n\r\n\
+              GET /c HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n",
+        )
+        .unwrap();
+    let mut reader = BufReader::new(stream);
+    for _ in 0..3 {
+        let head = read_response(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    }
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+}
+
+#[test]
+fn parked_connections_close_after_the_idle_timeout() {
+    let proxy = start(300);
+
+    let mut stream = TcpStream::connect(proxy.listen).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let head = request(&mut stream, &mut reader);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+
+    let started = Instant::now();
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+    assert!(started.elapsed() < Duration::from_secs(4));
+    assert_eq!(proxy.wait_for_idle(0), 0);
+}
+
+/// An upstream that upgrades every request to a WebSocket it holds open until
+/// the client closes it.
+fn spawn_tunnel_upstream() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                read_head(&mut reader);
+                let switching = b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                    Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+                let _ = stream.write_all(switching);
+                let _ = reader.read_to_end(&mut Vec::new());
+            });
+        }
+    });
+    address
+}
+
+#[test]
+fn tunnels_do_not_tie_up_handler_threads() {
+    let upstream = Upstream::spawn();
+    let tunnels = spawn_tunnel_upstream();
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+
+[upstreams.app]
+servers = ["{app}"]
+
+[upstreams.tunnels]
+servers = ["{tunnels}"]
+
+[[routes]]
+prefix = "/"
+upstream = "app"
+
+[[routes]]
+prefix = "/tunnel"
+upstream = "tunnels"
+
+[access_log]
+enabled = false
+"#,
+        app = upstream.address,
+    );
+    start_proxy(&config, listen);
+
+    let connect = || {
+        let mut stream = TcpStream::connect(listen).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let head = request(&mut stream, &mut reader);
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        (stream, reader)
+    };
+    let (mut parked, mut parked_reader) = connect();
+
+    // More tunnels than a worker has handler threads, each opened from a
+    // resumed connection.
+    let mut open = Vec::new();
+    for _ in 0..300 {
+        let (mut stream, mut reader) = connect();
+        stream
+            .write_all(
+                b"GET /tunnel HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\nUpgrade: websocket\r\n\
+                  Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+            )
+            .unwrap();
+        let head = read_head(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+        open.push(stream);
+    }
+
+    let head = request(&mut parked, &mut parked_reader);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+}
+
+#[test]
+fn connections_yet_to_send_a_request_hold_no_thread() {
+    let upstream = Upstream::spawn();
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+
+[upstream]
+address = "{upstream}"
+
+[access_log]
+enabled = false
+"#,
+        upstream = upstream.address,
+    );
+    let proxy = ProxyProcess::spawn(&config, listen);
+    let threads = || std::fs::read_dir(format!("/proc/{}/task", proxy.pid())).unwrap().count();
+    let before = threads();
+
+    let silent: Vec<TcpStream> = (0..200).map(|_| TcpStream::connect(listen).unwrap()).collect();
+    thread::sleep(Duration::from_millis(300));
+    assert!(threads() < before + 10, "{} threads, {} before", threads(), before);
+
+    let mut stream = TcpStream::connect(listen).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let head = request(&mut stream, &mut reader);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    drop(silent);
+}
+
+#[test]
+fn resumed_connections_share_a_bounded_pool_of_threads() {
+    let seen = Arc::new(Mutex::new(Vec::new()));
+    let pool = {
+        let seen = Arc::clone(&seen);
+        HandlerPool::new("handler-test", 4, Duration::from_millis(200), move |job: usize| {
+            thread::sleep(Duration::from_millis(10));
+            seen.lock().unwrap().push((job, thread::current().id()));
+        })
+    };
+
+    for job in 0..40 {
+        pool.submit(job);
+        assert!(pool.threads() <= 4);
+    }
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while seen.lock().unwrap().len() < 40 {
+        assert!(Instant::now() < deadline, "jobs were left queued");
+        thread::sleep(Duration::from_millis(10));
+    }
+    let seen = seen.lock().unwrap();
+    let jobs: HashSet<usize> = seen.iter().map(|(job, _)| *job).collect();
+    assert_eq!(jobs.len(), 40);
+    let threads: HashSet<_> = seen.iter().map(|(_, thread)| *thread).collect();
+    assert!(threads.len() <= 4, "{} threads", threads.len());
+    drop(seen);
+
+    // Idle threads exit.
+    while pool.threads() > 0 {
+        assert!(Instant::now() < deadline, "{} threads still running", pool.threads());
+        thread::sleep(Duration::from_millis(20));
+    }
+}
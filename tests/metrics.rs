@@ -0,0 +1,130 @@
+// Metrics in the Prometheus text format: counters, cumulative histogram
+// buckets with their sum and count, label escaping, and the admin
+// listener's `/metrics` endpoint.
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use common::{Upstream, fetch_metrics, free_port, metric_value, start_proxy};
+use orion::metrics::{CounterVec, HistogramVec, TextEncoder};
+
+#[test]
+fn counters_are_rendered_per_label_set() {
+    let counter = CounterVec::new(&["route", "status"]);
+    counter.inc(&["api", "2xx"]);
+    counter.add(&["api", "2xx"], 4);
+    counter.inc(&["web", "5xx"]);
+
+    let mut encoder = TextEncoder::new();
+    encoder.counter_vec("test_total", "Things counted.", &counter);
+    assert_eq!(
+        encoder.finish(),
+        "# HELP test_total Things counted.\n\
+         # TYPE test_total counter\n\
+         test_total{route=\"api\",status=\"2xx\"} 5\n\
+         test_total{route=\"web\",status=\"5xx\"} 1\n"
+    );
+}
+
+#[test]
+fn histogram_buckets_are_cumulative() {
+    let histogram = HistogramVec::new(&["route"], &[0.1, 0.5, 1.0]);
+    for value in [0.05, 0.1, 0.3, 0.7, 2.0] {
+        histogram.observe(&["api"], value);
+    }
+
+    let mut encoder = TextEncoder::new();
+    encoder.histogram_vec("test_seconds", "Time taken.", &histogram);
+    assert_eq!(
+        encoder.finish(),
+        "# HELP test_seconds Time taken.\n\
+         # TYPE test_seconds histogram\n\
+         test_seconds_bucket{route=\"api\",le=\"0.1\"} 2\n\
+         test_seconds_bucket{route=\"api\",le=\"0.5\"} 3\n\
+         test_seconds_bucket{route=\"api\",le=\"1\"} 4\n\
+         test_seconds_bucket{route=\"api\",le=\"+Inf\"} 5\n\
+         test_seconds_sum{route=\"api\"} 3.15\n\
+         test_seconds_count{route=\"api\"} 5\n"
+    );
+}
+
+#[test]
+fn label_values_are_escaped() {
+    let counter = CounterVec::new(&["path"]);
+    counter.inc(&["a\"b\\c\nd"]);
+
+    let mut encoder = TextEncoder::new();
+    encoder.counter_vec("test_total", "Escaping.", &counter);
+    let rendered = encoder.finish();
+    assert!(rendered.ends_with("test_total{path=\"a\\\"b\\\\c\\nd\"} 1\n"), "{}", rendered);
+}
+
+#[test]
+fn the_metrics_endpoint_reports_requests() {
+    let upstream = Upstream::spawn();
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let admin: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+
+[admin]
+listen = "{admin}"
+
+[upstreams.app]
+servers = ["{upstream}"]
+
+[[routes]]
+name = "app"
+prefix = "/"
+upstream = "app"
+
+[access_log]
+enabled = false
+"#,
+        upstream = upstream.address
+    );
+    start_proxy(&config, listen);
+
+    for _ in 0..3 {
+        let mut stream = TcpStream::connect(listen).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n").unwrap();
+        stream.read_to_end(&mut Vec::new()).unwrap();
+    }
+
+    // Requests are counted once their response is written, which may be
+    // just after the client has read it.
+    let requests = "orion_requests_total{route=\"app\",method=\"GET\",status_class=\"2xx\"}";
+    let mut metrics = fetch_metrics(admin);
+    for _ in 0..50 {
+        if metric_value(&metrics, requests) == Some(3.0) {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+        metrics = fetch_metrics(admin);
+    }
+    assert!(metrics.contains("# TYPE orion_requests_total counter\n"), "{}", metrics);
+    assert_eq!(metric_value(&metrics, requests), Some(3.0));
+
+    assert!(metrics.contains("# TYPE orion_request_duration_seconds histogram\n"));
+    let buckets: Vec<f64> = metrics
+        .lines()
+        .filter(|line| line.starts_with("orion_request_duration_seconds_bucket{route=\"app\","))
+        .map(|line| line.rsplit_once(' ').unwrap().1.parse().unwrap())
+        .collect();
+    assert_eq!(buckets.len(), 12, "{}", metrics);
+    assert!(buckets.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", buckets);
+    assert_eq!(buckets.last(), Some(&3.0));
+    assert_eq!(metric_value(&metrics, "orion_request_duration_seconds_count{route=\"app\"}"), Some(3.0));
+    let sum = metric_value(&metrics, "orion_request_duration_seconds_sum{route=\"app\"}").unwrap();
+    assert!(sum > 0.0 && sum < 15.0, "{}", sum);
+
+    let backend = format!("orion_upstream_duration_seconds_count{{upstream=\"app\",backend=\"{}\"}}", upstream.address);
+    assert_eq!(metric_value(&metrics, &backend), Some(3.0), "{}", metrics);
+}
@@ -0,0 +1,166 @@
+// Client certificate authentication on the HTTPS listener, against a CA
+// generated for each test.
+
+mod common;
+
+use std::io::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use common::{Issued, TestCa, TlsStream, Upstream, connect_tls, free_port, get, header, start_proxy, temp_dir};
+use rustls::ClientConfig;
+
+struct Proxy {
+    https: SocketAddr,
+    server_ca: TestCa,
+    client_ca: TestCa,
+    upstream: Upstream,
+    billing: Upstream,
+}
+
+/// A proxy requiring client certificates from `client_ca` (with the given
+/// `client_auth` mode). Clients whose certificate names `billing` are routed
+/// to a separate upstream.
+fn start(client_auth: &str) -> Proxy {
+    let dir = temp_dir("mtls");
+    let server_ca = TestCa::new("Orion Test Server CA");
+    let client_ca = TestCa::new("Orion Test Client CA");
+    let (cert, key) = server_ca.issue("localhost", &["localhost"]).write(&dir, "server");
+    let client_ca_path = client_ca.write(&dir);
+
+    let upstream = Upstream::spawn();
+    let billing = Upstream::spawn();
+    let http: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let https: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{http}"
+
+[upstreams.main]
+servers = ["{upstream}"]
+
+[upstreams.billing]
+servers = ["{billing}"]
+
+[[routes]]
+prefix = "/"
+upstream = "main"
+
+[[routes]]
+prefix = "/"
+upstream = "billing"
+client_subjects = ["DNS:billing.internal"]
+
+[access_log]
+enabled = false
+
+[tls]
+listen = "{https}"
+client_ca = "{client_ca_path}"
+client_auth = "{client_auth}"
+
+[[tls.certificates]]
+cert = "{cert}"
+key = "{key}"
+"#,
+        upstream = upstream.address,
+        billing = billing.address,
+    );
+    start_proxy(&config, https);
+
+    Proxy {
+        https,
+        server_ca,
+        client_ca,
+        upstream,
+        billing,
+    }
+}
+
+impl Proxy {
+    fn client(&self, identity: Option<&Issued>) -> ClientConfig {
+        let builder = ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(self.server_ca.roots());
+        match identity {
+            Some(issued) => builder
+                .with_client_auth_cert(issued.chain(), issued.private_key())
+                .unwrap(),
+            None => builder.with_no_client_auth(),
+        }
+    }
+
+    fn connect(&self, identity: Option<&Issued>) -> TlsStream {
+        connect_tls(self.https, self.client(identity), "localhost").expect("handshake completes")
+    }
+}
+
+#[test]
+fn verified_client_certificate_is_forwarded() {
+    let proxy = start("require");
+    let client = proxy.client_ca.issue("alice", &["alice.example"]);
+
+    let response = get(&mut proxy.connect(Some(&client)), "localhost", "/");
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    let head = proxy.upstream.last_request();
+    assert_eq!(header(&head, "x-client-cert-subject").as_deref(), Some("CN=alice"));
+    assert_eq!(header(&head, "x-client-cert-san").as_deref(), Some("DNS:alice.example"));
+}
+
+#[test]
+fn missing_certificate_is_forbidden_when_required() {
+    let proxy = start("require");
+
+    let response = get(&mut proxy.connect(None), "localhost", "/");
+    assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+    assert!(proxy.upstream.requests.lock().unwrap().is_empty());
+}
+
+#[test]
+fn certificate_from_another_ca_is_forbidden() {
+    let proxy = start("optional");
+    let stranger = TestCa::new("Someone Else").issue("mallory", &["mallory.example"]);
+
+    let response = get(&mut proxy.connect(Some(&stranger)), "localhost", "/");
+    assert!(response.starts_with("HTTP/1.1 403"), "{}", response);
+    assert!(proxy.upstream.requests.lock().unwrap().is_empty());
+}
+
+#[test]
+fn optional_mode_strips_spoofed_identity_headers() {
+    let proxy = start("optional");
+
+    let mut stream = proxy.connect(None);
+    stream
+        .write_all(
+            b"GET / HTTP/1.1\r\nHost: localhost\r\nX-Client-Cert-Subject: CN=admin\r\nX-Client-Cert-San: DNS:admin\r\n\
+              Connection: close\r\n\r\n",
+        )
+        .unwrap();
+    let mut response = String::new();
+    std::io::Read::read_to_string(&mut stream, &mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+
+    let head = proxy.upstream.last_request();
+    assert_eq!(header(&head, "x-client-cert-subject"), None);
+    assert_eq!(header(&head, "x-client-cert-san"), None);
+}
+
+#[test]
+fn routes_match_on_client_certificate_names() {
+    let proxy = start("require");
+    let billing = proxy.client_ca.issue("billing-service", &["billing.internal"]);
+    let other = proxy.client_ca.issue("web", &["web.internal"]);
+
+    let response = get(&mut proxy.connect(Some(&billing)), "localhost", "/invoices");
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert_eq!(proxy.billing.requests.lock().unwrap().len(), 1);
+
+    let response = get(&mut proxy.connect(Some(&other)), "localhost", "/invoices");
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert_eq!(proxy.billing.requests.lock().unwrap().len(), 1);
+    assert_eq!(proxy.upstream.requests.lock().unwrap().len(), 1);
+}
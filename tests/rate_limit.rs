@@ -0,0 +1,219 @@
+// Token-bucket rate limiting: bursts, refill, keys and their scope across
+// routes, eviction of idle buckets, and the configuration that sets it up.
+
+mod common;
+
+use std::io::{BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::Duration;
+
+use common::{Upstream, fetch_metrics, free_port, header, metric_value, read_head, start_proxy};
+use orion::config::{Config, RateLimitKeyPart};
+
+/// A proxy with routes `/a` and `/b` (both under `config`) in front of one
+/// upstream; `route_a` adds settings to `/a`.
+fn start(config: &str, route_a: &str) -> (SocketAddr, SocketAddr, Upstream) {
+    let upstream = Upstream::spawn();
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let admin: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+
+[admin]
+listen = "{admin}"
+
+[upstreams.app]
+servers = ["{upstream}"]
+
+[[routes]]
+name = "a"
+prefix = "/a"
+upstream = "app"
+{route_a}
+
+[[routes]]
+name = "b"
+prefix = "/b"
+upstream = "app"
+
+[rate_limit]
+{config}
+
+[access_log]
+enabled = false
+"#,
+        upstream = upstream.address
+    );
+    start_proxy(&config, listen);
+    (listen, admin, upstream)
+}
+
+fn get(proxy: SocketAddr, path: &str, headers: &str) -> String {
+    let mut stream = TcpStream::connect(proxy).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: test\r\n{}Connection: close\r\n\r\n", path, headers).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+fn status(response: &str) -> u16 {
+    response.split(' ').nth(1).unwrap().parse().unwrap()
+}
+
+#[test]
+fn requests_past_the_burst_are_refused() {
+    let (proxy, admin, upstream) = start("rate = 1\nburst = 3", "");
+
+    for remaining in ["2", "1", "0"] {
+        let response = get(proxy, "/a", "");
+        assert_eq!(status(&response), 200, "{}", response);
+        assert_eq!(header(&response, "ratelimit-limit").as_deref(), Some("3"));
+        assert_eq!(header(&response, "ratelimit-remaining").as_deref(), Some(remaining));
+        assert_eq!(header(&response, "retry-after"), None);
+    }
+
+    let refused = get(proxy, "/a", "");
+    assert_eq!(status(&refused), 429, "{}", refused);
+    assert!(refused.starts_with("HTTP/1.1 429 Too Many Requests\r\n"));
+    assert_eq!(header(&refused, "retry-after").as_deref(), Some("1"));
+    assert_eq!(header(&refused, "ratelimit-remaining").as_deref(), Some("0"));
+    let reset: u64 = header(&refused, "ratelimit-reset").unwrap().parse().unwrap();
+    assert!((2..=3).contains(&reset), "reset in {}s", reset);
+    assert_eq!(upstream.requests.lock().unwrap().len(), 3);
+
+    let metrics = fetch_metrics(admin);
+    assert_eq!(metric_value(&metrics, "orion_rate_limited_requests_total{route=\"a\"}"), Some(1.0));
+}
+
+#[test]
+fn refused_clients_keep_their_connection() {
+    let (proxy, _, _) = start("rate = 1\nburst = 1", "");
+
+    let mut stream = TcpStream::connect(proxy).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    for expected in [200, 429, 429] {
+        stream.write_all(b"GET /a HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+        let head = read_head(&mut reader);
+        assert_eq!(status(&head), expected, "{}", head);
+        assert_eq!(header(&head, "connection"), None);
+        let length: usize = header(&head, "content-length").unwrap().parse().unwrap();
+        reader.read_exact(&mut vec![0; length]).unwrap();
+    }
+}
+
+#[test]
+fn buckets_refill_at_the_configured_rate() {
+    let (proxy, _, _) = start("rate = 20\nburst = 1", "");
+
+    assert_eq!(status(&get(proxy, "/a", "")), 200);
+    assert_eq!(status(&get(proxy, "/a", "")), 429);
+    thread::sleep(Duration::from_millis(120));
+    assert_eq!(status(&get(proxy, "/a", "")), 200);
+}
+
+#[test]
+fn the_default_limit_is_shared_across_routes_unless_keyed_by_route() {
+    let (proxy, _, _) = start("rate = 1\nburst = 1", "");
+    assert_eq!(status(&get(proxy, "/a", "")), 200);
+    assert_eq!(status(&get(proxy, "/b", "")), 429);
+
+    let (proxy, _, _) = start("rate = 1\nburst = 1\nkey = [\"client_ip\", \"route\"]", "");
+    assert_eq!(status(&get(proxy, "/a", "")), 200);
+    assert_eq!(status(&get(proxy, "/b", "")), 200);
+    assert_eq!(status(&get(proxy, "/b", "")), 429);
+}
+
+#[test]
+fn routes_can_set_their_own_limit_or_opt_out() {
+    let (proxy, _, _) = start(
+        "rate = 1\nburst = 1",
+        "rate_limit = { rate = 1, burst = 2, key = [\"header:x-api-key\"] }",
+    );
+
+    // Each API key has a bucket of its own, apart from the default limit's.
+    for key in ["one", "two"] {
+        let api_key = format!("X-Api-Key: {}\r\n", key);
+        assert_eq!(status(&get(proxy, "/a", &api_key)), 200);
+        assert_eq!(status(&get(proxy, "/a", &api_key)), 200);
+        assert_eq!(status(&get(proxy, "/a", &api_key)), 429, "{}", key);
+    }
+    assert_eq!(status(&get(proxy, "/b", "")), 200);
+    assert_eq!(status(&get(proxy, "/b", "")), 429);
+
+    let (proxy, _, _) = start("rate = 1\nburst = 1", "rate_limit = false");
+    for _ in 0..3 {
+        let response = get(proxy, "/a", "");
+        assert_eq!(status(&response), 200);
+        assert_eq!(header(&response, "ratelimit-limit"), None);
+    }
+}
+
+#[test]
+fn rejected_requests_do_not_use_up_quota() {
+    let (proxy, _, _) = start("rate = 1\nburst = 1", "");
+
+    // HTTP/1.1 without Host is refused before it is rate limited.
+    for _ in 0..3 {
+        let mut stream = TcpStream::connect(proxy).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        stream.write_all(b"GET /a HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert_eq!(status(&response), 400);
+        assert_eq!(header(&response, "ratelimit-limit"), None);
+    }
+
+    let response = get(proxy, "/a", "");
+    assert_eq!(status(&response), 200);
+    assert_eq!(header(&response, "ratelimit-remaining").as_deref(), Some("0"));
+}
+
+#[test]
+fn idle_buckets_are_evicted_first() {
+    let (proxy, _, _) = start("rate = 0.01\nburst = 1\nkey = [\"header:x-api-key\"]\nmax_keys = 2", "");
+    let key = |name: &str| format!("X-Api-Key: {}\r\n", name);
+
+    for name in ["one", "two", "three"] {
+        assert_eq!(status(&get(proxy, "/a", &key(name))), 200);
+    }
+    // "one" was dropped to make room for "three", and starts again full.
+    assert_eq!(status(&get(proxy, "/a", &key("one"))), 200);
+    assert_eq!(status(&get(proxy, "/a", &key("three"))), 429);
+}
+
+#[test]
+fn rate_limit_configuration() {
+    let config = Config::parse(
+        r#"
+[upstream]
+address = "127.0.0.1:8081"
+
+[rate_limit]
+rate = 2.5
+"#,
+    )
+    .unwrap();
+    let limit = config.routes[0].rate_limit.as_ref().unwrap();
+    assert_eq!(limit.rate, 2.5);
+    assert_eq!(limit.burst, 3);
+    assert_eq!(limit.key, vec![RateLimitKeyPart::ClientIp]);
+    assert_eq!(config.rate_limit.unwrap().max_keys, 100_000);
+
+    for invalid in [
+        "[rate_limit]\nrate = 0",
+        "[rate_limit]\nrate = 1\nburst = 0",
+        "[rate_limit]\nrate = 1\nkey = [\"cookie:id\"]",
+        "[rate_limit]\nburst = 5",
+        "[rate_limit]\nmax_keys = 0",
+        "[[routes]]\nupstream = \"default\"\nrate_limit = { burst = 5 }",
+        "[[routes]]\nupstream = \"default\"\nrate_limit = 5",
+    ] {
+        let config = format!("[upstream]\naddress = \"127.0.0.1:8081\"\n{}", invalid);
+        assert!(Config::parse(&config).is_err(), "{}", invalid);
+    }
+}
@@ -0,0 +1,115 @@
+// Request IDs: generated for each request, forwarded upstream and echoed to
+// the client; incoming IDs reused only from trusted clients and only when
+// they are well-formed.
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+use common::{Upstream, free_port, header, start_proxy};
+
+fn start(request_id: &str) -> (SocketAddr, Upstream) {
+    let upstream = Upstream::spawn();
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+
+[upstream]
+address = "{upstream}"
+
+[request_id]
+{request_id}
+
+[access_log]
+enabled = false
+"#,
+        upstream = upstream.address
+    );
+    start_proxy(&config, listen);
+    (listen, upstream)
+}
+
+/// The response to a GET carrying `headers`, and the ID the upstream saw.
+fn get(proxy: SocketAddr, upstream: &Upstream, headers: &str) -> (String, Option<String>) {
+    let mut stream = TcpStream::connect(proxy).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(stream, "GET / HTTP/1.1\r\nHost: test\r\n{}Connection: close\r\n\r\n", headers).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    (response, header(&upstream.last_request(), "x-request-id"))
+}
+
+fn is_uuid(id: &str) -> bool {
+    let groups: Vec<&str> = id.split('-').collect();
+    groups.iter().map(|g| g.len()).eq([8, 4, 4, 4, 12]) && id.bytes().all(|b| b == b'-' || b.is_ascii_hexdigit())
+}
+
+#[test]
+fn generated_ids_are_forwarded_and_echoed() {
+    let (proxy, upstream) = start("");
+
+    let (response, forwarded) = get(proxy, &upstream, "");
+    let echoed = header(&response, "x-request-id").unwrap();
+    assert!(is_uuid(&echoed), "{}", echoed);
+    assert_eq!(forwarded.as_deref(), Some(echoed.as_str()));
+
+    let (second, _) = get(proxy, &upstream, "");
+    assert_ne!(header(&second, "x-request-id"), Some(echoed));
+}
+
+#[test]
+fn incoming_ids_are_replaced_unless_trusted() {
+    let (proxy, upstream) = start("");
+    let (response, forwarded) = get(proxy, &upstream, "X-Request-Id: client-chosen\r\n");
+    let echoed = header(&response, "x-request-id").unwrap();
+    assert!(is_uuid(&echoed), "{}", echoed);
+    assert_eq!(forwarded, Some(echoed));
+
+    // Trusted, but only from another address.
+    let (proxy, upstream) = start("trust_incoming = true\ntrusted_clients = [\"10.0.0.1\"]");
+    let (response, _) = get(proxy, &upstream, "X-Request-Id: client-chosen\r\n");
+    assert_ne!(header(&response, "x-request-id").as_deref(), Some("client-chosen"));
+}
+
+#[test]
+fn trusted_incoming_ids_are_forwarded_and_echoed() {
+    let (proxy, upstream) = start("trust_incoming = true\ntrusted_clients = [\"127.0.0.1\"]");
+
+    let id = "edge-7f3a_01:2.b";
+    let (response, forwarded) = get(proxy, &upstream, &format!("X-Request-Id: {}\r\n", id));
+    assert_eq!(header(&response, "x-request-id").as_deref(), Some(id));
+    assert_eq!(forwarded.as_deref(), Some(id));
+}
+
+#[test]
+fn malformed_or_long_incoming_ids_are_replaced() {
+    let (proxy, upstream) = start("trust_incoming = true");
+
+    let longest = "a".repeat(128);
+    let (response, forwarded) = get(proxy, &upstream, &format!("X-Request-Id: {}\r\n", longest));
+    assert_eq!(header(&response, "x-request-id").as_deref(), Some(longest.as_str()));
+    assert_eq!(forwarded, Some(longest));
+
+    for invalid in ["a".repeat(129), "has space".to_string(), "quote\"d".to_string(), "<script>".to_string()] {
+        let (response, forwarded) = get(proxy, &upstream, &format!("X-Request-Id: {}\r\n", invalid));
+        let echoed = header(&response, "x-request-id").unwrap();
+        assert!(is_uuid(&echoed), "{} replaced by {}", invalid, echoed);
+        assert_eq!(forwarded, Some(echoed));
+    }
+}
+
+#[test]
+fn the_header_and_format_are_configurable() {
+    let (proxy, upstream) = start("header = \"X-Trace\"\nformat = \"ulid\"");
+
+    let (response, _) = get(proxy, &upstream, "");
+    let id = header(&response, "x-trace").unwrap();
+    assert_eq!(id.len(), 26);
+    assert!(id.bytes().all(|b| b.is_ascii_digit() || b.is_ascii_uppercase()), "{}", id);
+    assert_eq!(header(&upstream.last_request(), "x-trace"), Some(id));
+    assert_eq!(header(&response, "x-request-id"), None);
+}
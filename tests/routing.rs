@@ -0,0 +1,115 @@
+// Routing and load balancing: the most specific route wins whatever order
+// the routes are configured in, and requests are spread over an upstream's
+// backends by smooth weighted round-robin.
+
+mod common;
+
+use std::net::SocketAddr;
+
+use common::{Upstream, free_port, get_plain, start_proxy};
+
+fn start(config: &str) -> SocketAddr {
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+
+{config}
+
+[access_log]
+enabled = false
+"#
+    );
+    start_proxy(&config, listen);
+    listen
+}
+
+/// Send a request; the index of the upstream that received it.
+fn served_by(proxy: SocketAddr, host: &str, path: &str, upstreams: &[&Upstream]) -> usize {
+    let before: Vec<usize> = upstreams.iter().map(|u| u.requests.lock().unwrap().len()).collect();
+    let response = get_plain(proxy, host, path);
+    assert!(response.starts_with("HTTP/1.1 200"), "{} {}: {}", host, path, response);
+    let served: Vec<usize> = (0..upstreams.len())
+        .filter(|&i| upstreams[i].requests.lock().unwrap().len() > before[i])
+        .collect();
+    assert_eq!(served.len(), 1, "{} {} served by {:?}", host, path, served);
+    served[0]
+}
+
+#[test]
+fn the_most_specific_route_wins() {
+    let (root, api, v2, admin) = (Upstream::spawn(), Upstream::spawn(), Upstream::spawn(), Upstream::spawn());
+    // Least specific first, so file order cannot be what decides.
+    let proxy = start(&format!(
+        r#"
+[upstreams.root]
+servers = ["{}"]
+
+[upstreams.api]
+servers = ["{}"]
+
+[upstreams.v2]
+servers = ["{}"]
+
+[upstreams.admin]
+servers = ["{}"]
+
+[[routes]]
+prefix = "/"
+upstream = "root"
+
+[[routes]]
+prefix = "/api"
+upstream = "api"
+
+[[routes]]
+prefix = "/api/v2"
+upstream = "v2"
+
+[[routes]]
+host = "admin.test"
+prefix = "/"
+upstream = "admin"
+"#,
+        root.address, api.address, v2.address, admin.address
+    ));
+    let upstreams = [&root, &api, &v2, &admin];
+
+    assert_eq!(served_by(proxy, "www.test", "/", &upstreams), 0);
+    assert_eq!(served_by(proxy, "www.test", "/api", &upstreams), 1);
+    assert_eq!(served_by(proxy, "www.test", "/api/users?page=2", &upstreams), 1);
+    assert_eq!(served_by(proxy, "www.test", "/api/v2/users", &upstreams), 2);
+    // Prefixes match whole path segments.
+    assert_eq!(served_by(proxy, "www.test", "/apiary", &upstreams), 0);
+    assert_eq!(served_by(proxy, "www.test", "/api/v2x", &upstreams), 1);
+    // A host-specific route beats a longer prefix, with or without a port.
+    assert_eq!(served_by(proxy, "admin.test", "/api/v2/users", &upstreams), 3);
+    assert_eq!(served_by(proxy, "ADMIN.test:8080", "/", &upstreams), 3);
+}
+
+#[test]
+fn backends_are_chosen_in_proportion_to_their_weights() {
+    let (heavy, light, idle) = (Upstream::spawn(), Upstream::spawn(), Upstream::spawn());
+    let proxy = start(&format!(
+        r#"
+[upstreams.app]
+servers = [{{ address = "{}", weight = 3 }}, {{ address = "{}", weight = 1 }}, {{ address = "{}", weight = 0 }}]
+
+[[routes]]
+prefix = "/"
+upstream = "app"
+"#,
+        heavy.address, light.address, idle.address
+    ));
+    let upstreams = [&heavy, &light, &idle];
+
+    // Smooth: every window of four requests is split 3:1, never 4:0.
+    for _ in 0..3 {
+        let mut counts = [0; 3];
+        for _ in 0..4 {
+            counts[served_by(proxy, "test", "/", &upstreams)] += 1;
+        }
+        assert_eq!(counts, [3, 1, 0]);
+    }
+}
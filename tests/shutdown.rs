@@ -0,0 +1,120 @@
+// Graceful shutdown on SIGTERM: requests in flight complete, new
+// connections are refused, and the process exits once drained or when the
+// grace period runs out.
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{ProxyProcess, free_port, header};
+
+/// An upstream that holds each request for `delay` before answering, and
+/// reports on the returned channel when a request arrives.
+fn spawn_slow_upstream(delay: Duration) -> (SocketAddr, Receiver<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let (arrived, arrivals) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let arrived = arrived.clone();
+            thread::spawn(move || {
+                let mut head = Vec::new();
+                let mut byte = [0u8; 1];
+                while !head.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap_or(0) == 1 {
+                    head.push(byte[0]);
+                }
+                let _ = arrived.send(());
+                thread::sleep(delay);
+                let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nslow");
+            });
+        }
+    });
+    (address, arrivals)
+}
+
+fn start(upstream: SocketAddr, grace_ms: u64) -> (SocketAddr, ProxyProcess) {
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+shutdown_grace_ms = {grace_ms}
+
+[upstream]
+address = "{upstream}"
+timeout_ms = 60000
+
+[access_log]
+enabled = false
+"#
+    );
+    (listen, ProxyProcess::spawn(&config, listen))
+}
+
+/// Send a request on its own thread; the response arrives on the channel.
+fn send_in_background(proxy: SocketAddr) -> Receiver<String> {
+    let (done, response) = mpsc::channel();
+    let mut stream = TcpStream::connect(proxy).unwrap();
+    thread::spawn(move || {
+        stream.set_read_timeout(Some(Duration::from_secs(30))).unwrap();
+        stream.write_all(b"GET /slow HTTP/1.1\r\nHost: test\r\n\r\n").unwrap();
+        let mut reply = String::new();
+        let _ = stream.read_to_string(&mut reply);
+        let _ = done.send(reply);
+    });
+    response
+}
+
+/// Wait for the proxy's listener to close.
+fn wait_until_refused(proxy: SocketAddr) {
+    let deadline = Instant::now() + Duration::from_secs(3);
+    while TcpStream::connect(proxy).is_ok() {
+        assert!(Instant::now() < deadline, "still accepting connections after SIGTERM");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+#[test]
+fn sigterm_lets_in_flight_requests_finish() {
+    let (upstream, arrivals) = spawn_slow_upstream(Duration::from_millis(1500));
+    let (proxy, mut process) = start(upstream, 10_000);
+
+    let response = send_in_background(proxy);
+    arrivals.recv_timeout(Duration::from_secs(5)).unwrap();
+    let signalled = Instant::now();
+    process.signal(libc::SIGTERM);
+
+    wait_until_refused(proxy);
+    assert!(response.try_recv().is_err(), "answered before the upstream did");
+
+    let reply = response.recv_timeout(Duration::from_secs(10)).unwrap();
+    assert!(reply.starts_with("HTTP/1.1 200"), "{}", reply);
+    assert!(reply.ends_with("slow"), "{}", reply);
+    // The connection closes after the response, not kept alive.
+    assert_eq!(header(&reply, "connection").as_deref(), Some("close"));
+
+    let status = process.wait_timeout(Duration::from_secs(5)).expect("proxy exits once drained");
+    assert!(status.success(), "{:?}", status);
+    assert!(signalled.elapsed() < Duration::from_secs(5), "took {:?}", signalled.elapsed());
+}
+
+#[test]
+fn sigterm_exits_when_the_grace_period_runs_out() {
+    let (upstream, arrivals) = spawn_slow_upstream(Duration::from_secs(60));
+    let (proxy, mut process) = start(upstream, 1_000);
+
+    let _response = send_in_background(proxy);
+    arrivals.recv_timeout(Duration::from_secs(5)).unwrap();
+    let signalled = Instant::now();
+    process.signal(libc::SIGTERM);
+
+    wait_until_refused(proxy);
+    process.wait_timeout(Duration::from_secs(5)).expect("proxy exits after the grace period");
+    let elapsed = signalled.elapsed();
+    assert!(elapsed >= Duration::from_millis(900), "exited after {:?}, before the grace period", elapsed);
+}
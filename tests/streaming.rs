@@ -0,0 +1,191 @@
+// Request and response bodies flowing through the proxy without being
+// buffered whole.
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use common::{connect, header, proxy_to, read_head};
+
+/// An upstream handing every connection to `handler` on its own thread.
+fn spawn_upstream(handler: impl Fn(TcpStream) + Send + Sync + Clone + 'static) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(stream) = stream else { continue };
+            let handler = handler.clone();
+            thread::spawn(move || handler(stream));
+        }
+    });
+    address
+}
+
+/// Read a body framed by Content-Length or chunked coding, decoded.
+fn read_body(reader: &mut impl BufRead, head: &str) -> Vec<u8> {
+    if let Some(length) = header(head, "content-length") {
+        let mut body = vec![0u8; length.parse().unwrap()];
+        reader.read_exact(&mut body).unwrap();
+        return body;
+    }
+    let mut body = Vec::new();
+    loop {
+        let mut size = String::new();
+        reader.read_line(&mut size).unwrap();
+        let size = usize::from_str_radix(size.trim(), 16).unwrap();
+        let mut chunk = vec![0u8; size + 2];
+        reader.read_exact(&mut chunk).unwrap();
+        if size == 0 {
+            return body;
+        }
+        body.extend_from_slice(&chunk[..size]);
+    }
+}
+
+/// Answers each request with its own body, keeping the connection open.
+fn echo(stream: TcpStream) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut writer = stream;
+    loop {
+        let head = read_head(&mut reader);
+        if head.is_empty() {
+            return;
+        }
+        let body = read_body(&mut reader, &head);
+        let chunked = header(&head, "transfer-encoding").unwrap_or_default();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nX-Request-Transfer-Encoding: {}\r\n\r\n",
+            body.len(),
+            chunked
+        );
+        writer.write_all(response.as_bytes()).unwrap();
+        writer.write_all(&body).unwrap();
+    }
+}
+
+#[test]
+fn response_body_reaches_client_before_upstream_finishes() {
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+    let release_rx = std::sync::Arc::new(std::sync::Mutex::new(release_rx));
+    let upstream = spawn_upstream(move |mut stream| {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        read_head(&mut reader);
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello")
+            .unwrap();
+        let _ = release_rx.lock().unwrap().recv_timeout(Duration::from_secs(10));
+        stream.write_all(b"world").unwrap();
+    });
+    let proxy = proxy_to(upstream, "");
+
+    let mut client = connect(proxy);
+    client
+        .write_all(b"GET /download HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(client);
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert_eq!(header(&head, "content-length").as_deref(), Some("10"));
+
+    let mut first = [0u8; 5];
+    reader.read_exact(&mut first).expect("first half arrives while the upstream waits");
+    assert_eq!(&first, b"hello");
+
+    release_tx.send(()).unwrap();
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "world");
+}
+
+#[test]
+fn close_delimited_response_is_sent_chunked() {
+    let upstream = spawn_upstream(|mut stream| {
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        read_head(&mut reader);
+        stream
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nno length here")
+            .unwrap();
+    });
+    let proxy = proxy_to(upstream, "");
+
+    let mut client = connect(proxy);
+    client
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(client);
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert_eq!(header(&head, "transfer-encoding").as_deref(), Some("chunked"));
+    assert_eq!(read_body(&mut reader, &head), b"no length here");
+}
+
+#[test]
+fn chunked_request_body_is_streamed_to_upstream() {
+    let proxy = proxy_to(spawn_upstream(echo), "");
+
+    let mut client = connect(proxy);
+    client
+        .write_all(
+            b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n6;ext=1\r\n world\r\n0\r\nX-Trailer: ignored\r\n\r\n",
+        )
+        .unwrap();
+    let mut reader = BufReader::new(client);
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert_eq!(header(&head, "x-request-transfer-encoding").as_deref(), Some("chunked"));
+    assert_eq!(read_body(&mut reader, &head), b"hello world");
+}
+
+#[test]
+fn bodies_do_not_break_keep_alive() {
+    let proxy = proxy_to(spawn_upstream(echo), "");
+
+    let mut client = connect(proxy);
+    let mut reader = BufReader::new(client.try_clone().unwrap());
+    for body in ["first", "second request"] {
+        let request = format!(
+            "PUT /item HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        client.write_all(request.as_bytes()).unwrap();
+        let head = read_head(&mut reader);
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        assert_eq!(read_body(&mut reader, &head), body.as_bytes());
+    }
+}
+
+#[test]
+fn oversized_chunked_body_is_rejected_as_it_arrives() {
+    let proxy = proxy_to(spawn_upstream(echo), "");
+
+    let mut client = connect(proxy);
+    // Announces 16 MiB; nothing past the chunk header is ever sent.
+    client
+        .write_all(b"POST /upload HTTP/1.1\r\nHost: localhost\r\nTransfer-Encoding: chunked\r\n\r\n1000000\r\n")
+        .unwrap();
+    let mut reader = BufReader::new(client);
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 413"), "{}", head);
+    assert_eq!(header(&head, "connection").as_deref(), Some("close"));
+}
+
+#[test]
+fn conflicting_framing_is_rejected() {
+    let proxy = proxy_to(spawn_upstream(echo), "");
+
+    let mut client = connect(proxy);
+    client
+        .write_all(
+            b"POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n",
+        )
+        .unwrap();
+    let mut response = String::new();
+    client.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 400"), "{}", response);
+}
@@ -0,0 +1,152 @@
+// TLS termination on the HTTPS listener, against self-signed certificates
+// generated for each test.
+
+mod common;
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+use common::{
+    Upstream, client_config, client_config_with_versions, connect_tls, free_port, get, header, start_proxy, temp_dir,
+};
+use rcgen::{CertifiedKey, generate_simple_self_signed};
+
+fn write_cert(dir: &Path, name: &str, cert: &CertifiedKey) -> (String, String) {
+    let cert_path = dir.join(format!("{}.pem", name));
+    let key_path = dir.join(format!("{}.key", name));
+    std::fs::write(&cert_path, cert.cert.pem()).unwrap();
+    std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
+    (cert_path.display().to_string(), key_path.display().to_string())
+}
+
+struct Proxy {
+    dir: PathBuf,
+    https: SocketAddr,
+    upstream: Upstream,
+    alpha: CertifiedKey,
+    beta: CertifiedKey,
+}
+
+/// A proxy whose HTTPS listener serves `alpha.test` (the default) and
+/// `*.beta.test`.
+fn start(extra_tls: &str) -> Proxy {
+    let dir = temp_dir("tls");
+    let alpha = generate_simple_self_signed(vec!["alpha.test".to_string()]).unwrap();
+    let beta = generate_simple_self_signed(vec!["www.beta.test".to_string()]).unwrap();
+    let (alpha_cert, alpha_key) = write_cert(&dir, "alpha", &alpha);
+    let (beta_cert, beta_key) = write_cert(&dir, "beta", &beta);
+
+    let upstream = Upstream::spawn();
+    let http: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let https: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{http}"
+
+[upstream]
+address = "{upstream}"
+
+[access_log]
+enabled = false
+
+[tls]
+listen = "{https}"
+{extra_tls}
+
+[[tls.certificates]]
+cert = "{beta_cert}"
+key = "{beta_key}"
+server_names = ["*.beta.test"]
+
+[[tls.certificates]]
+cert = "{alpha_cert}"
+key = "{alpha_key}"
+"#,
+        upstream = upstream.address,
+    );
+    start_proxy(&config, https);
+
+    Proxy {
+        dir,
+        https,
+        upstream,
+        alpha,
+        beta,
+    }
+}
+
+#[test]
+fn selects_certificate_by_sni() {
+    let proxy = start("");
+    let roots = [proxy.alpha.cert.der(), proxy.beta.cert.der()];
+
+    let beta = connect_tls(proxy.https, client_config(&roots), "www.beta.test").unwrap();
+    assert_eq!(&beta.conn.peer_certificates().unwrap()[0], proxy.beta.cert.der());
+
+    let alpha = connect_tls(proxy.https, client_config(&roots), "alpha.test").unwrap();
+    assert_eq!(&alpha.conn.peer_certificates().unwrap()[0], proxy.alpha.cert.der());
+
+    // The wildcard covers one label only, so this falls back to the default.
+    let nested = connect_tls(proxy.https, client_config(&roots), "a.www.beta.test");
+    assert!(nested.is_err(), "default certificate does not cover a.www.beta.test");
+}
+
+#[test]
+fn forwards_proto_and_rewrites_redirects() {
+    let proxy = start("");
+    let roots = [proxy.alpha.cert.der()];
+
+    let mut stream = connect_tls(proxy.https, client_config(&roots), "alpha.test").unwrap();
+    let response = get(&mut stream, "alpha.test", "/hello");
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    let forwarded = proxy.upstream.last_request();
+    assert_eq!(header(&forwarded, "x-forwarded-proto").as_deref(), Some("https"));
+
+    let mut stream = connect_tls(proxy.https, client_config(&roots), "alpha.test").unwrap();
+    let response = get(&mut stream, "alpha.test", "/redirect");
+    assert!(response.starts_with("HTTP/1.1 302"), "{}", response);
+    assert_eq!(header(&response, "location").as_deref(), Some("https://alpha.test/next"));
+}
+
+#[test]
+fn enforces_minimum_version() {
+    let proxy = start(r#"min_version = "1.3""#);
+    let roots = [proxy.alpha.cert.der()];
+
+    let tls12 = client_config_with_versions(&roots, &[&rustls::version::TLS12]);
+    assert!(connect_tls(proxy.https, tls12, "alpha.test").is_err());
+
+    let stream = connect_tls(proxy.https, client_config(&roots), "alpha.test").unwrap();
+    assert_eq!(stream.conn.protocol_version(), Some(rustls::ProtocolVersion::TLSv1_3));
+}
+
+#[test]
+fn reloads_rewritten_certificates() {
+    let proxy = start("reload_interval_ms = 20");
+    let rotated = generate_simple_self_signed(vec!["alpha.test".to_string()]).unwrap();
+    let roots = [proxy.alpha.cert.der(), rotated.cert.der()];
+    let served = || {
+        let stream = connect_tls(proxy.https, client_config(&roots), "alpha.test").unwrap();
+        stream.conn.peer_certificates().unwrap()[0].clone()
+    };
+    assert_eq!(&served(), proxy.alpha.cert.der());
+
+    write_cert(&proxy.dir, "alpha", &rotated);
+    let mut reloaded = false;
+    for _ in 0..250 {
+        if &served() == rotated.cert.der() {
+            reloaded = true;
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    assert!(reloaded, "rotated certificate was not picked up");
+
+    // A broken rewrite leaves the last good certificate in place.
+    std::fs::write(proxy.dir.join("alpha.pem"), "not a certificate").unwrap();
+    thread::sleep(Duration::from_millis(200));
+    assert_eq!(&served(), rotated.cert.der());
+}
@@ -0,0 +1,120 @@
+// Zero-downtime upgrades: a new process takes over the listeners of a
+// running one while clients keep connecting, including when it runs fewer
+// workers than its predecessor.
+
+mod common;
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use common::{ProxyProcess, Upstream, free_port, temp_dir};
+
+fn config(listen: SocketAddr, upstream: SocketAddr, socket: &Path, workers: usize) -> String {
+    format!(
+        r#"
+[server]
+listen = "{listen}"
+workers = {workers}
+upgrade_socket = "{socket}"
+shutdown_grace_ms = 5000
+
+[upstream]
+address = "{upstream}"
+
+[access_log]
+enabled = false
+"#,
+        socket = socket.display()
+    )
+}
+
+/// One request on a fresh connection; the error, if it failed.
+fn request(proxy: SocketAddr) -> Result<(), String> {
+    let mut stream = TcpStream::connect(proxy).map_err(|e| format!("connect: {}", e))?;
+    stream.set_read_timeout(Some(Duration::from_secs(10))).unwrap();
+    stream
+        .write_all(b"GET / HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n")
+        .map_err(|e| format!("write: {}", e))?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response).map_err(|e| format!("read: {}", e))?;
+    match response.starts_with("HTTP/1.1 200") {
+        true => Ok(()),
+        false => Err(format!("response: {:?}", response)),
+    }
+}
+
+/// Sockets listening on `port`, from the kernel's table.
+fn listening_sockets(port: u16) -> usize {
+    let table = std::fs::read_to_string("/proc/net/tcp").unwrap();
+    let local = format!(":{:04X}", port);
+    table
+        .lines()
+        .skip(1)
+        .filter(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            fields[1].ends_with(&local) && fields[3] == "0A"
+        })
+        .count()
+}
+
+#[test]
+fn listeners_are_handed_over_without_refusing_connections() {
+    let upstream = Upstream::spawn();
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let socket = temp_dir("upgrade").join("handoff.sock");
+
+    let mut old = ProxyProcess::spawn(&config(listen, upstream.address, &socket, 4), listen);
+    // The handoff socket is set up just after the listeners.
+    for _ in 0..100 {
+        if socket.exists() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(20));
+    }
+    let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600, "handoff socket mode {:o}", mode);
+
+    // A client connecting over and over for the whole upgrade.
+    let stop = Arc::new(AtomicBool::new(false));
+    let client = {
+        let stop = Arc::clone(&stop);
+        thread::spawn(move || {
+            let (mut served, mut failures) = (0, Vec::new());
+            while !stop.load(Ordering::SeqCst) {
+                match request(listen) {
+                    Ok(()) => served += 1,
+                    Err(e) => failures.push(e),
+                }
+            }
+            (served, failures)
+        })
+    };
+    thread::sleep(Duration::from_millis(200));
+
+    // The successor runs fewer workers, so it has listeners to adopt.
+    let _new = ProxyProcess::spawn(&config(listen, upstream.address, &socket, 2), listen);
+    let status = old.wait_timeout(Duration::from_secs(10)).expect("old process drains and exits");
+    assert!(status.success(), "{:?}", status);
+    let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600, "handoff socket mode {:o}", mode);
+    // None of the four inherited listeners were closed: connections queued
+    // on one would have been reset.
+    assert_eq!(listening_sockets(listen.port()), 4);
+
+    // Connections keep arriving on all four inherited listeners.
+    thread::sleep(Duration::from_millis(300));
+    stop.store(true, Ordering::SeqCst);
+    let (served, failures) = client.join().unwrap();
+    assert!(failures.is_empty(), "{} failed of {}: {:?}", failures.len(), served + failures.len(), failures);
+    assert!(served > 10, "only {} requests served", served);
+
+    for _ in 0..200 {
+        request(listen).unwrap();
+    }
+}
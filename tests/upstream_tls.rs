@@ -0,0 +1,115 @@
+// TLS connections from the proxy to HTTPS upstreams: CA verification, SNI
+// override, insecure-skip-verify and client certificates.
+
+mod common;
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use common::{TestCa, free_port, get_plain, spawn_tls_upstream, start_proxy, temp_dir};
+use rustls::server::WebPkiClientVerifier;
+
+struct Backend {
+    ca: TestCa,
+    address: SocketAddr,
+}
+
+/// An HTTPS upstream with a certificate for `backend.internal` issued by a
+/// fresh CA, which also accepts (but does not require) client certificates
+/// from that CA.
+fn backend() -> Backend {
+    let ca = TestCa::new("Orion Test CA");
+    let server = ca.issue("backend.internal", &["backend.internal"]);
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let verifier = WebPkiClientVerifier::builder_with_provider(ca.roots(), Arc::clone(&provider))
+        .allow_unauthenticated()
+        .build()
+        .unwrap();
+    let config = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .unwrap()
+        .with_client_cert_verifier(verifier)
+        .with_single_cert(server.chain(), server.private_key())
+        .unwrap();
+    let address = spawn_tls_upstream(config);
+    Backend { ca, address }
+}
+
+/// Start a proxy in front of `backend` with the given `[upstream.tls]` body
+/// and return its response to `GET /`.
+fn request_through(backend: &Backend, tls: &str) -> String {
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+
+[upstreams.secure]
+servers = ["{address}"]
+timeout_ms = 5000
+
+[upstreams.secure.tls]
+{tls}
+
+[access_log]
+enabled = false
+"#,
+        address = backend.address,
+    );
+    start_proxy(&config, listen);
+    get_plain(listen, "example.test", "/")
+}
+
+#[test]
+fn verifies_against_ca_bundle_with_server_name() {
+    let backend = backend();
+    let dir = temp_dir("upstream-tls");
+    let ca_file = backend.ca.write(&dir);
+
+    let response = request_through(
+        &backend,
+        &format!("ca_file = \"{}\"\nserver_name = \"backend.internal\"", ca_file),
+    );
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("no-client-cert"), "{}", response);
+}
+
+#[test]
+fn rejects_certificate_not_matching_address() {
+    let backend = backend();
+    let dir = temp_dir("upstream-tls");
+    let ca_file = backend.ca.write(&dir);
+
+    // Without `server_name` the certificate is checked against 127.0.0.1.
+    let response = request_through(&backend, &format!("ca_file = \"{}\"", ca_file));
+    assert!(response.starts_with("HTTP/1.1 502"), "{}", response);
+}
+
+#[test]
+fn insecure_skip_verify_accepts_untrusted_certificate() {
+    let backend = backend();
+
+    let response = request_through(&backend, "");
+    assert!(response.starts_with("HTTP/1.1 502"), "{}", response);
+
+    let response = request_through(&backend, "insecure_skip_verify = true");
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+}
+
+#[test]
+fn presents_client_certificate() {
+    let backend = backend();
+    let dir = temp_dir("upstream-tls");
+    let ca_file = backend.ca.write(&dir);
+    let (client_cert, client_key) = backend.ca.issue("orion", &["orion.internal"]).write(&dir, "client");
+
+    let response = request_through(
+        &backend,
+        &format!(
+            "ca_file = \"{}\"\nserver_name = \"backend.internal\"\nclient_cert = \"{}\"\nclient_key = \"{}\"",
+            ca_file, client_cert, client_key
+        ),
+    );
+    assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    assert!(response.ends_with("\r\n\r\nclient-cert"), "{}", response);
+}
@@ -0,0 +1,163 @@
+// WebSocket handshakes forwarded upstream and the upgraded connection
+// tunneled in both directions.
+
+mod common;
+
+use std::io::{BufReader, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use common::{connect_buffered, header, proxy_to, read_head};
+
+const HANDSHAKE: &[u8] = b"GET /chat HTTP/1.1\r\nHost: localhost\r\nConnection: keep-alive, Upgrade\r\n\
+    Upgrade: websocket\r\nSec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n";
+
+/// An upstream that accepts upgrades to `websocket` (greeting the client
+/// with `hello` right behind its 101), echoes bytes until the client is
+/// done sending, then says `bye` and closes. Anything else gets a 200.
+struct Upstream {
+    address: SocketAddr,
+    heads: Arc<Mutex<Vec<String>>>,
+}
+
+fn spawn_upstream() -> Upstream {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let heads = Arc::new(Mutex::new(Vec::new()));
+    let recorded = Arc::clone(&heads);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let recorded = Arc::clone(&recorded);
+            thread::spawn(move || {
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let head = read_head(&mut reader);
+                recorded.lock().unwrap().push(head.clone());
+                if header(&head, "upgrade").as_deref() != Some("websocket") {
+                    let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nplain");
+                    return;
+                }
+                stream
+                    .write_all(
+                        b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+                          Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\nhello",
+                    )
+                    .unwrap();
+                let mut buf = [0u8; 1024];
+                loop {
+                    match reader.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => stream.write_all(&buf[..n]).unwrap(),
+                    }
+                }
+                let _ = stream.write_all(b"bye");
+            });
+        }
+    });
+    Upstream { address, heads }
+}
+
+fn read_exactly(reader: &mut impl Read, len: usize) -> String {
+    let mut buf = vec![0u8; len];
+    reader.read_exact(&mut buf).unwrap();
+    String::from_utf8(buf).unwrap()
+}
+
+#[test]
+fn upgraded_connection_is_tunneled_both_ways() {
+    let upstream = spawn_upstream();
+    let proxy = proxy_to(upstream.address, "");
+
+    let (mut client, mut reader) = connect_buffered(proxy);
+    client.write_all(HANDSHAKE).unwrap();
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 101 Switching Protocols"), "{}", head);
+    assert_eq!(header(&head, "upgrade").as_deref(), Some("websocket"));
+    assert_eq!(header(&head, "connection").as_deref(), Some("Upgrade"));
+    assert!(header(&head, "sec-websocket-accept").is_some());
+    assert_eq!(read_exactly(&mut reader, 5), "hello");
+
+    for message in ["ping", "a longer message"] {
+        client.write_all(message.as_bytes()).unwrap();
+        assert_eq!(read_exactly(&mut reader, message.len()), message);
+    }
+
+    // Half-close: the upstream still gets to answer after the client is done.
+    client.shutdown(Shutdown::Write).unwrap();
+    let mut rest = String::new();
+    reader.read_to_string(&mut rest).unwrap();
+    assert_eq!(rest, "bye");
+
+    let forwarded = upstream.heads.lock().unwrap()[0].clone();
+    assert_eq!(header(&forwarded, "connection").as_deref(), Some("Upgrade"));
+    assert_eq!(header(&forwarded, "upgrade").as_deref(), Some("websocket"));
+    assert_eq!(header(&forwarded, "sec-websocket-key").as_deref(), Some("dGhlIHNhbXBsZSBub25jZQ=="));
+}
+
+#[test]
+fn idle_tunnel_is_closed() {
+    let upstream = spawn_upstream();
+    let proxy = proxy_to(upstream.address, "tunnel_idle_timeout_ms = 300");
+
+    let (mut client, mut reader) = connect_buffered(proxy);
+    client.write_all(HANDSHAKE).unwrap();
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 101"), "{}", head);
+    assert_eq!(read_exactly(&mut reader, 5), "hello");
+
+    let started = Instant::now();
+    let mut rest = Vec::new();
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(rest.is_empty());
+    assert!(started.elapsed() < Duration::from_secs(4));
+}
+
+#[test]
+fn other_upgrades_are_forwarded_as_plain_requests() {
+    let upstream = spawn_upstream();
+    let proxy = proxy_to(upstream.address, "");
+
+    let (mut client, mut reader) = connect_buffered(proxy);
+    client
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade\r\nUpgrade: something-else\r\n\r\n")
+        .unwrap();
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+    assert_eq!(read_exactly(&mut reader, 5), "plain");
+
+    let forwarded = upstream.heads.lock().unwrap()[0].clone();
+    assert_eq!(header(&forwarded, "upgrade"), None);
+    assert_eq!(header(&forwarded, "connection").as_deref(), Some("close"));
+}
+
+#[test]
+fn declined_upgrade_is_an_ordinary_response() {
+    // An upstream that ignores the upgrade and answers normally.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            read_head(&mut reader);
+            let _ = stream.write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 6\r\n\r\nno way");
+        }
+    });
+    let proxy = proxy_to(address, "");
+
+    let (mut client, mut reader) = connect_buffered(proxy);
+    client.write_all(HANDSHAKE).unwrap();
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 403"), "{}", head);
+    assert_eq!(header(&head, "upgrade"), None);
+    assert_eq!(read_exactly(&mut reader, 6), "no way");
+
+    // The client connection is still usable for plain HTTP.
+    client
+        .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .unwrap();
+    let head = read_head(&mut reader);
+    assert!(head.starts_with("HTTP/1.1 403"), "{}", head);
+}
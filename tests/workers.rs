@@ -0,0 +1,58 @@
+// Several accept workers sharing the listen address through SO_REUSEPORT.
+
+mod common;
+
+use std::net::SocketAddr;
+
+use common::{Upstream, fetch_metrics, free_port, get_plain, metric_value, start_proxy};
+use orion::config::Config;
+
+#[test]
+fn connections_are_spread_across_workers() {
+    let upstream = Upstream::spawn();
+    let listen: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let admin: SocketAddr = format!("127.0.0.1:{}", free_port()).parse().unwrap();
+    let config = format!(
+        r#"
+[server]
+listen = "{listen}"
+workers = 4
+pin_workers = true
+
+[upstream]
+address = "{upstream}"
+
+[admin]
+listen = "{admin}"
+
+[access_log]
+enabled = false
+"#,
+        upstream = upstream.address,
+    );
+    start_proxy(&config, listen);
+
+    for _ in 0..64 {
+        let response = get_plain(listen, "localhost", "/");
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+    }
+
+    let metrics = fetch_metrics(admin);
+    let per_worker: Vec<f64> = (0..4)
+        .filter_map(|worker| {
+            metric_value(&metrics, &format!("orion_worker_requests_total{{worker=\"{}\"}}", worker))
+        })
+        .collect();
+    assert!(per_worker.len() > 1, "{}", metrics);
+    assert_eq!(per_worker.iter().sum::<f64>(), 64.0, "{}", metrics);
+}
+
+#[test]
+fn worker_count_is_validated() {
+    for workers in ["0", "1000"] {
+        let config = format!("[server]\nworkers = {}\n", workers);
+        assert!(Config::parse(&config).is_err(), "workers = {}", workers);
+    }
+    let config = Config::parse("[server]\nworkers = 8\n").unwrap();
+    assert_eq!(config.server.workers, 8);
+}